@@ -1,7 +1,7 @@
 import {Link, ScrollRestoration, useLoaderData} from 'react-router-dom';
 import {List, ListItem, ListItemButton, ListItemIcon, ListItemText} from '@mui/material';
 import {Description, Folder} from '@mui/icons-material';
-import {GetObjectCommand, type ListObjectsV2CommandOutput, S3Client} from '@aws-sdk/client-s3';
+import {GetObjectCommand, HeadObjectCommand, type ListObjectsV2CommandOutput, S3Client} from '@aws-sdk/client-s3';
 import {getSignedUrl} from '@aws-sdk/s3-request-presigner';
 
 const basename = (path: string) => path.split('/').reverse().find(s => s.length);
@@ -15,13 +15,19 @@ function MediaChooser({s3Client, bucket}: MediaChooserProps) {
   const res = useLoaderData() as ListObjectsV2CommandOutput;
 
   const play = async (path: string) =>
-    await getSignedUrl(s3Client, new GetObjectCommand({
-      Bucket: bucket,
-      Key: path,
-    }), {
-      expiresIn: 86400,
-    }).then(url => {
-      const media = new window.chrome.cast.media.MediaInfo(url, '');
+    await Promise.all([
+      getSignedUrl(s3Client, new GetObjectCommand({
+        Bucket: bucket,
+        Key: path,
+      }), {
+        expiresIn: 86400,
+      }),
+      s3Client.send(new HeadObjectCommand({
+        Bucket: bucket,
+        Key: path,
+      })),
+    ]).then(([url, head]) => {
+      const media = new window.chrome.cast.media.MediaInfo(url, head.ContentType ?? '');
       const request = new window.chrome.cast.media.LoadRequest(media);
       return window.cast.framework.CastContext
         .getInstance()
@@ -0,0 +1,103 @@
+use aws_sdk_s3::types::ChecksumAlgorithm;
+use aws_smithy_types::base64;
+use axum::http::HeaderMap;
+use crc::{Algorithm, Crc, Digest, CRC_32_ISCSI, CRC_32_ISO_HDLC};
+use sha2::Digest as _;
+
+use super::error::{Error, ErrorCode, Result};
+
+const CRC_64_NVME: Algorithm<u64> = Algorithm {
+    width: 64,
+    poly: 0xad93d23594c93659,
+    init: 0xffffffffffffffff,
+    refin: true,
+    refout: true,
+    xorout: 0xffffffffffffffff,
+    check: 0xae8b14860a799888,
+    residue: 0xf310303b2b6f6e42,
+};
+
+static CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+static CRC32C: Crc<u32> = Crc::<u32>::new(&CRC_32_ISCSI);
+static CRC64NVME: Crc<u64> = Crc::<u64>::new(&CRC_64_NVME);
+
+const ALGORITHMS: [ChecksumAlgorithm; 5] = [
+    ChecksumAlgorithm::Crc32,
+    ChecksumAlgorithm::Crc32C,
+    ChecksumAlgorithm::Crc64Nvme,
+    ChecksumAlgorithm::Sha1,
+    ChecksumAlgorithm::Sha256,
+];
+
+/// Returns the name of the header or trailer carrying the checksum for `algorithm`.
+pub fn header_name(algorithm: &ChecksumAlgorithm) -> String {
+    format!("x-amz-checksum-{}", algorithm.as_str().to_ascii_lowercase())
+}
+
+/// Returns the checksum algorithm whose header or trailer is named `name`.
+pub fn from_header_name(name: &str) -> Option<ChecksumAlgorithm> {
+    ALGORITHMS
+        .into_iter()
+        .find(|a| header_name(a).eq_ignore_ascii_case(name))
+}
+
+/// Returns the checksum sent along with the request headers, if any.
+pub fn from_headers(headers: &HeaderMap) -> Result<Option<(ChecksumAlgorithm, String)>> {
+    let mut found = ALGORITHMS.into_iter().filter_map(|a| {
+        headers
+            .get(header_name(&a))
+            .map(|v| (a, String::from_utf8_lossy(v.as_bytes()).into_owned()))
+    });
+
+    let checksum = found.next();
+    if found.next().is_some() {
+        return Err(Error::from(ErrorCode::InvalidRequest).message(
+            "Expecting a single x-amz-checksum- header. Multiple checksum Types are not allowed.",
+        ));
+    }
+
+    Ok(checksum)
+}
+
+pub enum Checksum {
+    Crc32(Digest<'static, u32>),
+    Crc32C(Digest<'static, u32>),
+    Crc64Nvme(Digest<'static, u64>),
+    Sha1(sha1::Sha1),
+    Sha256(sha2::Sha256),
+}
+
+impl Checksum {
+    pub fn new(algorithm: &ChecksumAlgorithm) -> Result<Self> {
+        match algorithm {
+            ChecksumAlgorithm::Crc32 => Ok(Self::Crc32(CRC32.digest())),
+            ChecksumAlgorithm::Crc32C => Ok(Self::Crc32C(CRC32C.digest())),
+            ChecksumAlgorithm::Crc64Nvme => Ok(Self::Crc64Nvme(CRC64NVME.digest())),
+            ChecksumAlgorithm::Sha1 => Ok(Self::Sha1(sha1::Sha1::new())),
+            ChecksumAlgorithm::Sha256 => Ok(Self::Sha256(sha2::Sha256::new())),
+            _ => Err(Error::from(ErrorCode::InvalidRequest)
+                .message("Checksum algorithm provided is unsupported.")),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Crc32(digest) | Self::Crc32C(digest) => digest.update(data),
+            Self::Crc64Nvme(digest) => digest.update(data),
+            Self::Sha1(hasher) => hasher.update(data),
+            Self::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    /// Returns the base64 encoded checksum as it appears in the headers.
+    pub fn finalize(self) -> String {
+        match self {
+            Self::Crc32(digest) | Self::Crc32C(digest) => {
+                base64::encode(digest.finalize().to_be_bytes())
+            }
+            Self::Crc64Nvme(digest) => base64::encode(digest.finalize().to_be_bytes()),
+            Self::Sha1(hasher) => base64::encode(hasher.finalize()),
+            Self::Sha256(hasher) => base64::encode(hasher.finalize()),
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use bytes::{Bytes, BytesMut};
+use futures::prelude::*;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+use super::auth::ChunkSigner;
+use super::error::{Error, ErrorCode, Result};
+
+const MAX_LINE_LENGTH: u64 = 4096;
+const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+const TRAILER_SIGNATURE: &str = "x-amz-trailer-signature";
+
+/// Framing of a request payload, as announced by the `x-amz-content-sha256` header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Payload {
+    Unsigned,
+    Sha256(String),
+    Streaming { signed: bool, trailer: bool },
+}
+
+impl Payload {
+    /// Reads the framing from the `x-amz-content-sha256` and `Content-Encoding` headers, failing
+    /// for streaming payloads that are not supported and for `aws-chunked` bodies that are not
+    /// announced as streaming.
+    pub fn from_headers(sha256: Option<&str>, content_encoding: Option<&str>) -> Result<Self> {
+        let payload = match sha256 {
+            Some("STREAMING-AWS4-HMAC-SHA256-PAYLOAD") => Self::Streaming {
+                signed: true,
+                trailer: false,
+            },
+            Some("STREAMING-AWS4-HMAC-SHA256-PAYLOAD-TRAILER") => Self::Streaming {
+                signed: true,
+                trailer: true,
+            },
+            Some("STREAMING-UNSIGNED-PAYLOAD-TRAILER") => Self::Streaming {
+                signed: false,
+                trailer: true,
+            },
+            Some(v) if v.starts_with("STREAMING-") => {
+                return Err(Error::from(ErrorCode::NotImplemented)
+                    .message(format!("The {v} payload is not supported.")));
+            }
+            Some(v) if v.len() == 64 && v.bytes().all(|b| b.is_ascii_hexdigit()) => {
+                Self::Sha256(v.to_ascii_lowercase())
+            }
+            _ => Self::Unsigned,
+        };
+
+        let chunked = content_encoding.is_some_and(|v| {
+            v.split(',')
+                .any(|coding| coding.trim().eq_ignore_ascii_case("aws-chunked"))
+        });
+        if chunked && !matches!(payload, Self::Streaming { .. }) {
+            return Err(Error::from(ErrorCode::InvalidRequest).message(
+                "The aws-chunked content encoding requires a streaming x-amz-content-sha256 value.",
+            ));
+        }
+
+        Ok(payload)
+    }
+}
+
+#[derive(Debug)]
+pub enum Frame {
+    Data(Bytes),
+    Trailers(Vec<(String, String)>),
+}
+
+/// Strips the `aws-chunked` framing from `reader`, verifying the chunk signatures with `signer`
+/// if given.
+pub fn decode<R>(reader: R, signer: Option<ChunkSigner>) -> impl Stream<Item = Result<Frame>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let decoder = Decoder {
+        reader,
+        signer,
+        done: false,
+    };
+    stream::try_unfold(decoder, |mut decoder| async move {
+        Ok(decoder.next_frame().await?.map(|frame| (frame, decoder)))
+    })
+}
+
+struct Decoder<R> {
+    reader: R,
+    signer: Option<ChunkSigner>,
+    done: bool,
+}
+
+impl<R: AsyncBufRead + Unpin> Decoder<R> {
+    async fn next_frame(&mut self) -> Result<Option<Frame>> {
+        if self.done {
+            return Ok(None);
+        }
+
+        let line = self.read_line().await?.ok_or_else(incomplete)?;
+        let (size, extension) = match line.split_once(';') {
+            Some((size, extension)) => (size, Some(extension)),
+            None => (line.as_str(), None),
+        };
+        let size = usize::from_str_radix(size.trim(), 16).map_err(|_| malformed())?;
+        if MAX_CHUNK_SIZE < size {
+            return Err(malformed());
+        }
+
+        let mut data = BytesMut::zeroed(size);
+        self.reader
+            .read_exact(&mut data)
+            .await
+            .map_err(|_| incomplete())?;
+
+        if let Some(signer) = &mut self.signer {
+            let signature = extension
+                .and_then(|e| e.trim().strip_prefix("chunk-signature="))
+                .ok_or_else(malformed)?;
+            signer.verify_chunk(signature, &Sha256::digest(&data))?;
+        }
+
+        if 0 < size {
+            match self.read_line().await? {
+                Some(line) if line.is_empty() => return Ok(Some(Frame::Data(data.freeze()))),
+                Some(_) => return Err(malformed()),
+                None => return Err(incomplete()),
+            }
+        }
+
+        self.done = true;
+
+        let mut trailers = Vec::new();
+        let mut signature = None;
+        while let Some(line) = self.read_line().await? {
+            if line.is_empty() {
+                break;
+            }
+
+            let (name, value) = line.split_once(':').ok_or_else(malformed)?;
+            let name = name.trim().to_ascii_lowercase();
+            let value = value.trim().to_string();
+            if name == TRAILER_SIGNATURE {
+                signature = Some(value);
+            } else {
+                trailers.push((name, value));
+            }
+        }
+
+        if let (Some(signer), false) = (&mut self.signer, trailers.is_empty()) {
+            let signature = signature.ok_or_else(malformed)?;
+            let trailer = trailers
+                .iter()
+                .map(|(name, value)| format!("{name}:{value}\n"))
+                .collect::<String>();
+            signer.verify_trailer(&signature, &trailer)?;
+        }
+
+        Ok(Some(Frame::Trailers(trailers)))
+    }
+
+    async fn read_line(&mut self) -> Result<Option<String>> {
+        let mut line = Vec::new();
+        (&mut self.reader)
+            .take(MAX_LINE_LENGTH)
+            .read_until(b'\n', &mut line)
+            .await?;
+        if line.is_empty() {
+            return Ok(None);
+        }
+
+        let line = line.strip_suffix(b"\r\n").ok_or_else(malformed)?.to_vec();
+        String::from_utf8(line).map(Some).map_err(|_| malformed())
+    }
+}
+
+fn malformed() -> Error {
+    Error::from(ErrorCode::InvalidRequest).message("The aws-chunked payload is malformed.")
+}
+
+fn incomplete() -> Error {
+    Error::from(ErrorCode::IncompleteBody)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payloads() {
+        let hash = "E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855";
+        assert_eq!(
+            Payload::from_headers(Some(hash), None).unwrap(),
+            Payload::Sha256(hash.to_ascii_lowercase())
+        );
+        assert_eq!(
+            Payload::from_headers(Some("UNSIGNED-PAYLOAD"), None).unwrap(),
+            Payload::Unsigned
+        );
+        assert_eq!(
+            Payload::from_headers(
+                Some("STREAMING-UNSIGNED-PAYLOAD-TRAILER"),
+                Some("gzip, aws-chunked")
+            )
+            .unwrap(),
+            Payload::Streaming {
+                signed: false,
+                trailer: true
+            }
+        );
+
+        for value in [
+            "STREAMING-AWS4-ECDSA-P256-SHA256-PAYLOAD",
+            "STREAMING-AWS4-ECDSA-P256-SHA256-PAYLOAD-TRAILER",
+        ] {
+            let e = Payload::from_headers(Some(value), Some("aws-chunked")).unwrap_err();
+            assert_eq!(e.code(), ErrorCode::NotImplemented, "{value}");
+        }
+        for value in [None, Some("UNSIGNED-PAYLOAD"), Some(hash)] {
+            let e = Payload::from_headers(value, Some("aws-chunked")).unwrap_err();
+            assert_eq!(e.code(), ErrorCode::InvalidRequest, "{value:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn unsigned_chunks() {
+        let body = b"5\r\nhello\r\n0\r\nx-amz-checksum-crc32:NhCmhg==\r\n\r\n";
+        let frames: Vec<_> = decode(&body[..], None).try_collect().await.unwrap();
+        assert!(matches!(&frames[0], Frame::Data(data) if data == "hello"));
+        assert!(matches!(
+            &frames[1],
+            Frame::Trailers(trailers) if trailers == &[("x-amz-checksum-crc32".to_string(), "NhCmhg==".to_string())]
+        ));
+        assert_eq!(frames.len(), 2);
+
+        let truncated = b"5\r\nhel";
+        let frames: Vec<_> = decode(&truncated[..], None).collect().await;
+        assert!(frames.iter().any(Result::is_err));
+    }
+}
@@ -0,0 +1,144 @@
+use aws_smithy_types::base64;
+use axum::extract::Request;
+use axum::http::{HeaderName, HeaderValue, Uri};
+use axum::middleware::Next;
+use axum::response::Response;
+use percent_encoding::percent_decode_str;
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::LazyLock;
+use std::time::SystemTime;
+use tracing::{debug, info_span, Instrument};
+
+const X_AMZ_REQUEST_ID: HeaderName = HeaderName::from_static("x-amz-request-id");
+const X_AMZ_ID_2: HeaderName = HeaderName::from_static("x-amz-id-2");
+
+static SEED: LazyLock<[u8; 32]> = LazyLock::new(|| {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    Sha256::new()
+        .chain_update(std::process::id().to_be_bytes())
+        .chain_update(now.as_nanos().to_be_bytes())
+        .finalize()
+        .into()
+});
+
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+tokio::task_local! {
+    static CONTEXT: Context;
+}
+
+/// Identifies a request in responses and logs.
+#[derive(Debug, Clone)]
+pub struct Context {
+    pub request_id: String,
+    pub host_id: String,
+    pub resource: String,
+}
+
+impl Context {
+    fn new(resource: String) -> Self {
+        let digest = Sha256::new()
+            .chain_update(*SEED)
+            .chain_update(COUNTER.fetch_add(1, Ordering::Relaxed).to_be_bytes())
+            .finalize();
+        Self {
+            request_id: hex::encode_upper(&digest[..8]),
+            host_id: base64::encode(&digest[8..]),
+            resource,
+        }
+    }
+
+    /// Returns the context of the request being handled by the current task, if any.
+    pub fn current() -> Option<Self> {
+        CONTEXT.try_with(Clone::clone).ok()
+    }
+}
+
+/// Assigns an ID to each request, and runs the rest of the stack in a span carrying it.
+pub async fn handle(request: Request, next: Next) -> Response {
+    let resource = percent_decode_str(request.uri().path())
+        .decode_utf8_lossy()
+        .into_owned();
+    let context = Context::new(resource);
+    let span = info_span!(
+        "request",
+        id = %context.request_id,
+        method = %request.method(),
+        uri = %redact(request.uri()),
+    );
+
+    let request_id = HeaderValue::try_from(&context.request_id);
+    let host_id = HeaderValue::try_from(&context.host_id);
+    let mut response = CONTEXT
+        .scope(context, next.run(request))
+        .instrument(span.clone())
+        .await;
+    span.in_scope(|| debug!("responded with {}", response.status()));
+
+    if let Ok(request_id) = request_id {
+        response.headers_mut().insert(X_AMZ_REQUEST_ID, request_id);
+    }
+    if let Ok(host_id) = host_id {
+        response.headers_mut().insert(X_AMZ_ID_2, host_id);
+    }
+    response
+}
+
+/// Returns the path and query of `uri` with the values of the `X-Amz-*` query parameters, and
+/// of the `AWSAccessKeyId` and `Signature` ones of older presigned URLs, left out, since they
+/// carry credentials.
+fn redact(uri: &Uri) -> String {
+    let Some(query) = uri.query() else {
+        return uri.path().to_string();
+    };
+
+    let query = query
+        .split('&')
+        .map(|pair| {
+            let name = pair.split_once('=').map_or(pair, |(name, _)| name);
+            let decoded = percent_decode_str(name).decode_utf8_lossy();
+            let secret = decoded
+                .get(..6)
+                .is_some_and(|prefix| prefix.eq_ignore_ascii_case("x-amz-"))
+                || matches!(decoded.as_ref(), "AWSAccessKeyId" | "Signature");
+            if secret {
+                format!("{name}=REDACTED")
+            } else {
+                pair.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+    format!("{}?{query}", uri.path())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacted_uris() {
+        let redacted = |uri: &str| redact(&uri.parse().unwrap());
+        assert_eq!(redacted("/api/contents/a.mp4"), "/api/contents/a.mp4");
+        assert_eq!(
+            redacted("/api/contents?list-type=2&prefix=kids%2F"),
+            "/api/contents?list-type=2&prefix=kids%2F"
+        );
+        assert_eq!(
+            redacted("/api/contents/a.mp4?AWSAccessKeyId=AKID&Signature=abc%3D&Expires=1"),
+            "/api/contents/a.mp4?AWSAccessKeyId=REDACTED&Signature=REDACTED&Expires=1"
+        );
+        assert_eq!(
+            redacted(
+                "/api/contents/a.mp4?kayo-probe&X-Amz-Algorithm=AWS4-HMAC-SHA256\
+                 &X-Amz-Credential=AKID%2F20130524%2Fus-east-1%2Fs3%2Faws4_request\
+                 &x-amz-signature=abc&X%2DAmz%2DSecurity%2DToken=secret"
+            ),
+            "/api/contents/a.mp4?kayo-probe&X-Amz-Algorithm=REDACTED&X-Amz-Credential=REDACTED\
+             &x-amz-signature=REDACTED&X%2DAmz%2DSecurity%2DToken=REDACTED"
+        );
+    }
+}
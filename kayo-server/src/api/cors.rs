@@ -0,0 +1,551 @@
+use axum::extract::{Request, State};
+use axum::http::header::{
+    ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+    ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE,
+    ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD, ORIGIN, VARY,
+};
+use axum::http::{HeaderMap, HeaderValue, Method};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use serde::Deserialize;
+use tower::{Layer, ServiceExt};
+use tower_http::cors::CorsLayer;
+
+use super::error::{Error, ErrorCode, Result};
+use super::store::{Configuration, Store};
+use super::XMLNS;
+use crate::de_xml;
+
+const MAX_RULES: usize = 100;
+const METHODS: [&str; 5] = ["GET", "PUT", "HEAD", "POST", "DELETE"];
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CorsRule {
+    #[serde(rename = "ID")]
+    id: Option<String>,
+    #[serde(rename = "AllowedOrigin", default)]
+    allowed_origins: Vec<String>,
+    #[serde(rename = "AllowedMethod", default)]
+    allowed_methods: Vec<String>,
+    #[serde(rename = "AllowedHeader", default)]
+    allowed_headers: Vec<String>,
+    #[serde(rename = "ExposeHeader", default)]
+    expose_headers: Vec<String>,
+    #[serde(rename = "MaxAgeSeconds")]
+    max_age_seconds: Option<u32>,
+}
+
+impl CorsRule {
+    fn matches(&self, origin: &str, method: &str, headers: &[&str]) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|o| wildcard_match(o, origin))
+            && self.allowed_methods.iter().any(|m| m == method)
+            && headers.iter().all(|h| {
+                self.allowed_headers
+                    .iter()
+                    .any(|a| wildcard_match(&a.to_ascii_lowercase(), &h.to_ascii_lowercase()))
+            })
+    }
+
+    fn apply(&self, origin: &HeaderValue, headers: &mut HeaderMap) {
+        let origin = if self.allowed_origins.iter().any(|o| o == "*") {
+            HeaderValue::from_static("*")
+        } else {
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+            origin.clone()
+        };
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+
+        if let Ok(methods) = HeaderValue::try_from(self.allowed_methods.join(", ")) {
+            headers.insert(ACCESS_CONTROL_ALLOW_METHODS, methods);
+        }
+
+        if !self.expose_headers.is_empty() {
+            if let Ok(expose) = HeaderValue::try_from(self.expose_headers.join(", ")) {
+                headers.insert(ACCESS_CONTROL_EXPOSE_HEADERS, expose);
+            }
+        }
+
+        if let Some(max_age) = self.max_age_seconds {
+            headers.insert(ACCESS_CONTROL_MAX_AGE, max_age.into());
+        }
+    }
+}
+
+impl Serialize for CorsRule {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("CORSRule", 0)?;
+
+        if let Some(id) = &self.id {
+            s.serialize_field("ID", id)?;
+        }
+
+        self.allowed_headers
+            .iter()
+            .try_for_each(|h| s.serialize_field("AllowedHeader", h))?;
+        self.allowed_methods
+            .iter()
+            .try_for_each(|m| s.serialize_field("AllowedMethod", m))?;
+        self.allowed_origins
+            .iter()
+            .try_for_each(|o| s.serialize_field("AllowedOrigin", o))?;
+        self.expose_headers
+            .iter()
+            .try_for_each(|h| s.serialize_field("ExposeHeader", h))?;
+
+        if let Some(max_age_seconds) = self.max_age_seconds {
+            s.serialize_field("MaxAgeSeconds", &max_age_seconds)?;
+        }
+
+        s.end()
+    }
+}
+
+/// The CORS configuration of a bucket, persisted as XML in the configuration directory.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename = "CORSConfiguration", deny_unknown_fields)]
+pub struct CorsConfiguration {
+    #[serde(rename = "CORSRule", default)]
+    rules: Vec<CorsRule>,
+}
+
+impl CorsConfiguration {
+    fn find(&self, origin: &str, method: &str, headers: &[&str]) -> Option<&CorsRule> {
+        self.rules
+            .iter()
+            .find(|r| r.matches(origin, method, headers))
+    }
+}
+
+impl Serialize for CorsConfiguration {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("CORSConfiguration", 0)?;
+        s.serialize_field("xmlns", XMLNS)?;
+        self.rules
+            .iter()
+            .try_for_each(|r| s.serialize_field("", r))?;
+        s.end()
+    }
+}
+
+impl Configuration for CorsConfiguration {
+    const FILE_NAME: &'static str = "cors.xml";
+
+    fn parse(_: &str, text: &str) -> Result<Self> {
+        let config = de_xml::from_bytes::<Self>(text.as_bytes()).map_err(|_| malformed_xml())?;
+
+        if config.rules.is_empty() || MAX_RULES < config.rules.len() {
+            return Err(malformed_xml());
+        }
+
+        for rule in &config.rules {
+            if rule.allowed_origins.is_empty() || rule.allowed_methods.is_empty() {
+                return Err(malformed_xml());
+            }
+
+            if let Some(method) = rule
+                .allowed_methods
+                .iter()
+                .find(|m| !METHODS.contains(&m.as_str()))
+            {
+                return Err(Error::from(ErrorCode::InvalidRequest).message(format!(
+                    "Found unsupported HTTP method in CORS config. Unsupported method is {method}"
+                )));
+            }
+
+            if rule
+                .allowed_origins
+                .iter()
+                .any(|o| 1 < o.matches('*').count())
+            {
+                return Err(Error::from(ErrorCode::InvalidRequest)
+                    .message("AllowedOrigin can not have more than one wildcard."));
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+fn malformed_xml() -> Error {
+    Error::from(ErrorCode::MalformedXML)
+}
+
+/// The state of the CORS middleware for a bucket.
+#[derive(Clone)]
+pub struct Cors {
+    pub bucket: String,
+    pub config: Store<CorsConfiguration>,
+    /// Whether to allow any cross-origin request to buckets without a CORS configuration.
+    pub permissive: bool,
+}
+
+/// Evaluates cross-origin requests against the CORS configuration of the requested bucket.
+pub async fn handle(State(cors): State<Cors>, request: Request, next: Next) -> Response {
+    let bucket = request.uri().path().trim_start_matches('/');
+    let bucket = bucket.split('/').next().unwrap_or_default();
+    let Some(origin) = request.headers().get(ORIGIN).cloned() else {
+        return next.run(request).await;
+    };
+    if bucket != cors.bucket {
+        return next.run(request).await;
+    }
+
+    let preflight = request.method() == Method::OPTIONS
+        && request
+            .headers()
+            .contains_key(ACCESS_CONTROL_REQUEST_METHOD);
+
+    let Some(config) = cors.config.get() else {
+        if cors.permissive {
+            return CorsLayer::permissive()
+                .layer(next)
+                .oneshot(request)
+                .await
+                .into_response();
+        }
+
+        if preflight {
+            return forbidden("CORSResponse: CORS is not enabled for this bucket.");
+        }
+
+        return next.run(request).await;
+    };
+
+    let Ok(origin_str) = origin.to_str() else {
+        return next.run(request).await;
+    };
+
+    if preflight {
+        let method = request
+            .headers()
+            .get(ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        let requested_headers = request
+            .headers()
+            .get(ACCESS_CONTROL_REQUEST_HEADERS)
+            .cloned();
+        let headers = requested_headers
+            .as_ref()
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|h| !h.is_empty())
+            .collect::<Vec<_>>();
+
+        let Some(rule) = config.find(origin_str, method, &headers) else {
+            return forbidden("CORSResponse: This CORS request is not allowed. This is usually because the evalution of Origin, request method / Access-Control-Request-Method or Access-Control-Request-Headers are not whitelisted by the resource's CORS spec.");
+        };
+
+        let mut response = Response::default();
+        rule.apply(&origin, response.headers_mut());
+        if let Some(requested_headers) = requested_headers {
+            response
+                .headers_mut()
+                .insert(ACCESS_CONTROL_ALLOW_HEADERS, requested_headers);
+        }
+        add_vary(response.headers_mut());
+        return response;
+    }
+
+    let method = request.method().to_string();
+    let rule = config.find(origin_str, &method, &[]);
+    let mut response = next.run(request).await;
+    if let Some(rule) = rule {
+        rule.apply(&origin, response.headers_mut());
+    }
+    add_vary(response.headers_mut());
+    response
+}
+
+fn add_vary(headers: &mut HeaderMap) {
+    headers.append(
+        VARY,
+        HeaderValue::from_static(
+            "Origin, Access-Control-Request-Headers, Access-Control-Request-Method",
+        ),
+    );
+}
+
+fn forbidden(message: &str) -> Response {
+    Error::from(ErrorCode::AccessForbidden)
+        .message(message)
+        .into_response()
+}
+
+/// Matches `value` against `pattern`, which may contain a single `*` wildcard.
+fn wildcard_match(pattern: &str, value: &str) -> bool {
+    match pattern.split_once('*') {
+        Some((prefix, suffix)) => {
+            prefix.len() + suffix.len() <= value.len()
+                && value.starts_with(prefix)
+                && value.ends_with(suffix)
+        }
+        None => pattern == value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{HeaderName, StatusCode};
+    use axum::middleware::from_fn_with_state;
+    use axum::routing::any;
+    use axum::Router;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const CONFIG: &str = "<CORSConfiguration>\
+        <CORSRule>\
+        <AllowedOrigin>http://*.example.com</AllowedOrigin>\
+        <AllowedMethod>GET</AllowedMethod><AllowedMethod>HEAD</AllowedMethod>\
+        <AllowedHeader>x-amz-*</AllowedHeader><AllowedHeader>Range</AllowedHeader>\
+        <ExposeHeader>ETag</ExposeHeader>\
+        <MaxAgeSeconds>300</MaxAgeSeconds>\
+        </CORSRule>\
+        <CORSRule><AllowedOrigin>*</AllowedOrigin><AllowedMethod>GET</AllowedMethod></CORSRule>\
+        </CORSConfiguration>";
+
+    fn cors(config: Option<&str>, permissive: bool) -> Cors {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let root = std::env::temp_dir().join(format!(
+            "kayo-cors-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        if let Some(config) = config {
+            std::fs::create_dir_all(root.join("contents")).unwrap();
+            std::fs::write(root.join("contents").join("cors.xml"), config).unwrap();
+        }
+        let cors = Cors {
+            bucket: "contents".to_string(),
+            config: Store::open("contents", &root),
+            permissive,
+        };
+        let _ = std::fs::remove_dir_all(&root);
+        cors
+    }
+
+    async fn send(cors: &Cors, method: &str, uri: &str, headers: &[(&str, &str)]) -> Response {
+        let request = headers
+            .iter()
+            .fold(Request::builder().method(method).uri(uri), |r, (k, v)| {
+                r.header(*k, *v)
+            })
+            .body(Body::empty())
+            .unwrap();
+        Router::new()
+            .route("/{*path}", any(|| async { "ok" }))
+            .layer(from_fn_with_state(cors.clone(), handle))
+            .oneshot(request)
+            .await
+            .unwrap()
+    }
+
+    fn header<'a>(response: &'a Response, name: &HeaderName) -> Option<&'a str> {
+        response.headers().get(name).map(|v| v.to_str().unwrap())
+    }
+
+    #[test]
+    fn origins() {
+        assert!(wildcard_match("*", "http://a.test"));
+        assert!(wildcard_match(
+            "http://*.example.com",
+            "http://tv.example.com"
+        ));
+        assert!(wildcard_match(
+            "http://*.example.com",
+            "http://a.b.example.com"
+        ));
+        assert!(!wildcard_match(
+            "http://*.example.com",
+            "http://example.com"
+        ));
+        assert!(!wildcard_match(
+            "http://*.example.com",
+            "https://tv.example.com"
+        ));
+        assert!(!wildcard_match(
+            "http://*.example.com",
+            "http://tv.example.com.evil"
+        ));
+        assert!(wildcard_match(
+            "http://tv.example.com",
+            "http://tv.example.com"
+        ));
+        assert!(!wildcard_match(
+            "http://tv.example.com",
+            "http://tv.example.co"
+        ));
+    }
+
+    #[tokio::test]
+    async fn preflights() {
+        let cors = cors(Some(CONFIG), false);
+        let origin = "http://tv.example.com";
+
+        let response = send(
+            &cors,
+            "OPTIONS",
+            "/contents/a.mp4",
+            &[
+                ("origin", origin),
+                ("access-control-request-method", "GET"),
+                ("access-control-request-headers", "range, X-Amz-Date"),
+            ],
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            header(&response, &ACCESS_CONTROL_ALLOW_ORIGIN),
+            Some(origin)
+        );
+        assert_eq!(
+            header(&response, &ACCESS_CONTROL_ALLOW_CREDENTIALS),
+            Some("true")
+        );
+        assert_eq!(
+            header(&response, &ACCESS_CONTROL_ALLOW_METHODS),
+            Some("GET, HEAD")
+        );
+        assert_eq!(
+            header(&response, &ACCESS_CONTROL_ALLOW_HEADERS),
+            Some("range, X-Amz-Date")
+        );
+        assert_eq!(
+            header(&response, &ACCESS_CONTROL_EXPOSE_HEADERS),
+            Some("ETag")
+        );
+        assert_eq!(header(&response, &ACCESS_CONTROL_MAX_AGE), Some("300"));
+        assert!(header(&response, &VARY).is_some());
+
+        // Any other origin falls through to the wildcard rule, which allows no headers.
+        let response = send(
+            &cors,
+            "OPTIONS",
+            "/contents/a.mp4",
+            &[
+                ("origin", "http://other.test"),
+                ("access-control-request-method", "GET"),
+            ],
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, &ACCESS_CONTROL_ALLOW_ORIGIN), Some("*"));
+        assert_eq!(header(&response, &ACCESS_CONTROL_ALLOW_CREDENTIALS), None);
+
+        for headers in [
+            [
+                ("origin", origin),
+                ("access-control-request-method", "PUT"),
+                ("access-control-request-headers", ""),
+            ],
+            [
+                ("origin", origin),
+                ("access-control-request-method", "GET"),
+                ("access-control-request-headers", "authorization"),
+            ],
+            [
+                ("origin", "http://other.test"),
+                ("access-control-request-method", "GET"),
+                ("access-control-request-headers", "range"),
+            ],
+        ] {
+            let response = send(&cors, "OPTIONS", "/contents/a.mp4", &headers).await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{headers:?}");
+            assert_eq!(header(&response, &ACCESS_CONTROL_ALLOW_ORIGIN), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn actual_requests() {
+        let cors = cors(Some(CONFIG), false);
+        let origin = "http://tv.example.com";
+
+        let response = send(&cors, "GET", "/contents/a.mp4", &[("origin", origin)]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            header(&response, &ACCESS_CONTROL_ALLOW_ORIGIN),
+            Some(origin)
+        );
+        assert_eq!(
+            header(&response, &ACCESS_CONTROL_EXPOSE_HEADERS),
+            Some("ETag")
+        );
+
+        // Requests that no rule allows are still handled, just without CORS headers.
+        let response = send(&cors, "PUT", "/contents/a.mp4", &[("origin", origin)]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, &ACCESS_CONTROL_ALLOW_ORIGIN), None);
+        assert!(header(&response, &VARY).is_some());
+
+        let response = send(&cors, "GET", "/contents/a.mp4", &[]).await;
+        assert_eq!(header(&response, &ACCESS_CONTROL_ALLOW_ORIGIN), None);
+        assert_eq!(header(&response, &VARY), None);
+
+        let response = send(&cors, "GET", "/other/a.mp4", &[("origin", origin)]).await;
+        assert_eq!(header(&response, &ACCESS_CONTROL_ALLOW_ORIGIN), None);
+    }
+
+    #[tokio::test]
+    async fn unconfigured_buckets() {
+        let preflight = [
+            ("origin", "http://other.test"),
+            ("access-control-request-method", "PUT"),
+        ];
+        let actual = [("origin", "http://other.test")];
+
+        let strict = cors(None, false);
+        let response = send(&strict, "OPTIONS", "/contents/a.mp4", &preflight).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = send(&strict, "GET", "/contents/a.mp4", &actual).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, &ACCESS_CONTROL_ALLOW_ORIGIN), None);
+
+        let permissive = cors(None, true);
+        let response = send(&permissive, "OPTIONS", "/contents/a.mp4", &preflight).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, &ACCESS_CONTROL_ALLOW_ORIGIN), Some("*"));
+        let response = send(&permissive, "GET", "/contents/a.mp4", &actual).await;
+        assert_eq!(header(&response, &ACCESS_CONTROL_ALLOW_ORIGIN), Some("*"));
+
+        // A configuration takes precedence over the permissive fallback.
+        let configured = cors(Some(CONFIG), true);
+        let response = send(&configured, "OPTIONS", "/contents/a.mp4", &preflight).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn invalid_configurations() {
+        let rule = |rule: &str| {
+            CorsConfiguration::parse(
+                "contents",
+                &format!("<CORSConfiguration><CORSRule>{rule}</CORSRule></CORSConfiguration>"),
+            )
+        };
+        assert!(rule("<AllowedOrigin>*</AllowedOrigin><AllowedMethod>GET</AllowedMethod>").is_ok());
+        assert!(rule("<AllowedOrigin>*</AllowedOrigin>").is_err());
+        assert!(rule("<AllowedMethod>GET</AllowedMethod>").is_err());
+        assert!(
+            rule("<AllowedOrigin>*</AllowedOrigin><AllowedMethod>PATCH</AllowedMethod>").is_err()
+        );
+        assert!(rule(
+            "<AllowedOrigin>http://*.*.test</AllowedOrigin><AllowedMethod>GET</AllowedMethod>"
+        )
+        .is_err());
+        assert!(rule(
+            "<AllowedOrigin>*</AllowedOrigin><AllowedMethod>GET</AllowedMethod><Extra />"
+        )
+        .is_err());
+        assert!(CorsConfiguration::parse("contents", "<CORSConfiguration />").is_err());
+    }
+}
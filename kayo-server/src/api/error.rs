@@ -1,36 +1,110 @@
 use axum::http::{header, StatusCode};
 use axum::response::{IntoResponse, Response};
+use serde::ser::{SerializeStruct, Serializer};
 use serde::Serialize;
 use std::io::ErrorKind;
 
+use super::context::Context;
 use crate::ser_xml;
 
-#[derive(Debug, Copy, Clone, Serialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
 pub enum ErrorCode {
     AccessDenied,
+    AccessForbidden,
+    AuthorizationHeaderMalformed,
+    AuthorizationQueryParametersError,
+    BadDigest,
+    IncompleteBody,
     InternalError,
+    InvalidAccessKeyId,
     InvalidArgument,
+    InvalidRange,
+    InvalidRequest,
+    MalformedPolicy,
+    MalformedXML,
+    MethodNotAllowed,
     NoSuchBucket,
+    NoSuchBucketPolicy,
+    NoSuchCORSConfiguration,
     NoSuchKey,
+    NoSuchLifecycleConfiguration,
+    NotImplemented,
+    ObjectLockConfigurationNotFoundError,
+    RequestTimeTooSkewed,
+    ServerSideEncryptionConfigurationNotFoundError,
+    SignatureDoesNotMatch,
+    XAmzContentSHA256Mismatch,
 }
 
 impl ErrorCode {
     fn as_message(&self) -> &str {
         match self {
             Self::AccessDenied => "Access Denied",
+            Self::AuthorizationHeaderMalformed => "The authorization header is malformed.",
+            Self::BadDigest => "The checksum you specified did not match what we received.",
+            Self::IncompleteBody => {
+                "You did not provide the number of bytes specified by the Content-Length HTTP header."
+            }
             Self::InternalError => "An internal error occurred. Try again.",
+            Self::InvalidAccessKeyId => {
+                "The AWS access key ID you provided does not exist in our records."
+            }
+            Self::InvalidRange => "The requested range is not satisfiable",
+            Self::MalformedPolicy => "Policies must be valid JSON and the first byte must be '{'",
+            Self::MalformedXML => "The XML you provided was not well-formed or did not validate against our published schema.",
+            Self::MethodNotAllowed => "The specified method is not allowed against this resource.",
             Self::NoSuchBucket => "The specified bucket does not exist.",
+            Self::NoSuchBucketPolicy => "The bucket policy does not exist",
+            Self::NoSuchCORSConfiguration => "The CORS configuration does not exist",
             Self::NoSuchKey => "The specified key does not exist.",
+            Self::NoSuchLifecycleConfiguration => "The lifecycle configuration does not exist",
+            Self::NotImplemented => {
+                "A header you provided implies functionality that is not implemented."
+            }
+            Self::ObjectLockConfigurationNotFoundError => {
+                "Object Lock configuration does not exist for this bucket"
+            }
+            Self::RequestTimeTooSkewed => {
+                "The difference between the request time and the server's time is too large."
+            }
+            Self::ServerSideEncryptionConfigurationNotFoundError => {
+                "The server side encryption configuration was not found"
+            }
+            Self::SignatureDoesNotMatch => "The request signature that the server calculated does not match the signature that you provided.",
+            Self::XAmzContentSHA256Mismatch => {
+                "The provided 'x-amz-content-sha256' header does not match what was computed."
+            }
             _ => "",
         }
     }
 
     fn to_status_code(self) -> StatusCode {
         match self {
-            Self::InvalidArgument => StatusCode::BAD_REQUEST,
-            Self::AccessDenied => StatusCode::FORBIDDEN,
-            Self::NoSuchBucket | Self::NoSuchKey => StatusCode::NOT_FOUND,
+            Self::AuthorizationHeaderMalformed
+            | Self::AuthorizationQueryParametersError
+            | Self::BadDigest
+            | Self::IncompleteBody
+            | Self::InvalidArgument
+            | Self::InvalidRequest
+            | Self::MalformedPolicy
+            | Self::MalformedXML
+            | Self::XAmzContentSHA256Mismatch => StatusCode::BAD_REQUEST,
+            Self::AccessDenied
+            | Self::AccessForbidden
+            | Self::InvalidAccessKeyId
+            | Self::RequestTimeTooSkewed
+            | Self::SignatureDoesNotMatch => StatusCode::FORBIDDEN,
+            Self::NoSuchBucket
+            | Self::NoSuchBucketPolicy
+            | Self::NoSuchCORSConfiguration
+            | Self::NoSuchKey
+            | Self::NoSuchLifecycleConfiguration
+            | Self::ObjectLockConfigurationNotFoundError
+            | Self::ServerSideEncryptionConfigurationNotFoundError => StatusCode::NOT_FOUND,
+            Self::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            Self::InvalidRange => StatusCode::RANGE_NOT_SATISFIABLE,
             Self::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::NotImplemented => StatusCode::NOT_IMPLEMENTED,
         }
     }
 }
@@ -45,16 +119,13 @@ impl From<ErrorKind> for ErrorCode {
     }
 }
 
-#[derive(Debug, Serialize)]
-#[serde(rename_all = "PascalCase")]
+#[derive(Debug)]
 pub struct Error {
     code: ErrorCode,
-    #[serde(skip_serializing_if = "Option::is_none")]
     message: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
     bucket_name: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
     key: Option<String>,
+    argument: Option<Box<(String, String)>>,
 }
 
 impl Error {
@@ -64,9 +135,15 @@ impl Error {
             message: Some(code.as_message().to_string()),
             bucket_name: None,
             key: None,
+            argument: None,
         }
     }
 
+    #[cfg(test)]
+    pub fn code(&self) -> ErrorCode {
+        self.code
+    }
+
     pub fn message(mut self, message: impl Into<String>) -> Self {
         self.message = Some(message.into());
         self
@@ -81,6 +158,12 @@ impl Error {
         self.key = Some(key);
         self
     }
+
+    /// Sets the name and value of the argument that was not valid.
+    pub fn argument(mut self, name: String, value: String) -> Self {
+        self.argument = Some(Box::new((name, value)));
+        self
+    }
 }
 
 impl From<std::io::Error> for Error {
@@ -95,9 +178,51 @@ impl From<std::convert::Infallible> for Error {
     }
 }
 
+/// The error response body, including the identifiers of the request that failed.
+struct Envelope<'a> {
+    error: &'a Error,
+    context: Option<Context>,
+}
+
+impl Serialize for Envelope<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Error", 0)?;
+        s.serialize_field("Code", &self.error.code)?;
+
+        if let Some(message) = &self.error.message {
+            s.serialize_field("Message", message)?;
+        }
+
+        if let Some(argument) = &self.error.argument {
+            s.serialize_field("ArgumentName", &argument.0)?;
+            s.serialize_field("ArgumentValue", &argument.1)?;
+        }
+
+        if let Some(bucket_name) = &self.error.bucket_name {
+            s.serialize_field("BucketName", bucket_name)?;
+        }
+
+        if let Some(key) = &self.error.key {
+            s.serialize_field("Key", key)?;
+        }
+
+        if let Some(context) = &self.context {
+            s.serialize_field("Resource", &context.resource)?;
+            s.serialize_field("RequestId", &context.request_id)?;
+            s.serialize_field("HostId", &context.host_id)?;
+        }
+
+        s.end()
+    }
+}
+
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
-        match ser_xml::to_bytes(&self) {
+        let envelope = Envelope {
+            error: &self,
+            context: Context::current(),
+        };
+        match ser_xml::to_bytes(&envelope) {
             Ok(body) => (
                 self.code.to_status_code(),
                 [(header::CONTENT_TYPE, "application/xml")],
@@ -1,23 +1,75 @@
+mod auth;
+mod checksum;
+mod chunked;
+mod context;
+mod cors;
 mod error;
+mod operation;
+mod owner;
+mod policy;
 mod request;
 mod response;
+mod store;
 
+use aws_sdk_s3::operation::get_bucket_acl::GetBucketAclOutput;
+use aws_sdk_s3::operation::get_bucket_location::GetBucketLocationOutput;
+use aws_sdk_s3::operation::get_bucket_versioning::GetBucketVersioningOutput;
+use aws_sdk_s3::operation::list_objects::ListObjectsOutput;
 use aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Output;
-use aws_sdk_s3::types::{CommonPrefix, Object};
-use axum::extract::{Path as Uri, Query};
-use axum::http::Request;
-use axum::response::Response;
-use axum::routing::{any, get};
+use aws_sdk_s3::types::{
+    ChecksumAlgorithm, CommonPrefix, Grant, Grantee, Object, ObjectOwnership, Owner,
+    OwnershipControlsRule, Permission, Type,
+};
+use axum::body::Body;
+use axum::extract::{ConnectInfo, OriginalUri, Path as Uri, Query, State};
+use axum::http::header::{CONTENT_ENCODING, CONTENT_RANGE, CONTENT_TYPE, ETAG};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode};
+use axum::middleware::{from_fn, from_fn_with_state};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Extension;
 use futures::prelude::*;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::io;
+use std::net::SocketAddr;
 use std::path::{Component, Path, PathBuf};
-use tokio::fs::read_dir;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::fs::{create_dir_all, read_dir, remove_file, rename, File};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio_util::io::StreamReader;
 use tower::util::Ready;
 use tower::{Service, ServiceExt};
-use tower_http::services::fs::{ServeDir, ServeFileSystemResponseBody};
+use tower_http::services::fs::ServeDir;
+use tracing::{debug, warn};
 
+pub use auth::Credentials;
+pub use owner::UserMapping;
+
+use auth::{Authenticator, ChunkSigner, Principal};
+use checksum::Checksum;
+use chunked::{Frame, Payload};
+use cors::{Cors, CorsConfiguration};
 use error::{Error, ErrorCode, Result};
-use request::ListBucketRequest;
-use response::ListBucketResult;
+use operation::Operation;
+use owner::Owners;
+use policy::{Decision, Policy};
+use request::{
+    byte_range, cast_requested, not_modified, ConvertRequest, DashRequest, Format, HlsPart,
+    HlsRequest, ListBucketRequest, RemuxRequest,
+};
+use response::{
+    AccessControlPolicy, Dash, Entry, Faststart, Hls, Json, ListBucketResult, ListBucketResultV1,
+    LocationConstraint, Objects, OwnershipControls, PolicyStatus, Probe, Remux,
+    VersioningConfiguration, WebVtt, Xml,
+};
+use store::Store;
+
+use crate::media::dash::Mpd;
+use crate::media::probe::Container;
+use crate::media::remux::Remuxer;
+use crate::media::{cast, faststart, hls, mime, probe, subtitle, webvtt};
 
 trait IntoOption {
     fn into_option(self) -> Option<Self>
@@ -36,89 +88,495 @@ impl<T> IntoOption for Vec<T> {
 }
 
 const BUCKET_NAME: &str = "contents";
+const MAX_KEYS: i32 = 1000;
+/// The directory under the root that uploads are written in until they are complete, which is
+/// not part of the bucket.
+const STAGING_DIR: &str = ".kayo-uploads";
+/// The namespace of the elements in S3 responses.
+const XMLNS: &str = "http://s3.amazonaws.com/doc/2006-03-01/";
+
+#[derive(Clone)]
+struct Bucket {
+    root: PathBuf,
+    serve_dir: ServeDir,
+    policy: Store<Policy>,
+    cors: Store<CorsConfiguration>,
+    owners: Owners,
+    probes: probe::Cache,
+    packages: hls::Cache,
+    faststart: faststart::Cache,
+    profiles: Arc<cast::Profiles>,
+}
+
+impl Bucket {
+    /// Returns the path of the file holding the object `key`, unless the key would escape the
+    /// bucket or is in the staging directory.
+    fn object_path(&self, key: &str) -> Option<PathBuf> {
+        (Self::is_plain(key) && !Self::is_staging(key)).then(|| self.root.join(key))
+    }
+
+    /// Returns whether every component of `key` is a name, so that it neither escapes the bucket
+    /// nor names a file in more than one way.
+    fn is_plain(key: &str) -> bool {
+        Path::new(key)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+    }
 
-pub fn router<S>(root: PathBuf) -> axum::Router<S>
+    /// Returns whether `key` is in the staging directory of uploads rather than an object.
+    fn is_staging(key: &str) -> bool {
+        Path::new(key).components().next() == Some(Component::Normal(STAGING_DIR.as_ref()))
+    }
+
+    /// Returns the owner of the bucket, which is the owner of its root directory.
+    fn owner(&self) -> Option<Owner> {
+        std::fs::metadata(&self.root)
+            .ok()
+            .and_then(|metadata| self.owners.get(&metadata))
+    }
+
+    fn authorize(&self, request: &policy::Request) -> Result<()> {
+        let decision = self
+            .policy
+            .get()
+            .map_or(Decision::NotApplicable, |p| p.evaluate(request));
+        match decision {
+            Decision::Allow => Ok(()),
+            Decision::NotApplicable if request.principal.is_owner => Ok(()),
+            Decision::NotApplicable
+                if request.principal.can_read
+                    && matches!(request.action, "s3:GetObject" | "s3:ListBucket") =>
+            {
+                Ok(())
+            }
+            _ => Err(Error::from(ErrorCode::AccessDenied)),
+        }
+    }
+}
+
+pub fn router<S>(
+    root: PathBuf,
+    config_root: PathBuf,
+    credentials: Vec<Credentials>,
+    users: Vec<UserMapping>,
+    cors: bool,
+    profiles: cast::Profiles,
+    faststart: faststart::Cache,
+) -> axum::Router<S>
 where
     S: Clone + Send + Sync + 'static,
 {
-    let serve_dir = ServeDir::new(&root);
-    let handler = get(move |p, q| list_bucket(root, p, q));
+    let bucket = Bucket {
+        serve_dir: ServeDir::new(&root),
+        root,
+        policy: Store::open(BUCKET_NAME, &config_root),
+        cors: Store::open(BUCKET_NAME, &config_root),
+        owners: Owners::new(users),
+        probes: probe::Cache::default(),
+        packages: hls::Cache::default(),
+        faststart,
+        profiles: Arc::new(profiles),
+    };
+    let cors = Cors {
+        bucket: BUCKET_NAME.to_string(),
+        config: bucket.cors.clone(),
+        permissive: cors,
+    };
+    let handler = get(get_bucket).put(put_bucket).delete(delete_bucket);
     axum::Router::new()
-        .route("/:bucket", handler.clone())
-        .route("/:bucket/", handler)
-        .route(
-            "/:bucket/*key",
-            any(move |p, r| get_object(serve_dir, p, r)),
-        )
+        .route("/{bucket}", handler.clone())
+        .route("/{bucket}/", handler)
+        .route("/{bucket}/{*key}", get(get_object).put(put_object))
+        .with_state(bucket)
+        .layer(from_fn(operation::dispatch))
+        .layer(from_fn_with_state(
+            Authenticator::new(credentials),
+            auth::authenticate,
+        ))
+        .layer(from_fn_with_state(cors, cors::handle))
+        .layer(from_fn(context::handle))
 }
 
-async fn list_bucket(
-    root: PathBuf,
-    Uri(bucket): Uri<String>,
-    Query(ListBucketRequest(request)): Query<ListBucketRequest>,
-) -> Result<ListBucketResult> {
-    if bucket != BUCKET_NAME {
-        return Err(Error::from(ErrorCode::NoSuchBucket).bucket_name(bucket));
+async fn get_bucket(
+    State(bucket): State<Bucket>,
+    Extension(principal): Extension<Principal>,
+    Extension(operation): Extension<Operation>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    Uri(name): Uri<String>,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+) -> Result<Response> {
+    if name != BUCKET_NAME {
+        return Err(Error::from(ErrorCode::NoSuchBucket).bucket_name(name));
     }
 
+    match operation {
+        Operation::ListObjects => {}
+        Operation::GetBucketPolicy => return get_bucket_policy(bucket, principal, name),
+        Operation::GetBucketCors => return get_bucket_cors(bucket, principal, name),
+        operation => return get_bucket_configuration(bucket, principal, name, operation),
+    }
+
+    let Query(params) = Query::<Vec<(String, String)>>::try_from_uri(&uri)
+        .map_err(|e| Error::from(ErrorCode::InvalidArgument).message(e.body_text()))?;
+    let format = Format::negotiate(&params, &headers)?;
+    let cast = cast_requested(&params)?;
+    let request = ListBucketRequest::try_from(params)?;
     let prefix = request.prefix().unwrap_or_default();
-    let path = Path::new(prefix);
-    if !path.components().all(|c| matches!(c, Component::Normal(_))) {
+    bucket.authorize(&policy::Request {
+        principal: &principal,
+        action: "s3:ListBucket",
+        resource: policy::resource(BUCKET_NAME, None),
+        source_ip: remote.ip(),
+        prefix: Some(prefix),
+    })?;
+
+    let fetch_owner = match &request {
+        ListBucketRequest::V1(_) => true,
+        ListBucketRequest::V2(input) => input.fetch_owner().unwrap_or_default(),
+    };
+    let (objects, common_prefixes) = list_bucket(&bucket, prefix, fetch_owner, cast).await?;
+
+    match &request {
+        ListBucketRequest::V1(input) => {
+            let output = ListObjectsOutput::builder()
+                .name(BUCKET_NAME)
+                .prefix(prefix)
+                .set_marker(input.marker().map(str::to_string))
+                .delimiter("/")
+                .max_keys(input.max_keys().unwrap_or(MAX_KEYS))
+                .is_truncated(false)
+                .set_common_prefixes(common_prefixes.into_option())
+                .build();
+            Ok(ListBucketResultV1 {
+                output,
+                objects,
+                format,
+            }
+            .into_response())
+        }
+        ListBucketRequest::V2(input) => {
+            let output = ListObjectsV2Output::builder()
+                .name(BUCKET_NAME)
+                .prefix(prefix)
+                .delimiter("/")
+                .max_keys(input.max_keys().unwrap_or(MAX_KEYS))
+                .is_truncated(false)
+                .set_common_prefixes(common_prefixes.into_option())
+                .build();
+            Ok(ListBucketResult {
+                output,
+                objects,
+                format,
+            }
+            .into_response())
+        }
+    }
+}
+
+async fn put_bucket(
+    State(bucket): State<Bucket>,
+    Extension(principal): Extension<Principal>,
+    Extension(operation): Extension<Operation>,
+    Uri(name): Uri<String>,
+    body: String,
+) -> Result<Response> {
+    if name != BUCKET_NAME {
+        return Err(Error::from(ErrorCode::NoSuchBucket).bucket_name(name));
+    }
+
+    if !principal.is_owner {
+        return Err(Error::from(ErrorCode::AccessDenied).bucket_name(name));
+    }
+
+    match operation {
+        Operation::PutBucketPolicy => {
+            bucket.policy.put(body).await?;
+            Ok(StatusCode::NO_CONTENT.into_response())
+        }
+        Operation::PutBucketCors => {
+            bucket.cors.put(body).await?;
+            Ok(StatusCode::OK.into_response())
+        }
+        _ => Err(Error::from(ErrorCode::NotImplemented)),
+    }
+}
+
+async fn delete_bucket(
+    State(bucket): State<Bucket>,
+    Extension(principal): Extension<Principal>,
+    Extension(operation): Extension<Operation>,
+    Uri(name): Uri<String>,
+) -> Result<Response> {
+    if name != BUCKET_NAME {
+        return Err(Error::from(ErrorCode::NoSuchBucket).bucket_name(name));
+    }
+
+    if !principal.is_owner {
+        return Err(Error::from(ErrorCode::AccessDenied).bucket_name(name));
+    }
+
+    match operation {
+        Operation::DeleteBucketPolicy => bucket.policy.delete().await?,
+        Operation::DeleteBucketCors => bucket.cors.delete().await?,
+        _ => return Err(Error::from(ErrorCode::NotImplemented)),
+    }
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+fn get_bucket_policy(bucket: Bucket, principal: Principal, name: String) -> Result<Response> {
+    if !principal.is_owner {
+        return Err(Error::from(ErrorCode::AccessDenied).bucket_name(name));
+    }
+
+    match bucket.policy.text() {
+        Some(policy) => Ok(([(CONTENT_TYPE, "application/json")], policy).into_response()),
+        None => Err(Error::from(ErrorCode::NoSuchBucketPolicy).bucket_name(name)),
+    }
+}
+
+fn get_bucket_cors(bucket: Bucket, principal: Principal, name: String) -> Result<Response> {
+    if !principal.is_owner {
+        return Err(Error::from(ErrorCode::AccessDenied).bucket_name(name));
+    }
+
+    match bucket.cors.get() {
+        Some(cors) => Ok(Xml(cors.as_ref()).into_response()),
+        None => Err(Error::from(ErrorCode::NoSuchCORSConfiguration).bucket_name(name)),
+    }
+}
+
+/// Answers the configuration requests that clients probe for, for features that are not supported.
+fn get_bucket_configuration(
+    bucket: Bucket,
+    principal: Principal,
+    name: String,
+    operation: Operation,
+) -> Result<Response> {
+    // These only tell that features are not supported, which readers may know as well.
+    if !principal.is_owner && !principal.can_read {
+        return Err(Error::from(ErrorCode::AccessDenied).bucket_name(name));
+    }
+
+    let not_found = |code| Err(Error::from(code).bucket_name(name.clone()));
+    let response = match operation {
+        Operation::GetBucketVersioning => Xml(VersioningConfiguration(
+            GetBucketVersioningOutput::builder().build(),
+        ))
+        .into_response(),
+        Operation::GetBucketAcl => {
+            let owner = bucket.owner();
+            let grantee = Grantee::builder()
+                .set_id(owner.as_ref().and_then(|o| o.id.clone()))
+                .set_display_name(owner.as_ref().and_then(|o| o.display_name.clone()))
+                .r#type(Type::CanonicalUser)
+                .build()
+                .map_err(|e| Error::from(ErrorCode::InternalError).message(e.to_string()))?;
+            let output = GetBucketAclOutput::builder()
+                .set_owner(owner)
+                .grants(
+                    Grant::builder()
+                        .grantee(grantee)
+                        .permission(Permission::FullControl)
+                        .build(),
+                )
+                .build();
+            Xml(AccessControlPolicy(output)).into_response()
+        }
+        Operation::GetBucketPolicyStatus => {
+            let is_public = bucket.policy.get().is_some_and(|p| p.is_public());
+            Xml(PolicyStatus(
+                aws_sdk_s3::types::PolicyStatus::builder()
+                    .is_public(is_public)
+                    .build(),
+            ))
+            .into_response()
+        }
+        Operation::GetBucketLifecycleConfiguration => {
+            return not_found(ErrorCode::NoSuchLifecycleConfiguration)
+        }
+        Operation::GetBucketEncryption => {
+            return not_found(ErrorCode::ServerSideEncryptionConfigurationNotFoundError)
+        }
+        Operation::GetObjectLockConfiguration => {
+            return not_found(ErrorCode::ObjectLockConfigurationNotFoundError)
+        }
+        Operation::GetBucketOwnershipControls => {
+            let rule = OwnershipControlsRule::builder()
+                .object_ownership(ObjectOwnership::BucketOwnerEnforced)
+                .build()
+                .and_then(|rule| {
+                    aws_sdk_s3::types::OwnershipControls::builder()
+                        .rules(rule)
+                        .build()
+                })
+                .map_err(|e| Error::from(ErrorCode::InternalError).message(e.to_string()))?;
+            Xml(OwnershipControls(rule)).into_response()
+        }
+        Operation::GetBucketLocation => Xml(LocationConstraint(
+            GetBucketLocationOutput::builder().build(),
+        ))
+        .into_response(),
+        _ => return Err(Error::from(ErrorCode::NotImplemented)),
+    };
+
+    Ok(response)
+}
+
+/// Lists the objects and common prefixes directly under `prefix`, sorted by key.
+///
+/// Only the names of the entries are read up front, since they have to be sorted. The metadata
+/// of the objects is read as they are serialized, and media objects are probed then if `cast`
+/// verdicts are requested.
+async fn list_bucket(
+    bucket: &Bucket,
+    prefix: &str,
+    fetch_owner: bool,
+    cast: bool,
+) -> Result<(Objects, Vec<CommonPrefix>)> {
+    let root = &bucket.root;
+    if !Bucket::is_plain(prefix) {
         return Err(Error::from(ErrorCode::InvalidArgument)
             .message("The specified argument was not valid."));
     }
-    let path = root.join(path);
+    let path = root.join(prefix);
 
     let mut entries = read_dir(&path).await?;
-    let mut contents = Vec::new();
+    let mut keys = Vec::new();
     let mut common_prefixes = Vec::new();
     while let Some(entry) = entries.next_entry().await? {
-        let metadata = entry.metadata().await?;
+        let file_type = entry.file_type().await?;
         let key = entry
             .path()
-            .strip_prefix(&root)
+            .strip_prefix(root)
             .map_err(|e| Error::from(ErrorCode::InternalError).message(e.to_string()))?
             .to_str()
             .unwrap()
             .to_string();
 
-        if metadata.is_file() {
-            contents.push(
-                Object::builder()
-                    .key(key)
-                    .last_modified(metadata.modified()?.into())
-                    .size(metadata.len() as _)
-                    .build(),
-            );
-        } else if metadata.is_dir() {
+        if Bucket::is_staging(&key) {
+            continue;
+        } else if file_type.is_file() {
+            keys.push(key);
+        } else if file_type.is_dir() {
             common_prefixes.push(CommonPrefix::builder().prefix(format!("{key}/")).build());
         }
     }
 
-    contents.sort_by(|a, b| a.key().cmp(&b.key()));
+    keys.sort();
     common_prefixes.sort_by(|a, b| a.prefix().cmp(&b.prefix()));
 
-    Ok(ListObjectsV2Output::builder()
-        .name(BUCKET_NAME)
-        .prefix(prefix)
-        .delimiter("/")
-        .key_count(contents.len() as _)
-        .set_contents(contents.into_option())
-        .set_common_prefixes(common_prefixes.into_option())
-        .build()
-        .into())
+    let root = root.clone();
+    let owners = bucket.owners.clone();
+    let probes = bucket.probes.clone();
+    let profiles = bucket.profiles.clone();
+    let keys: Arc<[String]> = keys.into();
+    let siblings = keys.clone();
+    let objects = (0..keys.len()).filter_map(move |index| {
+        let key = &siblings[index];
+        let metadata = match std::fs::symlink_metadata(root.join(key)) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+            Err(e) => return Some(Err(e)),
+        };
+
+        let modified = match metadata.modified() {
+            Ok(modified) => modified,
+            Err(e) => return Some(Err(e)),
+        };
+        let path = root.join(key);
+        // Reading the header of every object would make listings slow, so it is only sniffed for
+        // GET and HEAD requests.
+        let content_type = mime::from_extension(&path);
+        let media =
+            content_type.is_some_and(|t| t.starts_with("video/") || t.starts_with("audio/"));
+        let (verdicts, probe_error) = if media && cast {
+            match probes.probe(&path) {
+                Ok(info) => (info.map(|info| profiles.evaluate(&info)), None),
+                Err(e) => (None, Some(e.to_string())),
+            }
+        } else {
+            (None, None)
+        };
+        let subtitles = if media {
+            subtitle::sidecars(key, &siblings)
+        } else {
+            Vec::new()
+        };
+        let object = Object::builder()
+            .key(key.clone())
+            .last_modified(modified.into())
+            .size(metadata.len() as _)
+            .set_owner(fetch_owner.then(|| owners.get(&metadata)).flatten())
+            .build();
+        Some(Ok(Entry {
+            object,
+            content_type,
+            cast: verdicts.unwrap_or_default(),
+            probe_error,
+            subtitles,
+        }))
+    });
+
+    Ok((Objects::new(objects), common_prefixes))
 }
 
 async fn get_object<B>(
-    mut serve_dir: ServeDir,
-    Uri((bucket, key)): Uri<(String, String)>,
+    State(bucket): State<Bucket>,
+    Extension(principal): Extension<Principal>,
+    Extension(operation): Extension<Operation>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    Uri((name, key)): Uri<(String, String)>,
     mut request: Request<B>,
-) -> Result<Response<ServeFileSystemResponseBody>>
+) -> Result<Response>
 where
     B: axum::body::HttpBody + Send + 'static,
 {
-    if bucket != BUCKET_NAME {
-        return Err(Error::from(ErrorCode::NoSuchBucket).bucket_name(bucket));
+    if name != BUCKET_NAME {
+        return Err(Error::from(ErrorCode::NoSuchBucket).bucket_name(name));
+    }
+
+    bucket
+        .authorize(&policy::Request {
+            principal: &principal,
+            action: "s3:GetObject",
+            resource: policy::resource(BUCKET_NAME, Some(&key)),
+            source_ip: remote.ip(),
+            prefix: None,
+        })
+        .map_err(|e| e.key(key.clone()))?;
+
+    if !Bucket::is_plain(&key) || Bucket::is_staging(&key) {
+        return Err(Error::from(ErrorCode::NoSuchKey).key(key));
+    }
+
+    match operation {
+        Operation::ProbeObject => return probe_object(&bucket, key).await,
+        Operation::RemuxObject => {
+            let head = request.method() == Method::HEAD;
+            return remux_object(&bucket, key, request.uri(), head).await;
+        }
+        Operation::PackageObject => {
+            let head = request.method() == Method::HEAD;
+            return package_object(&bucket, key, request.uri(), head).await;
+        }
+        Operation::DescribeObject => {
+            let head = request.method() == Method::HEAD;
+            return describe_object(&bucket, key, request.uri(), head).await;
+        }
+        Operation::ConvertObject => {
+            let head = request.method() == Method::HEAD;
+            return convert_object(&bucket, key, request.uri(), head).await;
+        }
+        Operation::GetFaststartObject => {
+            let head = request.method() == Method::HEAD;
+            let headers = request.headers().clone();
+            if let Some(response) = faststart_object(&bucket, &key, &headers, head).await? {
+                return Ok(response);
+            }
+        }
+        _ => {}
     }
 
     let uri = request.uri();
@@ -140,10 +598,562 @@ where
         .build()
         .map_err(|e| Error::from(ErrorCode::InternalError).message(e.to_string()))?;
 
+    // ServeDir only knows about extensions, so the type is detected separately from the content.
+    let content_type = match bucket.object_path(&key) {
+        Some(path) => tokio::task::spawn_blocking(move || mime::detect_file(&path))
+            .await
+            .ok()
+            .and_then(Result::ok)
+            .flatten(),
+        None => None,
+    };
+
+    let mut serve_dir = bucket.serve_dir;
     let ready: Ready<_, Request<B>> = serve_dir.ready();
-    ready
+    let mut response = ready
         .and_then(|s| s.call(request))
         .err_into()
         .map_err(|e: Error| e.key(key))
-        .await
+        .await?;
+
+    if let Some(content_type) = content_type {
+        if response.headers().contains_key(CONTENT_TYPE) {
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        }
+    }
+    Ok(response.map(Body::new))
+}
+
+/// Responds with what the headers of a media object tell about it.
+async fn probe_object(bucket: &Bucket, key: String) -> Result<Response> {
+    let Some(path) = bucket.object_path(&key) else {
+        return Err(Error::from(ErrorCode::NoSuchKey).key(key));
+    };
+
+    let probes = bucket.probes.clone();
+    let sidecar_key = key.clone();
+    let info = tokio::task::spawn_blocking(move || {
+        let Some(info) = probes.probe(&path)? else {
+            return Ok(None);
+        };
+        let subtitles = subtitle::find(&path, &sidecar_key)?;
+        io::Result::Ok(Some((info, subtitles)))
+    })
+    .await
+    .map_err(|e| Error::from(ErrorCode::InternalError).message(e.to_string()))?;
+    match info {
+        Ok(Some((info, subtitles))) => {
+            let cast = bucket.profiles.evaluate(&info);
+            Ok(Json(Probe {
+                info: &info,
+                cast,
+                subtitles,
+            })
+            .into_response())
+        }
+        Ok(None) => Err(Error::from(ErrorCode::InvalidRequest)
+            .message("The object is not in a supported media format.")
+            .key(key)),
+        Err(e) => Err(media_error(e).key(key)),
+    }
+}
+
+/// Streams a Matroska or WebM object as fragmented MP4, from the time given in the query.
+async fn remux_object(
+    bucket: &Bucket,
+    key: String,
+    uri: &axum::http::Uri,
+    head: bool,
+) -> Result<Response> {
+    let Query(params) = Query::<Vec<(String, String)>>::try_from_uri(uri)
+        .map_err(|e| Error::from(ErrorCode::InvalidArgument).message(e.body_text()))?;
+    let request = RemuxRequest::try_from(params)?;
+    let Some(path) = bucket.object_path(&key) else {
+        return Err(Error::from(ErrorCode::NoSuchKey).key(key));
+    };
+
+    let probes = bucket.probes.clone();
+    let remuxer = tokio::task::spawn_blocking(move || {
+        let info = probes.probe(&path)?;
+        match info.map(|info| info.container) {
+            Some(Container::Matroska | Container::Webm) => {
+                let mut remuxer = Remuxer::open(&path, request.audio)?;
+                remuxer.seek(request.start)?;
+                Ok(Some(remuxer))
+            }
+            _ => Ok(None),
+        }
+    })
+    .await
+    .map_err(|e| Error::from(ErrorCode::InternalError).message(e.to_string()))?;
+    match remuxer {
+        Ok(Some(remuxer)) => Ok(Remux { remuxer, head }.into_response()),
+        Ok(None) => Err(Error::from(ErrorCode::InvalidRequest)
+            .message("Only Matroska and WebM objects can be remuxed.")
+            .key(key)),
+        Err(e) => Err(media_error(e).key(key)),
+    }
+}
+
+/// Serves a playlist or a segment of a media object packaged for HTTP Live Streaming.
+async fn package_object(
+    bucket: &Bucket,
+    key: String,
+    uri: &axum::http::Uri,
+    head: bool,
+) -> Result<Response> {
+    let Query(params) = Query::<Vec<(String, String)>>::try_from_uri(uri)
+        .map_err(|e| Error::from(ErrorCode::InvalidArgument).message(e.body_text()))?;
+    let request = HlsRequest::try_from(params)?;
+    let Some(path) = bucket.object_path(&key) else {
+        return Err(Error::from(ErrorCode::NoSuchKey).key(key));
+    };
+
+    let probes = bucket.probes.clone();
+    let packages = bucket.packages.clone();
+    let file = path.clone();
+    let package = tokio::task::spawn_blocking(move || {
+        let info = probes.probe(&file)?;
+        match info.map(|info| info.container) {
+            Some(
+                container @ (Container::Mp4
+                | Container::Mov
+                | Container::Matroska
+                | Container::Webm),
+            ) => packages.package(&file, container, request.audio).map(Some),
+            _ => Ok(None),
+        }
+    })
+    .await
+    .map_err(|e| Error::from(ErrorCode::InternalError).message(e.to_string()))?;
+    let package = match package {
+        Ok(Some(package)) => package,
+        Ok(None) => {
+            return Err(Error::from(ErrorCode::InvalidRequest)
+                .message("Only MP4, Matroska and WebM objects can be streamed over HLS.")
+                .key(key))
+        }
+        Err(e) => return Err(media_error(e).key(key)),
+    };
+
+    if let HlsPart::Segment(index) = request.part {
+        if package.segments.len() <= index {
+            return Err(Error::from(ErrorCode::InvalidArgument)
+                .message("The object has no such HLS segment.")
+                .argument("kayo-hls".to_string(), index.to_string())
+                .key(key));
+        }
+    }
+    let name = key.rsplit('/').next().unwrap_or_default().to_string();
+    Ok(Hls {
+        package,
+        path,
+        name,
+        part: request.part,
+        head,
+    }
+    .into_response())
+}
+
+/// Responds with the MPEG-DASH manifest of a media object, whose segments are read through byte
+/// ranges of the object itself if it is fragmented, or from its HLS package otherwise.
+async fn describe_object(
+    bucket: &Bucket,
+    key: String,
+    uri: &axum::http::Uri,
+    head: bool,
+) -> Result<Response> {
+    let Query(params) = Query::<Vec<(String, String)>>::try_from_uri(uri)
+        .map_err(|e| Error::from(ErrorCode::InvalidArgument).message(e.body_text()))?;
+    let request = DashRequest::try_from(params)?;
+    let Some(path) = bucket.object_path(&key) else {
+        return Err(Error::from(ErrorCode::NoSuchKey).key(key));
+    };
+
+    let probes = bucket.probes.clone();
+    let packages = bucket.packages.clone();
+    let name = key.rsplit('/').next().unwrap_or_default().to_string();
+    let manifest = tokio::task::spawn_blocking(move || {
+        let info = probes.probe(&path)?;
+        match info {
+            Some(info)
+                if matches!(
+                    info.container,
+                    Container::Mp4 | Container::Mov | Container::Matroska | Container::Webm
+                ) =>
+            {
+                Mpd::describe(&path, &info, &packages, request.audio, &name).map(Some)
+            }
+            _ => Ok(None),
+        }
+    })
+    .await
+    .map_err(|e| Error::from(ErrorCode::InternalError).message(e.to_string()))?;
+    match manifest {
+        Ok(Some((mpd, etag))) => Ok(Dash { mpd, etag, head }.into_response()),
+        Ok(None) => Err(Error::from(ErrorCode::InvalidRequest)
+            .message("Only MP4, Matroska and WebM objects can be streamed over DASH.")
+            .key(key)),
+        Err(e) => Err(media_error(e).key(key)),
+    }
+}
+
+/// Converts a subtitle object to WebVTT, with its cues delayed by the offset given in the query.
+async fn convert_object(
+    bucket: &Bucket,
+    key: String,
+    uri: &axum::http::Uri,
+    head: bool,
+) -> Result<Response> {
+    let Query(params) = Query::<Vec<(String, String)>>::try_from_uri(uri)
+        .map_err(|e| Error::from(ErrorCode::InvalidArgument).message(e.body_text()))?;
+    let request = ConvertRequest::try_from(params)?;
+    let Some(path) = bucket.object_path(&key) else {
+        return Err(Error::from(ErrorCode::NoSuchKey).key(key));
+    };
+
+    let format = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .and_then(subtitle::Format::from_extension);
+    let text = match format {
+        Some(format) => {
+            tokio::task::spawn_blocking(move || webvtt::convert(&path, format, request.offset))
+                .await
+                .map_err(|e| Error::from(ErrorCode::InternalError).message(e.to_string()))?
+        }
+        None => Ok(None),
+    };
+    match text {
+        Ok(Some(text)) => Ok(WebVtt { text, head }.into_response()),
+        Ok(None) => Err(Error::from(ErrorCode::InvalidRequest)
+            .message("Only SRT, WebVTT, ASS and SSA objects can be converted to WebVTT.")
+            .key(key)),
+        Err(e) => Err(media_error(e).key(key)),
+    }
+}
+
+/// Serves an MP4 object with its `moov` box moved before its media data, or `None` to serve it
+/// as is if the box is already there or the object is not an MP4 file.
+async fn faststart_object(
+    bucket: &Bucket,
+    key: &str,
+    headers: &HeaderMap,
+    head: bool,
+) -> Result<Option<Response>> {
+    let Some(path) = bucket.object_path(key) else {
+        return Ok(None);
+    };
+
+    let probes = bucket.probes.clone();
+    let cache = bucket.faststart.clone();
+    let file = path.clone();
+    let layout = tokio::task::spawn_blocking(move || {
+        let content_type = match probes.probe(&file)?.map(|info| info.container) {
+            Some(Container::Mp4) => "video/mp4",
+            Some(Container::Mov) => "video/quicktime",
+            _ => return Ok(None),
+        };
+        io::Result::Ok(cache.layout(&file)?.map(|layout| (layout, content_type)))
+    })
+    .await
+    .map_err(|e| Error::from(ErrorCode::InternalError).message(e.to_string()))?;
+    let (layout, content_type) = match layout {
+        Ok(Some(layout)) => layout,
+        Ok(None) => return Ok(None),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            warn!("failed to relocate the moov box of {}: {e}", path.display());
+            return Ok(None);
+        }
+    };
+
+    if not_modified(headers, &layout.etag) {
+        let etag = HeaderValue::from_str(&layout.etag).unwrap();
+        return Ok(Some(
+            (StatusCode::NOT_MODIFIED, [(ETAG, etag)]).into_response(),
+        ));
+    }
+    let range = match byte_range(headers, layout.len, &layout.etag) {
+        Ok(range) => range,
+        Err(e) => {
+            let mut response = e.key(key.to_string()).into_response();
+            let content_range = HeaderValue::from_str(&format!("bytes */{}", layout.len)).unwrap();
+            response.headers_mut().insert(CONTENT_RANGE, content_range);
+            return Ok(Some(response));
+        }
+    };
+    let response = Faststart {
+        layout,
+        path,
+        content_type,
+        range,
+        head,
+    };
+    Ok(Some(response.into_response()))
+}
+
+/// Converts an error reading a media object.
+fn media_error(e: io::Error) -> Error {
+    match e.kind() {
+        io::ErrorKind::NotFound => Error::from(ErrorCode::NoSuchKey),
+        io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => {
+            Error::from(ErrorCode::InvalidRequest).message(format!(
+                "The media headers of the object are not valid: {e}"
+            ))
+        }
+        io::ErrorKind::InvalidInput => Error::from(ErrorCode::InvalidArgument)
+            .message(format!("The request does not apply to the object: {e}.")),
+        io::ErrorKind::Unsupported => Error::from(ErrorCode::InvalidRequest)
+            .message(format!("The object cannot be remuxed: {e}.")),
+        kind => Error::from(ErrorCode::from(kind)).message(e.to_string()),
+    }
+}
+
+async fn put_object(
+    State(bucket): State<Bucket>,
+    Extension(principal): Extension<Principal>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    signer: Option<Extension<ChunkSigner>>,
+    Uri((name, key)): Uri<(String, String)>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response> {
+    if name != BUCKET_NAME {
+        return Err(Error::from(ErrorCode::NoSuchBucket).bucket_name(name));
+    }
+
+    bucket
+        .authorize(&policy::Request {
+            principal: &principal,
+            action: "s3:PutObject",
+            resource: policy::resource(BUCKET_NAME, Some(&key)),
+            source_ip: remote.ip(),
+            prefix: None,
+        })
+        .map_err(|e| e.key(key.clone()))?;
+
+    if key.ends_with('/') || !Bucket::is_plain(&key) || Bucket::is_staging(&key) {
+        return Err(Error::from(ErrorCode::InvalidArgument)
+            .message("The specified argument was not valid.")
+            .key(key));
+    }
+    let path = bucket.root.join(&key);
+
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+
+    let payload = Payload::from_headers(
+        header("x-amz-content-sha256"),
+        header(CONTENT_ENCODING.as_str()),
+    )
+    .map_err(|e| e.key(key.clone()))?;
+    let expected = checksum::from_headers(&headers)?;
+    let algorithm = match (&expected, header("x-amz-trailer")) {
+        (Some((algorithm, _)), _) => Some(algorithm.clone()),
+        (None, Some(trailer)) => Some(checksum::from_header_name(trailer).ok_or_else(|| {
+            Error::from(ErrorCode::InvalidRequest)
+                .message("The value specified in the x-amz-trailer header is not supported")
+        })?),
+        (None, None) => None,
+    };
+
+    let frames = match payload {
+        Payload::Streaming { signed, .. } => {
+            let reader = StreamReader::new(body.into_data_stream().map_err(io::Error::other));
+            let signer = signer.filter(|_| signed).map(|Extension(s)| s);
+            chunked::decode(reader, signer).boxed()
+        }
+        _ => body
+            .into_data_stream()
+            .map_ok(Frame::Data)
+            .map_err(|e| Error::from(ErrorCode::IncompleteBody).message(e.to_string()))
+            .boxed(),
+    };
+
+    let staging = bucket.root.join(STAGING_DIR);
+    create_dir_all(&staging).await?;
+    let temp = temp_path(&staging, &path);
+    let upload = match write_object(&temp, frames, &payload, algorithm.as_ref()).await {
+        Ok(upload) => upload,
+        Err(e) => {
+            let _ = remove_file(&temp).await;
+            return Err(e.key(key));
+        }
+    };
+
+    if let Err(e) = upload.verify(&payload, header("x-amz-decoded-content-length"), expected) {
+        let _ = remove_file(&temp).await;
+        return Err(e.key(key));
+    }
+
+    let moved = async {
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent).await?;
+        }
+        rename(&temp, &path).await
+    };
+    if let Err(e) = moved.await {
+        let _ = remove_file(&temp).await;
+        let e: Error = e.into();
+        return Err(e.key(key));
+    }
+    debug!(
+        "{} uploaded {key} ({} bytes)",
+        principal.access_key.as_deref().unwrap_or("anonymous"),
+        upload.length
+    );
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        ETAG,
+        HeaderValue::from_str(&format!("\"{}\"", upload.e_tag))
+            .map_err(|e| Error::from(ErrorCode::InternalError).message(e.to_string()))?,
+    );
+    if let (Some(algorithm), Some(checksum)) = (&algorithm, upload.checksum) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::try_from(checksum::header_name(algorithm)),
+            HeaderValue::try_from(checksum),
+        ) {
+            headers.insert(name, value);
+        }
+    }
+
+    Ok(headers.into_response())
+}
+
+struct Upload {
+    e_tag: String,
+    sha256: Option<String>,
+    checksum: Option<String>,
+    length: u64,
+    trailers: Vec<(String, String)>,
+}
+
+impl Upload {
+    fn verify(
+        &self,
+        payload: &Payload,
+        decoded_content_length: Option<&str>,
+        expected: Option<(ChecksumAlgorithm, String)>,
+    ) -> Result<()> {
+        if let (Payload::Sha256(expected), Some(actual)) = (payload, &self.sha256) {
+            if expected != actual {
+                return Err(Error::from(ErrorCode::XAmzContentSHA256Mismatch));
+            }
+        }
+
+        if let Some(length) = decoded_content_length {
+            if length.parse::<u64>().ok() != Some(self.length) {
+                return Err(Error::from(ErrorCode::IncompleteBody));
+            }
+        }
+
+        let Some(actual) = &self.checksum else {
+            return Ok(());
+        };
+
+        let (name, expected) = match expected {
+            Some((algorithm, expected)) => (algorithm.as_str().to_string(), expected),
+            None => {
+                let (name, value) = self
+                    .trailers
+                    .iter()
+                    .find(|(name, _)| checksum::from_header_name(name).is_some())
+                    .ok_or_else(|| {
+                        Error::from(ErrorCode::InvalidRequest)
+                            .message("The checksum trailer declared in x-amz-trailer was not sent.")
+                    })?;
+                (name.to_ascii_uppercase(), value.clone())
+            }
+        };
+
+        if &expected != actual {
+            return Err(Error::from(ErrorCode::BadDigest).message(format!(
+                "The {} you specified did not match the calculated checksum.",
+                name.trim_start_matches("X-AMZ-CHECKSUM-")
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+async fn write_object(
+    path: &Path,
+    mut frames: impl Stream<Item = Result<Frame>> + Unpin,
+    payload: &Payload,
+    algorithm: Option<&ChecksumAlgorithm>,
+) -> Result<Upload> {
+    let mut file = BufWriter::new(File::create(path).await?);
+    let mut md5 = Md5::new();
+    let mut sha256 = matches!(payload, Payload::Sha256(_)).then(Sha256::new);
+    let mut checksum = algorithm.map(Checksum::new).transpose()?;
+    let mut length = 0;
+    let mut trailers = Vec::new();
+
+    while let Some(frame) = frames.try_next().await? {
+        match frame {
+            Frame::Data(data) => {
+                md5.update(&data);
+                if let Some(sha256) = &mut sha256 {
+                    sha256.update(&data);
+                }
+                if let Some(checksum) = &mut checksum {
+                    checksum.update(&data);
+                }
+                length += data.len() as u64;
+                file.write_all(&data).await?;
+            }
+            Frame::Trailers(t) => trailers = t,
+        }
+    }
+
+    file.flush().await?;
+
+    Ok(Upload {
+        e_tag: hex::encode(md5.finalize()),
+        sha256: sha256.map(|h| hex::encode(h.finalize())),
+        checksum: checksum.map(Checksum::finalize),
+        length,
+        trailers,
+    })
+}
+
+/// Returns a path in the `staging` directory to write an upload to `path` in, which is moved to
+/// `path` once it is complete.
+fn temp_path(staging: &Path, path: &Path) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    staging.join(format!(
+        "{name}.{}-{}.upload",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn staging_keys() {
+        assert!(Bucket::is_staging(".kayo-uploads/a.mp4.1-0.upload"));
+        assert!(Bucket::is_staging(".kayo-uploads"));
+        assert!(!Bucket::is_staging("media/.kayo-uploads/a.mp4"));
+        assert!(!Bucket::is_staging(".kayo-uploads-old/a.mp4"));
+
+        // Other spellings of the staging directory are not plain keys at all.
+        for key in [
+            "/.kayo-uploads/a.mp4.1-0.upload",
+            "./.kayo-uploads/a.mp4.1-0.upload",
+            "media/../.kayo-uploads/a.mp4.1-0.upload",
+        ] {
+            assert!(!Bucket::is_plain(key), "{key}");
+        }
+        assert!(Bucket::is_plain("media/a.mp4"));
+        assert!(Bucket::is_plain("media/"));
+    }
 }
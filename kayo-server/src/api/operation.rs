@@ -0,0 +1,239 @@
+use axum::extract::Request;
+use axum::http::Method;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+use super::error::{Error, ErrorCode, Result};
+
+/// Query parameters that select a subresource or an operation other than the default one.
+const SUBRESOURCES: [&str; 42] = [
+    "accelerate",
+    "acl",
+    "analytics",
+    "attributes",
+    "cors",
+    "delete",
+    "encryption",
+    "intelligent-tiering",
+    "inventory",
+    "kayo-dash",
+    "kayo-faststart",
+    "kayo-format",
+    "kayo-hls",
+    "kayo-probe",
+    "kayo-remux",
+    "legal-hold",
+    "lifecycle",
+    "location",
+    "logging",
+    "metadataConfiguration",
+    "metrics",
+    "notification",
+    "object-lock",
+    "ownershipControls",
+    "partNumber",
+    "policy",
+    "policyStatus",
+    "publicAccessBlock",
+    "replication",
+    "requestPayment",
+    "restore",
+    "retention",
+    "select",
+    "session",
+    "tagging",
+    "torrent",
+    "uploadId",
+    "uploads",
+    "versionId",
+    "versioning",
+    "versions",
+    "website",
+];
+
+/// The S3 operations served by the API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    ListObjects,
+    GetBucketPolicy,
+    PutBucketPolicy,
+    DeleteBucketPolicy,
+    GetBucketCors,
+    PutBucketCors,
+    DeleteBucketCors,
+    GetBucketVersioning,
+    GetBucketAcl,
+    GetBucketPolicyStatus,
+    GetBucketLifecycleConfiguration,
+    GetBucketEncryption,
+    GetObjectLockConfiguration,
+    GetBucketOwnershipControls,
+    GetBucketLocation,
+    GetObject,
+    /// Gets an MP4 object with its `moov` box moved before its media data, a kayo extension.
+    GetFaststartObject,
+    PutObject,
+    /// Parses the headers of a media object, a kayo extension.
+    ProbeObject,
+    /// Streams a media object in another container, a kayo extension.
+    RemuxObject,
+    /// Serves a media object as HTTP Live Streaming playlists and segments, a kayo extension.
+    PackageObject,
+    /// Describes a media object in an MPEG-DASH manifest, a kayo extension.
+    DescribeObject,
+    /// Converts a subtitle object to WebVTT, a kayo extension.
+    ConvertObject,
+}
+
+impl Operation {
+    /// Resolves the operation requested by `method` on a bucket, or on an object if `object` is
+    /// set, with the given subresources.
+    fn resolve(method: &Method, object: bool, subresources: &[&str]) -> Result<Self> {
+        if !matches!(
+            *method,
+            Method::GET | Method::HEAD | Method::PUT | Method::POST | Method::DELETE
+        ) {
+            return Err(Error::from(ErrorCode::MethodNotAllowed));
+        }
+
+        let operation = match (object, subresources, method.as_str()) {
+            (false, [], "GET" | "HEAD") => Self::ListObjects,
+            (false, ["policy"], "GET") => Self::GetBucketPolicy,
+            (false, ["policy"], "PUT") => Self::PutBucketPolicy,
+            (false, ["policy"], "DELETE") => Self::DeleteBucketPolicy,
+            (false, ["cors"], "GET") => Self::GetBucketCors,
+            (false, ["cors"], "PUT") => Self::PutBucketCors,
+            (false, ["cors"], "DELETE") => Self::DeleteBucketCors,
+            (false, ["versioning"], "GET") => Self::GetBucketVersioning,
+            (false, ["acl"], "GET") => Self::GetBucketAcl,
+            (false, ["policyStatus"], "GET") => Self::GetBucketPolicyStatus,
+            (false, ["lifecycle"], "GET") => Self::GetBucketLifecycleConfiguration,
+            (false, ["encryption"], "GET") => Self::GetBucketEncryption,
+            (false, ["object-lock"], "GET") => Self::GetObjectLockConfiguration,
+            (false, ["ownershipControls"], "GET") => Self::GetBucketOwnershipControls,
+            (false, ["location"], "GET") => Self::GetBucketLocation,
+            (true, [], "GET" | "HEAD") => Self::GetObject,
+            (true, [], "PUT") => Self::PutObject,
+            (true, ["kayo-faststart"], "GET" | "HEAD") => Self::GetFaststartObject,
+            (true, ["kayo-probe"], "GET" | "HEAD") => Self::ProbeObject,
+            (true, ["kayo-remux"], "GET" | "HEAD") => Self::RemuxObject,
+            (true, ["kayo-hls"], "GET" | "HEAD") => Self::PackageObject,
+            (true, ["kayo-dash"], "GET" | "HEAD") => Self::DescribeObject,
+            (true, ["kayo-format"], "GET" | "HEAD") => Self::ConvertObject,
+            _ => return Err(Error::from(ErrorCode::NotImplemented)),
+        };
+
+        Ok(operation)
+    }
+}
+
+/// Resolves the operation of the request, rejecting the ones that are not supported.
+pub async fn dispatch(mut request: Request, next: Next) -> Response {
+    let path = request.uri().path().trim_start_matches('/');
+    let object = path.split_once('/').is_some_and(|(_, key)| !key.is_empty());
+
+    let subresources = request
+        .uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .map(|p| p.split('=').next().unwrap_or_default())
+        .filter(|p| SUBRESOURCES.contains(p))
+        .collect::<Vec<_>>();
+
+    match Operation::resolve(request.method(), object, &subresources) {
+        Ok(operation) => {
+            request.extensions_mut().insert(operation);
+            next.run(request).await
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A method, whether an object is requested, the subresources and the expected resolution.
+    type Case = (
+        &'static str,
+        bool,
+        &'static [&'static str],
+        Result<Operation, ErrorCode>,
+    );
+
+    #[test]
+    fn operations() {
+        use ErrorCode::{MethodNotAllowed, NotImplemented};
+        use Operation::*;
+
+        let cases: &[Case] = &[
+            ("GET", false, &[], Ok(ListObjects)),
+            ("HEAD", false, &[], Ok(ListObjects)),
+            ("GET", false, &["policy"], Ok(GetBucketPolicy)),
+            ("PUT", false, &["policy"], Ok(PutBucketPolicy)),
+            ("DELETE", false, &["policy"], Ok(DeleteBucketPolicy)),
+            ("GET", false, &["cors"], Ok(GetBucketCors)),
+            ("PUT", false, &["cors"], Ok(PutBucketCors)),
+            ("DELETE", false, &["cors"], Ok(DeleteBucketCors)),
+            ("GET", false, &["versioning"], Ok(GetBucketVersioning)),
+            ("GET", false, &["acl"], Ok(GetBucketAcl)),
+            ("GET", false, &["policyStatus"], Ok(GetBucketPolicyStatus)),
+            (
+                "GET",
+                false,
+                &["lifecycle"],
+                Ok(GetBucketLifecycleConfiguration),
+            ),
+            ("GET", false, &["encryption"], Ok(GetBucketEncryption)),
+            (
+                "GET",
+                false,
+                &["object-lock"],
+                Ok(GetObjectLockConfiguration),
+            ),
+            (
+                "GET",
+                false,
+                &["ownershipControls"],
+                Ok(GetBucketOwnershipControls),
+            ),
+            ("GET", false, &["location"], Ok(GetBucketLocation)),
+            ("GET", true, &[], Ok(GetObject)),
+            ("HEAD", true, &[], Ok(GetObject)),
+            ("PUT", true, &[], Ok(PutObject)),
+            ("GET", true, &["kayo-faststart"], Ok(GetFaststartObject)),
+            ("HEAD", true, &["kayo-probe"], Ok(ProbeObject)),
+            ("GET", true, &["kayo-remux"], Ok(RemuxObject)),
+            ("GET", true, &["kayo-hls"], Ok(PackageObject)),
+            ("GET", true, &["kayo-dash"], Ok(DescribeObject)),
+            ("GET", true, &["kayo-format"], Ok(ConvertObject)),
+            // Operations S3 has but the API does not serve.
+            ("PUT", false, &[], Err(NotImplemented)),
+            ("DELETE", false, &[], Err(NotImplemented)),
+            ("POST", false, &["delete"], Err(NotImplemented)),
+            ("PUT", false, &["versioning"], Err(NotImplemented)),
+            ("DELETE", true, &[], Err(NotImplemented)),
+            ("POST", true, &["uploads"], Err(NotImplemented)),
+            ("GET", true, &["tagging"], Err(NotImplemented)),
+            ("GET", true, &["policy"], Err(NotImplemented)),
+            ("GET", false, &["kayo-probe"], Err(NotImplemented)),
+            ("PUT", true, &["kayo-probe"], Err(NotImplemented)),
+            ("GET", true, &["kayo-hls", "kayo-dash"], Err(NotImplemented)),
+            ("GET", false, &["policy", "cors"], Err(NotImplemented)),
+            // Methods S3 does not have at all.
+            ("OPTIONS", false, &[], Err(MethodNotAllowed)),
+            ("PATCH", true, &[], Err(MethodNotAllowed)),
+            ("TRACE", true, &["kayo-probe"], Err(MethodNotAllowed)),
+        ];
+
+        for (method, object, subresources, expected) in cases {
+            let method = Method::from_bytes(method.as_bytes()).unwrap();
+            let resolved = Operation::resolve(&method, *object, subresources).map_err(|e| e.code());
+            assert_eq!(
+                &resolved, expected,
+                "{method} object={object} {subresources:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use aws_sdk_s3::types::Owner;
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+#[derive(Debug, Clone)]
+pub struct UserMapping {
+    uid: u32,
+    name: String,
+}
+
+impl FromStr for UserMapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some((uid, name)) if !name.is_empty() => match uid.parse() {
+                Ok(uid) => Ok(Self {
+                    uid,
+                    name: name.to_string(),
+                }),
+                Err(_) => Err("expected a numeric UID".to_string()),
+            },
+            _ => Err("expected UID:NAME".to_string()),
+        }
+    }
+}
+
+/// Resolves the owners of files from their Unix user IDs.
+///
+/// User names are taken from the configured mappings, or looked up through NSS otherwise. Lookups
+/// are cached for the lifetime of the server, since they may involve a directory service.
+#[derive(Debug, Clone, Default)]
+pub struct Owners(Arc<RwLock<HashMap<u32, Option<String>>>>);
+
+impl Owners {
+    pub fn new(mappings: impl IntoIterator<Item = UserMapping>) -> Self {
+        Self(Arc::new(RwLock::new(
+            mappings
+                .into_iter()
+                .map(|m| (m.uid, Some(m.name)))
+                .collect(),
+        )))
+    }
+
+    /// Returns the owner of the file with `metadata`, if it can be determined on this platform.
+    pub fn get(&self, metadata: &Metadata) -> Option<Owner> {
+        let uid = uid(metadata)?;
+        let name = self.0.read().unwrap().get(&uid).cloned();
+        let name = match name {
+            Some(name) => name,
+            None => {
+                let name = lookup(uid);
+                self.0.write().unwrap().insert(uid, name.clone());
+                name
+            }
+        };
+
+        Some(
+            Owner::builder()
+                .id(uid.to_string())
+                .display_name(name.unwrap_or_else(|| uid.to_string()))
+                .build(),
+        )
+    }
+}
+
+#[cfg(unix)]
+fn uid(metadata: &Metadata) -> Option<u32> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some(metadata.uid())
+}
+
+#[cfg(not(unix))]
+fn uid(_: &Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn lookup(uid: u32) -> Option<String> {
+    use std::ffi::CStr;
+    use std::mem::MaybeUninit;
+    use std::ptr;
+
+    const MAX_BUFFER_SIZE: usize = 1024 * 1024;
+
+    let mut buffer = vec![0; 1024];
+    loop {
+        let mut passwd = MaybeUninit::<libc::passwd>::uninit();
+        let mut result = ptr::null_mut();
+        // SAFETY: all pointers are valid for the duration of the call, and `buffer.len()` is the
+        // size of the buffer that `passwd` is allowed to point into.
+        let error = unsafe {
+            libc::getpwuid_r(
+                uid,
+                passwd.as_mut_ptr(),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+
+        if error == libc::ERANGE && buffer.len() < MAX_BUFFER_SIZE {
+            buffer.resize(buffer.len() * 2, 0);
+            continue;
+        }
+
+        if error != 0 || result.is_null() {
+            return None;
+        }
+
+        // SAFETY: on success `result` points to `passwd`, whose `pw_name` is a NUL-terminated
+        // string stored in `buffer`, which is still alive.
+        let name = unsafe { CStr::from_ptr((*result).pw_name) };
+        return Some(name.to_string_lossy().into_owned());
+    }
+}
+
+#[cfg(not(unix))]
+fn lookup(_: u32) -> Option<String> {
+    None
+}
@@ -0,0 +1,534 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use super::auth::Principal;
+use super::error::{Error, ErrorCode, Result};
+use super::store::Configuration;
+
+const ARN_PREFIX: &str = "arn:aws:s3:::";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> OneOrMany<T> {
+    fn iter(&self) -> std::slice::Iter<'_, T> {
+        match self {
+            Self::One(v) => std::slice::from_ref(v).iter(),
+            Self::Many(v) => v.iter(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+enum Effect {
+    Allow,
+    Deny,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum PrincipalSpec {
+    Wildcard(String),
+    Aws {
+        #[serde(rename = "AWS")]
+        aws: OneOrMany<String>,
+    },
+}
+
+impl PrincipalSpec {
+    fn matches(&self, principal: &Principal) -> bool {
+        match self {
+            Self::Wildcard(p) => p == "*",
+            Self::Aws { aws } => aws
+                .iter()
+                .any(|p| p == "*" || principal.access_key.as_deref() == Some(p.as_str())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+enum Operator {
+    IpAddress,
+    NotIpAddress,
+    StringEquals,
+    StringNotEquals,
+    StringLike,
+    StringNotLike,
+}
+
+impl Operator {
+    fn is_negated(self) -> bool {
+        matches!(
+            self,
+            Self::NotIpAddress | Self::StringNotEquals | Self::StringNotLike
+        )
+    }
+
+    fn matches(self, pattern: &str, value: &str) -> bool {
+        match self {
+            Self::IpAddress | Self::NotIpAddress => cidr_contains(pattern, value),
+            Self::StringEquals | Self::StringNotEquals => pattern == value,
+            Self::StringLike | Self::StringNotLike => wildcard_match(pattern, value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase", deny_unknown_fields)]
+struct Statement {
+    #[serde(rename = "Sid")]
+    _sid: Option<String>,
+    effect: Effect,
+    principal: PrincipalSpec,
+    action: OneOrMany<String>,
+    resource: OneOrMany<String>,
+    #[serde(default)]
+    condition: HashMap<Operator, HashMap<String, OneOrMany<String>>>,
+}
+
+impl Statement {
+    fn matches(&self, request: &Request) -> bool {
+        self.principal.matches(request.principal)
+            && self.action.iter().any(|a| {
+                wildcard_match(
+                    &a.to_ascii_lowercase(),
+                    &request.action.to_ascii_lowercase(),
+                )
+            })
+            && self
+                .resource
+                .iter()
+                .any(|r| wildcard_match(r, &request.resource))
+            && self.condition.iter().all(|(operator, conditions)| {
+                conditions
+                    .iter()
+                    .all(|(key, patterns)| match request.condition_value(key) {
+                        Some(value) if operator.is_negated() => {
+                            !patterns.iter().any(|p| operator.matches(p, &value))
+                        }
+                        Some(value) => patterns.iter().any(|p| operator.matches(p, &value)),
+                        None => operator.is_negated(),
+                    })
+            })
+    }
+
+    fn validate(&self, bucket: &str) -> Result<()> {
+        if !self
+            .action
+            .iter()
+            .all(|a| a == "*" || a.get(..3).is_some_and(|p| p.eq_ignore_ascii_case("s3:")))
+        {
+            return Err(malformed("Policy has invalid action"));
+        }
+
+        if !self.resource.iter().all(|r| {
+            r.strip_prefix(ARN_PREFIX)
+                .map(|r| r.split('/').next().unwrap_or_default())
+                .is_some_and(|b| wildcard_match(b, bucket))
+        }) {
+            return Err(malformed("Policy has invalid resource"));
+        }
+
+        for (operator, conditions) in &self.condition {
+            if !conditions.keys().all(|key| {
+                matches!(
+                    key.to_ascii_lowercase().as_str(),
+                    "aws:sourceip" | "s3:prefix"
+                )
+            }) {
+                return Err(malformed("Policy has an invalid condition key"));
+            }
+            if matches!(operator, Operator::IpAddress | Operator::NotIpAddress)
+                && !conditions
+                    .values()
+                    .flat_map(OneOrMany::iter)
+                    .all(|p| parse_cidr(p).is_some())
+            {
+                return Err(malformed("Invalid IP address or CIDR block in condition"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A bucket policy, persisted as JSON in the configuration directory.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase", deny_unknown_fields)]
+pub struct Policy {
+    version: Option<String>,
+    #[serde(rename = "Id")]
+    _id: Option<String>,
+    statement: OneOrMany<Statement>,
+}
+
+/// The result of evaluating a bucket policy against a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    Deny,
+    NotApplicable,
+}
+
+/// The request context a bucket policy is evaluated against.
+#[derive(Debug)]
+pub struct Request<'a> {
+    pub principal: &'a Principal,
+    pub action: &'static str,
+    pub resource: String,
+    pub source_ip: IpAddr,
+    pub prefix: Option<&'a str>,
+}
+
+impl Request<'_> {
+    fn condition_value(&self, key: &str) -> Option<String> {
+        match key.to_ascii_lowercase().as_str() {
+            "aws:sourceip" => Some(self.source_ip.to_string()),
+            "s3:prefix" => self.prefix.map(str::to_string),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the ARN of `bucket`, or of the object `key` in it.
+pub fn resource(bucket: &str, key: Option<&str>) -> String {
+    match key {
+        Some(key) => format!("{ARN_PREFIX}{bucket}/{key}"),
+        None => format!("{ARN_PREFIX}{bucket}"),
+    }
+}
+
+impl Policy {
+    /// Returns whether the policy allows anyone to access the bucket unconditionally.
+    pub fn is_public(&self) -> bool {
+        self.statement.iter().any(|s| {
+            s.effect == Effect::Allow
+                && s.condition.is_empty()
+                && s.principal.matches(&Principal {
+                    access_key: None,
+                    is_owner: false,
+                    can_read: false,
+                })
+        })
+    }
+
+    /// Evaluates the policy. An explicit deny takes precedence over any allow.
+    pub fn evaluate(&self, request: &Request) -> Decision {
+        let mut decision = Decision::NotApplicable;
+        for statement in self.statement.iter().filter(|s| s.matches(request)) {
+            match statement.effect {
+                Effect::Deny => return Decision::Deny,
+                Effect::Allow => decision = Decision::Allow,
+            }
+        }
+
+        decision
+    }
+}
+
+impl Configuration for Policy {
+    const FILE_NAME: &'static str = "policy.json";
+
+    fn parse(bucket: &str, text: &str) -> Result<Self> {
+        let policy = serde_json::from_str::<Policy>(text)
+            .map_err(|e| malformed(&format!("Policies must be valid JSON: {e}")))?;
+
+        if !matches!(
+            policy.version.as_deref(),
+            None | Some("2012-10-17") | Some("2008-10-17")
+        ) {
+            return Err(malformed("The policy must contain a valid version string"));
+        }
+
+        policy
+            .statement
+            .iter()
+            .try_for_each(|s| s.validate(bucket))?;
+
+        Ok(policy)
+    }
+}
+
+fn malformed(message: &str) -> Error {
+    Error::from(ErrorCode::MalformedPolicy).message(message)
+}
+
+fn parse_cidr(cidr: &str) -> Option<(IpAddr, u8)> {
+    let (addr, len) = match cidr.split_once('/') {
+        Some((addr, len)) => (addr.parse::<IpAddr>().ok()?, Some(len.parse::<u8>().ok()?)),
+        None => (cidr.parse::<IpAddr>().ok()?, None),
+    };
+
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    let len = len.unwrap_or(max);
+    (len <= max).then_some((addr, len))
+}
+
+fn cidr_contains(cidr: &str, addr: &str) -> bool {
+    let (Some((network, len)), Ok(addr)) = (parse_cidr(cidr), addr.parse::<IpAddr>()) else {
+        return false;
+    };
+
+    let addr = match (network, addr) {
+        (IpAddr::V4(_), IpAddr::V6(v6)) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(addr),
+        _ => addr,
+    };
+
+    match (network, addr) {
+        (IpAddr::V4(network), IpAddr::V4(addr)) => {
+            let mask = u32::MAX.checked_shl(32 - len as u32).unwrap_or(0);
+            u32::from(network) & mask == u32::from(addr) & mask
+        }
+        (IpAddr::V6(network), IpAddr::V6(addr)) => {
+            let mask = u128::MAX.checked_shl(128 - len as u32).unwrap_or(0);
+            u128::from(network) & mask == u128::from(addr) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Matches `value` against `pattern`, where `*` matches any sequence of characters and `?`
+/// matches any single character.
+fn wildcard_match(pattern: &str, value: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let value = value.chars().collect::<Vec<_>>();
+
+    let (mut p, mut v) = (0, 0);
+    let mut backtrack = None;
+    while v < value.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, v));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bv)) => {
+                    backtrack = Some((bp, bv + 1));
+                    p = bp + 1;
+                    v = bv + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(statements: &str) -> Result<Policy> {
+        Policy::parse(
+            "contents",
+            &format!(r#"{{"Version": "2012-10-17", "Statement": [{statements}]}}"#),
+        )
+    }
+
+    fn principal(access_key: Option<&str>) -> Principal {
+        Principal {
+            access_key: access_key.map(str::to_string),
+            is_owner: false,
+            can_read: false,
+        }
+    }
+
+    fn evaluate(
+        policy: &Policy,
+        principal: &Principal,
+        action: &'static str,
+        key: Option<&str>,
+        source_ip: &str,
+        prefix: Option<&str>,
+    ) -> Decision {
+        policy.evaluate(&Request {
+            principal,
+            action,
+            resource: resource("contents", key),
+            source_ip: source_ip.parse().unwrap(),
+            prefix,
+        })
+    }
+
+    #[test]
+    fn deny_precedence() {
+        let policy = policy(
+            r#"{"Effect": "Allow", "Principal": "*", "Action": "s3:GetObject", "Resource": "arn:aws:s3:::contents/*"},
+               {"Sid": "private", "Effect": "Deny", "Principal": {"AWS": "*"}, "Action": "s3:*", "Resource": "arn:aws:s3:::contents/private/*"},
+               {"Effect": "Allow", "Principal": {"AWS": ["AKID"]}, "Action": "s3:GetObject", "Resource": "arn:aws:s3:::contents/private/*"}"#,
+        )
+        .unwrap();
+        let anonymous = principal(None);
+        let akid = principal(Some("AKID"));
+        let ip = "192.0.2.1";
+
+        let get =
+            |principal, key| evaluate(&policy, principal, "s3:GetObject", Some(key), ip, None);
+        assert_eq!(get(&anonymous, "kids/a.mp4"), Decision::Allow);
+        assert_eq!(get(&anonymous, "private/a.mp4"), Decision::Deny);
+        assert_eq!(get(&akid, "private/a.mp4"), Decision::Deny);
+        assert_eq!(
+            evaluate(
+                &policy,
+                &anonymous,
+                "s3:PutObject",
+                Some("kids/a.mp4"),
+                ip,
+                None
+            ),
+            Decision::NotApplicable
+        );
+        assert!(policy.is_public());
+    }
+
+    #[test]
+    fn wildcards() {
+        let policy = policy(
+            r#"{"Effect": "Allow", "Principal": "*", "Action": ["S3:get*", "s3:List?ucket"], "Resource": ["arn:aws:s3:::content?", "arn:aws:s3:::contents/kids/*.mp4"]}"#,
+        )
+        .unwrap();
+        let anonymous = principal(None);
+        let ip = "192.0.2.1";
+
+        let decide = |action, key| evaluate(&policy, &anonymous, action, key, ip, None);
+        assert_eq!(decide("s3:GetObject", Some("kids/a.mp4")), Decision::Allow);
+        assert_eq!(
+            decide("s3:GetObject", Some("kids/b/c.mp4")),
+            Decision::Allow
+        );
+        assert_eq!(
+            decide("s3:GetObject", Some("kids/a.mkv")),
+            Decision::NotApplicable
+        );
+        assert_eq!(
+            decide("s3:GetObject", Some("a.mp4")),
+            Decision::NotApplicable
+        );
+        assert_eq!(decide("s3:ListBucket", None), Decision::Allow);
+        assert_eq!(
+            decide("s3:PutObject", Some("kids/a.mp4")),
+            Decision::NotApplicable
+        );
+
+        let everything = policy_text(r#""Action": "*", "Resource": "arn:aws:s3:::*""#).unwrap();
+        assert_eq!(
+            evaluate(&everything, &anonymous, "s3:PutObject", Some("a"), ip, None),
+            Decision::Allow
+        );
+    }
+
+    fn policy_text(fields: &str) -> Result<Policy> {
+        policy(&format!(
+            r#"{{"Effect": "Allow", "Principal": "*", {fields}}}"#
+        ))
+    }
+
+    #[test]
+    fn source_ip() {
+        let policy = policy_text(
+            r#""Action": "s3:GetObject", "Resource": "arn:aws:s3:::contents/*", "Condition": {"IpAddress": {"aws:SourceIp": ["192.168.1.0/24", "2001:db8::/32"]}, "NotIpAddress": {"aws:SourceIp": "192.168.1.13"}}"#,
+        )
+        .unwrap();
+        let anonymous = principal(None);
+
+        let get = |ip| evaluate(&policy, &anonymous, "s3:GetObject", Some("a"), ip, None);
+        assert_eq!(get("192.168.1.1"), Decision::Allow);
+        assert_eq!(get("::ffff:192.168.1.254"), Decision::Allow);
+        assert_eq!(get("2001:db8::1"), Decision::Allow);
+        assert_eq!(get("192.168.1.13"), Decision::NotApplicable);
+        assert_eq!(get("192.168.2.1"), Decision::NotApplicable);
+        assert_eq!(get("2001:db9::1"), Decision::NotApplicable);
+        assert!(!policy.is_public());
+
+        assert!(cidr_contains("0.0.0.0/0", "203.0.113.7"));
+        assert!(cidr_contains("10.0.0.1", "10.0.0.1"));
+        assert!(!cidr_contains("10.0.0.1", "10.0.0.2"));
+    }
+
+    #[test]
+    fn prefix_conditions() {
+        let policy = policy_text(
+            r#""Action": "s3:ListBucket", "Resource": "arn:aws:s3:::contents", "Condition": {"StringLike": {"s3:prefix": ["kids/*", ""]}, "StringNotEquals": {"s3:prefix": "kids/private/"}}"#,
+        )
+        .unwrap();
+        let anonymous = principal(None);
+
+        let list = |prefix| {
+            evaluate(
+                &policy,
+                &anonymous,
+                "s3:ListBucket",
+                None,
+                "192.0.2.1",
+                prefix,
+            )
+        };
+        assert_eq!(list(Some("")), Decision::Allow);
+        assert_eq!(list(Some("kids/")), Decision::Allow);
+        assert_eq!(list(Some("kids/cartoons/")), Decision::Allow);
+        assert_eq!(list(Some("kids/private/")), Decision::NotApplicable);
+        assert_eq!(list(Some("movies/")), Decision::NotApplicable);
+        assert_eq!(list(None), Decision::NotApplicable);
+
+        let equals = policy_text(
+            r#""Action": "s3:ListBucket", "Resource": "arn:aws:s3:::contents", "Condition": {"StringEquals": {"s3:prefix": "kids/"}}"#,
+        )
+        .unwrap();
+        let list = |prefix| {
+            evaluate(
+                &equals,
+                &anonymous,
+                "s3:ListBucket",
+                None,
+                "192.0.2.1",
+                Some(prefix),
+            )
+        };
+        assert_eq!(list("kids/"), Decision::Allow);
+        assert_eq!(list("kids/a/"), Decision::NotApplicable);
+    }
+
+    #[test]
+    fn invalid_policies() {
+        let resource = r#""Resource": "arn:aws:s3:::contents/*""#;
+        for fields in [
+            format!(r#""NotAction": "s3:GetObject", {resource}"#),
+            r#""Action": "s3:GetObject", "NotResource": "arn:aws:s3:::contents/a""#.to_string(),
+            format!(r#""Action": "ec2:RunInstances", {resource}"#),
+            r#""Action": "s3:GetObject", "Resource": "arn:aws:s3:::other/*""#.to_string(),
+            r#""Action": "s3:GetObject", "Resource": "contents/*""#.to_string(),
+            format!(
+                r#""Action": "s3:GetObject", {resource}, "Condition": {{"DateGreaterThan": {{"aws:CurrentTime": "2020-01-01T00:00:00Z"}}}}"#
+            ),
+            format!(
+                r#""Action": "s3:GetObject", {resource}, "Condition": {{"StringNotEquals": {{"aws:Referer": "x"}}}}"#
+            ),
+            format!(
+                r#""Action": "s3:GetObject", {resource}, "Condition": {{"IpAddress": {{"aws:SourceIp": "192.168.1.0/33"}}}}"#
+            ),
+        ] {
+            assert!(policy_text(&fields).is_err(), "{fields}");
+        }
+
+        let not_principal = format!(
+            r#"{{"Effect": "Allow", "NotPrincipal": "*", "Action": "s3:GetObject", {resource}}}"#
+        );
+        assert!(policy(&not_principal).is_err());
+
+        assert!(
+            Policy::parse("contents", r#"{"Version": "2024-01-01", "Statement": []}"#).is_err()
+        );
+        assert!(Policy::parse("contents", r#"{"Statement": [], "Extra": 1}"#).is_err());
+        assert!(Policy::parse("contents", "not json").is_err());
+    }
+}
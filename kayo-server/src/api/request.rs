@@ -1,160 +1,571 @@
+use aws_sdk_s3::operation::list_objects::ListObjectsInput;
 use aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Input;
-use serde::de;
-use std::fmt;
-use std::marker::PhantomData;
+use aws_sdk_s3::types::{EncodingType, RequestPayer};
+use axum::http::header::{ACCEPT, IF_NONE_MATCH, IF_RANGE, RANGE};
+use axum::http::HeaderMap;
+use std::ops::Range;
 use std::str::FromStr;
+use tracing::debug;
 
-struct EnumHelper<T>(T);
-
-impl<'de, T> de::Deserialize<'de> for EnumHelper<T>
-where
-    T: FromStr,
-    T::Err: fmt::Display,
-{
-    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
-        struct Visitor<T>(PhantomData<fn() -> T>);
-
-        impl<'de, T> de::Visitor<'de> for Visitor<T>
-        where
-            T: FromStr,
-            T::Err: fmt::Display,
-        {
-            type Value = T;
-
-            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("a string")
-            }
+use super::error::{Error, ErrorCode};
+
+enum Field {
+    ListType,
+    Bucket,
+    Delimiter,
+    EncodingType,
+    MaxKeys,
+    Prefix,
+    ContinuationToken,
+    FetchOwner,
+    StartAfter,
+    Marker,
+    RequestPayer,
+    ExpectedBucketOwner,
+    Format,
+    Cast,
+}
+
+impl FromStr for Field {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "list-type" => Ok(Self::ListType),
+            "bucket" => Ok(Self::Bucket),
+            "delimiter" => Ok(Self::Delimiter),
+            "encoding-type" => Ok(Self::EncodingType),
+            "max-keys" => Ok(Self::MaxKeys),
+            "prefix" => Ok(Self::Prefix),
+            "continuation-token" => Ok(Self::ContinuationToken),
+            "fetch-owner" => Ok(Self::FetchOwner),
+            "start-after" => Ok(Self::StartAfter),
+            "marker" => Ok(Self::Marker),
+            "request-payer" => Ok(Self::RequestPayer),
+            "expected-bucket-owner" => Ok(Self::ExpectedBucketOwner),
+            "format" => Ok(Self::Format),
+            "kayo-cast" => Ok(Self::Cast),
+            _ => Err(()),
+        }
+    }
+}
+
+fn invalid_argument(message: &str, name: String, value: String) -> Error {
+    Error::from(ErrorCode::InvalidArgument)
+        .message(message)
+        .argument(name, value)
+}
+
+/// The representation of a listing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// The S3 XML document, expected by S3 clients.
+    #[default]
+    Xml,
+    /// The same data as a JSON object, for clients that are not S3 clients.
+    Json,
+}
 
-            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
-                v.parse::<T>().map_err(de::Error::custom)
+impl Format {
+    /// Picks the format requested by the `format` query parameter if any, or by the `Accept`
+    /// header otherwise.
+    pub fn negotiate(params: &[(String, String)], headers: &HeaderMap) -> Result<Self, Error> {
+        if let Some((name, value)) = params.iter().find(|(name, _)| name == "format") {
+            return match value.as_str() {
+                "xml" => Ok(Self::Xml),
+                "json" => Ok(Self::Json),
+                _ => Err(invalid_argument(
+                    "Invalid format specified in Request",
+                    name.clone(),
+                    value.clone(),
+                )),
+            };
+        }
+
+        let accept = headers
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|range| range.split(';').next().unwrap_or_default().trim());
+        for range in accept {
+            match range {
+                "application/json" => return Ok(Self::Json),
+                "application/xml" | "text/xml" => return Ok(Self::Xml),
+                _ => {}
             }
         }
 
-        deserializer
-            .deserialize_str(Visitor(PhantomData))
-            .map(EnumHelper)
+        Ok(Self::Xml)
+    }
+}
+
+/// Returns whether a listing should tell which Cast devices play its media objects, as requested
+/// by the `kayo-cast` query parameter. Telling so probes every media object listed.
+pub fn cast_requested(params: &[(String, String)]) -> Result<bool, Error> {
+    match params.iter().find(|(name, _)| name == "kayo-cast") {
+        Some((name, value)) => value.parse::<bool>().map_err(|_| {
+            invalid_argument("Invalid value for kayo-cast", name.clone(), value.clone())
+        }),
+        None => Ok(false),
     }
 }
 
-#[repr(transparent)]
+/// A request to list a bucket with either version of the ListObjects API.
 #[derive(Debug)]
-pub struct ListBucketRequest(pub ListObjectsV2Input);
+pub enum ListBucketRequest {
+    V1(ListObjectsInput),
+    V2(ListObjectsV2Input),
+}
 
-impl<'de> de::Deserialize<'de> for ListBucketRequest {
-    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
-        struct Visitor;
+impl ListBucketRequest {
+    pub fn prefix(&self) -> Option<&str> {
+        match self {
+            Self::V1(input) => input.prefix(),
+            Self::V2(input) => input.prefix(),
+        }
+    }
+}
 
-        impl<'de> de::Visitor<'de> for Visitor {
-            type Value = ListBucketRequest;
+impl TryFrom<Vec<(String, String)>> for ListBucketRequest {
+    type Error = Error;
 
-            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("a map")
+    fn try_from(params: Vec<(String, String)>) -> Result<Self, Self::Error> {
+        let mut v1 = ListObjectsInput::builder();
+        let mut builder = ListObjectsV2Input::builder();
+        let mut v2 = false;
+
+        for (name, value) in params {
+            let Ok(field) = name.parse::<Field>() else {
+                debug!("ignoring unknown query parameter {name}");
+                continue;
+            };
+
+            match field {
+                Field::ListType => {
+                    if value != "2" {
+                        return Err(invalid_argument(
+                            "Invalid List Type specified in Request",
+                            name,
+                            value,
+                        ));
+                    }
+                    v2 = true;
+                }
+                Field::Bucket => builder = builder.bucket(value),
+                Field::Delimiter => builder = builder.delimiter(value),
+                Field::EncodingType => {
+                    if !EncodingType::values().contains(&value.as_str()) {
+                        return Err(invalid_argument(
+                            "Invalid Encoding Method specified in Request",
+                            name,
+                            value,
+                        ));
+                    }
+                    builder = builder.encoding_type(EncodingType::from(value.as_str()))
+                }
+                Field::MaxKeys => {
+                    let Some(max_keys) = value.parse::<i32>().ok().filter(|n| 0 <= *n) else {
+                        return Err(invalid_argument(
+                            "Provided max-keys not an integer or within integer range",
+                            name,
+                            value,
+                        ));
+                    };
+                    builder = builder.max_keys(max_keys)
+                }
+                Field::Prefix => builder = builder.prefix(value),
+                Field::ContinuationToken => builder = builder.continuation_token(value),
+                Field::FetchOwner => {
+                    let fetch_owner = value.parse::<bool>().map_err(|_| {
+                        invalid_argument("Invalid value for fetch-owner", name, value)
+                    })?;
+                    builder = builder.fetch_owner(fetch_owner)
+                }
+                Field::StartAfter => builder = builder.start_after(value),
+                Field::Marker => v1 = v1.marker(value),
+                Field::RequestPayer => {
+                    if !RequestPayer::values().contains(&value.as_str()) {
+                        return Err(invalid_argument(
+                            "Invalid value for x-amz-request-payer",
+                            name,
+                            value,
+                        ));
+                    }
+                    builder = builder.request_payer(RequestPayer::from(value.as_str()))
+                }
+                Field::ExpectedBucketOwner => builder = builder.expected_bucket_owner(value),
+                // Negotiated along with the `Accept` header, see `Format::negotiate`.
+                Field::Format => {}
+                // See `cast_requested`.
+                Field::Cast => {}
             }
+        }
+
+        let request = if v2 {
+            builder.build().map(ListBucketRequest::V2)
+        } else {
+            v1.set_bucket(builder.get_bucket().clone())
+                .set_delimiter(builder.get_delimiter().clone())
+                .set_encoding_type(builder.get_encoding_type().clone())
+                .set_max_keys(*builder.get_max_keys())
+                .set_prefix(builder.get_prefix().clone())
+                .set_request_payer(builder.get_request_payer().clone())
+                .set_expected_bucket_owner(builder.get_expected_bucket_owner().clone())
+                .build()
+                .map(ListBucketRequest::V1)
+        };
+
+        request.map_err(|e| Error::from(ErrorCode::InvalidRequest).message(e.to_string()))
+    }
+}
 
-            fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
-                enum Field {
-                    ListType,
-                    Bucket,
-                    Delimiter,
-                    EncodingType,
-                    MaxKeys,
-                    Prefix,
-                    ContinuationToken,
-                    FetchOwner,
-                    StartAfter,
-                    RequestPayer,
-                    ExpectedBucketOwner,
+/// A request to remux a media object, a kayo extension.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RemuxRequest {
+    /// The time to start from, in seconds.
+    pub start: f64,
+    /// The number of the audio track to keep, or `None` for the default one.
+    pub audio: Option<u64>,
+}
+
+impl TryFrom<Vec<(String, String)>> for RemuxRequest {
+    type Error = Error;
+
+    fn try_from(params: Vec<(String, String)>) -> Result<Self, Self::Error> {
+        let mut request = Self::default();
+        for (name, value) in params {
+            match name.as_str() {
+                "kayo-remux" => {
+                    if value != "mp4" {
+                        return Err(invalid_argument(
+                            "Invalid remux container specified in Request",
+                            name,
+                            value,
+                        ));
+                    }
+                }
+                "kayo-start" => {
+                    let Some(start) = value.parse::<f64>().ok().filter(|s| *s >= 0.0) else {
+                        return Err(invalid_argument(
+                            "Provided kayo-start not a non-negative number of seconds",
+                            name,
+                            value,
+                        ));
+                    };
+                    request.start = start;
+                }
+                "kayo-audio" => {
+                    let audio = value.parse::<u64>().map_err(|_| {
+                        invalid_argument("Invalid value for kayo-audio", name, value)
+                    })?;
+                    request.audio = Some(audio);
                 }
+                _ => debug!("ignoring unknown query parameter {name}"),
+            }
+        }
+        Ok(request)
+    }
+}
+
+/// The part of an HTTP Live Streaming package that is requested.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HlsPart {
+    /// The playlist of the variants.
+    #[default]
+    Master,
+    /// The playlist of the segments.
+    Media,
+    /// The initialization segment.
+    Init,
+    /// A media segment, numbered from 0.
+    Segment(usize),
+}
 
-                impl<'de> de::Deserialize<'de> for Field {
-                    fn deserialize<D: de::Deserializer<'de>>(
-                        deserializer: D,
-                    ) -> Result<Self, D::Error> {
-                        struct Visitor;
+/// A request for a part of a media object packaged for HTTP Live Streaming, a kayo extension.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HlsRequest {
+    pub part: HlsPart,
+    /// The number of the audio track to keep, or `None` for the default one.
+    pub audio: Option<u64>,
+}
 
-                        impl<'de> de::Visitor<'de> for Visitor {
-                            type Value = Field;
+impl TryFrom<Vec<(String, String)>> for HlsRequest {
+    type Error = Error;
 
-                            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                                formatter.write_str("an identifier")
+    fn try_from(params: Vec<(String, String)>) -> Result<Self, Self::Error> {
+        let mut request = Self::default();
+        for (name, value) in params {
+            match name.as_str() {
+                "kayo-hls" => {
+                    request.part = match value.as_str() {
+                        "" | "master" => HlsPart::Master,
+                        "media" => HlsPart::Media,
+                        "init" => HlsPart::Init,
+                        index => match index.parse() {
+                            Ok(index) => HlsPart::Segment(index),
+                            Err(_) => {
+                                return Err(invalid_argument(
+                                    "Invalid value for kayo-hls",
+                                    name,
+                                    value,
+                                ))
                             }
+                        },
+                    };
+                }
+                "kayo-audio" => {
+                    let audio = value.parse::<u64>().map_err(|_| {
+                        invalid_argument("Invalid value for kayo-audio", name, value)
+                    })?;
+                    request.audio = Some(audio);
+                }
+                _ => debug!("ignoring unknown query parameter {name}"),
+            }
+        }
+        Ok(request)
+    }
+}
 
-                            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
-                                match v {
-                                    "list-type" => Ok(Field::ListType),
-                                    "bucket" => Ok(Field::Bucket),
-                                    "delimiter" => Ok(Field::Delimiter),
-                                    "encoding-type" => Ok(Field::EncodingType),
-                                    "max-keys" => Ok(Field::MaxKeys),
-                                    "prefix" => Ok(Field::Prefix),
-                                    "continuation-token" => Ok(Field::ContinuationToken),
-                                    "fetch-owner" => Ok(Field::FetchOwner),
-                                    "start-after" => Ok(Field::StartAfter),
-                                    "request-payer" => Ok(Field::RequestPayer),
-                                    "expected-bucket-owner" => Ok(Field::ExpectedBucketOwner),
-                                    _ => Err(de::Error::unknown_field(
-                                        v,
-                                        &[
-                                            "list-type",
-                                            "bucket",
-                                            "delimiter",
-                                            "encoding-type",
-                                            "max-keys",
-                                            "prefix",
-                                            "continuation-token",
-                                            "fetch-owner",
-                                            "start-after",
-                                            "request-payer",
-                                            "expected-bucket-owner",
-                                        ],
-                                    )),
-                                }
-                            }
-                        }
+/// A request for the MPEG-DASH manifest of a media object, a kayo extension.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DashRequest {
+    /// The number of the audio track to keep, or `None` for the default one.
+    pub audio: Option<u64>,
+}
 
-                        deserializer.deserialize_identifier(Visitor)
+impl TryFrom<Vec<(String, String)>> for DashRequest {
+    type Error = Error;
+
+    fn try_from(params: Vec<(String, String)>) -> Result<Self, Self::Error> {
+        let mut request = Self::default();
+        for (name, value) in params {
+            match name.as_str() {
+                "kayo-dash" => {
+                    if !matches!(value.as_str(), "" | "mpd") {
+                        return Err(invalid_argument("Invalid value for kayo-dash", name, value));
                     }
                 }
+                "kayo-audio" => {
+                    let audio = value.parse::<u64>().map_err(|_| {
+                        invalid_argument("Invalid value for kayo-audio", name, value)
+                    })?;
+                    request.audio = Some(audio);
+                }
+                _ => debug!("ignoring unknown query parameter {name}"),
+            }
+        }
+        Ok(request)
+    }
+}
+
+/// The largest delay of subtitles, in seconds, which is a day either way.
+const MAX_SUBTITLE_OFFSET: f64 = 86400.0;
+
+/// A request for a subtitle object converted to WebVTT, a kayo extension.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ConvertRequest {
+    /// The delay to add to the cues, in seconds, up to [`MAX_SUBTITLE_OFFSET`] either way.
+    pub offset: f64,
+}
 
-                let mut builder = ListObjectsV2Input::builder();
-
-                while let Some(field) = map.next_key()? {
-                    match field {
-                        Field::ListType => {
-                            map.next_value::<de::IgnoredAny>()?;
-                        }
-                        Field::Bucket => builder = builder.bucket(map.next_value::<String>()?),
-                        Field::Delimiter => {
-                            builder = builder.delimiter(map.next_value::<String>()?)
-                        }
-                        Field::EncodingType => {
-                            builder = builder.encoding_type(map.next_value::<EnumHelper<_>>()?.0)
-                        }
-                        Field::MaxKeys => builder = builder.max_keys(map.next_value()?),
-                        Field::Prefix => builder = builder.prefix(map.next_value::<String>()?),
-                        Field::ContinuationToken => {
-                            builder = builder.continuation_token(map.next_value::<String>()?)
-                        }
-                        Field::FetchOwner => builder = builder.fetch_owner(map.next_value()?),
-                        Field::StartAfter => {
-                            builder = builder.start_after(map.next_value::<String>()?)
-                        }
-                        Field::RequestPayer => {
-                            builder = builder.request_payer(map.next_value::<EnumHelper<_>>()?.0)
-                        }
-                        Field::ExpectedBucketOwner => {
-                            builder = builder.expected_bucket_owner(map.next_value::<String>()?)
-                        }
+impl TryFrom<Vec<(String, String)>> for ConvertRequest {
+    type Error = Error;
+
+    fn try_from(params: Vec<(String, String)>) -> Result<Self, Self::Error> {
+        let mut request = Self::default();
+        for (name, value) in params {
+            match name.as_str() {
+                "kayo-format" => {
+                    if value != "vtt" {
+                        return Err(invalid_argument(
+                            "Invalid subtitle format specified in Request",
+                            name,
+                            value,
+                        ));
                     }
                 }
-
-                builder
-                    .build()
-                    .map(ListBucketRequest)
-                    .map_err(de::Error::custom)
+                "kayo-offset" => {
+                    let Some(offset) = value.parse::<f64>().ok().filter(|o| o.is_finite()) else {
+                        return Err(invalid_argument(
+                            "Provided kayo-offset not a number of seconds",
+                            name,
+                            value,
+                        ));
+                    };
+                    request.offset = offset.clamp(-MAX_SUBTITLE_OFFSET, MAX_SUBTITLE_OFFSET);
+                }
+                _ => debug!("ignoring unknown query parameter {name}"),
             }
         }
+        Ok(request)
+    }
+}
+
+/// Parses the `Range` header of a request for an object of `len` bytes tagged `etag`. Headers
+/// that do not select a single range of bytes are ignored, so that the whole object is sent, as
+/// are ranges of another version of the object than the one named by `If-Range`.
+pub fn byte_range(headers: &HeaderMap, len: u64, etag: &str) -> Result<Option<Range<u64>>, Error> {
+    // Dates are never matched, since objects are tagged rather than dated.
+    if headers
+        .get(IF_RANGE)
+        .is_some_and(|tag| tag.as_bytes() != etag.as_bytes())
+    {
+        return Ok(None);
+    }
+    let Some(spec) = headers
+        .get(RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("bytes="))
+    else {
+        return Ok(None);
+    };
+    let Some((first, last)) = spec.split_once('-').filter(|_| !spec.contains(',')) else {
+        return Ok(None);
+    };
+
+    let range = match (first.trim(), last.trim()) {
+        ("", suffix) => suffix
+            .parse::<u64>()
+            .ok()
+            .map(|n| len.saturating_sub(n)..len),
+        (first, "") => first.parse::<u64>().ok().map(|first| first..len),
+        (first, last) => match (first.parse::<u64>(), last.parse::<u64>()) {
+            (Ok(first), Ok(last)) if first <= last => Some(first..len.min(last.saturating_add(1))),
+            _ => None,
+        },
+    };
+    match range {
+        Some(range) if range.is_empty() => Err(Error::from(ErrorCode::InvalidRange)),
+        range => Ok(range),
+    }
+}
+
+/// Returns whether the `If-None-Match` header of a request names `etag`, so that the client
+/// already has the object.
+pub fn not_modified(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ETAG: &str = "\"1-64\"";
+
+    fn list(params: &[(&str, &str)]) -> Result<ListBucketRequest, Error> {
+        let params = params
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Vec<_>>();
+        ListBucketRequest::try_from(params)
+    }
+
+    #[test]
+    fn list_requests() {
+        let request = list(&[
+            ("list-type", "2"),
+            ("prefix", "media/"),
+            ("max-keys", "10"),
+            ("start-after", "media/a"),
+            ("x-id", "ListObjectsV2"),
+        ])
+        .unwrap();
+        let ListBucketRequest::V2(input) = &request else {
+            panic!("expected a V2 request: {request:?}");
+        };
+        assert_eq!(input.max_keys(), Some(10));
+        assert_eq!(input.start_after(), Some("media/a"));
+        assert_eq!(request.prefix(), Some("media/"));
+
+        // Without list-type, the V1 parameters are taken along with the common ones.
+        let request = list(&[("marker", "b"), ("max-keys", "0"), ("delimiter", "/")]).unwrap();
+        let ListBucketRequest::V1(input) = &request else {
+            panic!("expected a V1 request: {request:?}");
+        };
+        assert_eq!(input.marker(), Some("b"));
+        assert_eq!(input.max_keys(), Some(0));
+        assert_eq!(input.delimiter(), Some("/"));
+    }
+
+    #[test]
+    fn invalid_list_arguments() {
+        for (name, value) in [
+            ("list-type", "1"),
+            ("list-type", ""),
+            ("max-keys", "-1"),
+            ("max-keys", "ten"),
+            ("max-keys", "2147483648"),
+            ("encoding-type", "base64"),
+            ("fetch-owner", "yes"),
+            ("request-payer", "owner"),
+        ] {
+            let e = list(&[("prefix", "a"), (name, value)]).unwrap_err();
+            assert_eq!(e.code(), ErrorCode::InvalidArgument, "{name}={value}");
+        }
+    }
+
+    fn range(headers: &[(&'static str, &'static str)]) -> Result<Option<Range<u64>>, Error> {
+        let headers = headers
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), value.parse().unwrap()))
+            .collect::<HeaderMap>();
+        byte_range(&headers, 100, ETAG)
+    }
+
+    #[test]
+    fn byte_ranges() {
+        assert_eq!(range(&[]).unwrap(), None);
+        assert_eq!(range(&[("range", "bytes=0-9")]).unwrap(), Some(0..10));
+        assert_eq!(range(&[("range", "bytes=10-")]).unwrap(), Some(10..100));
+        assert_eq!(range(&[("range", "bytes=-10")]).unwrap(), Some(90..100));
+        assert_eq!(range(&[("range", "bytes=-1000")]).unwrap(), Some(0..100));
+        assert_eq!(range(&[("range", "bytes=90-200")]).unwrap(), Some(90..100));
+        assert_eq!(
+            range(&[("range", "bytes=0-18446744073709551615")]).unwrap(),
+            Some(0..100)
+        );
+
+        assert!(range(&[("range", "bytes=100-")]).is_err());
+        assert!(range(&[("range", "bytes=-0")]).is_err());
+
+        assert_eq!(range(&[("range", "bytes=5-2")]).unwrap(), None);
+        assert_eq!(range(&[("range", "bytes=0-1,5-6")]).unwrap(), None);
+        assert_eq!(range(&[("range", "items=0-1")]).unwrap(), None);
+        assert_eq!(range(&[("range", "bytes=a-b")]).unwrap(), None);
+    }
+
+    #[test]
+    fn if_range() {
+        let headers = [("range", "bytes=0-9"), ("if-range", ETAG)];
+        assert_eq!(range(&headers).unwrap(), Some(0..10));
+        let headers = [("range", "bytes=0-9"), ("if-range", "\"2-64\"")];
+        assert_eq!(range(&headers).unwrap(), None);
+        let headers = [
+            ("range", "bytes=0-9"),
+            ("if-range", "Wed, 21 Oct 2015 07:28:00 GMT"),
+        ];
+        assert_eq!(range(&headers).unwrap(), None);
+    }
 
-        deserializer.deserialize_map(Visitor)
+    #[test]
+    fn if_none_match() {
+        let headers = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(IF_NONE_MATCH, value.parse().unwrap());
+            headers
+        };
+        assert!(!not_modified(&HeaderMap::new(), ETAG));
+        assert!(not_modified(&headers(ETAG), ETAG));
+        assert!(not_modified(&headers("W/\"1-64\""), ETAG));
+        assert!(not_modified(&headers("\"0-1\", \"1-64\""), ETAG));
+        assert!(not_modified(&headers("*"), ETAG));
+        assert!(!not_modified(&headers("\"2-64\""), ETAG));
     }
 }
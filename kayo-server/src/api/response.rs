@@ -1,69 +1,481 @@
+use aws_sdk_s3::operation::list_objects::ListObjectsOutput;
 use aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Output;
-use aws_smithy_types::date_time::Format;
-use axum::http::header::CONTENT_TYPE;
-use axum::http::StatusCode;
+use aws_sdk_s3::types::Object;
+use aws_smithy_types::date_time::Format as DateTimeFormat;
+use axum::body::Body;
+use axum::http::header::{ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG};
+use axum::http::{HeaderName, HeaderValue, StatusCode};
 use axum::response::{IntoResponse, Response};
-use serde::ser::{self, Serialize, SerializeStruct, Serializer};
-
+use bytes::{Bytes, BytesMut};
+use futures::stream;
+use serde::ser::{self, Serialize, SerializeSeq, SerializeStruct, Serializer};
+use std::cell::Cell;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use super::request::{Format, HlsPart};
+use super::XMLNS;
+use crate::media::cast::Verdict;
+use crate::media::dash::Mpd;
+use crate::media::faststart::Layout;
+use crate::media::hls::Package;
+use crate::media::probe::MediaInfo;
+use crate::media::remux::Remuxer;
+use crate::media::subtitle::Subtitle;
 use crate::ser_xml;
 
-#[repr(transparent)]
-pub struct ListBucketResult(pub ListObjectsV2Output);
+/// Serializes the wrapped value as an XML response body.
+pub struct Xml<T>(pub T);
+
+/// Serializes the wrapped value as an XML response body while it is being sent, so that large
+/// documents are neither buffered whole nor delayed until they are complete.
+pub struct XmlStream<T>(pub T);
 
-impl From<ListObjectsV2Output> for ListBucketResult {
-    fn from(inner: ListObjectsV2Output) -> Self {
-        Self(inner)
+impl<T: Serialize + Send + 'static> IntoResponse for XmlStream<T> {
+    fn into_response(self) -> Response {
+        stream_response("application/xml", move |writer| {
+            ser_xml::to_writer(writer, &self.0).map_err(io::Error::other)
+        })
     }
 }
 
-impl Serialize for ListBucketResult {
+/// Serializes the wrapped value as a JSON response body while it is being sent, like
+/// [`XmlStream`].
+pub struct JsonStream<T>(pub T);
+
+impl<T: Serialize + Send + 'static> IntoResponse for JsonStream<T> {
+    fn into_response(self) -> Response {
+        stream_response("application/json", move |writer| {
+            serde_json::to_writer(&mut *writer, &self.0)?;
+            writer.flush()
+        })
+    }
+}
+
+/// The header giving the time in the original object that a remuxed stream starts from.
+const KAYO_START: HeaderName = HeaderName::from_static("x-kayo-start");
+
+/// Streams a media object remuxed to fragmented MP4.
+pub struct Remux {
+    pub remuxer: Remuxer,
+    /// Whether to only send the headers, for HEAD requests.
+    pub head: bool,
+}
+
+impl IntoResponse for Remux {
+    fn into_response(self) -> Response {
+        let start = HeaderValue::from_str(&format!("{:.3}", self.remuxer.start())).unwrap();
+        let mut response = if self.head {
+            (StatusCode::OK, [(CONTENT_TYPE, "video/mp4")]).into_response()
+        } else {
+            stream_response("video/mp4", move |writer| self.remuxer.write_to(writer))
+        };
+        response.headers_mut().insert(KAYO_START, start);
+        response
+    }
+}
+
+/// The type of HTTP Live Streaming playlists.
+const MPEGURL: &str = "application/vnd.apple.mpegurl";
+
+/// Serves a part of a media object packaged for HTTP Live Streaming.
+pub struct Hls {
+    pub package: Arc<Package>,
+    /// The path of the file holding the object.
+    pub path: PathBuf,
+    /// The name of the object, that the URIs in playlists are relative to.
+    pub name: String,
+    pub part: HlsPart,
+    /// Whether to only send the headers, for HEAD requests.
+    pub head: bool,
+}
+
+impl IntoResponse for Hls {
+    fn into_response(self) -> Response {
+        let etag = HeaderValue::from_str(&self.package.etag).unwrap();
+        let (content_type, body) = match self.part {
+            HlsPart::Master => (MPEGURL, self.package.master_playlist(&self.name).into()),
+            HlsPart::Media => (MPEGURL, self.package.media_playlist(&self.name).into()),
+            HlsPart::Init => ("video/mp4", Bytes::from(self.package.init.clone())),
+            HlsPart::Segment(_) if self.head => ("video/mp4", Bytes::new()),
+            HlsPart::Segment(index) => {
+                let (package, path) = (self.package, self.path);
+                let mut response = stream_response("video/mp4", move |writer| {
+                    package.write_segment(&path, index, writer)
+                });
+                response.headers_mut().insert(ETAG, etag);
+                return response;
+            }
+        };
+        let mut response = if self.head {
+            (StatusCode::OK, [(CONTENT_TYPE, content_type)]).into_response()
+        } else {
+            (StatusCode::OK, [(CONTENT_TYPE, content_type)], body).into_response()
+        };
+        response.headers_mut().insert(ETAG, etag);
+        response
+    }
+}
+
+/// Serves the MPEG-DASH manifest of a media object.
+pub struct Dash {
+    pub mpd: Mpd,
+    pub etag: String,
+    /// Whether to only send the headers, for HEAD requests.
+    pub head: bool,
+}
+
+impl IntoResponse for Dash {
+    fn into_response(self) -> Response {
+        let body = match ser_xml::to_bytes(&self.mpd) {
+            Ok(body) => body,
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        };
+        let headers = [
+            (
+                CONTENT_TYPE,
+                HeaderValue::from_static("application/dash+xml"),
+            ),
+            (ETAG, HeaderValue::from_str(&self.etag).unwrap()),
+        ];
+        if self.head {
+            (StatusCode::OK, headers).into_response()
+        } else {
+            (StatusCode::OK, headers, body).into_response()
+        }
+    }
+}
+
+/// Serves a subtitle object converted to WebVTT.
+pub struct WebVtt {
+    pub text: String,
+    /// Whether to only send the headers, for HEAD requests.
+    pub head: bool,
+}
+
+impl IntoResponse for WebVtt {
+    fn into_response(self) -> Response {
+        let headers = [
+            (CONTENT_TYPE, HeaderValue::from_static("text/vtt")),
+            (CONTENT_LENGTH, HeaderValue::from(self.text.len())),
+        ];
+        if self.head {
+            (StatusCode::OK, headers).into_response()
+        } else {
+            (StatusCode::OK, headers, self.text).into_response()
+        }
+    }
+}
+
+/// Serves an MP4 object with its `moov` box relocated, or a range of it.
+pub struct Faststart {
+    pub layout: Arc<Layout>,
+    /// The path of the file holding the object.
+    pub path: PathBuf,
+    pub content_type: &'static str,
+    /// The requested range, or `None` for the whole object.
+    pub range: Option<Range<u64>>,
+    /// Whether to only send the headers, for HEAD requests.
+    pub head: bool,
+}
+
+impl IntoResponse for Faststart {
+    fn into_response(self) -> Response {
+        let len = self.layout.len;
+        let (status, range) = match self.range {
+            Some(range) => (StatusCode::PARTIAL_CONTENT, range),
+            None => (StatusCode::OK, 0..len),
+        };
+        let content_range = format!("bytes {}-{}/{len}", range.start, range.end - 1);
+        let etag = HeaderValue::from_str(&self.layout.etag).unwrap();
+        let content_length = range.end - range.start;
+
+        let mut response = if self.head {
+            [(CONTENT_TYPE, self.content_type)].into_response()
+        } else {
+            let (layout, path) = (self.layout, self.path);
+            stream_response(self.content_type, move |writer| {
+                layout.write_range(&path, range, writer)
+            })
+        };
+        *response.status_mut() = status;
+        let headers = response.headers_mut();
+        headers.insert(CONTENT_LENGTH, content_length.into());
+        headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        headers.insert(ETAG, etag);
+        if status == StatusCode::PARTIAL_CONTENT {
+            headers.insert(
+                CONTENT_RANGE,
+                HeaderValue::from_str(&content_range).unwrap(),
+            );
+        }
+        response
+    }
+}
+
+/// Responds with the body written by `write` on a blocking thread.
+fn stream_response<F>(content_type: &'static str, write: F) -> Response
+where
+    F: FnOnce(&mut ChunkWriter) -> io::Result<()> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel(1);
+    tokio::task::spawn_blocking(move || {
+        let mut writer = ChunkWriter {
+            buffer: BytesMut::new(),
+            sender,
+        };
+        if let Err(e) = write(&mut writer) {
+            warn!("aborting response: {e}");
+            let _ = writer.sender.blocking_send(Err(e));
+        }
+    });
+
+    let body = stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+    (
+        StatusCode::OK,
+        [(CONTENT_TYPE, content_type)],
+        Body::from_stream(body),
+    )
+        .into_response()
+}
+
+/// The namespace of the `xsi:type` attribute of grantees.
+const XSI: &str = "http://www.w3.org/2001/XMLSchema-instance";
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Sends what is written to it as chunks of at least [`CHUNK_SIZE`] bytes.
+struct ChunkWriter {
+    buffer: BytesMut,
+    sender: mpsc::Sender<io::Result<Bytes>>,
+}
+
+impl io::Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if CHUNK_SIZE <= self.buffer.len() {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let chunk = self.buffer.split().freeze();
+        self.sender
+            .blocking_send(Ok(chunk))
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+}
+
+impl<T: Serialize> IntoResponse for Xml<T> {
+    fn into_response(self) -> Response {
+        match ser_xml::to_bytes(&self.0) {
+            Ok(body) => (StatusCode::OK, [(CONTENT_TYPE, "application/xml")], body).into_response(),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        }
+    }
+}
+
+/// Serializes the wrapped value as a JSON response body.
+pub struct Json<T>(pub T);
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        match serde_json::to_vec(&self.0) {
+            Ok(body) => {
+                (StatusCode::OK, [(CONTENT_TYPE, "application/json")], body).into_response()
+            }
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        }
+    }
+}
+
+/// The media information of an object, along with whether Cast devices play it and the
+/// subtitle files accompanying it.
+#[derive(serde::Serialize)]
+pub struct Probe<'a> {
+    #[serde(flatten)]
+    pub info: &'a MediaInfo,
+    #[serde(rename = "Cast")]
+    pub cast: Vec<Verdict>,
+    #[serde(rename = "Subtitles")]
+    pub subtitles: Vec<Subtitle>,
+}
+
+/// An object of a listing, along with what kayo tells about it in addition to S3.
+pub struct Entry {
+    pub object: Object,
+    pub content_type: Option<&'static str>,
+    pub cast: Vec<Verdict>,
+    /// Why the object could not be probed for the Cast verdicts.
+    pub probe_error: Option<String>,
+    pub subtitles: Vec<Subtitle>,
+}
+
+/// Objects that are produced while a listing is serialized, so that it can be streamed.
+pub struct Objects(Mutex<Option<Box<dyn Iterator<Item = io::Result<Entry>> + Send>>>);
+
+impl Objects {
+    pub fn new(objects: impl Iterator<Item = io::Result<Entry>> + Send + 'static) -> Self {
+        Self(Mutex::new(Some(Box::new(objects))))
+    }
+
+    /// Serializes the objects as `Contents` elements, or as a `Contents` array in JSON, and
+    /// returns how many were serialized.
+    fn serialize_into<S: SerializeStruct>(
+        &self,
+        s: &mut S,
+        format: Format,
+    ) -> Result<usize, S::Error> {
+        let count = Cell::new(0);
+        match format {
+            Format::Xml => {
+                for object in self.take() {
+                    let object = object.map_err(ser::Error::custom)?;
+                    s.serialize_field("", &Contents(&object, format))?;
+                    count.set(count.get() + 1);
+                }
+            }
+            Format::Json => s.serialize_field(
+                "Contents",
+                &ContentsList {
+                    objects: self,
+                    count: &count,
+                },
+            )?,
+        }
+
+        Ok(count.get())
+    }
+
+    fn take(&self) -> impl Iterator<Item = io::Result<Entry>> {
+        self.0.lock().unwrap().take().into_iter().flatten()
+    }
+}
+
+struct ContentsList<'a> {
+    objects: &'a Objects,
+    count: &'a Cell<usize>,
+}
+
+impl Serialize for ContentsList<'_> {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        let mut s = serializer.serialize_struct("ListBucketResult", 0)?;
+        let mut s = serializer.serialize_seq(None)?;
+        for object in self.objects.take() {
+            let object = object.map_err(ser::Error::custom)?;
+            s.serialize_element(&Contents(&object, Format::Json))?;
+            self.count.set(self.count.get() + 1);
+        }
+
+        s.end()
+    }
+}
+
+/// Counts the fields of a struct that are present, to pass to `serialize_struct`, since JSON
+/// serializers write an empty object when told a struct has none. The objects of a listing count
+/// as the single `Contents` field they are in JSON, as the XML serializer ignores the count.
+fn field_count(present: &[bool]) -> usize {
+    present.iter().filter(|present| **present).count()
+}
+
+/// Returns the field name to serialize a child struct under. In XML the struct names its own
+/// element, while in JSON it needs a key.
+fn child(format: Format, name: &'static str) -> &'static str {
+    match format {
+        Format::Xml => "",
+        Format::Json => name,
+    }
+}
+
+pub struct ListBucketResult {
+    pub output: ListObjectsV2Output,
+    pub objects: Objects,
+    pub format: Format,
+}
 
-        s.serialize_field("IsTruncated", &self.0.is_truncated())?;
+impl IntoResponse for ListBucketResult {
+    fn into_response(self) -> Response {
+        match self.format {
+            Format::Xml => XmlStream(self).into_response(),
+            Format::Json => JsonStream(self).into_response(),
+        }
+    }
+}
 
-        if let Some(contents) = &self.0.contents {
-            let contents = contents.iter().map(Contents).collect::<Vec<_>>();
-            s.serialize_field("", &contents)?;
+impl Serialize for ListBucketResult {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let output = &self.output;
+        let len = field_count(&[
+            self.format == Format::Xml,
+            true, // IsTruncated
+            true, // Contents
+            output.name().is_some(),
+            output.prefix().is_some(),
+            output.delimiter().is_some(),
+            true, // MaxKeys
+            true, // CommonPrefixes
+            output.encoding_type().is_some(),
+            true, // KeyCount
+            output.continuation_token().is_some(),
+            output.next_continuation_token().is_some(),
+            output.start_after().is_some(),
+        ]);
+        let mut s = serializer.serialize_struct("ListBucketResult", len)?;
+        if self.format == Format::Xml {
+            s.serialize_field("xmlns", XMLNS)?;
         }
 
-        if let Some(name) = self.0.name() {
+        s.serialize_field("IsTruncated", &self.output.is_truncated())?;
+
+        let count = self.objects.serialize_into(&mut s, self.format)?;
+
+        if let Some(name) = self.output.name() {
             s.serialize_field("Name", name)?;
         }
 
-        if let Some(prefix) = self.0.prefix() {
+        if let Some(prefix) = self.output.prefix() {
             s.serialize_field("Prefix", prefix)?;
         }
 
-        if let Some(delimiter) = self.0.delimiter() {
+        if let Some(delimiter) = self.output.delimiter() {
             s.serialize_field("Delimiter", delimiter)?;
         }
 
-        s.serialize_field("MaxKeys", &self.0.max_keys())?;
+        s.serialize_field("MaxKeys", &self.output.max_keys())?;
 
-        if let Some(common_prefixes) = &self.0.common_prefixes {
-            let common_prefixes = common_prefixes
-                .iter()
-                .map(CommonPrefixes)
-                .collect::<Vec<_>>();
-            s.serialize_field("", &common_prefixes)?;
-        }
+        let common_prefixes = self
+            .output
+            .common_prefixes()
+            .iter()
+            .map(CommonPrefixes)
+            .collect::<Vec<_>>();
+        s.serialize_field(child(self.format, "CommonPrefixes"), &common_prefixes)?;
 
-        if let Some(encoding_type) = self.0.encoding_type() {
+        if let Some(encoding_type) = self.output.encoding_type() {
             s.serialize_field("EncodingType", encoding_type.as_str())?;
         }
 
-        s.serialize_field("KeyCount", &self.0.key_count())?;
+        s.serialize_field("KeyCount", &(count + common_prefixes.len()))?;
 
-        if let Some(continuation_token) = self.0.continuation_token() {
+        if let Some(continuation_token) = self.output.continuation_token() {
             s.serialize_field("ContinuationToken", continuation_token)?;
         }
 
-        if let Some(next_continuation_token) = self.0.next_continuation_token() {
+        if let Some(next_continuation_token) = self.output.next_continuation_token() {
             s.serialize_field("NextContinuationToken", next_continuation_token)?;
         }
 
-        if let Some(start_after) = self.0.start_after() {
+        if let Some(start_after) = self.output.start_after() {
             s.serialize_field("StartAfter", start_after)?;
         }
 
@@ -71,51 +483,152 @@ impl Serialize for ListBucketResult {
     }
 }
 
-impl IntoResponse for ListBucketResult {
+pub struct ListBucketResultV1 {
+    pub output: ListObjectsOutput,
+    pub objects: Objects,
+    pub format: Format,
+}
+
+impl IntoResponse for ListBucketResultV1 {
     fn into_response(self) -> Response {
-        match ser_xml::to_bytes(&self) {
-            Ok(body) => (StatusCode::OK, [(CONTENT_TYPE, "application/xml")], body).into_response(),
-            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        match self.format {
+            Format::Xml => XmlStream(self).into_response(),
+            Format::Json => JsonStream(self).into_response(),
         }
     }
 }
 
-#[repr(transparent)]
-struct Contents<'a>(&'a aws_sdk_s3::types::Object);
+impl Serialize for ListBucketResultV1 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let output = &self.output;
+        let len = field_count(&[
+            self.format == Format::Xml,
+            true, // IsTruncated
+            output.marker().is_some(),
+            output.next_marker().is_some(),
+            true, // Contents
+            output.name().is_some(),
+            output.prefix().is_some(),
+            output.delimiter().is_some(),
+            true, // MaxKeys
+            true, // CommonPrefixes
+            output.encoding_type().is_some(),
+        ]);
+        let mut s = serializer.serialize_struct("ListBucketResult", len)?;
+        if self.format == Format::Xml {
+            s.serialize_field("xmlns", XMLNS)?;
+        }
+
+        s.serialize_field("IsTruncated", &self.output.is_truncated())?;
+
+        if let Some(marker) = self.output.marker() {
+            s.serialize_field("Marker", marker)?;
+        }
+
+        if let Some(next_marker) = self.output.next_marker() {
+            s.serialize_field("NextMarker", next_marker)?;
+        }
+
+        self.objects.serialize_into(&mut s, self.format)?;
+
+        if let Some(name) = self.output.name() {
+            s.serialize_field("Name", name)?;
+        }
+
+        if let Some(prefix) = self.output.prefix() {
+            s.serialize_field("Prefix", prefix)?;
+        }
+
+        if let Some(delimiter) = self.output.delimiter() {
+            s.serialize_field("Delimiter", delimiter)?;
+        }
+
+        s.serialize_field("MaxKeys", &self.output.max_keys())?;
+
+        let common_prefixes = self
+            .output
+            .common_prefixes()
+            .iter()
+            .map(CommonPrefixes)
+            .collect::<Vec<_>>();
+        s.serialize_field(child(self.format, "CommonPrefixes"), &common_prefixes)?;
+
+        if let Some(encoding_type) = self.output.encoding_type() {
+            s.serialize_field("EncodingType", encoding_type.as_str())?;
+        }
+
+        s.end()
+    }
+}
+
+struct Contents<'a>(&'a Entry, Format);
 
 impl Serialize for Contents<'_> {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        let mut s = serializer.serialize_struct("Contents", 0)?;
-
-        if let Some(key) = self.0.key() {
+        let Self(entry, format) = self;
+        let object = &entry.object;
+        let len = field_count(&[
+            object.key().is_some(),
+            object.last_modified().is_some(),
+            object.e_tag().is_some(),
+            object.checksum_algorithm.is_some(),
+            true, // Size
+            object.storage_class().is_some(),
+            object.owner().is_some(),
+            entry.content_type.is_some(),
+            !entry.cast.is_empty(),
+            entry.probe_error.is_some(),
+            !entry.subtitles.is_empty(),
+        ]);
+        let mut s = serializer.serialize_struct("Contents", len)?;
+
+        if let Some(key) = object.key() {
             s.serialize_field("Key", key)?;
         }
 
-        if let Some(last_modified) = self.0.last_modified() {
+        if let Some(last_modified) = object.last_modified() {
             let last_modified = last_modified
-                .fmt(Format::DateTime)
+                .fmt(DateTimeFormat::DateTime)
                 .map_err(ser::Error::custom)?;
             s.serialize_field("LastModified", &last_modified)?;
         }
 
-        if let Some(e_tag) = self.0.e_tag() {
+        if let Some(e_tag) = object.e_tag() {
             s.serialize_field("ETag", e_tag)?;
         }
 
-        if let Some(checksum_algorithms) = &self.0.checksum_algorithm {
-            checksum_algorithms
+        if let Some(checksum_algorithms) = &object.checksum_algorithm {
+            let checksum_algorithms = checksum_algorithms
                 .iter()
-                .try_for_each(|a| s.serialize_field("ChecksumAlgorithm", a.as_str()))?;
+                .map(|a| a.as_str())
+                .collect::<Vec<_>>();
+            s.serialize_field("ChecksumAlgorithm", &checksum_algorithms)?;
         }
 
-        s.serialize_field("Size", &self.0.size())?;
+        s.serialize_field("Size", &object.size())?;
 
-        if let Some(storage_class) = self.0.storage_class() {
+        if let Some(storage_class) = object.storage_class() {
             s.serialize_field("StorageClass", storage_class.as_str())?;
         }
 
-        if let Some(owner) = self.0.owner() {
-            s.serialize_field("", &Owner(owner))?;
+        if let Some(owner) = object.owner() {
+            s.serialize_field(child(*format, "Owner"), &Owner(owner))?;
+        }
+
+        if let Some(content_type) = entry.content_type {
+            s.serialize_field("ContentType", content_type)?;
+        }
+
+        if !entry.cast.is_empty() {
+            s.serialize_field(child(*format, "Cast"), &entry.cast)?;
+        }
+
+        if let Some(probe_error) = &entry.probe_error {
+            s.serialize_field("ProbeError", probe_error)?;
+        }
+
+        if !entry.subtitles.is_empty() {
+            s.serialize_field(child(*format, "Subtitles"), &entry.subtitles)?;
         }
 
         s.end()
@@ -127,7 +640,8 @@ struct CommonPrefixes<'a>(&'a aws_sdk_s3::types::CommonPrefix);
 
 impl Serialize for CommonPrefixes<'_> {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        let mut s = serializer.serialize_struct("CommonPrefixes", 0)?;
+        let len = field_count(&[self.0.prefix().is_some()]);
+        let mut s = serializer.serialize_struct("CommonPrefixes", len)?;
 
         if let Some(prefix) = self.0.prefix() {
             s.serialize_field("Prefix", prefix)?;
@@ -142,16 +656,323 @@ struct Owner<'a>(&'a aws_sdk_s3::types::Owner);
 
 impl Serialize for Owner<'_> {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        let mut s = serializer.serialize_struct("Owner", 0)?;
+        let len = field_count(&[self.0.display_name().is_some(), self.0.id().is_some()]);
+        let mut s = serializer.serialize_struct("Owner", len)?;
+
+        if let Some(display_name) = self.0.display_name() {
+            s.serialize_field("DisplayName", display_name)?;
+        }
+
+        if let Some(id) = self.0.id() {
+            s.serialize_field("ID", id)?;
+        }
+
+        s.end()
+    }
+}
+
+#[repr(transparent)]
+pub struct VersioningConfiguration(
+    pub aws_sdk_s3::operation::get_bucket_versioning::GetBucketVersioningOutput,
+);
+
+impl Serialize for VersioningConfiguration {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("VersioningConfiguration", 0)?;
+        s.serialize_field("xmlns", XMLNS)?;
+
+        if let Some(status) = self.0.status() {
+            s.serialize_field("Status", status.as_str())?;
+        }
+
+        if let Some(mfa_delete) = self.0.mfa_delete() {
+            s.serialize_field("MfaDelete", mfa_delete.as_str())?;
+        }
+
+        s.end()
+    }
+}
+
+#[repr(transparent)]
+pub struct AccessControlPolicy(pub aws_sdk_s3::operation::get_bucket_acl::GetBucketAclOutput);
+
+impl Serialize for AccessControlPolicy {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("AccessControlPolicy", 0)?;
+        s.serialize_field("xmlns", XMLNS)?;
+
+        if let Some(owner) = self.0.owner() {
+            s.serialize_field("", &Owner(owner))?;
+        }
+
+        let grants = self.0.grants().iter().map(Grant).collect::<Vec<_>>();
+        s.serialize_field("AccessControlList", &grants)?;
+
+        s.end()
+    }
+}
+
+#[repr(transparent)]
+struct Grant<'a>(&'a aws_sdk_s3::types::Grant);
+
+impl Serialize for Grant<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Grant", 0)?;
+
+        if let Some(grantee) = self.0.grantee() {
+            s.serialize_field("", &Grantee(grantee))?;
+        }
+
+        if let Some(permission) = self.0.permission() {
+            s.serialize_field("Permission", permission.as_str())?;
+        }
+
+        s.end()
+    }
+}
+
+#[repr(transparent)]
+struct Grantee<'a>(&'a aws_sdk_s3::types::Grantee);
+
+impl Serialize for Grantee<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Grantee", 0)?;
+        s.serialize_field("xmlns:xsi", XSI)?;
+        s.serialize_field("@xsi:type", self.0.r#type().as_str())?;
 
         if let Some(display_name) = self.0.display_name() {
             s.serialize_field("DisplayName", display_name)?;
         }
 
+        if let Some(email_address) = self.0.email_address() {
+            s.serialize_field("EmailAddress", email_address)?;
+        }
+
         if let Some(id) = self.0.id() {
             s.serialize_field("ID", id)?;
         }
 
+        if let Some(uri) = self.0.uri() {
+            s.serialize_field("URI", uri)?;
+        }
+
+        s.end()
+    }
+}
+
+#[repr(transparent)]
+pub struct PolicyStatus(pub aws_sdk_s3::types::PolicyStatus);
+
+impl Serialize for PolicyStatus {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("PolicyStatus", 0)?;
+        s.serialize_field("xmlns", XMLNS)?;
+
+        if let Some(is_public) = self.0.is_public() {
+            s.serialize_field("IsPublic", &is_public)?;
+        }
+
+        s.end()
+    }
+}
+
+#[repr(transparent)]
+pub struct OwnershipControls(pub aws_sdk_s3::types::OwnershipControls);
+
+impl Serialize for OwnershipControls {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("OwnershipControls", 0)?;
+        s.serialize_field("xmlns", XMLNS)?;
+
+        self.0
+            .rules()
+            .iter()
+            .try_for_each(|r| s.serialize_field("", &OwnershipControlsRule(r)))?;
+
+        s.end()
+    }
+}
+
+#[repr(transparent)]
+struct OwnershipControlsRule<'a>(&'a aws_sdk_s3::types::OwnershipControlsRule);
+
+impl Serialize for OwnershipControlsRule<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Rule", 0)?;
+        s.serialize_field("ObjectOwnership", self.0.object_ownership().as_str())?;
+        s.end()
+    }
+}
+
+#[repr(transparent)]
+pub struct LocationConstraint(
+    pub aws_sdk_s3::operation::get_bucket_location::GetBucketLocationOutput,
+);
+
+impl Serialize for LocationConstraint {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("LocationConstraint", 0)?;
+        s.serialize_field("xmlns", XMLNS)?;
+
+        if let Some(location_constraint) = self.0.location_constraint() {
+            s.serialize_field("", location_constraint.as_str())?;
+        }
+
         s.end()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::cast::Playback;
+    use aws_sdk_s3::types::{CommonPrefix, ObjectStorageClass, Owner as S3Owner};
+    use aws_smithy_types::DateTime;
+    use serde_json::{json, Value};
+
+    fn entries() -> Objects {
+        let video = Entry {
+            object: Object::builder()
+                .key("media/a.mkv")
+                .last_modified(DateTime::from_secs(0))
+                .e_tag("\"1-a\"")
+                .size(10)
+                .storage_class(ObjectStorageClass::Standard)
+                .owner(S3Owner::builder().display_name("kayo").id("1").build())
+                .build(),
+            content_type: Some("video/x-matroska"),
+            cast: vec![Verdict {
+                device: "TV".to_string(),
+                playback: Playback::NeedsRemux,
+                reasons: vec!["matroska container is not supported".to_string()],
+            }],
+            probe_error: None,
+            subtitles: Vec::new(),
+        };
+        let text = Entry {
+            object: Object::builder().key("media/b.txt").size(3).build(),
+            content_type: None,
+            cast: Vec::new(),
+            probe_error: Some("invalid data".to_string()),
+            subtitles: Vec::new(),
+        };
+        Objects::new([Ok(video), Ok(text)].into_iter())
+    }
+
+    /// Serializes a listing as JSON text, which is invalid if a struct is given the wrong number
+    /// of fields, and parses it back.
+    fn json<T: Serialize>(value: &T) -> Value {
+        serde_json::from_str(&serde_json::to_string(value).unwrap()).unwrap()
+    }
+
+    fn contents() -> Value {
+        json!([
+            {
+                "Key": "media/a.mkv",
+                "LastModified": "1970-01-01T00:00:00Z",
+                "ETag": "\"1-a\"",
+                "Size": 10,
+                "StorageClass": "STANDARD",
+                "Owner": { "DisplayName": "kayo", "ID": "1" },
+                "ContentType": "video/x-matroska",
+                "Cast": [{
+                    "Device": "TV",
+                    "Verdict": "needs-remux",
+                    "Reason": ["matroska container is not supported"]
+                }]
+            },
+            { "Key": "media/b.txt", "Size": 3, "ProbeError": "invalid data" }
+        ])
+    }
+
+    #[test]
+    fn json_listing() {
+        let result = ListBucketResult {
+            output: ListObjectsV2Output::builder()
+                .is_truncated(false)
+                .name("contents")
+                .prefix("media/")
+                .delimiter("/")
+                .max_keys(1000)
+                .common_prefixes(CommonPrefix::builder().prefix("media/sub/").build())
+                .common_prefixes(CommonPrefix::builder().build())
+                .build(),
+            objects: entries(),
+            format: Format::Json,
+        };
+        assert_eq!(
+            json(&result),
+            json!({
+                "IsTruncated": false,
+                "Contents": contents(),
+                "Name": "contents",
+                "Prefix": "media/",
+                "Delimiter": "/",
+                "MaxKeys": 1000,
+                "CommonPrefixes": [{ "Prefix": "media/sub/" }, {}],
+                "KeyCount": 4
+            })
+        );
+
+        let result = ListBucketResultV1 {
+            output: ListObjectsOutput::builder()
+                .is_truncated(true)
+                .marker("media/0")
+                .next_marker("media/b.txt")
+                .max_keys(2)
+                .build(),
+            objects: entries(),
+            format: Format::Json,
+        };
+        assert_eq!(
+            json(&result),
+            json!({
+                "IsTruncated": true,
+                "Marker": "media/0",
+                "NextMarker": "media/b.txt",
+                "Contents": contents(),
+                "MaxKeys": 2,
+                "CommonPrefixes": []
+            })
+        );
+    }
+
+    #[test]
+    fn empty_json_listing() {
+        let result = ListBucketResult {
+            output: ListObjectsV2Output::builder().max_keys(1000).build(),
+            objects: Objects::new(std::iter::empty()),
+            format: Format::Json,
+        };
+        assert_eq!(
+            json(&result),
+            json!({
+                "IsTruncated": null,
+                "Contents": [],
+                "MaxKeys": 1000,
+                "CommonPrefixes": [],
+                "KeyCount": 0
+            })
+        );
+    }
+
+    #[test]
+    fn xml_listing() {
+        let result = ListBucketResult {
+            output: ListObjectsV2Output::builder()
+                .name("contents")
+                .max_keys(1000)
+                .build(),
+            objects: entries(),
+            format: Format::Xml,
+        };
+        let xml = String::from_utf8(ser_xml::to_bytes(&result).unwrap().to_vec()).unwrap();
+        // Objects are repeated Contents elements, rather than an array.
+        assert_eq!(xml.matches("<Contents>").count(), 2);
+        assert!(xml.contains(
+            "<Key>media/b.txt</Key><Size>3</Size><ProbeError>invalid data</ProbeError></Contents>"
+        ));
+        assert!(xml.contains("<Cast><Device>TV</Device><Verdict>needs-remux</Verdict>"));
+        assert!(xml.ends_with("<KeyCount>2</KeyCount></ListBucketResult>"));
+    }
+}
@@ -0,0 +1,96 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use tracing::warn;
+
+use super::error::Result;
+
+/// A bucket subresource configuration, stored as a file in the configuration directory.
+pub trait Configuration: Sized {
+    const FILE_NAME: &'static str;
+
+    fn parse(bucket: &str, text: &str) -> Result<Self>;
+}
+
+type Entry<T> = (String, Arc<T>);
+
+/// Keeps a parsed bucket configuration in memory, along with the text it was parsed from.
+#[derive(Debug)]
+pub struct Store<T> {
+    bucket: String,
+    path: PathBuf,
+    inner: Arc<RwLock<Option<Entry<T>>>>,
+}
+
+impl<T> Clone for Store<T> {
+    fn clone(&self) -> Self {
+        Self {
+            bucket: self.bucket.clone(),
+            path: self.path.clone(),
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T: Configuration> Store<T> {
+    pub fn open(bucket: &str, config_root: &Path) -> Self {
+        let path = config_root.join(bucket).join(T::FILE_NAME);
+        let inner = match fs::read_to_string(&path) {
+            Ok(text) => match T::parse(bucket, &text) {
+                Ok(value) => Some((text, Arc::new(value))),
+                Err(e) => {
+                    warn!("ignoring {}: {e:?}", path.display());
+                    None
+                }
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => {
+                warn!("failed to read {}: {e}", path.display());
+                None
+            }
+        };
+
+        Self {
+            bucket: bucket.to_string(),
+            path,
+            inner: Arc::new(RwLock::new(inner)),
+        }
+    }
+
+    pub fn get(&self) -> Option<Arc<T>> {
+        self.inner
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|(_, value)| value.clone())
+    }
+
+    pub fn text(&self) -> Option<String> {
+        self.inner
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|(text, _)| text.clone())
+    }
+
+    pub async fn put(&self, text: String) -> Result<()> {
+        let value = T::parse(&self.bucket, &text)?;
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&self.path, &text).await?;
+        *self.inner.write().unwrap() = Some((text, Arc::new(value)));
+        Ok(())
+    }
+
+    pub async fn delete(&self) -> Result<()> {
+        match tokio::fs::remove_file(&self.path).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        *self.inner.write().unwrap() = None;
+        Ok(())
+    }
+}
@@ -0,0 +1,709 @@
+use serde::de::value::StrDeserializer;
+use serde::de::{self, DeserializeOwned, Error as DeError, IntoDeserializer};
+use std::error::Error as StdError;
+use std::fmt;
+use std::str::FromStr;
+use xml::reader::{Error as XmlError, ParserConfig, XmlEvent};
+
+#[derive(Debug)]
+pub enum Error {
+    Custom(String),
+    Xml(XmlError),
+    /// The document is larger or more deeply nested than allowed by the [`Config`].
+    LimitExceeded,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Custom(message) => fmt::Display::fmt(message, f),
+            Self::Xml(source) => fmt::Display::fmt(source, f),
+            Self::LimitExceeded => f.write_str("document exceeds the configured limits"),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::Xml(source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl DeError for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self::Custom(msg.to_string())
+    }
+}
+
+impl From<XmlError> for Error {
+    fn from(source: XmlError) -> Self {
+        Self::Xml(source)
+    }
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Limits on the documents accepted by the deserializer.
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    /// The maximum size of a document in bytes.
+    pub max_size: usize,
+    /// The maximum nesting depth of elements, including the root element.
+    pub max_depth: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_size: 1024 * 1024,
+            max_depth: 32,
+        }
+    }
+}
+
+pub fn from_bytes<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    from_bytes_with_config(bytes, &Config::default())
+}
+
+pub fn from_bytes_with_config<T: DeserializeOwned>(bytes: &[u8], config: &Config) -> Result<T> {
+    if config.max_size < bytes.len() {
+        return Err(Error::LimitExceeded);
+    }
+
+    let root = Element::parse(bytes, config)?;
+    T::deserialize(Content {
+        element: &root,
+        root: true,
+    })
+}
+
+/// An element of a parsed document.
+///
+/// Documents are read into a tree before being deserialized, so that repeated elements can be
+/// gathered into a sequence even if other elements come between them.
+#[derive(Debug, Default)]
+struct Element {
+    /// The local name of the element, without any namespace prefix.
+    name: String,
+    /// The attributes of the element, named as the serializer expects them, with a `@` prefix.
+    attributes: Vec<(String, String)>,
+    children: Vec<Element>,
+    text: String,
+}
+
+impl Element {
+    fn parse(bytes: &[u8], config: &Config) -> Result<Self> {
+        let reader = ParserConfig::new()
+            .cdata_to_characters(true)
+            .allow_multiple_root_elements(false)
+            .max_data_length(config.max_size)
+            .create_reader(bytes);
+
+        let mut stack = Vec::<Self>::new();
+        for event in reader {
+            match event? {
+                XmlEvent::StartElement {
+                    name, attributes, ..
+                } => {
+                    if stack.len() == config.max_depth {
+                        return Err(Error::LimitExceeded);
+                    }
+
+                    stack.push(Self {
+                        name: name.local_name,
+                        attributes: attributes
+                            .into_iter()
+                            .map(|a| match a.name.prefix {
+                                Some(prefix) => {
+                                    (format!("@{prefix}:{}", a.name.local_name), a.value)
+                                }
+                                None => (format!("@{}", a.name.local_name), a.value),
+                            })
+                            .collect(),
+                        ..Self::default()
+                    });
+                }
+                XmlEvent::Characters(s) => {
+                    if let Some(element) = stack.last_mut() {
+                        element.text.push_str(&s);
+                    }
+                }
+                XmlEvent::EndElement { .. } => {
+                    let element = stack.pop().ok_or_else(|| Error::custom("unexpected end"))?;
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(element),
+                        None => return Ok(element),
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Err(Error::custom("missing root element"))
+    }
+
+    /// Groups the attributes and children of the element by name, in document order.
+    fn fields(&self) -> Vec<(&str, Field<'_>)> {
+        let mut fields = self
+            .attributes
+            .iter()
+            .map(|(name, value)| (name.as_str(), Field::Attribute(value)))
+            .collect::<Vec<_>>();
+
+        for child in &self.children {
+            match fields
+                .iter_mut()
+                .find(|(name, _)| *name == child.name.as_str())
+            {
+                Some((_, Field::Elements(elements))) => elements.push(child),
+                _ => fields.push((child.name.as_str(), Field::Elements(vec![child]))),
+            }
+        }
+
+        fields
+    }
+}
+
+enum Field<'a> {
+    Attribute(&'a str),
+    Elements(Vec<&'a Element>),
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+                visitor.$visit(self.parse()?)
+            }
+        )*
+    };
+}
+
+macro_rules! deserialize_text {
+    ($($method:ident,)*) => {
+        $(
+            fn $method<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+                Text(&self.element.text).$method(visitor)
+            }
+        )*
+    };
+}
+
+/// Deserializes the text of an element or the value of an attribute.
+struct Text<'a>(&'a str);
+
+impl Text<'_> {
+    fn parse<T: FromStr>(&self) -> Result<T> {
+        self.0
+            .trim()
+            .parse()
+            .map_err(|_| Error::custom(format!("invalid value: {:?}", self.0)))
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Text<'_> {
+    type Error = Error;
+
+    fn deserialize_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_str(self.0)
+    }
+
+    fn deserialize_bool<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.0.trim() {
+            "true" | "1" => visitor.visit_bool(true),
+            "false" | "0" => visitor.visit_bool(false),
+            _ => Err(Error::custom(format!("invalid boolean: {:?}", self.0))),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_bytes<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value> {
+        Err(Error::custom("unsupported data type"))
+    }
+
+    fn deserialize_byte_buf<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value> {
+        Err(Error::custom("unsupported data type"))
+    }
+
+    fn deserialize_option<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: de::Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: de::Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_enum(self.0.trim().into_deserializer())
+    }
+
+    fn deserialize_ignored_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        str string unit_struct seq tuple tuple_struct map struct identifier
+    }
+}
+
+/// Deserializes the content of an element.
+struct Content<'a> {
+    element: &'a Element,
+    /// Whether the element is the root of the document, whose name must match the type.
+    root: bool,
+}
+
+impl<'de> de::Deserializer<'de> for Content<'_> {
+    type Error = Error;
+
+    fn deserialize_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if self.element.children.is_empty() && self.element.attributes.is_empty() {
+            visitor.visit_str(&self.element.text)
+        } else {
+            self.deserialize_map(visitor)
+        }
+    }
+
+    deserialize_text! {
+        deserialize_bool,
+        deserialize_i8,
+        deserialize_i16,
+        deserialize_i32,
+        deserialize_i64,
+        deserialize_i128,
+        deserialize_u8,
+        deserialize_u16,
+        deserialize_u32,
+        deserialize_u64,
+        deserialize_u128,
+        deserialize_f32,
+        deserialize_f64,
+        deserialize_char,
+        deserialize_str,
+        deserialize_string,
+        deserialize_bytes,
+        deserialize_byte_buf,
+        deserialize_identifier,
+    }
+
+    fn deserialize_option<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: de::Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: de::Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        Group(vec![self.element]).deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple<V: de::Visitor<'de>>(self, _: usize, visitor: V) -> Result<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: de::Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: usize,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_map(Fields {
+            fields: self.element.fields().into_iter(),
+            value: None,
+        })
+    }
+
+    fn deserialize_struct<V: de::Visitor<'de>>(
+        self,
+        name: &'static str,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        if self.root && self.element.name != name {
+            return Err(Error::custom(format!(
+                "expected root element {name}, found {}",
+                self.element.name
+            )));
+        }
+
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V: de::Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        match self.element.children.as_slice() {
+            [] => Text(&self.element.text).deserialize_enum(name, variants, visitor),
+            [child] => visitor.visit_enum(Variant(child)),
+            _ => Err(Error::custom(format!(
+                "expected a single variant in {}",
+                self.element.name
+            ))),
+        }
+    }
+
+    fn deserialize_ignored_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+}
+
+/// Deserializes the elements of a struct field, which form a sequence if the field is repeated.
+struct Group<'a>(Vec<&'a Element>);
+
+impl<'a> Group<'a> {
+    fn single(self) -> Result<Content<'a>> {
+        match self.0.as_slice() {
+            [element] => Ok(Content {
+                element,
+                root: false,
+            }),
+            [element, ..] => Err(Error::custom(format!("duplicate element {}", element.name))),
+            [] => Err(Error::custom("missing element")),
+        }
+    }
+}
+
+macro_rules! deserialize_single {
+    ($($method:ident($($arg:ident: $ty:ty),*),)*) => {
+        $(
+            fn $method<V: de::Visitor<'de>>(self, $($arg: $ty,)* visitor: V) -> Result<V::Value> {
+                self.single()?.$method($($arg,)* visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Group<'_> {
+    type Error = Error;
+
+    fn deserialize_option<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_seq<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_seq(Elements(self.0.into_iter()))
+    }
+
+    fn deserialize_tuple<V: de::Visitor<'de>>(self, _: usize, visitor: V) -> Result<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: de::Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: usize,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_ignored_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    deserialize_single! {
+        deserialize_any(),
+        deserialize_bool(),
+        deserialize_i8(),
+        deserialize_i16(),
+        deserialize_i32(),
+        deserialize_i64(),
+        deserialize_i128(),
+        deserialize_u8(),
+        deserialize_u16(),
+        deserialize_u32(),
+        deserialize_u64(),
+        deserialize_u128(),
+        deserialize_f32(),
+        deserialize_f64(),
+        deserialize_char(),
+        deserialize_str(),
+        deserialize_string(),
+        deserialize_bytes(),
+        deserialize_byte_buf(),
+        deserialize_unit(),
+        deserialize_unit_struct(name: &'static str),
+        deserialize_newtype_struct(name: &'static str),
+        deserialize_map(),
+        deserialize_struct(name: &'static str, fields: &'static [&'static str]),
+        deserialize_enum(name: &'static str, variants: &'static [&'static str]),
+        deserialize_identifier(),
+    }
+}
+
+struct Elements<'a>(std::vec::IntoIter<&'a Element>);
+
+impl<'de> de::SeqAccess<'de> for Elements<'_> {
+    type Error = Error;
+
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>> {
+        self.0
+            .next()
+            .map(|element| {
+                seed.deserialize(Content {
+                    element,
+                    root: false,
+                })
+            })
+            .transpose()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+struct Fields<'a> {
+    fields: std::vec::IntoIter<(&'a str, Field<'a>)>,
+    value: Option<Field<'a>>,
+}
+
+impl<'de> de::MapAccess<'de> for Fields<'_> {
+    type Error = Error;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        let Some((name, value)) = self.fields.next() else {
+            return Ok(None);
+        };
+
+        self.value = Some(value);
+        seed.deserialize(name.into_deserializer()).map(Some)
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        match self.value.take() {
+            Some(Field::Attribute(value)) => seed.deserialize(Text(value)),
+            Some(Field::Elements(elements)) => seed.deserialize(Group(elements)),
+            None => Err(Error::custom("value requested before key")),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.fields.len())
+    }
+}
+
+/// Deserializes an enum variant written as a child element named after the variant.
+struct Variant<'a>(&'a Element);
+
+impl<'de> de::EnumAccess<'de> for Variant<'_> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self)> {
+        let name: StrDeserializer<'_, Error> = self.0.name.as_str().into_deserializer();
+        let variant = seed.deserialize(name)?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Variant<'_> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        seed.deserialize(Content {
+            element: self.0,
+            root: false,
+        })
+    }
+
+    fn tuple_variant<V: de::Visitor<'de>>(self, _: usize, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_seq(
+            Content {
+                element: self.0,
+                root: false,
+            },
+            visitor,
+        )
+    }
+
+    fn struct_variant<V: de::Visitor<'de>>(
+        self,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        de::Deserializer::deserialize_map(
+            Content {
+                element: self.0,
+                root: false,
+            },
+            visitor,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ser_xml;
+    use serde::ser::{Serialize, SerializeStruct, Serializer};
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(rename = "Delete")]
+    struct Delete {
+        #[serde(rename = "Object", default)]
+        objects: Vec<Object>,
+        #[serde(rename = "Quiet")]
+        quiet: Option<bool>,
+    }
+
+    impl Serialize for Delete {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut s = serializer.serialize_struct("Delete", 0)?;
+            s.serialize_field("xmlns", "http://s3.amazonaws.com/doc/2006-03-01/")?;
+            self.objects
+                .iter()
+                .try_for_each(|o| s.serialize_field("", o))?;
+            if let Some(quiet) = self.quiet {
+                s.serialize_field("Quiet", &quiet)?;
+            }
+            s.end()
+        }
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Object {
+        #[serde(rename = "@xsi:type")]
+        kind: String,
+        #[serde(rename = "Key")]
+        key: String,
+        #[serde(rename = "Size")]
+        size: Option<u64>,
+    }
+
+    impl Serialize for Object {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut s = serializer.serialize_struct("Object", 0)?;
+            s.serialize_field("xmlns:xsi", "http://www.w3.org/2001/XMLSchema-instance")?;
+            s.serialize_field("@xsi:type", &self.kind)?;
+            s.serialize_field("Key", &self.key)?;
+            if let Some(size) = self.size {
+                s.serialize_field("Size", &size)?;
+            }
+            s.end()
+        }
+    }
+
+    fn delete() -> Delete {
+        Delete {
+            objects: vec![
+                Object {
+                    kind: "File".to_string(),
+                    key: "a <&> b.txt".to_string(),
+                    size: Some(42),
+                },
+                Object {
+                    kind: "Directory".to_string(),
+                    key: "sub/".to_string(),
+                    size: None,
+                },
+            ],
+            quiet: Some(true),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = ser_xml::to_bytes(&delete()).unwrap();
+        assert_eq!(from_bytes::<Delete>(&bytes).unwrap(), delete());
+    }
+
+    #[test]
+    fn interleaved_elements() {
+        let text = r#"<?xml version="1.0"?>
+            <s3:Delete xmlns:s3="http://s3.amazonaws.com/doc/2006-03-01/"
+                xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance">
+                <s3:Object xsi:type="File"><Key>a &lt;&amp;&gt; b.txt</Key><Size>42</Size></s3:Object>
+                <Quiet>1</Quiet>
+                <Object xsi:type="Directory"><Key><![CDATA[sub/]]></Key></Object>
+            </s3:Delete>"#;
+        assert_eq!(from_bytes::<Delete>(text.as_bytes()).unwrap(), delete());
+    }
+
+    #[test]
+    fn root_element() {
+        let result = from_bytes::<Delete>(b"<Tagging><Quiet>true</Quiet></Tagging>");
+        assert!(matches!(result, Err(Error::Custom(_))));
+    }
+
+    #[test]
+    fn limits() {
+        let bytes = ser_xml::to_bytes(&delete()).unwrap();
+        let config = Config {
+            max_size: bytes.len() - 1,
+            ..Config::default()
+        };
+        let result = from_bytes_with_config::<Delete>(&bytes, &config);
+        assert!(matches!(result, Err(Error::LimitExceeded)));
+
+        let config = Config {
+            max_depth: 2,
+            ..Config::default()
+        };
+        let result = from_bytes_with_config::<Delete>(&bytes, &config);
+        assert!(matches!(result, Err(Error::LimitExceeded)));
+    }
+}
@@ -1,11 +1,14 @@
 mod api;
+pub(crate) mod de_xml;
+mod media;
 pub(crate) mod ser_xml;
 
-use anyhow::Result;
-use axum::routing::any_service;
+use anyhow::{Context, Result};
 use clap::Parser;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use tokio::net::TcpListener;
+use tower::ServiceBuilder;
 use tower_http::cors::CorsLayer;
 use tower_http::services::ServeDir;
 use tracing::{debug, error, info};
@@ -109,18 +112,62 @@ struct Args {
     #[arg(short, long, value_name = "PATH", default_value = ".")]
     player_root: PathBuf,
 
-    /// Enable CORS.
+    /// Specify path to the directory where bucket configurations are stored.
+    #[arg(long, value_name = "PATH", default_value = "config")]
+    config_root: PathBuf,
+
+    /// Enable CORS for buckets without a CORS configuration. Cast receivers fetch subtitles and
+    /// HLS and DASH streams from their own origin, so they need either this or a CORS rule
+    /// allowing that origin.
     #[arg(long)]
     cors: bool,
+
+    /// Require requests to be signed with the specified credentials. Objects streamed over HLS
+    /// or DASH then need a bucket policy allowing anyone to get them, since playlists and
+    /// manifests refer to their segments by unsigned URIs. Without credentials the bucket is
+    /// read-only, unless a policy in the configuration directory allows more.
+    #[arg(short = 'k', long, value_name = "ACCESS_KEY:SECRET_KEY")]
+    credentials: Vec<api::Credentials>,
+
+    /// Show the specified name as the owner of files owned by the Unix user ID in listings.
+    #[arg(short, long = "user", value_name = "UID:NAME")]
+    users: Vec<api::UserMapping>,
+
+    /// Read the Cast device profiles that media files are checked against from the specified
+    /// JSON file.
+    #[arg(long, value_name = "PATH")]
+    cast_profiles: Option<PathBuf>,
+
+    /// Persist the relocated `moov` boxes of MP4 objects requested with `kayo-faststart` in the
+    /// specified directory.
+    #[arg(long, value_name = "PATH")]
+    faststart_cache: Option<PathBuf>,
 }
 
 async fn async_main(args: Args) -> Result<()> {
-    let mut app = axum::Router::new()
-        .nest("/api", api::router(args.contents_root))
-        .nest_service("/", any_service(ServeDir::new(&args.player_root)));
-    if args.cors {
-        app = app.layer(CorsLayer::permissive());
-    }
+    let profiles = match &args.cast_profiles {
+        Some(path) => media::cast::Profiles::load(path)
+            .with_context(|| format!("failed to read {}", path.display()))?,
+        None => media::cast::Profiles::default(),
+    };
+
+    let player = ServiceBuilder::new()
+        .option_layer(args.cors.then(CorsLayer::permissive))
+        .service(ServeDir::new(&args.player_root));
+    let app = axum::Router::new()
+        .nest(
+            "/api",
+            api::router(
+                args.contents_root,
+                args.config_root,
+                args.credentials,
+                args.users,
+                args.cors,
+                profiles,
+                media::faststart::Cache::new(args.faststart_cache),
+            ),
+        )
+        .fallback_service(player);
 
     let listener = TcpListener::bind(&args.bind).await?;
     if let Ok(addr) = listener.local_addr() {
@@ -129,8 +176,11 @@ async fn async_main(args: Args) -> Result<()> {
         info!("server started");
     }
 
-    axum::serve(listener, app)
-        .with_graceful_shutdown(receive_signal())
-        .await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(receive_signal())
+    .await?;
     Ok(())
 }
@@ -0,0 +1,409 @@
+//! Converts ASS and SSA subtitles to WebVTT cues. Only italic, bold and underline text, along with
+//! the alignment and position of events, are kept from their styling.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use super::webvtt::{escape, timestamp, Cue};
+
+/// The fields of `Style` lines when a script does not give them.
+const STYLE_FORMAT: &str = "Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, \
+    OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, \
+    Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding";
+
+/// The fields of `Dialogue` lines when a script does not give them.
+const EVENT_FORMAT: &str =
+    "Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text";
+
+/// The style of text, from a `Style` line or override tags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Style {
+    bold: bool,
+    italic: bool,
+    underline: bool,
+    /// The alignment of events, as on a numeric keypad.
+    alignment: u8,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            bold: false,
+            italic: false,
+            underline: false,
+            alignment: 2,
+        }
+    }
+}
+
+/// The names of the fields of lines in a section, as given by its `Format` line.
+struct Format(Vec<String>);
+
+impl Format {
+    fn new(value: &str) -> Self {
+        Self(value.split(',').map(|f| f.trim().to_string()).collect())
+    }
+
+    /// Splits the value of a line into its fields, the last of which may contain commas.
+    fn split<'a>(&self, value: &'a str) -> Fields<'_, 'a> {
+        Fields {
+            names: &self.0,
+            values: value.splitn(self.0.len(), ',').collect(),
+        }
+    }
+}
+
+/// The fields of a line.
+struct Fields<'f, 'a> {
+    names: &'f [String],
+    values: Vec<&'a str>,
+}
+
+impl<'a> Fields<'_, 'a> {
+    fn get(&self, name: &str) -> Option<&'a str> {
+        let index = self
+            .names
+            .iter()
+            .position(|n| n.eq_ignore_ascii_case(name))?;
+        self.values.get(index).copied()
+    }
+}
+
+/// Parses the `Dialogue` events of an ASS or SSA script as cues. Events shown at the same time
+/// and place are merged into a single cue, stacked like ASS renderers do.
+pub fn parse(text: &str) -> Vec<Cue> {
+    let mut section = String::new();
+    let mut legacy = false;
+    let mut play_res = (None, None);
+    let mut style_format = Format::new(STYLE_FORMAT);
+    let mut event_format = Format::new(EVENT_FORMAT);
+    let mut styles = HashMap::new();
+    let mut cues: Vec<Cue> = Vec::new();
+
+    for line in text.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            section = line.to_ascii_lowercase();
+            // SSA numbers alignments differently from ASS.
+            legacy |= section == "[v4 styles]";
+            continue;
+        }
+        let Some((kind, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim_start();
+
+        match (section.as_str(), kind) {
+            ("[script info]", "PlayResX") => play_res.0 = value.parse::<f64>().ok(),
+            ("[script info]", "PlayResY") => play_res.1 = value.parse::<f64>().ok(),
+            ("[v4 styles]" | "[v4+ styles]", "Format") => style_format = Format::new(value),
+            ("[v4 styles]" | "[v4+ styles]", "Style") => {
+                let fields = style_format.split(value);
+                let flag = |name| {
+                    fields
+                        .get(name)
+                        .and_then(|v| v.trim().parse::<i32>().ok())
+                        .is_some_and(|v| v != 0)
+                };
+                let alignment = fields.get("Alignment").and_then(|v| v.trim().parse().ok());
+                let style = Style {
+                    bold: flag("Bold"),
+                    italic: flag("Italic"),
+                    underline: flag("Underline"),
+                    alignment: alignment
+                        .and_then(|a| if legacy { from_legacy(a) } else { Some(a) })
+                        .filter(|a| (1..=9).contains(a))
+                        .unwrap_or(2),
+                };
+                let name = fields.get("Name").unwrap_or_default().trim();
+                styles.insert(name.trim_start_matches('*').to_string(), style);
+            }
+            ("[events]", "Format") => event_format = Format::new(value),
+            ("[events]", "Dialogue") => {
+                let fields = event_format.split(value);
+                let times = fields
+                    .get("Start")
+                    .and_then(|start| timestamp(start.trim()))
+                    .zip(fields.get("End").and_then(|end| timestamp(end.trim())));
+                let Some((start, end)) = times else {
+                    continue;
+                };
+                let name = fields.get("Style").unwrap_or_default().trim();
+                let style = styles
+                    .get(name.trim_start_matches('*'))
+                    .or_else(|| styles.get("Default"))
+                    .copied()
+                    .unwrap_or_default();
+                let event = Event::render(fields.get("Text").unwrap_or_default(), style, &styles);
+                let settings = event.settings(play_res);
+                if event.text.is_empty() {
+                    continue;
+                }
+
+                let same = cues
+                    .iter_mut()
+                    .find(|cue| (cue.start, cue.end, &cue.settings) == (start, end, &settings));
+                match same {
+                    // Later events are stacked above earlier ones at the bottom, and below them
+                    // elsewhere.
+                    Some(cue) if (1..=3).contains(&event.alignment) => {
+                        cue.text = format!("{}\n{}", event.text, cue.text);
+                    }
+                    Some(cue) => {
+                        cue.text.push('\n');
+                        cue.text.push_str(&event.text);
+                    }
+                    None => cues.push(Cue {
+                        start,
+                        end,
+                        settings,
+                        text: event.text,
+                    }),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    cues.sort_by_key(|cue| cue.start);
+    cues
+}
+
+/// The text of an event in WebVTT markup, with where it is placed.
+struct Event {
+    text: String,
+    alignment: u8,
+    /// The position given by a `\pos` tag, in script pixels.
+    position: Option<(f64, f64)>,
+}
+
+impl Event {
+    /// Renders the text of an event in `style`, applying its override tags.
+    fn render(text: &str, style: Style, styles: &HashMap<String, Style>) -> Self {
+        let mut current = style;
+        let mut alignment = None;
+        let mut position = None;
+        let mut drawing = false;
+        // Lines of spans of text in the same style.
+        let mut lines: Vec<Vec<(Style, String)>> = vec![Vec::new()];
+        let push = |c: char, current: Style, lines: &mut Vec<Vec<(Style, String)>>| {
+            let line = lines.last_mut().unwrap();
+            match line.last_mut() {
+                Some((s, span)) if *s == current => escape(c, span),
+                _ => {
+                    let mut span = String::new();
+                    escape(c, &mut span);
+                    line.push((current, span));
+                }
+            }
+        };
+
+        let mut rest = text;
+        while let Some(c) = rest.chars().next() {
+            match c {
+                '{' if rest.contains('}') => {
+                    let end = rest.find('}').unwrap();
+                    for tag in rest[1..end].split('\\').skip(1) {
+                        let tag = tag.trim();
+                        let number = |prefix: &str| {
+                            let value = tag.strip_prefix(prefix)?;
+                            value
+                                .bytes()
+                                .all(|b| b.is_ascii_digit())
+                                .then(|| value.parse::<u32>().ok())
+                        };
+                        if tag.starts_with("alpha") {
+                            continue;
+                        } else if let Some(an) = number("an").flatten() {
+                            alignment = alignment.or(u8::try_from(an).ok());
+                        } else if let Some(a) = number("a").flatten() {
+                            let a = u8::try_from(a).ok().and_then(from_legacy);
+                            alignment = alignment.or(a);
+                        } else if let Some(pos) = tag.strip_prefix("pos(") {
+                            let mut coordinates = pos
+                                .trim_end_matches(')')
+                                .split(',')
+                                .map(|v| v.trim().parse::<f64>().ok());
+                            if let (Some(Some(x)), Some(Some(y))) =
+                                (coordinates.next(), coordinates.next())
+                            {
+                                position = position.or(Some((x, y)));
+                            }
+                        } else if let Some(p) = number("p") {
+                            drawing = p.is_some_and(|p| p != 0);
+                        } else if let Some(i) = number("i") {
+                            current.italic = i.map_or(style.italic, |i| i != 0);
+                        } else if let Some(b) = number("b") {
+                            current.bold = b.map_or(style.bold, |b| b != 0);
+                        } else if let Some(u) = number("u") {
+                            current.underline = u.map_or(style.underline, |u| u != 0);
+                        } else if let Some(name) = tag.strip_prefix('r') {
+                            let reset = styles.get(name.trim_start_matches('*'));
+                            let reset = reset.copied().unwrap_or(style);
+                            current = Style {
+                                alignment: current.alignment,
+                                ..reset
+                            };
+                        }
+                        // Other tags, such as colors, fonts and karaoke timings, are dropped.
+                    }
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+                '\\' if rest.starts_with("\\N") => lines.push(Vec::new()),
+                '\\' if rest.starts_with("\\n") => push(' ', current, &mut lines),
+                '\\' if rest.starts_with("\\h") => push('\u{a0}', current, &mut lines),
+                // The text of drawings is made of commands drawing shapes.
+                _ if drawing => {}
+                c => push(c, current, &mut lines),
+            }
+            let len = if c == '\\' && rest[1..].starts_with(['N', 'n', 'h']) {
+                2
+            } else {
+                c.len_utf8()
+            };
+            rest = &rest[len..];
+        }
+
+        // Blank lines would end cues in WebVTT.
+        let text = lines
+            .into_iter()
+            .map(|spans| {
+                spans
+                    .into_iter()
+                    .map(|(style, span)| {
+                        let tags = [
+                            (style.bold, "b"),
+                            (style.italic, "i"),
+                            (style.underline, "u"),
+                        ];
+                        let mut out = String::new();
+                        for (_, tag) in tags.iter().filter(|(on, _)| *on) {
+                            let _ = write!(out, "<{tag}>");
+                        }
+                        out.push_str(&span);
+                        for (_, tag) in tags.iter().rev().filter(|(on, _)| *on) {
+                            let _ = write!(out, "</{tag}>");
+                        }
+                        out
+                    })
+                    .collect::<String>()
+            })
+            .filter(|line| line.chars().any(|c| !c.is_whitespace() && c != '\u{a0}'))
+            .map(|line| line.trim().to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        Self {
+            text,
+            alignment: alignment
+                .filter(|a| (1..=9).contains(a))
+                .unwrap_or(style.alignment),
+            position,
+        }
+    }
+
+    /// Returns the WebVTT cue settings placing the event, with positions relative to a script
+    /// of `play_res` pixels.
+    fn settings(&self, play_res: (Option<f64>, Option<f64>)) -> String {
+        let (column, row) = ((self.alignment - 1) % 3, (self.alignment - 1) / 3);
+        let align = ["left", "center", "right"][usize::from(column)];
+        let position = match (self.position, play_res) {
+            (Some((x, y)), (Some(width), Some(height))) if width > 0.0 && height > 0.0 => {
+                Some((x / width * 100.0, y / height * 100.0))
+            }
+            _ => None,
+        };
+
+        let mut settings = Vec::new();
+        match position {
+            Some((x, y)) => {
+                let line_align = ["end", "center", "start"][usize::from(row)];
+                let position_align = ["line-left", "center", "line-right"][usize::from(column)];
+                settings.push(format!("line:{:.2}%,{line_align}", y.clamp(0.0, 100.0)));
+                settings.push(format!(
+                    "position:{:.2}%,{position_align}",
+                    x.clamp(0.0, 100.0)
+                ));
+            }
+            None => match row {
+                1 => settings.push("line:50%,center".to_string()),
+                2 => settings.push("line:0".to_string()),
+                _ => {}
+            },
+        }
+        if column != 1 {
+            settings.push(format!("align:{align}"));
+        }
+        settings.join(" ")
+    }
+}
+
+/// Converts an SSA alignment, where 1 to 3 are at the bottom, 5 to 7 at the top and 9 to 11 in
+/// the middle, to an ASS one.
+fn from_legacy(alignment: u8) -> Option<u8> {
+    match alignment {
+        1..=3 => Some(alignment),
+        5..=7 => Some(alignment + 2),
+        9..=11 => Some(alignment - 5),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STYLE: &str = "Arial,20,&H00FFFFFF,&H000000FF,&H00000000,&H00000000";
+
+    #[test]
+    fn events() {
+        let script = format!(
+            "[Script Info]\nPlayResX: 640\nPlayResY: 480\n\n[V4+ Styles]\nFormat: {STYLE_FORMAT}\n\
+             Style: Default,{STYLE},0,0,0,0,100,100,0,0,1,2,2,2,10,10,10,1\n\
+             Style: Sign,{STYLE},-1,0,0,0,100,100,0,0,1,2,2,8,10,10,10,1\n\
+             Style: Bad,{STYLE},0,0,0,0,100,100,0,0,1,2,2,42,10,10,10,1\n\n\
+             [Events]\nFormat: {EVENT_FORMAT}\n\
+             Dialogue: 0,0:00:01.00,0:00:02.50,Default,,0,0,0,,{{\\i1}}Hello{{\\i0}}, world\\Nagain\n\
+             Dialogue: 0,0:00:01.00,0:00:02.50,Default,,0,0,0,,Above\n\
+             Dialogue: 0,0:00:03.00,0:00:04.00,Sign,,0,0,0,,Sign & text\n\
+             Dialogue: 0,0:00:05.00,0:00:06.00,Default,,0,0,0,,{{\\pos(320,240)}}Centered\n\
+             Dialogue: 0,0:00:07.00,0:00:08.00,Bad,,0,0,0,,Fallback\n\
+             Dialogue: 0,0:00:09.00,0:00:10.00,Default,,0,0,0,,{{\\p1}}m 0 0 l 10 10{{\\p0}}\n"
+        );
+        let cues = parse(&script)
+            .into_iter()
+            .map(|cue| (cue.start, cue.end, cue.settings, cue.text))
+            .collect::<Vec<_>>();
+        let cue = |start, end, settings: &str, text: &str| {
+            (start, end, settings.to_string(), text.to_string())
+        };
+        assert_eq!(
+            cues,
+            [
+                cue(1000, 2500, "", "Above\n<i>Hello</i>, world\nagain"),
+                cue(3000, 4000, "line:0", "<b>Sign &amp; text</b>"),
+                cue(
+                    5000,
+                    6000,
+                    "line:50.00%,end position:50.00%,center",
+                    "Centered"
+                ),
+                cue(7000, 8000, "", "Fallback"),
+            ]
+        );
+    }
+
+    #[test]
+    fn legacy_alignments() {
+        let script = "[V4 Styles]\nFormat: Name, Alignment\nStyle: Default,6\n\n[Events]\n\
+                      Format: Marked, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, \
+                      Text\nDialogue: Marked=0,0:00:01.00,0:00:02.00,Default,,0,0,0,,Top\n\
+                      Dialogue: Marked=0,0:00:03.00,0:00:04.00,Default,,0,0,0,,{\\a9}Middle\n";
+        let settings = parse(script)
+            .into_iter()
+            .map(|cue| cue.settings)
+            .collect::<Vec<_>>();
+        assert_eq!(settings, ["line:0", "line:50%,center align:left"]);
+    }
+}
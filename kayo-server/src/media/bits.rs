@@ -0,0 +1,143 @@
+use std::io;
+
+pub fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn truncated() -> io::Error {
+    io::Error::from(io::ErrorKind::UnexpectedEof)
+}
+
+/// Reads integers from a byte slice, big-endian unless stated otherwise.
+#[derive(Debug, Clone)]
+pub struct ByteReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+macro_rules! read_int {
+    ($($name:ident: $ty:ty => $from:ident,)*) => {
+        $(
+            pub fn $name(&mut self) -> io::Result<$ty> {
+                let bytes = self.bytes(size_of::<$ty>())?;
+                Ok(<$ty>::$from(bytes.try_into().unwrap()))
+            }
+        )*
+    };
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    pub fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.position..self.position.saturating_add(len))
+            .ok_or_else(truncated)?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    pub fn skip(&mut self, len: usize) -> io::Result<()> {
+        self.bytes(len).map(drop)
+    }
+
+    /// Returns the bytes that have not been read yet, leaving the reader at the end.
+    pub fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.data[self.position..];
+        self.position = self.data.len();
+        rest
+    }
+
+    pub fn u24(&mut self) -> io::Result<u32> {
+        let bytes = self.bytes(3)?;
+        Ok(u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]))
+    }
+
+    read_int! {
+        u8: u8 => from_be_bytes,
+        u16: u16 => from_be_bytes,
+        u32: u32 => from_be_bytes,
+        u64: u64 => from_be_bytes,
+        u16_le: u16 => from_le_bytes,
+        u32_le: u32 => from_le_bytes,
+        i64_le: i64 => from_le_bytes,
+        f64: f64 => from_be_bytes,
+    }
+}
+
+/// Reads bit fields from a byte slice, most significant bit first.
+#[derive(Debug, Clone)]
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    pub fn read(&mut self, bits: u32) -> io::Result<u64> {
+        debug_assert!(bits <= 64);
+        let mut value = 0;
+        for _ in 0..bits {
+            let byte = self.data.get(self.position / 8).ok_or_else(truncated)?;
+            let bit = (byte >> (7 - self.position % 8)) & 1;
+            value = value << 1 | u64::from(bit);
+            self.position += 1;
+        }
+
+        Ok(value)
+    }
+
+    pub fn flag(&mut self) -> io::Result<bool> {
+        self.read(1).map(|bit| bit == 1)
+    }
+
+    pub fn skip(&mut self, bits: usize) -> io::Result<()> {
+        if self.data.len() * 8 < self.position + bits {
+            return Err(truncated());
+        }
+
+        self.position += bits;
+        Ok(())
+    }
+
+    /// Reads an unsigned Exp-Golomb code.
+    pub fn ue(&mut self) -> io::Result<u32> {
+        let mut zeros = 0;
+        while !self.flag()? {
+            zeros += 1;
+            if 31 < zeros {
+                return Err(invalid_data("invalid Exp-Golomb code"));
+            }
+        }
+
+        Ok(((1u64 << zeros) - 1 + self.read(zeros)?) as u32)
+    }
+
+    /// Reads a signed Exp-Golomb code.
+    pub fn se(&mut self) -> io::Result<i32> {
+        let value = self.ue()?;
+        Ok(if value % 2 == 1 {
+            value.div_ceil(2) as i32
+        } else {
+            -((value / 2) as i32)
+        })
+    }
+}
@@ -0,0 +1,363 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::probe::{Container, MediaInfo, Stream, StreamKind};
+use super::remux;
+
+/// The profiles used unless others are configured.
+const DEFAULT_PROFILES: &str = include_str!("cast.json");
+
+/// What a Cast device plays without transcoding.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase", deny_unknown_fields)]
+pub struct Profile {
+    pub name: String,
+    pub containers: Vec<Container>,
+    pub video: Vec<VideoCodec>,
+    /// The names of the audio codecs, where a trailing `*` matches any suffix.
+    pub audio: Vec<String>,
+    #[serde(default)]
+    pub max_width: Option<u32>,
+    #[serde(default)]
+    pub max_height: Option<u32>,
+    #[serde(default)]
+    pub max_frame_rate: Option<f64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase", deny_unknown_fields)]
+pub struct VideoCodec {
+    pub codec: String,
+    /// The supported profiles, or `None` for any.
+    #[serde(default)]
+    pub profiles: Option<Vec<String>>,
+    #[serde(default)]
+    pub max_level: Option<f64>,
+    #[serde(default)]
+    pub max_bit_depth: Option<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Playback {
+    DirectPlay,
+    /// The streams are supported, but have to be moved to another container with `kayo-remux`.
+    NeedsRemux,
+    Unsupported,
+}
+
+/// Whether a device plays a media file, and why not.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename = "Cast", rename_all = "PascalCase")]
+pub struct Verdict {
+    pub device: String,
+    #[serde(rename = "Verdict")]
+    pub playback: Playback,
+    #[serde(rename = "Reason", skip_serializing_if = "Vec::is_empty")]
+    pub reasons: Vec<String>,
+}
+
+/// The Cast devices that media files are checked against.
+#[derive(Debug, Clone)]
+pub struct Profiles(Vec<Profile>);
+
+impl Default for Profiles {
+    fn default() -> Self {
+        Self(serde_json::from_str(DEFAULT_PROFILES).expect("invalid default Cast profiles"))
+    }
+}
+
+impl Profiles {
+    /// Reads profiles from a JSON file holding an array of them.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        serde_json::from_str(&text)
+            .map(Self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn evaluate(&self, info: &MediaInfo) -> Vec<Verdict> {
+        self.0
+            .iter()
+            .map(|profile| profile.evaluate(info))
+            .collect()
+    }
+}
+
+impl Profile {
+    pub fn evaluate(&self, info: &MediaInfo) -> Verdict {
+        let mut reasons = Vec::new();
+        let mut streams = false;
+
+        if let Some(video) = info.streams.iter().find(|s| s.kind == StreamKind::Video) {
+            streams = true;
+            self.check_video(video, &mut reasons);
+        }
+
+        // The player picks any supported audio track.
+        let audio = info
+            .streams
+            .iter()
+            .filter(|s| s.kind == StreamKind::Audio)
+            .collect::<Vec<_>>();
+        if !audio.is_empty() {
+            streams = true;
+            if !audio.iter().any(|s| self.supports_audio(&s.codec)) {
+                let mut codecs = audio.iter().map(|s| s.codec.as_str()).collect::<Vec<_>>();
+                codecs.sort_unstable();
+                codecs.dedup();
+                reasons.push(format!("{} audio is not supported", codecs.join(", ")));
+            }
+        }
+
+        if !streams {
+            reasons.push("no audio or video stream".to_string());
+        }
+
+        let playback = if !reasons.is_empty() {
+            Playback::Unsupported
+        } else if self.containers.contains(&info.container) {
+            Playback::DirectPlay
+        } else {
+            reasons.push(format!("{} container is not supported", info.container));
+            match self.check_remux(info) {
+                Some(reason) => {
+                    reasons.push(reason);
+                    Playback::Unsupported
+                }
+                None => Playback::NeedsRemux,
+            }
+        };
+
+        Verdict {
+            device: self.name.clone(),
+            playback,
+            reasons,
+        }
+    }
+
+    fn check_video(&self, stream: &Stream, reasons: &mut Vec<String>) {
+        let codec = &stream.codec;
+        let Some(rule) = self.video.iter().find(|rule| rule.codec == *codec) else {
+            reasons.push(format!("{codec} video is not supported"));
+            return;
+        };
+
+        if let (Some(profiles), Some(profile)) = (&rule.profiles, &stream.profile) {
+            if !profiles.contains(profile) {
+                reasons.push(format!("{codec} profile {profile} is not supported"));
+            }
+        }
+
+        let level = stream.level.as_deref().and_then(|l| l.parse::<f64>().ok());
+        if let (Some(max), Some(level)) = (rule.max_level, level) {
+            if max < level {
+                reasons.push(format!("{codec} level {level} exceeds {max}"));
+            }
+        }
+
+        if let (Some(max), Some(depth)) = (rule.max_bit_depth, stream.bit_depth) {
+            if max < depth {
+                reasons.push(format!("{depth}-bit {codec} is not supported"));
+            }
+        }
+
+        if let (Some(width), Some(height)) = (stream.width, stream.height) {
+            let max_width = self.max_width.unwrap_or(u32::MAX);
+            let max_height = self.max_height.unwrap_or(u32::MAX);
+            // Portrait videos are decoded like their landscape counterparts.
+            let (long, short) = (width.max(height), width.min(height));
+            if max_width.max(max_height) < long || max_width.min(max_height) < short {
+                reasons.push(format!("{width}x{height} exceeds {max_width}x{max_height}"));
+            }
+        }
+
+        if let (Some(max), Some(rate)) = (self.max_frame_rate, stream.frame_rate) {
+            // Allow for rates such as 59.94 being rounded up in profiles.
+            if max + 0.5 < rate {
+                reasons.push(format!("{rate:.2} fps exceeds {max} fps"));
+            }
+        }
+    }
+
+    /// Returns why the remuxer cannot turn the file into MP4 that plays, if it cannot.
+    fn check_remux(&self, info: &MediaInfo) -> Option<String> {
+        if !remux::CONTAINERS.contains(&info.container) {
+            return Some(format!("{} files cannot be remuxed", info.container));
+        }
+
+        if let Some(video) = info.streams.iter().find(|s| s.kind == StreamKind::Video) {
+            if !remux::VIDEO_CODECS.contains(&video.codec.as_str()) {
+                return Some(format!("{} video cannot be remuxed", video.codec));
+            }
+        }
+
+        // An audio track can be chosen for remuxing, like the player chooses one.
+        let mut audio = info
+            .streams
+            .iter()
+            .filter(|s| s.kind == StreamKind::Audio)
+            .peekable();
+        if audio.peek().is_some()
+            && !audio.any(|s| {
+                remux::AUDIO_CODECS.contains(&s.codec.as_str()) && self.supports_audio(&s.codec)
+            })
+        {
+            return Some("no supported audio track can be remuxed".to_string());
+        }
+
+        None
+    }
+
+    fn supports_audio(&self, codec: &str) -> bool {
+        self.audio
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => codec.starts_with(prefix),
+                None => pattern == codec,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile() -> Profile {
+        serde_json::from_str(
+            r#"{
+                "Name": "Test",
+                "Containers": ["mp4", "webm"],
+                "Video": [
+                    { "Codec": "h264", "Profiles": ["High"], "MaxLevel": 4.1, "MaxBitDepth": 8 }
+                ],
+                "Audio": ["aac", "opus*"],
+                "MaxWidth": 1920,
+                "MaxHeight": 1080,
+                "MaxFrameRate": 30
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn media(container: Container, video: Option<Stream>, audio: &[&str]) -> MediaInfo {
+        let mut info = MediaInfo::new(container);
+        info.streams.extend(video);
+        for codec in audio {
+            info.streams.push(Stream::new(2, StreamKind::Audio, *codec));
+        }
+        info
+    }
+
+    fn h264(level: &str, width: u32, height: u32, frame_rate: f64) -> Stream {
+        let mut stream = Stream::new(1, StreamKind::Video, "h264");
+        stream.profile = Some("High".to_string());
+        stream.level = Some(level.to_string());
+        stream.bit_depth = Some(8);
+        (stream.width, stream.height) = (Some(width), Some(height));
+        stream.frame_rate = Some(frame_rate);
+        stream
+    }
+
+    #[test]
+    fn direct_play() {
+        let verdict = profile().evaluate(&media(
+            Container::Mp4,
+            Some(h264("4.0", 1920, 1080, 29.97)),
+            &["aac"],
+        ));
+        assert_eq!(verdict.device, "Test");
+        assert_eq!(verdict.playback, Playback::DirectPlay);
+        assert!(verdict.reasons.is_empty());
+
+        // Portrait videos and any supported audio track play as well.
+        let verdict = profile().evaluate(&media(
+            Container::Webm,
+            Some(h264("4.0", 1080, 1920, 30.0)),
+            &["ac3", "opus"],
+        ));
+        assert_eq!(verdict.playback, Playback::DirectPlay);
+    }
+
+    #[test]
+    fn needs_remux() {
+        let verdict = profile().evaluate(&media(
+            Container::Matroska,
+            Some(h264("4.0", 1280, 720, 25.0)),
+            &["aac"],
+        ));
+        assert_eq!(verdict.playback, Playback::NeedsRemux);
+        assert_eq!(verdict.reasons, ["matroska container is not supported"]);
+    }
+
+    #[test]
+    fn not_remuxable() {
+        // MP4 files in a QuickTime container can only be segmented, not remuxed.
+        let verdict = profile().evaluate(&media(
+            Container::Mov,
+            Some(h264("4.0", 1920, 1080, 30.0)),
+            &["aac"],
+        ));
+        assert_eq!(verdict.playback, Playback::Unsupported);
+        assert_eq!(
+            verdict.reasons,
+            [
+                "mov container is not supported",
+                "mov files cannot be remuxed"
+            ]
+        );
+
+        let mut profile = profile();
+        profile
+            .video
+            .push(serde_json::from_str(r#"{ "Codec": "vp8" }"#).unwrap());
+        profile.audio.push("vorbis".to_string());
+        let verdict = profile.evaluate(&media(
+            Container::Matroska,
+            Some(Stream::new(1, StreamKind::Video, "vp8")),
+            &["aac"],
+        ));
+        assert_eq!(verdict.playback, Playback::Unsupported);
+        assert_eq!(verdict.reasons[1], "vp8 video cannot be remuxed");
+
+        let verdict = profile.evaluate(&media(Container::Matroska, None, &["vorbis", "ac3"]));
+        assert_eq!(verdict.playback, Playback::Unsupported);
+        assert_eq!(
+            verdict.reasons[1],
+            "no supported audio track can be remuxed"
+        );
+
+        let verdict = profile.evaluate(&media(Container::Matroska, None, &["vorbis", "aac"]));
+        assert_eq!(verdict.playback, Playback::NeedsRemux);
+    }
+
+    #[test]
+    fn unsupported() {
+        let verdict = profile().evaluate(&media(
+            Container::Matroska,
+            Some(h264("5.1", 3840, 2160, 60.0)),
+            &["dts", "ac3", "dts"],
+        ));
+        assert_eq!(verdict.playback, Playback::Unsupported);
+        assert_eq!(
+            verdict.reasons,
+            [
+                "h264 level 5.1 exceeds 4.1",
+                "3840x2160 exceeds 1920x1080",
+                "60.00 fps exceeds 30 fps",
+                "ac3, dts audio is not supported",
+            ]
+        );
+
+        let mut hevc = Stream::new(1, StreamKind::Video, "hevc");
+        hevc.bit_depth = Some(10);
+        let verdict = profile().evaluate(&media(Container::Mp4, Some(hevc), &[]));
+        assert_eq!(verdict.reasons, ["hevc video is not supported"]);
+
+        let verdict = profile().evaluate(&media(Container::Mp4, None, &[]));
+        assert_eq!(verdict.playback, Playback::Unsupported);
+        assert_eq!(verdict.reasons, ["no audio or video stream"]);
+    }
+}
@@ -0,0 +1,710 @@
+use std::io;
+
+use super::bits::{invalid_data, BitReader, ByteReader};
+use super::probe::Stream;
+
+const AAC_SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+const AC3_BITRATES: [u64; 19] = [
+    32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384, 448, 512, 576, 640,
+];
+
+/// Sets the codec details of an H.264 stream from an `AVCDecoderConfigurationRecord`.
+pub fn avc_config(stream: &mut Stream, data: &[u8]) -> io::Result<()> {
+    let mut r = ByteReader::new(data);
+    r.u8()?; // configurationVersion
+    let profile_idc = r.u8()?;
+    let constraints = r.u8()?;
+    let level_idc = r.u8()?;
+    stream.codecs = Some(format!(
+        "avc1.{profile_idc:02x}{constraints:02x}{level_idc:02x}"
+    ));
+    stream.profile = Some(h264_profile(profile_idc, constraints));
+    stream.level = Some(h264_level(level_idc, constraints));
+
+    r.u8()?; // lengthSizeMinusOne
+    if r.u8()? & 0x1f != 0 {
+        let len = r.u16()?;
+        let sps = r.bytes(usize::from(len))?;
+        h264_sps(stream, sps)?;
+    }
+
+    Ok(())
+}
+
+/// Sets the codec details of an H.264 stream from a sequence parameter set NAL unit.
+pub fn h264_sps(stream: &mut Stream, nal: &[u8]) -> io::Result<()> {
+    let rbsp = unescape(nal.get(1..).unwrap_or_default());
+    let mut r = BitReader::new(&rbsp);
+    let profile_idc = r.read(8)? as u8;
+    let constraints = r.read(8)? as u8;
+    let level_idc = r.read(8)? as u8;
+    r.ue()?; // seq_parameter_set_id
+
+    stream
+        .codecs
+        .get_or_insert_with(|| format!("avc1.{profile_idc:02x}{constraints:02x}{level_idc:02x}"));
+    stream
+        .profile
+        .get_or_insert_with(|| h264_profile(profile_idc, constraints));
+    stream
+        .level
+        .get_or_insert_with(|| h264_level(level_idc, constraints));
+
+    let mut chroma_format_idc = 1;
+    let mut bit_depth = 8;
+    if matches!(
+        profile_idc,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    ) {
+        chroma_format_idc = r.ue()?;
+        if 3 < chroma_format_idc {
+            return Err(invalid_data("invalid chroma_format_idc"));
+        }
+        if chroma_format_idc == 3 {
+            r.skip(1)?; // separate_colour_plane_flag
+        }
+        bit_depth += match r.ue()? {
+            depth @ 0..=6 => depth as u8,
+            _ => return Err(invalid_data("invalid bit_depth_luma_minus8")),
+        };
+        r.ue()?; // bit_depth_chroma_minus8
+        r.skip(1)?; // qpprime_y_zero_transform_bypass_flag
+        if r.flag()? {
+            let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+            for i in 0..lists {
+                if r.flag()? {
+                    skip_scaling_list(&mut r, if i < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+    stream.bit_depth = Some(bit_depth);
+
+    r.ue()?; // log2_max_frame_num_minus4
+    match r.ue()? {
+        0 => {
+            r.ue()?; // log2_max_pic_order_cnt_lsb_minus4
+        }
+        1 => {
+            r.skip(1)?; // delta_pic_order_always_zero_flag
+            r.se()?; // offset_for_non_ref_pic
+            r.se()?; // offset_for_top_to_bottom_field
+            let cycle = r.ue()?;
+            if 255 < cycle {
+                return Err(invalid_data(
+                    "invalid num_ref_frames_in_pic_order_cnt_cycle",
+                ));
+            }
+            for _ in 0..cycle {
+                r.se()?; // offset_for_ref_frame
+            }
+        }
+        _ => {}
+    }
+    r.ue()?; // max_num_ref_frames
+    r.skip(1)?; // gaps_in_frame_num_value_allowed_flag
+
+    let width_in_mbs = r.ue()?.saturating_add(1);
+    let height_in_map_units = r.ue()?.saturating_add(1);
+    let frame_mbs_only = r.flag()?;
+    if !frame_mbs_only {
+        r.skip(1)?; // mb_adaptive_frame_field_flag
+    }
+    r.skip(1)?; // direct_8x8_inference_flag
+
+    let field_factor = 2 - u32::from(frame_mbs_only);
+    let (mut crop_x, mut crop_y) = (Some(0), Some(0));
+    if r.flag()? {
+        let (left, right, top, bottom) = (r.ue()?, r.ue()?, r.ue()?, r.ue()?);
+        let (unit_x, unit_y) = match chroma_format_idc {
+            0 => (1, field_factor),
+            1 => (2, 2 * field_factor),
+            2 => (2, field_factor),
+            _ => (1, field_factor),
+        };
+        crop_x = left.checked_add(right).and_then(|n| n.checked_mul(unit_x));
+        crop_y = top.checked_add(bottom).and_then(|n| n.checked_mul(unit_y));
+    }
+
+    // The cropped frame must be at least one sample in each dimension.
+    let width = width_in_mbs
+        .checked_mul(16)
+        .zip(crop_x)
+        .and_then(|(width, crop)| width.checked_sub(crop))
+        .filter(|&width| width != 0)
+        .ok_or_else(|| invalid_data("invalid frame width"))?;
+    let height = height_in_map_units
+        .checked_mul(16 * field_factor)
+        .zip(crop_y)
+        .and_then(|(height, crop)| height.checked_sub(crop))
+        .filter(|&height| height != 0)
+        .ok_or_else(|| invalid_data("invalid frame height"))?;
+    stream.width.get_or_insert(width);
+    stream.height.get_or_insert(height);
+
+    if r.flag()? {
+        // vui_parameters
+        if r.flag()? && r.read(8)? == 255 {
+            r.skip(32)?; // sar_width, sar_height
+        }
+        if r.flag()? {
+            r.skip(1)?; // overscan_appropriate_flag
+        }
+        if r.flag()? {
+            r.skip(4)?; // video_format, video_full_range_flag
+            if r.flag()? {
+                r.skip(24)?; // colour_primaries, transfer_characteristics, matrix_coefficients
+            }
+        }
+        if r.flag()? {
+            r.ue()?; // chroma_sample_loc_type_top_field
+            r.ue()?; // chroma_sample_loc_type_bottom_field
+        }
+        if r.flag()? {
+            let num_units_in_tick = r.read(32)?;
+            let time_scale = r.read(32)?;
+            if num_units_in_tick != 0 && time_scale != 0 {
+                stream
+                    .frame_rate
+                    .get_or_insert(time_scale as f64 / (2 * num_units_in_tick) as f64);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn skip_scaling_list(r: &mut BitReader, size: usize) -> io::Result<()> {
+    let mut last = 8;
+    let mut next = 8;
+    for _ in 0..size {
+        if next != 0 {
+            next = (last + r.se()? + 256) % 256;
+        }
+        if next != 0 {
+            last = next;
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes the emulation prevention bytes from a NAL unit.
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut rbsp = Vec::with_capacity(data.len());
+    let mut zeros = 0;
+    for &byte in data {
+        if zeros == 2 && byte == 3 {
+            zeros = 0;
+            continue;
+        }
+
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        rbsp.push(byte);
+    }
+
+    rbsp
+}
+
+fn h264_profile(profile_idc: u8, constraints: u8) -> String {
+    let name = match profile_idc {
+        66 if constraints & 0x40 != 0 => "Constrained Baseline",
+        66 => "Baseline",
+        77 => "Main",
+        88 => "Extended",
+        100 => "High",
+        110 => "High 10",
+        122 => "High 4:2:2",
+        244 => "High 4:4:4 Predictive",
+        44 => "CAVLC 4:4:4 Intra",
+        _ => return profile_idc.to_string(),
+    };
+    name.to_string()
+}
+
+fn h264_level(level_idc: u8, constraints: u8) -> String {
+    if level_idc == 11 && constraints & 0x10 != 0 {
+        return "1b".to_string();
+    }
+
+    decimal(u32::from(level_idc), 10)
+}
+
+/// Formats `value / scale` with at most one decimal.
+fn decimal(value: u32, scale: u32) -> String {
+    let (whole, fraction) = (value / scale, value % scale * 10 / scale);
+    if fraction == 0 {
+        whole.to_string()
+    } else {
+        format!("{whole}.{fraction}")
+    }
+}
+
+/// Sets the codec details of an H.265 stream from an `HEVCDecoderConfigurationRecord`, found in
+/// a sample entry named `tag`.
+pub fn hevc_config(stream: &mut Stream, tag: &str, data: &[u8]) -> io::Result<()> {
+    let mut r = ByteReader::new(data);
+    r.u8()?; // configurationVersion
+    let byte = r.u8()?;
+    let (profile_space, tier, profile_idc) = (byte >> 6, byte >> 5 & 1, byte & 0x1f);
+    let compatibility = r.u32()?;
+    let constraints = r.bytes(6)?;
+    let level_idc = r.u8()?;
+    r.u16()?; // min_spatial_segmentation_idc
+    r.u8()?; // parallelismType
+    r.u8()?; // chromaFormat
+    stream.bit_depth = Some((r.u8()? & 7) + 8);
+
+    let space = ["", "A", "B", "C"][usize::from(profile_space)];
+    let tier = if tier == 0 { 'L' } else { 'H' };
+    let mut codecs = format!(
+        "{tag}.{space}{profile_idc}.{:X}.{tier}{level_idc}",
+        compatibility.reverse_bits()
+    );
+    let len = constraints
+        .iter()
+        .rposition(|b| *b != 0)
+        .map_or(0, |i| i + 1);
+    for byte in &constraints[..len] {
+        codecs.push_str(&format!(".{byte:X}"));
+    }
+    stream.codecs = Some(codecs);
+
+    let profile = match profile_idc {
+        1 => "Main",
+        2 => "Main 10",
+        3 => "Main Still Picture",
+        4 => "Format Range Extensions",
+        5 => "High Throughput",
+        9 => "Screen Content Coding",
+        _ => "",
+    };
+    stream.profile = Some(if profile.is_empty() {
+        profile_idc.to_string()
+    } else {
+        profile.to_string()
+    });
+    stream.level = Some(decimal(u32::from(level_idc), 30));
+    Ok(())
+}
+
+/// Sets the codec details of a VP9 stream from a `VPCodecConfigurationRecord` box.
+pub fn vp9_config(stream: &mut Stream, data: &[u8]) -> io::Result<()> {
+    let mut r = ByteReader::new(data);
+    r.u32()?; // version, flags
+    let profile = r.u8()?;
+    let level = r.u8()?;
+    let bit_depth = r.u8()? >> 4;
+    stream.codecs = Some(format!("vp09.{profile:02}.{level:02}.{bit_depth:02}"));
+    stream.profile = Some(format!("Profile {profile}"));
+    stream.level = Some(decimal(u32::from(level), 10));
+    stream.bit_depth = Some(bit_depth);
+    Ok(())
+}
+
+/// Sets the codec details of an AV1 stream from an `AV1CodecConfigurationRecord`.
+pub fn av1_config(stream: &mut Stream, data: &[u8]) -> io::Result<()> {
+    let mut r = ByteReader::new(data);
+    r.u8()?; // marker, version
+    let byte = r.u8()?;
+    let (profile, level_idx) = (byte >> 5, byte & 0x1f);
+    let byte = r.u8()?;
+    let tier = if byte & 0x80 == 0 { 'M' } else { 'H' };
+    let bit_depth = match (byte & 0x40 != 0, byte & 0x20 != 0) {
+        (true, true) => 12,
+        (true, false) => 10,
+        _ => 8,
+    };
+    stream.codecs = Some(format!(
+        "av01.{profile}.{level_idx:02}{tier}.{bit_depth:02}"
+    ));
+    stream.profile = Some(
+        match profile {
+            0 => "Main",
+            1 => "High",
+            2 => "Professional",
+            _ => "",
+        }
+        .to_string(),
+    );
+    stream.level = Some(format!("{}.{}", 2 + (level_idx >> 2), level_idx & 3));
+    stream.bit_depth = Some(bit_depth);
+    Ok(())
+}
+
+/// Sets the codec details of an AAC stream from an `AudioSpecificConfig`.
+pub fn audio_specific_config(stream: &mut Stream, data: &[u8]) -> io::Result<()> {
+    let mut r = BitReader::new(data);
+    let object_type = audio_object_type(&mut r)?;
+    let mut sample_rate = sampling_frequency(&mut r)?;
+    let channel_config = r.read(4)? as u16;
+    if object_type == 5 || object_type == 29 {
+        // Explicit SBR signalling gives the output sample rate.
+        sample_rate = sampling_frequency(&mut r)?;
+    }
+
+    set_aac(stream, object_type, sample_rate, channel_config);
+    Ok(())
+}
+
+fn audio_object_type(r: &mut BitReader) -> io::Result<u8> {
+    let object_type = r.read(5)? as u8;
+    Ok(if object_type == 31 {
+        32 + r.read(6)? as u8
+    } else {
+        object_type
+    })
+}
+
+fn sampling_frequency(r: &mut BitReader) -> io::Result<Option<u32>> {
+    let index = r.read(4)? as usize;
+    Ok(if index == 15 {
+        Some(r.read(24)? as u32)
+    } else {
+        AAC_SAMPLE_RATES.get(index).copied()
+    })
+}
+
+fn set_aac(stream: &mut Stream, object_type: u8, sample_rate: Option<u32>, channel_config: u16) {
+    stream.codecs = Some(format!("mp4a.40.{object_type}"));
+    let profile = match object_type {
+        1 => "Main",
+        2 => "LC",
+        3 => "SSR",
+        4 => "LTP",
+        5 => "HE-AAC",
+        23 => "LD",
+        29 => "HE-AACv2",
+        39 => "ELD",
+        _ => "",
+    };
+    if !profile.is_empty() {
+        stream.profile = Some(profile.to_string());
+    }
+    if sample_rate.is_some() {
+        stream.sample_rate = sample_rate;
+    }
+    match channel_config {
+        0 => {}
+        1..=6 => stream.channels = Some(channel_config),
+        7 => stream.channels = Some(8),
+        _ => {}
+    }
+}
+
+/// Sets the codec details of an AAC stream from the header of an ADTS frame.
+pub fn adts_header(stream: &mut Stream, data: &[u8]) -> io::Result<()> {
+    let mut r = BitReader::new(data);
+    if r.read(12)? != 0xfff {
+        return Err(invalid_data("missing ADTS sync word"));
+    }
+    r.skip(4)?; // ID, layer, protection_absent
+    let object_type = r.read(2)? as u8 + 1;
+    let sample_rate = AAC_SAMPLE_RATES.get(r.read(4)? as usize).copied();
+    r.skip(1)?; // private_bit
+    let channel_config = r.read(3)? as u16;
+    set_aac(stream, object_type, sample_rate, channel_config);
+    Ok(())
+}
+
+/// The sample format of linear PCM audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pcm {
+    Unsigned,
+    Signed,
+    Float,
+}
+
+/// Names linear PCM audio with samples of `bits` bits like FFmpeg does, such as `pcm_u8`,
+/// `pcm_s16le` or `pcm_f32be`, so that every container gives the same name.
+pub fn pcm(format: Pcm, bits: u16, big_endian: bool) -> String {
+    let format = match format {
+        Pcm::Unsigned => 'u',
+        Pcm::Signed => 's',
+        Pcm::Float => 'f',
+    };
+    match (bits, big_endian) {
+        (8, _) => format!("pcm_{format}8"),
+        (_, false) => format!("pcm_{format}{bits}le"),
+        (_, true) => format!("pcm_{format}{bits}be"),
+    }
+}
+
+fn ac3_channels(acmod: u64, lfeon: bool) -> u16 {
+    [2, 1, 2, 3, 3, 4, 4, 5][acmod as usize & 7] + u16::from(lfeon)
+}
+
+/// Sets the codec details of an AC-3 or E-AC-3 stream from the header of a sync frame, and
+/// returns the name of the codec.
+pub fn ac3_header(stream: &mut Stream, data: &[u8]) -> io::Result<&'static str> {
+    let mut r = BitReader::new(data);
+    if r.read(16)? != 0x0b77 {
+        return Err(invalid_data("missing AC-3 sync word"));
+    }
+
+    let bsid = BitReader::new(data.get(5..).unwrap_or_default()).read(5)?;
+    if bsid <= 10 {
+        r.skip(16)?; // crc1
+        let fscod = r.read(2)?;
+        let frmsizecod = r.read(6)? as usize;
+        r.skip(8)?; // bsid, bsmod
+        let acmod = r.read(3)?;
+        if acmod & 1 != 0 && acmod != 1 {
+            r.skip(2)?; // cmixlev
+        }
+        if acmod & 4 != 0 {
+            r.skip(2)?; // surmixlev
+        }
+        if acmod == 2 {
+            r.skip(2)?; // dsurmod
+        }
+        let lfeon = r.flag()?;
+        stream.channels = Some(ac3_channels(acmod, lfeon));
+        stream.sample_rate = [48000, 44100, 32000].get(fscod as usize).copied();
+        stream.bitrate = AC3_BITRATES.get(frmsizecod >> 1).map(|k| k * 1000);
+        Ok("ac3")
+    } else {
+        r.skip(16)?; // strmtyp, substreamid, frmsiz
+        let fscod = r.read(2)?;
+        let fscod2 = r.read(2)?;
+        let acmod = r.read(3)?;
+        let lfeon = r.flag()?;
+        stream.channels = Some(ac3_channels(acmod, lfeon));
+        stream.sample_rate = match fscod {
+            3 => [24000, 22050, 16000].get(fscod2 as usize).copied(),
+            _ => [48000, 44100, 32000].get(fscod as usize).copied(),
+        };
+        Ok("eac3")
+    }
+}
+
+/// Sets the codec details of an AC-3 stream from an `AC3SpecificBox`.
+pub fn dac3(stream: &mut Stream, data: &[u8]) -> io::Result<()> {
+    let mut r = BitReader::new(data);
+    let fscod = r.read(2)?;
+    r.skip(8)?; // bsid, bsmod
+    let acmod = r.read(3)?;
+    let lfeon = r.flag()?;
+    let bit_rate_code = r.read(5)? as usize;
+    stream.channels = Some(ac3_channels(acmod, lfeon));
+    stream.sample_rate = [48000, 44100, 32000].get(fscod as usize).copied();
+    stream.bitrate = AC3_BITRATES.get(bit_rate_code).map(|k| k * 1000);
+    Ok(())
+}
+
+/// Sets the codec details of an E-AC-3 stream from an `EC3SpecificBox`, describing its first
+/// independent substream.
+pub fn dec3(stream: &mut Stream, data: &[u8]) -> io::Result<()> {
+    let mut r = BitReader::new(data);
+    let data_rate = r.read(13)?;
+    r.skip(3)?; // num_ind_sub
+    let fscod = r.read(2)?;
+    r.skip(10)?; // bsid, reserved, asvc, bsmod
+    let acmod = r.read(3)?;
+    let lfeon = r.flag()?;
+    stream.channels = Some(ac3_channels(acmod, lfeon));
+    stream.sample_rate = [48000, 44100, 32000].get(fscod as usize).copied();
+    stream.bitrate = Some(data_rate * 1000);
+    Ok(())
+}
+
+/// Builds an `AudioSpecificConfig` for AAC LC, for streams stored without one.
+pub fn aac_lc_config(sample_rate: u32, channels: u16) -> Vec<u8> {
+    let channel_config = u64::from(if channels == 8 { 7 } else { channels.min(7) });
+    let (config, bits): (u64, usize) = match AAC_SAMPLE_RATES
+        .iter()
+        .position(|rate| *rate == sample_rate)
+    {
+        Some(index) => (2 << 8 | (index as u64) << 4 | channel_config, 13),
+        None => (
+            (2 << 4 | 15) << 28 | u64::from(sample_rate) << 4 | channel_config,
+            37,
+        ),
+    };
+    let len = bits.div_ceil(8);
+    (config << (len * 8 - bits)).to_be_bytes()[8 - len..].to_vec()
+}
+
+/// Builds the body of a `dac3` box from the header of an AC-3 sync frame.
+pub fn ac3_specific_box(frame: &[u8]) -> io::Result<Vec<u8>> {
+    let mut r = BitReader::new(frame);
+    if r.read(16)? != 0x0b77 {
+        return Err(invalid_data("missing AC-3 sync word"));
+    }
+    r.skip(16)?; // crc1
+    let fscod = r.read(2)?;
+    let frmsizecod = r.read(6)?;
+    let bsid = r.read(5)?;
+    let bsmod = r.read(3)?;
+    let acmod = r.read(3)?;
+    if acmod & 1 != 0 && acmod != 1 {
+        r.skip(2)?; // cmixlev
+    }
+    if acmod & 4 != 0 {
+        r.skip(2)?; // surmixlev
+    }
+    if acmod == 2 {
+        r.skip(2)?; // dsurmod
+    }
+    let lfeon = r.read(1)?;
+
+    let config = fscod << 22 | bsid << 17 | bsmod << 14 | acmod << 11 | lfeon << 10;
+    let config = config | (frmsizecod >> 1) << 5;
+    Ok(config.to_be_bytes()[5..].to_vec())
+}
+
+/// Builds the body of a `dec3` box from the header of an E-AC-3 sync frame, describing a
+/// single independent substream.
+pub fn eac3_specific_box(frame: &[u8]) -> io::Result<Vec<u8>> {
+    let mut r = BitReader::new(frame);
+    if r.read(16)? != 0x0b77 {
+        return Err(invalid_data("missing E-AC-3 sync word"));
+    }
+    r.skip(5)?; // strmtyp, substreamid
+    let frmsiz = r.read(11)?;
+    let fscod = r.read(2)?;
+    let fscod2 = r.read(2)?;
+    let acmod = r.read(3)?;
+    let lfeon = r.read(1)?;
+    let bsid = r.read(5)?;
+
+    let (sample_rate, blocks) = match fscod {
+        3 => ([24000, 22050, 16000, 0][fscod2 as usize], 6),
+        _ => (
+            [48000, 44100, 32000][fscod as usize],
+            [1, 2, 3, 6][fscod2 as usize],
+        ),
+    };
+    // The frame of 256 samples per block is given in 16-bit words.
+    let data_rate = (frmsiz + 1) * 16 * sample_rate / (256 * blocks) / 1000;
+
+    let config = data_rate << 27 | fscod << 22 | bsid << 17 | acmod << 9 | lfeon << 8;
+    Ok(config.to_be_bytes()[3..].to_vec())
+}
+
+/// Builds the body of a `dOps` box from an Opus identification header.
+pub fn opus_specific_box(head: &[u8]) -> io::Result<Vec<u8>> {
+    let mut r = ByteReader::new(head);
+    if r.bytes(8)? != b"OpusHead" {
+        return Err(invalid_data("missing Opus identification header"));
+    }
+    r.u8()?; // version
+    let channels = r.u8()?;
+    let pre_skip = r.u16_le()?;
+    let input_sample_rate = r.u32_le()?;
+    let output_gain = r.u16_le()?;
+    let mapping_family = r.u8()?;
+
+    // The same fields, big-endian.
+    let mut body = vec![0, channels];
+    body.extend_from_slice(&pre_skip.to_be_bytes());
+    body.extend_from_slice(&input_sample_rate.to_be_bytes());
+    body.extend_from_slice(&output_gain.to_be_bytes());
+    body.push(mapping_family);
+    if mapping_family != 0 {
+        // stream_count, coupled_count, channel_mapping
+        body.extend_from_slice(r.bytes(2 + usize::from(channels))?);
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::probe::StreamKind;
+
+    /// Writes the fields of a sequence parameter set, escaping the NAL unit when it is taken.
+    #[derive(Default)]
+    struct Sps {
+        bits: Vec<bool>,
+    }
+
+    impl Sps {
+        fn bits(mut self, value: u64, count: u32) -> Self {
+            self.bits
+                .extend((0..count).rev().map(|bit| value >> bit & 1 == 1));
+            self
+        }
+
+        fn ue(self, value: u32) -> Self {
+            let value = u64::from(value) + 1;
+            let len = u64::BITS - value.leading_zeros();
+            self.bits(0, len - 1).bits(value, len)
+        }
+
+        fn nal(self) -> Vec<u8> {
+            let mut bits = self.bits(1, 1).bits;
+            bits.resize(bits.len().next_multiple_of(8), false);
+            let mut nal = vec![0x67];
+            for byte in bits.chunks(8) {
+                let byte = byte.iter().fold(0, |byte, &bit| byte << 1 | u8::from(bit));
+                if nal.ends_with(&[0, 0]) && byte <= 3 {
+                    nal.push(3);
+                }
+                nal.push(byte);
+            }
+            nal
+        }
+    }
+
+    /// Returns a High profile sequence parameter set for a progressive frame of `width_in_mbs` by
+    /// `height_in_mbs` macroblocks.
+    fn high(bit_depth_minus8: u32, width_in_mbs: u32, height_in_mbs: u32) -> Sps {
+        Sps::default()
+            .bits(100, 8) // profile_idc
+            .bits(0, 8) // constraint_set_flags
+            .bits(40, 8) // level_idc
+            .ue(0) // seq_parameter_set_id
+            .ue(1) // chroma_format_idc
+            .ue(bit_depth_minus8)
+            .ue(bit_depth_minus8)
+            .bits(0, 2) // qpprime_y_zero_transform_bypass_flag, seq_scaling_matrix_present_flag
+            .ue(0) // log2_max_frame_num_minus4
+            .ue(2) // pic_order_cnt_type
+            .ue(1) // max_num_ref_frames
+            .bits(0, 1) // gaps_in_frame_num_value_allowed_flag
+            .ue(width_in_mbs - 1)
+            .ue(height_in_mbs - 1)
+            .bits(0b11, 2) // frame_mbs_only_flag, direct_8x8_inference_flag
+    }
+
+    fn parse(nal: &[u8]) -> io::Result<Stream> {
+        let mut stream = Stream::new(1, StreamKind::Video, "h264");
+        h264_sps(&mut stream, nal)?;
+        Ok(stream)
+    }
+
+    #[test]
+    fn sps() {
+        // 1920x1088 cropped by 8 lines at the bottom.
+        let nal = high(2, 120, 68)
+            .bits(1, 1)
+            .ue(0)
+            .ue(0)
+            .ue(0)
+            .ue(4)
+            .bits(0, 1);
+        let stream = parse(&nal.nal()).unwrap();
+        assert_eq!(stream.codecs.as_deref(), Some("avc1.640028"));
+        assert_eq!(stream.profile.as_deref(), Some("High"));
+        assert_eq!(stream.bit_depth, Some(10));
+        assert_eq!((stream.width, stream.height), (Some(1920), Some(1080)));
+    }
+
+    #[test]
+    fn invalid_sps() {
+        let uncropped = |sps: Sps| sps.bits(0, 2).nal();
+        assert!(parse(&uncropped(high(7, 120, 68))).is_err());
+        assert!(parse(&uncropped(high(u32::MAX - 8, 120, 68))).is_err());
+        assert!(parse(&uncropped(high(0, u32::MAX - 1, 68))).is_err());
+        assert!(parse(&uncropped(high(0, 120, u32::MAX / 16 + 1))).is_err());
+
+        let cropped = |left, right| high(0, 120, 68).bits(1, 1).ue(left).ue(right).ue(0).ue(0);
+        assert!(parse(&cropped(960, 0).bits(0, 1).nal()).is_err());
+        assert!(parse(&cropped(u32::MAX - 1, u32::MAX - 1).bits(0, 1).nal()).is_err());
+        assert!(parse(&cropped(959, 0).bits(0, 1).nal()).is_ok());
+    }
+}
@@ -0,0 +1,416 @@
+//! Describes media files in MPEG-DASH manifests: fragmented MP4 files are read through byte
+//! ranges given by their `sidx` box, and other files through the segments of their HLS package.
+//!
+//! Audio and video are multiplexed in the same segments, so manifests have a single
+//! representation of both rather than an adaptation set for each. Players that only take
+//! demultiplexed representations, like Shaka Player and dash.js, play HLS packages instead.
+
+use percent_encoding::utf8_percent_encode;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+use super::hls::{self, Package};
+use super::mp4::{self, SegmentIndex};
+use super::probe::{Container, MediaInfo, Stream, StreamKind};
+
+const MPD_NAMESPACE: &str = "urn:mpeg:dash:schema:mpd:2011";
+
+/// The profile of manifests giving the byte ranges of a single file.
+const ON_DEMAND_PROFILE: &str = "urn:mpeg:dash:profile:isoff-on-demand:2011";
+
+/// The profile of manifests listing separate segments.
+const LIVE_PROFILE: &str = "urn:mpeg:dash:profile:isoff-live:2011";
+
+/// The timescale of segment timelines, in ticks per second.
+const TIMESCALE: u32 = 1000;
+
+/// A manifest with a single representation of the media.
+#[derive(Debug, Serialize)]
+#[serde(rename = "MPD")]
+pub struct Mpd {
+    xmlns: &'static str,
+    #[serde(rename = "@profiles")]
+    profiles: &'static str,
+    #[serde(rename = "@type")]
+    kind: &'static str,
+    #[serde(rename = "@minBufferTime")]
+    min_buffer_time: &'static str,
+    #[serde(
+        rename = "@mediaPresentationDuration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    duration: Option<String>,
+    #[serde(rename = "")]
+    period: Period,
+}
+
+#[derive(Debug, Serialize)]
+struct Period {
+    #[serde(rename = "@id")]
+    id: &'static str,
+    #[serde(rename = "@start")]
+    start: &'static str,
+    #[serde(rename = "")]
+    adaptation_set: AdaptationSet,
+}
+
+#[derive(Debug, Serialize)]
+struct AdaptationSet {
+    #[serde(rename = "@mimeType")]
+    mime_type: &'static str,
+    #[serde(rename = "@segmentAlignment")]
+    segment_alignment: bool,
+    #[serde(rename = "@startWithSAP")]
+    start_with_sap: u8,
+    #[serde(rename = "")]
+    representation: Representation,
+}
+
+#[derive(Debug, Serialize)]
+struct Representation {
+    #[serde(rename = "@id")]
+    id: &'static str,
+    #[serde(rename = "@codecs", skip_serializing_if = "Option::is_none")]
+    codecs: Option<String>,
+    #[serde(rename = "@bandwidth")]
+    bandwidth: u64,
+    #[serde(rename = "@width", skip_serializing_if = "Option::is_none")]
+    width: Option<u32>,
+    #[serde(rename = "@height", skip_serializing_if = "Option::is_none")]
+    height: Option<u32>,
+    #[serde(rename = "@frameRate", skip_serializing_if = "Option::is_none")]
+    frame_rate: Option<String>,
+    #[serde(rename = "@audioSamplingRate", skip_serializing_if = "Option::is_none")]
+    audio_sampling_rate: Option<u32>,
+    #[serde(rename = "BaseURL", skip_serializing_if = "Option::is_none")]
+    base_url: Option<String>,
+    #[serde(rename = "", skip_serializing_if = "Option::is_none")]
+    segment_base: Option<SegmentBase>,
+    #[serde(rename = "", skip_serializing_if = "Option::is_none")]
+    segment_template: Option<SegmentTemplate>,
+}
+
+#[derive(Debug, Serialize)]
+struct SegmentBase {
+    #[serde(rename = "@indexRange")]
+    index_range: String,
+    #[serde(rename = "")]
+    initialization: Initialization,
+}
+
+#[derive(Debug, Serialize)]
+struct Initialization {
+    #[serde(rename = "@range")]
+    range: String,
+}
+
+#[derive(Debug, Serialize)]
+struct SegmentTemplate {
+    #[serde(rename = "@timescale")]
+    timescale: u32,
+    #[serde(rename = "@initialization")]
+    initialization: String,
+    #[serde(rename = "@media")]
+    media: String,
+    #[serde(rename = "@startNumber")]
+    start_number: usize,
+    #[serde(rename = "")]
+    timeline: SegmentTimeline,
+}
+
+#[derive(Debug, Serialize)]
+struct SegmentTimeline {
+    #[serde(rename = "")]
+    segments: Vec<TimelineEntry>,
+}
+
+/// Segments of the same duration following each other.
+#[derive(Debug, Serialize)]
+#[serde(rename = "S")]
+struct TimelineEntry {
+    #[serde(rename = "@t")]
+    time: u64,
+    #[serde(rename = "@d")]
+    duration: u64,
+    /// The number of segments repeating the first one.
+    #[serde(rename = "@r", skip_serializing_if = "is_zero")]
+    repeat: u64,
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+impl Mpd {
+    /// Describes the file at `path` named `name`, returning the manifest with the entity tag of
+    /// the file. Files without a `sidx` box are packaged like for HLS, with the audio track
+    /// chosen by its number, or the default one otherwise.
+    pub fn describe(
+        path: &Path,
+        info: &MediaInfo,
+        packages: &hls::Cache,
+        audio: Option<u64>,
+        name: &str,
+    ) -> io::Result<(Self, String)> {
+        if matches!(info.container, Container::Mp4 | Container::Mov) {
+            let file = File::open(path)?;
+            let metadata = file.metadata()?;
+            let index = mp4::segment_index(&mut BufReader::new(file), metadata.len())?;
+            if let Some(index) = index {
+                return Ok((Self::indexed(info, &index, name), hls::etag(&metadata)?));
+            }
+        }
+
+        let package = packages.package(path, info.container, audio)?;
+        Ok((Self::templated(&package, name), package.etag.clone()))
+    }
+
+    /// Describes a fragmented MP4 file named `name`, whose segments are the subsegments
+    /// referenced by its `sidx` box.
+    fn indexed(info: &MediaInfo, index: &SegmentIndex, name: &str) -> Self {
+        let mut representation = representation(&info.streams, info.bitrate.unwrap_or_default());
+        representation.base_url = Some(utf8_percent_encode(name, hls::NAME_ENCODE).to_string());
+        representation.segment_base = Some(SegmentBase {
+            index_range: range(index.index.start, index.index.end),
+            initialization: Initialization {
+                range: range(index.initialization.start, index.initialization.end),
+            },
+        });
+        Self::new(
+            ON_DEMAND_PROFILE,
+            info.duration,
+            &info.streams,
+            representation,
+        )
+    }
+
+    /// Describes the segments of the package of a file named `name`.
+    fn templated(package: &Package, name: &str) -> Self {
+        let (peak, _) = package.bandwidth();
+        let mut representation = representation(package.streams(), peak);
+
+        let mut segments: Vec<TimelineEntry> = Vec::new();
+        let mut time = 0.0;
+        for segment in &package.segments {
+            let start = (time * f64::from(TIMESCALE)).round() as u64;
+            time += segment.duration;
+            let duration = (time * f64::from(TIMESCALE)).round() as u64 - start;
+            match segments.last_mut() {
+                Some(last) if last.duration == duration => last.repeat += 1,
+                _ => segments.push(TimelineEntry {
+                    time: start,
+                    duration,
+                    repeat: 0,
+                }),
+            }
+        }
+        representation.segment_template = Some(SegmentTemplate {
+            timescale: TIMESCALE,
+            initialization: package.uri(name, "init"),
+            media: package.uri(name, "$Number$"),
+            start_number: 0,
+            timeline: SegmentTimeline { segments },
+        });
+        Self::new(LIVE_PROFILE, Some(time), package.streams(), representation)
+    }
+
+    fn new(
+        profiles: &'static str,
+        duration: Option<f64>,
+        streams: &[Stream],
+        representation: Representation,
+    ) -> Self {
+        let video = streams.iter().any(|s| s.kind == StreamKind::Video);
+        Self {
+            xmlns: MPD_NAMESPACE,
+            profiles,
+            kind: "static",
+            min_buffer_time: "PT2S",
+            duration: duration.map(|d| format!("PT{d:.3}S")),
+            period: Period {
+                id: "0",
+                start: "PT0S",
+                adaptation_set: AdaptationSet {
+                    mime_type: if video { "video/mp4" } else { "audio/mp4" },
+                    segment_alignment: true,
+                    start_with_sap: 1,
+                    representation,
+                },
+            },
+        }
+    }
+}
+
+/// Describes the audio and video streams of a file, which are multiplexed in its segments.
+fn representation(streams: &[Stream], bandwidth: u64) -> Representation {
+    let streams = streams
+        .iter()
+        .filter(|s| matches!(s.kind, StreamKind::Video | StreamKind::Audio))
+        .collect::<Vec<_>>();
+    let codecs = streams
+        .iter()
+        .map(|s| s.codecs.as_deref())
+        .collect::<Option<Vec<_>>>()
+        .map(|codecs| codecs.join(","));
+    let video = streams.iter().find(|s| s.kind == StreamKind::Video);
+    let audio = streams.iter().find(|s| s.kind == StreamKind::Audio);
+    Representation {
+        id: "0",
+        codecs,
+        bandwidth,
+        width: video.and_then(|s| s.width),
+        height: video.and_then(|s| s.height),
+        frame_rate: video.and_then(|s| s.frame_rate).and_then(frame_rate),
+        audio_sampling_rate: audio.and_then(|s| s.sample_rate),
+        base_url: None,
+        segment_base: None,
+        segment_template: None,
+    }
+}
+
+/// Formats a frame rate as an integer or a fraction, like `25` or `30000/1001`, since decimal
+/// frame rates are not valid in manifests.
+fn frame_rate(rate: f64) -> Option<String> {
+    let integer = |value: f64| {
+        let rounded = value.round();
+        ((value - rounded).abs() < 0.01 && 1.0 <= rounded).then_some(rounded as u64)
+    };
+    if let Some(rate) = integer(rate) {
+        Some(rate.to_string())
+    } else if let Some(rate) = integer(rate * 1.001) {
+        // NTSC rates, like 29.97 frames per second.
+        Some(format!("{}/1001", rate * 1000))
+    } else {
+        let numerator = (rate * 1000.0).round();
+        if !(1.0..=u64::MAX as f64).contains(&numerator) {
+            return None;
+        }
+        let numerator = numerator as u64;
+        let divisor = gcd(numerator, 1000);
+        Some(format!("{}/{}", numerator / divisor, 1000 / divisor))
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Formats the byte range from `start` to `end` excluded, as in HTTP `Range` headers.
+fn range(start: u64, end: u64) -> String {
+    format!("{start}-{}", end.saturating_sub(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::matroska::tests::{remove_sample, sample_file};
+    use crate::media::probe;
+    use crate::ser_xml;
+
+    fn xml(mpd: &Mpd) -> String {
+        String::from_utf8(ser_xml::to_bytes(mpd).unwrap().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn frame_rates() {
+        assert_eq!(frame_rate(25.0).as_deref(), Some("25"));
+        assert_eq!(frame_rate(59.999).as_deref(), Some("60"));
+        assert_eq!(frame_rate(29.97).as_deref(), Some("30000/1001"));
+        assert_eq!(frame_rate(23.976).as_deref(), Some("24000/1001"));
+        assert_eq!(frame_rate(12.5).as_deref(), Some("25/2"));
+        assert_eq!(frame_rate(0.0), None);
+        assert_eq!(frame_rate(f64::NAN), None);
+        assert_eq!(frame_rate(f64::INFINITY), None);
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(range(0, 1200), "0-1199");
+        assert_eq!(range(1200, 1201), "1200-1200");
+    }
+
+    #[test]
+    fn indexed() {
+        let mut video = Stream::new(1, StreamKind::Video, "h264");
+        video.codecs = Some("avc1.640028".to_string());
+        (video.width, video.height) = (Some(1920), Some(1080));
+        video.frame_rate = Some(29.97);
+        let mut audio = Stream::new(2, StreamKind::Audio, "aac");
+        audio.codecs = Some("mp4a.40.2".to_string());
+        audio.sample_rate = Some(48000);
+        let mut subtitle = Stream::new(3, StreamKind::Subtitle, "mov_text");
+        subtitle.codecs = Some("tx3g".to_string());
+        let mut info = MediaInfo::new(Container::Mp4);
+        info.duration = Some(10.0);
+        info.bitrate = Some(2_000_000);
+        info.streams = vec![video, audio, subtitle];
+        let index = SegmentIndex {
+            initialization: 0..1200,
+            index: 1200..1300,
+        };
+
+        // Subtitles are not multiplexed with the media.
+        assert_eq!(
+            xml(&Mpd::indexed(&info, &index, "a b.mp4")),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+             <MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\" \
+             profiles=\"urn:mpeg:dash:profile:isoff-on-demand:2011\" type=\"static\" \
+             minBufferTime=\"PT2S\" mediaPresentationDuration=\"PT10.000S\">\
+             <Period id=\"0\" start=\"PT0S\">\
+             <AdaptationSet mimeType=\"video/mp4\" segmentAlignment=\"true\" startWithSAP=\"1\">\
+             <Representation id=\"0\" codecs=\"avc1.640028,mp4a.40.2\" bandwidth=\"2000000\" \
+             width=\"1920\" height=\"1080\" frameRate=\"30000/1001\" audioSamplingRate=\"48000\">\
+             <BaseURL>a%20b.mp4</BaseURL>\
+             <SegmentBase indexRange=\"1200-1299\">\
+             <Initialization range=\"0-1199\" /></SegmentBase>\
+             </Representation></AdaptationSet></Period></MPD>"
+        );
+
+        // Audio files have an audio representation, without codecs if one is unknown.
+        info.streams.remove(0);
+        info.streams[0].codecs = None;
+        let manifest = xml(&Mpd::indexed(&info, &index, "a.m4a"));
+        assert!(manifest.contains(
+            "<AdaptationSet mimeType=\"audio/mp4\" segmentAlignment=\"true\" startWithSAP=\"1\">\
+             <Representation id=\"0\" bandwidth=\"2000000\" audioSamplingRate=\"48000\">"
+        ));
+    }
+
+    #[test]
+    fn templated() {
+        let path = sample_file();
+        let info = probe::probe(&path).unwrap().unwrap();
+        let packages = hls::Cache::default();
+        let (mpd, etag) = Mpd::describe(&path, &info, &packages, None, "a b.mkv").unwrap();
+        let package = packages.package(&path, info.container, None).unwrap();
+        assert_eq!(etag, package.etag);
+
+        // The two segments of 6 seconds are given by a single timeline entry.
+        let (peak, _) = package.bandwidth();
+        assert_eq!(
+            xml(&mpd),
+            format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+                 <MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\" \
+                 profiles=\"urn:mpeg:dash:profile:isoff-live:2011\" type=\"static\" \
+                 minBufferTime=\"PT2S\" mediaPresentationDuration=\"PT12.000S\">\
+                 <Period id=\"0\" start=\"PT0S\">\
+                 <AdaptationSet mimeType=\"video/mp4\" segmentAlignment=\"true\" \
+                 startWithSAP=\"1\">\
+                 <Representation id=\"0\" codecs=\"avc1.64001f,mp4a.40.2\" bandwidth=\"{peak}\" \
+                 width=\"320\" height=\"240\" frameRate=\"25\" audioSamplingRate=\"44100\">\
+                 <SegmentTemplate timescale=\"1000\" initialization=\"a%20b.mkv?kayo-hls=init\" \
+                 media=\"a%20b.mkv?kayo-hls=$Number$\" startNumber=\"0\">\
+                 <SegmentTimeline><S t=\"0\" d=\"6000\" r=\"1\" /></SegmentTimeline>\
+                 </SegmentTemplate></Representation></AdaptationSet></Period></MPD>"
+            )
+        );
+        remove_sample(&path);
+    }
+}
@@ -0,0 +1,531 @@
+//! Serves MP4 files whose `moov` box follows their media data as if it preceded it, so that
+//! players can start without fetching the end of the file first. The relocated `moov` box is
+//! built in memory and the rest is read from the original file.
+
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+use super::bits::{invalid_data, ByteReader};
+use super::fmp4::write_box;
+use super::hls::etag;
+use super::mp4::{boxes, full_box, read_body, read_header};
+
+/// The boxes holding the sample tables, whose chunk offsets are rewritten.
+const CONTAINERS: [&[u8; 4]; 4] = [b"trak", b"mdia", b"minf", b"stbl"];
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A part of the relocated file.
+enum Piece {
+    Data(Vec<u8>),
+    /// A range of the original file.
+    File(Range<u64>),
+}
+
+/// An MP4 file with its `moov` box moved before its media data.
+pub struct Layout {
+    pieces: Vec<Piece>,
+    /// The size of the relocated file.
+    pub len: u64,
+    /// Identifies the version of the original file.
+    pub etag: String,
+}
+
+impl Layout {
+    /// Writes the bytes in `range` of the relocated file, reading the original file at `path`.
+    pub fn write_range<W: Write>(
+        &self,
+        path: &Path,
+        range: Range<u64>,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        let mut file = File::open(path)?;
+        let mut start = 0;
+        for piece in &self.pieces {
+            let len = match piece {
+                Piece::Data(data) => data.len() as u64,
+                Piece::File(range) => range.end - range.start,
+            };
+            let (from, to) = (range.start.max(start), range.end.min(start + len));
+            if from < to {
+                let (from, to) = (from - start, to - start);
+                match piece {
+                    Piece::Data(data) => writer.write_all(&data[from as usize..to as usize])?,
+                    Piece::File(range) => {
+                        file.seek(io::SeekFrom::Start(range.start + from))?;
+                        let mut reader = (&mut file).take(to - from);
+                        let mut buffer = vec![0; CHUNK_SIZE];
+                        loop {
+                            let n = reader.read(&mut buffer)?;
+                            if n == 0 {
+                                break;
+                            }
+                            writer.write_all(&buffer[..n])?;
+                        }
+                        if reader.limit() != 0 {
+                            return Err(io::ErrorKind::UnexpectedEof.into());
+                        }
+                    }
+                }
+            }
+            start += len;
+        }
+        writer.flush()
+    }
+}
+
+/// Where the top-level boxes that are moved are in a file.
+struct Boxes {
+    len: u64,
+    /// The offset of the first `mdat` box.
+    media: u64,
+    /// The `moov` box.
+    moov: Range<u64>,
+}
+
+/// Finds the `moov` box of a file if it follows the media data and the file is not fragmented.
+fn find_boxes<R: Read + Seek>(reader: &mut R, len: u64) -> io::Result<Option<Boxes>> {
+    let mut media = None;
+    let mut moov = None;
+    let mut offset = 0;
+    while let Some(header) = read_header(reader, offset, len)? {
+        match &header.kind {
+            b"mdat" => {
+                media.get_or_insert(header.offset);
+            }
+            b"moov" => moov = Some(header.offset..header.end()),
+            b"moof" => return Ok(None),
+            _ => {}
+        }
+        offset = header.end();
+    }
+    Ok(match (media, moov) {
+        (Some(media), Some(moov)) if media < moov.start => Some(Boxes { len, media, moov }),
+        _ => None,
+    })
+}
+
+/// Builds the `moov` box with the chunk offsets of the samples moved by `shift`, returning
+/// whether any offset needs 64 bits. Chunk offsets are all written in 64 bits if `wide`.
+fn relocate(moov: &[u8], shift: &dyn Fn(u64) -> u64, wide: bool) -> io::Result<(Vec<u8>, bool)> {
+    fn rewrite(
+        data: &[u8],
+        out: &mut Vec<u8>,
+        shift: &dyn Fn(u64) -> u64,
+        wide: bool,
+    ) -> io::Result<bool> {
+        let mut overflow = false;
+        for child in boxes(data) {
+            let (kind, body) = child?;
+            let offsets = match &kind {
+                b"stco" | b"co64" => {
+                    let (_, _, mut r) = full_box(body)?;
+                    let count = r.u32()?;
+                    let offsets = (0..count)
+                        .map(|_| {
+                            if &kind == b"stco" {
+                                r.u32().map(u64::from)
+                            } else {
+                                r.u64()
+                            }
+                        })
+                        .map(|offset| offset.map(shift))
+                        .collect::<io::Result<Vec<_>>>()?;
+                    Some(offsets)
+                }
+                kind if CONTAINERS.contains(&kind) => {
+                    let mut result = Ok(false);
+                    write_box(out, kind, |out| result = rewrite(body, out, shift, wide));
+                    overflow |= result?;
+                    continue;
+                }
+                _ => None,
+            };
+
+            let Some(offsets) = offsets else {
+                write_box(out, &kind, |out| out.extend_from_slice(body));
+                continue;
+            };
+            let kind = if wide || &kind == b"co64" {
+                b"co64"
+            } else {
+                b"stco"
+            };
+            overflow |=
+                kind == b"stco" && offsets.iter().any(|offset| u64::from(u32::MAX) < *offset);
+            write_box(out, kind, |out| {
+                out.extend_from_slice(&[0; 4]); // version, flags
+                out.extend_from_slice(&(offsets.len() as u32).to_be_bytes());
+                for offset in offsets {
+                    if kind == b"co64" {
+                        out.extend_from_slice(&offset.to_be_bytes());
+                    } else {
+                        out.extend_from_slice(&(offset as u32).to_be_bytes());
+                    }
+                }
+            });
+        }
+        Ok(overflow)
+    }
+
+    let mut out = Vec::with_capacity(moov.len() + 8);
+    let mut result = Ok(false);
+    write_box(&mut out, b"moov", |out| {
+        result = rewrite(moov, out, shift, wide)
+    });
+    Ok((out, result?))
+}
+
+/// Reads the `moov` box of the file at `path`, returning the layout of the file with the box
+/// relocated, or `None` if it already precedes the media data.
+fn open(path: &Path, etag: String, moov: Option<Vec<u8>>) -> io::Result<Option<Layout>> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    let Some(found) = find_boxes(&mut reader, len)? else {
+        return Ok(None);
+    };
+
+    let moov = match moov {
+        Some(moov) => moov,
+        None => {
+            let header = read_header(&mut reader, found.moov.start, len)?
+                .ok_or_else(|| invalid_data("missing moov box"))?;
+            let body = read_body(&mut reader, &header)?;
+            // The size of the box is the same until chunk offsets need 64 bits.
+            let mut wide = false;
+            let mut size = found.moov.end - found.moov.start;
+            loop {
+                let shift = |offset: u64| {
+                    if found.moov.end <= offset {
+                        (offset - (found.moov.end - found.moov.start)).saturating_add(size)
+                    } else if found.media <= offset {
+                        offset.saturating_add(size)
+                    } else {
+                        offset
+                    }
+                };
+                let (moov, overflow) = relocate(&body, &shift, wide)?;
+                if overflow {
+                    wide = true;
+                } else if moov.len() as u64 == size {
+                    break moov;
+                } else {
+                    size = moov.len() as u64;
+                }
+            }
+        }
+    };
+
+    let relocated_len = len - (found.moov.end - found.moov.start) + moov.len() as u64;
+    let pieces = vec![
+        Piece::File(0..found.media),
+        Piece::Data(moov),
+        Piece::File(found.media..found.moov.start),
+        Piece::File(found.moov.end..found.len),
+    ];
+    Ok(Some(Layout {
+        pieces,
+        len: relocated_len,
+        etag,
+    }))
+}
+
+/// The number of files whose layout is kept by a [`Cache`].
+const MAX_CACHE_ENTRIES: usize = 64;
+
+type CacheEntry = (String, Option<Arc<Layout>>);
+
+/// Keeps the layouts of files until they are modified, and optionally persists their relocated
+/// `moov` boxes in a directory so that they are built only once.
+#[derive(Clone)]
+pub struct Cache {
+    entries: Arc<Mutex<HashMap<PathBuf, CacheEntry>>>,
+    directory: Option<PathBuf>,
+}
+
+impl Cache {
+    pub fn new(directory: Option<PathBuf>) -> Self {
+        Self {
+            entries: Arc::default(),
+            directory,
+        }
+    }
+
+    /// Returns the layout of the MP4 file at `path` with its `moov` box relocated, or `None`
+    /// if it already precedes the media data.
+    pub fn layout(&self, path: &Path) -> io::Result<Option<Arc<Layout>>> {
+        let etag = etag(&fs::metadata(path)?)?;
+        if let Some((e, layout)) = self.entries.lock().unwrap().get(path) {
+            if *e == etag {
+                return Ok(layout.clone());
+            }
+        }
+
+        let sidecar = self.sidecar(path);
+        let moov = sidecar
+            .as_deref()
+            .and_then(|sidecar| read_sidecar(sidecar, &etag));
+        let persisted = moov.is_some();
+        let layout = open(path, etag.clone(), moov)?.map(Arc::new);
+        if let (Some(sidecar), Some(layout), false) = (&sidecar, &layout, persisted) {
+            if let Err(e) = write_sidecar(sidecar, layout) {
+                warn!("failed to write {}: {e}", sidecar.display());
+            }
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        if MAX_CACHE_ENTRIES <= entries.len() {
+            entries.clear();
+        }
+        entries.insert(path.to_path_buf(), (etag, layout.clone()));
+        Ok(layout)
+    }
+
+    /// Returns the path of the file persisting the relocated `moov` box of the file at `path`.
+    fn sidecar(&self, path: &Path) -> Option<PathBuf> {
+        let digest = Sha256::digest(path.as_os_str().as_encoded_bytes());
+        let directory = self.directory.as_ref()?;
+        Some(directory.join(format!("{}.moov", hex::encode(digest))))
+    }
+}
+
+/// Reads a persisted `moov` box, which starts with the entity tag of the file it was built for
+/// on its own line.
+fn read_sidecar(path: &Path, etag: &str) -> Option<Vec<u8>> {
+    let data = fs::read(path).ok()?;
+    let moov = data.strip_prefix(etag.as_bytes())?.strip_prefix(b"\n")?;
+    let size = ByteReader::new(moov).u32().ok()?;
+    (size as usize == moov.len()).then(|| moov.to_vec())
+}
+
+fn write_sidecar(path: &Path, layout: &Layout) -> io::Result<()> {
+    let Some(Piece::Data(moov)) = layout.pieces.get(1) else {
+        return Ok(());
+    };
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+    // Written aside first, so that an interrupted write is never read.
+    let partial = path.with_extension("partial");
+    let mut data = Vec::with_capacity(layout.etag.len() + 1 + moov.len());
+    data.extend_from_slice(layout.etag.as_bytes());
+    data.push(b'\n');
+    data.extend_from_slice(moov);
+    fs::write(&partial, data)?;
+    fs::rename(partial, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::fmp4::write_full_box;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const FTYP: &[u8] = b"\0\0\0\x10ftypisom\0\0\x02\0";
+    /// The media data, whose samples start at 24 and 32 in the original file.
+    const MDAT: &[u8] = b"\0\0\0\x18mdatAAAAAAAABBBBBBBB";
+
+    fn temp_dir() -> PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = std::env::temp_dir().join(format!(
+            "kayo-faststart-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Returns a `moov` box with a track whose chunks are at `stco` and one whose chunks are at
+    /// `co64`.
+    fn moov(stco: &[u32], co64: &[u64]) -> Vec<u8> {
+        let track = |out: &mut Vec<u8>, chunks: &dyn Fn(&mut Vec<u8>)| {
+            write_box(out, b"trak", |out| {
+                write_full_box(out, b"tkhd", 0, 3, |out| out.extend_from_slice(&[0; 20]));
+                write_box(out, b"mdia", |out| {
+                    write_box(out, b"minf", |out| {
+                        write_box(out, b"stbl", |out| chunks(out));
+                    });
+                });
+            });
+        };
+
+        let mut out = Vec::new();
+        write_box(&mut out, b"moov", |out| {
+            write_full_box(out, b"mvhd", 0, 0, |out| out.extend_from_slice(&[0; 96]));
+            track(out, &|out| {
+                write_full_box(out, b"stco", 0, 0, |out| {
+                    out.extend_from_slice(&(stco.len() as u32).to_be_bytes());
+                    stco.iter()
+                        .for_each(|o| out.extend_from_slice(&o.to_be_bytes()));
+                });
+            });
+            track(out, &|out| {
+                write_full_box(out, b"co64", 0, 0, |out| {
+                    out.extend_from_slice(&(co64.len() as u32).to_be_bytes());
+                    co64.iter()
+                        .for_each(|o| out.extend_from_slice(&o.to_be_bytes()));
+                });
+            });
+        });
+        out
+    }
+
+    /// Writes a file with its `moov` box after its media data, returning its path and the file
+    /// as it should be served.
+    fn tail_moov(dir: &Path) -> (PathBuf, Vec<u8>) {
+        let original = moov(&[24, 32], &[28]);
+        let path = dir.join("tail.mp4");
+        fs::write(&path, [FTYP, MDAT, &original].concat()).unwrap();
+
+        let shift = original.len() as u64;
+        let relocated = moov(&[24 + shift as u32, 32 + shift as u32], &[28 + shift]);
+        (path, [FTYP, &relocated, MDAT].concat())
+    }
+
+    fn read(layout: &Layout, path: &Path, range: Range<u64>) -> Vec<u8> {
+        let mut out = Vec::new();
+        layout.write_range(path, range, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn relocated_layout() {
+        let dir = temp_dir();
+        let (path, expected) = tail_moov(&dir);
+        let layout = open(&path, "\"1-1\"".to_string(), None).unwrap().unwrap();
+        assert_eq!(layout.len, expected.len() as u64);
+        assert_eq!(read(&layout, &path, 0..layout.len), expected);
+
+        let moov_len = expected.len() - FTYP.len() - MDAT.len();
+        let mdat_start = (FTYP.len() + moov_len) as u64;
+        for range in [
+            0..8,
+            10..30,
+            mdat_start - 4..mdat_start + 12,
+            mdat_start + 8..layout.len,
+            layout.len - 1..layout.len,
+            5..5,
+        ] {
+            let (start, end) = (range.start as usize, range.end as usize);
+            assert_eq!(
+                read(&layout, &path, range.clone()),
+                &expected[start..end],
+                "{range:?}"
+            );
+        }
+
+        // Files that already start with their `moov` box, or that are fragmented, are served
+        // as they are.
+        let (tail, _) = tail_moov(&dir);
+        let front = dir.join("front.mp4");
+        fs::write(&front, [FTYP, &moov(&[0], &[]), MDAT].concat()).unwrap();
+        assert!(open(&front, String::new(), None).unwrap().is_none());
+        let fragmented = dir.join("fragmented.mp4");
+        let mut moof = Vec::new();
+        write_box(&mut moof, b"moof", |_| {});
+        fs::write(&fragmented, [&fs::read(&tail).unwrap(), &moof[..]].concat()).unwrap();
+        assert!(open(&fragmented, String::new(), None).unwrap().is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn wide_offsets() {
+        let original = moov(&[24, 32], &[28]);
+        let body = &original[8..];
+        let far = |offset| offset + u64::from(u32::MAX);
+
+        let (_, overflow) = relocate(body, &far, false).unwrap();
+        assert!(overflow);
+        let (relocated, overflow) = relocate(body, &far, true).unwrap();
+        assert!(!overflow);
+
+        // Both tracks now use `co64`, which takes 4 more bytes per chunk.
+        assert_eq!(relocated.len(), original.len() + 2 * 4);
+        let tables = |data: &[u8]| {
+            let mut tables = Vec::new();
+            let mut rest = data;
+            while let Some(index) = rest.windows(4).position(|w| w == b"co64" || w == b"stco") {
+                tables.push(rest[index..index + 4].to_vec());
+                rest = &rest[index + 4..];
+            }
+            tables
+        };
+        assert_eq!(tables(&relocated), [b"co64".to_vec(), b"co64".to_vec()]);
+        let first = relocated.windows(4).position(|w| w == b"co64").unwrap() + 12;
+        assert_eq!(
+            u64::from_be_bytes(relocated[first..first + 8].try_into().unwrap()),
+            24 + u64::from(u32::MAX)
+        );
+    }
+
+    #[test]
+    fn sidecars() {
+        let dir = temp_dir();
+        let (path, expected) = tail_moov(&dir);
+        let cache_dir = dir.join("cache");
+
+        let layout = Cache::new(Some(cache_dir.clone()))
+            .layout(&path)
+            .unwrap()
+            .unwrap();
+        assert_eq!(read(&layout, &path, 0..layout.len), expected);
+
+        let sidecars = fs::read_dir(&cache_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        assert_eq!(sidecars.len(), 1);
+        let sidecar = &sidecars[0];
+        assert_eq!(sidecar.extension().unwrap(), "moov");
+        let moov = &expected[FTYP.len()..expected.len() - MDAT.len()];
+        assert_eq!(
+            fs::read(sidecar).unwrap(),
+            [layout.etag.as_bytes(), b"\n", moov].concat()
+        );
+
+        // A persisted box is used as it is, without reading the original one.
+        let mut marked = fs::read(sidecar).unwrap();
+        let last = marked.len() - 1;
+        marked[last] ^= 0xff;
+        fs::write(sidecar, &marked).unwrap();
+        let layout = Cache::new(Some(cache_dir.clone()))
+            .layout(&path)
+            .unwrap()
+            .unwrap();
+        let served = read(&layout, &path, 0..layout.len);
+        assert_ne!(served, expected);
+        assert_eq!(served.len(), expected.len());
+
+        // Once the file changes, the box is built again and persisted over the old one.
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"\0\0\0\x08free").unwrap();
+        drop(file);
+        let layout = Cache::new(Some(cache_dir.clone()))
+            .layout(&path)
+            .unwrap()
+            .unwrap();
+        let served = read(&layout, &path, 0..layout.len);
+        assert_eq!(served, [&expected[..], b"\0\0\0\x08free"].concat());
+        assert!(fs::read(sidecar)
+            .unwrap()
+            .starts_with(format!("{}\n", layout.etag).as_bytes()));
+
+        // Truncated sidecars are ignored.
+        let data = fs::read(sidecar).unwrap();
+        fs::write(sidecar, &data[..data.len() - 1]).unwrap();
+        assert!(read_sidecar(sidecar, &layout.etag).is_none());
+        assert!(read_sidecar(sidecar, "\"0-0\"").is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,44 @@
+use std::io::{self, Read, Seek};
+
+use super::bits::{invalid_data, BitReader, ByteReader};
+use super::mp3;
+use super::probe::{read_at, Container, MediaInfo, Stream, StreamKind};
+
+/// Sets the details of a FLAC stream from the body of a STREAMINFO metadata block, returning the
+/// total number of samples if known.
+pub fn stream_info(stream: &mut Stream, data: &[u8]) -> io::Result<Option<u64>> {
+    let mut r = BitReader::new(data);
+    r.skip(80)?; // minimum and maximum block and frame sizes
+    let sample_rate = r.read(20)? as u32;
+    stream.channels = Some(r.read(3)? as u16 + 1);
+    stream.bit_depth = Some(r.read(5)? as u8 + 1);
+    let samples = r.read(36)?;
+    stream.sample_rate = Some(sample_rate);
+    Ok((samples != 0).then_some(samples))
+}
+
+pub fn probe<R: Read + Seek>(reader: &mut R, _len: u64) -> io::Result<MediaInfo> {
+    let header = read_at(reader, 0, 10)?;
+    let start = mp3::id3_len(&header) as u64;
+
+    let data = read_at(reader, start, 42)?;
+    let mut r = ByteReader::new(&data);
+    if r.bytes(4)? != b"fLaC" {
+        return Err(invalid_data("missing fLaC marker"));
+    }
+    if r.u8()? & 0x7f != 0 {
+        return Err(invalid_data("missing STREAMINFO block"));
+    }
+    let len = r.u24()? as usize;
+
+    let mut stream = Stream::new(0, StreamKind::Audio, "flac");
+    stream.codecs = Some("flac".to_string());
+    let samples = stream_info(&mut stream, r.bytes(len.min(34))?)?;
+
+    let mut info = MediaInfo::new(Container::Flac);
+    info.duration = samples
+        .zip(stream.sample_rate.filter(|rate| *rate != 0))
+        .map(|(samples, rate)| samples as f64 / f64::from(rate));
+    info.streams.push(stream);
+    Ok(info)
+}
@@ -0,0 +1,346 @@
+//! Writes fragmented MP4: an initialization segment describing the tracks, followed by movie
+//! fragments holding their samples.
+
+use super::probe::StreamKind;
+
+/// The timescale of the movie, and of the tracks of remuxed Matroska files, in ticks per second.
+pub const TIMESCALE: u32 = 1000;
+
+/// The flags of a sample that is decoded on its own.
+pub const SYNC_SAMPLE: u32 = 0x0200_0000;
+
+/// The flags of a sample that depends on others.
+pub const NON_SYNC_SAMPLE: u32 = 0x0101_0000;
+
+const MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+/// Appends a box of type `kind` whose body is written by `body`.
+pub fn write_box(out: &mut Vec<u8>, kind: &[u8; 4], body: impl FnOnce(&mut Vec<u8>)) {
+    let start = out.len();
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(kind);
+    body(out);
+    let size = (out.len() - start) as u32;
+    out[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+/// Appends a full box, whose body starts with a version and flags.
+pub fn write_full_box(
+    out: &mut Vec<u8>,
+    kind: &[u8; 4],
+    version: u8,
+    flags: u32,
+    body: impl FnOnce(&mut Vec<u8>),
+) {
+    write_box(out, kind, |out| {
+        out.extend_from_slice(&(u32::from(version) << 24 | flags).to_be_bytes());
+        body(out);
+    });
+}
+
+fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_matrix(out: &mut Vec<u8>) {
+    MATRIX.iter().for_each(|value| put_u32(out, *value));
+}
+
+/// Builds a visual sample entry holding the given configuration boxes.
+pub fn visual_sample_entry(format: &[u8; 4], width: u16, height: u16, config: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_box(&mut out, format, |out| {
+        out.extend_from_slice(&[0; 6]); // reserved
+        put_u16(out, 1); // data_reference_index
+        out.extend_from_slice(&[0; 16]); // pre_defined, reserved
+        put_u16(out, width);
+        put_u16(out, height);
+        put_u32(out, 0x0048_0000); // horizresolution
+        put_u32(out, 0x0048_0000); // vertresolution
+        put_u32(out, 0); // reserved
+        put_u16(out, 1); // frame_count
+        out.extend_from_slice(&[0; 32]); // compressorname
+        put_u16(out, 0x0018); // depth
+        put_u16(out, 0xffff); // pre_defined
+        out.extend_from_slice(config);
+    });
+    out
+}
+
+/// Builds an audio sample entry holding the given configuration boxes.
+pub fn audio_sample_entry(
+    format: &[u8; 4],
+    channels: u16,
+    sample_rate: u32,
+    config: &[u8],
+) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_box(&mut out, format, |out| {
+        out.extend_from_slice(&[0; 6]); // reserved
+        put_u16(out, 1); // data_reference_index
+        out.extend_from_slice(&[0; 8]); // reserved
+        put_u16(out, channels);
+        put_u16(out, 16); // samplesize
+        put_u32(out, 0); // pre_defined, reserved
+                         // Rates that do not fit in 16.16 fixed point are only given by the configuration.
+        let rate = if sample_rate <= 0xffff {
+            sample_rate
+        } else {
+            0
+        };
+        put_u32(out, rate << 16);
+        out.extend_from_slice(config);
+    });
+    out
+}
+
+/// Builds an `esds` box for an MPEG-4 audio stream.
+pub fn esds(object_type: u8, decoder_specific_info: &[u8]) -> Vec<u8> {
+    fn descriptor(out: &mut Vec<u8>, tag: u8, body: &[u8]) {
+        out.push(tag);
+        let len = body.len() as u32;
+        for shift in [21, 14, 7] {
+            out.push(0x80 | (len >> shift & 0x7f) as u8);
+        }
+        out.push((len & 0x7f) as u8);
+        out.extend_from_slice(body);
+    }
+
+    let mut decoder_config = vec![object_type, 0x15]; // streamType audio, upStream 0, reserved 1
+    decoder_config.extend_from_slice(&[0; 11]); // bufferSizeDB, maxBitrate, avgBitrate
+    if !decoder_specific_info.is_empty() {
+        descriptor(&mut decoder_config, 0x05, decoder_specific_info);
+    }
+
+    let mut es = vec![0, 1, 0]; // ES_ID, flags
+    descriptor(&mut es, 0x04, &decoder_config);
+    descriptor(&mut es, 0x06, &[0x02]); // SLConfigDescriptor
+
+    let mut out = Vec::new();
+    write_full_box(&mut out, b"esds", 0, 0, |out| descriptor(out, 0x03, &es));
+    out
+}
+
+/// Packs a language into an ISO 639-2 code, or `und` if it is not one.
+pub fn language(language: Option<&str>) -> [u8; 3] {
+    language
+        .and_then(|language| <[u8; 3]>::try_from(language.as_bytes()).ok())
+        .filter(|code| code.iter().all(u8::is_ascii_lowercase))
+        .unwrap_or(*b"und")
+}
+
+/// A track of the movie.
+pub struct Track {
+    pub id: u32,
+    pub kind: StreamKind,
+    /// The number of ticks per second of the times of the samples.
+    pub timescale: u32,
+    pub width: u16,
+    pub height: u16,
+    /// An ISO 639-2 language code.
+    pub language: [u8; 3],
+    pub sample_entry: Vec<u8>,
+}
+
+/// Builds the initialization segment of a movie lasting `duration` ticks if known.
+pub fn init_segment(tracks: &[Track], duration: Option<u64>) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_box(&mut out, b"ftyp", |out| {
+        out.extend_from_slice(b"iso6");
+        put_u32(out, 0);
+        out.extend_from_slice(b"iso6mp41");
+    });
+
+    write_box(&mut out, b"moov", |out| {
+        write_full_box(out, b"mvhd", 1, 0, |out| {
+            put_u64(out, 0); // creation_time
+            put_u64(out, 0); // modification_time
+            put_u32(out, TIMESCALE);
+            put_u64(out, 0); // duration, given by mehd
+            put_u32(out, 0x0001_0000); // rate
+            put_u16(out, 0x0100); // volume
+            out.extend_from_slice(&[0; 10]); // reserved
+            put_matrix(out);
+            out.extend_from_slice(&[0; 24]); // pre_defined
+            put_u32(
+                out,
+                tracks.iter().map(|t| t.id).max().unwrap_or_default() + 1,
+            );
+        });
+
+        for track in tracks {
+            write_track(out, track);
+        }
+
+        write_box(out, b"mvex", |out| {
+            if let Some(duration) = duration {
+                write_full_box(out, b"mehd", 1, 0, |out| put_u64(out, duration));
+            }
+            for track in tracks {
+                write_full_box(out, b"trex", 0, 0, |out| {
+                    put_u32(out, track.id);
+                    put_u32(out, 1); // default_sample_description_index
+                    put_u32(out, 0); // default_sample_duration
+                    put_u32(out, 0); // default_sample_size
+                    put_u32(out, 0); // default_sample_flags
+                });
+            }
+        });
+    });
+    out
+}
+
+fn write_track(out: &mut Vec<u8>, track: &Track) {
+    let video = track.kind == StreamKind::Video;
+    write_box(out, b"trak", |out| {
+        // track_enabled, track_in_movie
+        write_full_box(out, b"tkhd", 1, 3, |out| {
+            put_u64(out, 0); // creation_time
+            put_u64(out, 0); // modification_time
+            put_u32(out, track.id);
+            put_u32(out, 0); // reserved
+            put_u64(out, 0); // duration
+            out.extend_from_slice(&[0; 8]); // reserved
+            put_u16(out, 0); // layer
+            put_u16(out, 0); // alternate_group
+            put_u16(out, if video { 0 } else { 0x0100 }); // volume
+            put_u16(out, 0); // reserved
+            put_matrix(out);
+            put_u32(out, u32::from(track.width) << 16);
+            put_u32(out, u32::from(track.height) << 16);
+        });
+
+        write_box(out, b"mdia", |out| {
+            write_full_box(out, b"mdhd", 1, 0, |out| {
+                put_u64(out, 0); // creation_time
+                put_u64(out, 0); // modification_time
+                put_u32(out, track.timescale);
+                put_u64(out, 0); // duration
+                let language = track
+                    .language
+                    .iter()
+                    .fold(0, |packed, c| packed << 5 | u16::from(c - 0x60) & 0x1f);
+                put_u16(out, language);
+                put_u16(out, 0); // pre_defined
+            });
+
+            write_full_box(out, b"hdlr", 0, 0, |out| {
+                put_u32(out, 0); // pre_defined
+                out.extend_from_slice(if video { b"vide" } else { b"soun" });
+                out.extend_from_slice(&[0; 12]); // reserved
+                out.extend_from_slice(if video { b"Video\0" } else { b"Audio\0" });
+            });
+
+            write_box(out, b"minf", |out| {
+                if video {
+                    // graphicsmode, opcolor
+                    write_full_box(out, b"vmhd", 0, 1, |out| out.extend_from_slice(&[0; 8]));
+                } else {
+                    // balance, reserved
+                    write_full_box(out, b"smhd", 0, 0, |out| put_u32(out, 0));
+                }
+
+                write_box(out, b"dinf", |out| {
+                    write_full_box(out, b"dref", 0, 0, |out| {
+                        put_u32(out, 1);
+                        // The media data is in the same file.
+                        write_full_box(out, b"url ", 0, 1, |_| {});
+                    });
+                });
+
+                write_box(out, b"stbl", |out| {
+                    write_full_box(out, b"stsd", 0, 0, |out| {
+                        put_u32(out, 1);
+                        out.extend_from_slice(&track.sample_entry);
+                    });
+                    // The samples are all in the fragments.
+                    write_full_box(out, b"stts", 0, 0, |out| put_u32(out, 0));
+                    write_full_box(out, b"stsc", 0, 0, |out| put_u32(out, 0));
+                    write_full_box(out, b"stsz", 0, 0, |out| put_u64(out, 0));
+                    write_full_box(out, b"stco", 0, 0, |out| put_u32(out, 0));
+                });
+            });
+        });
+    });
+}
+
+/// A sample of a fragment, in decoding order.
+pub struct Sample {
+    pub duration: u32,
+    pub flags: u32,
+    /// The presentation time minus the decoding time.
+    pub composition_offset: i32,
+    pub data: Vec<u8>,
+}
+
+/// The samples of a track in a fragment.
+pub struct Run {
+    pub track: u32,
+    /// The decoding time of the first sample.
+    pub decode_time: u64,
+    pub samples: Vec<Sample>,
+}
+
+impl Run {
+    /// Appends the samples of a later run of the same track, ending the last sample where the
+    /// later run starts.
+    pub fn append(&mut self, run: Run) {
+        if let Some((last, samples)) = self.samples.split_last_mut() {
+            let start =
+                self.decode_time + samples.iter().map(|s| u64::from(s.duration)).sum::<u64>();
+            last.duration = run.decode_time.saturating_sub(start) as u32;
+        }
+        self.samples.extend(run.samples);
+    }
+}
+
+/// Builds a movie fragment, numbered from 1, and the media data of its samples.
+pub fn fragment(sequence: u32, runs: &[Run]) -> Vec<u8> {
+    let mut out = Vec::new();
+    // The positions of the data_offset fields, patched once the size of the moof box is known.
+    let mut data_offsets = Vec::new();
+    write_box(&mut out, b"moof", |out| {
+        write_full_box(out, b"mfhd", 0, 0, |out| put_u32(out, sequence));
+        for run in runs {
+            write_box(out, b"traf", |out| {
+                // default-base-is-moof
+                write_full_box(out, b"tfhd", 0, 0x02_0000, |out| put_u32(out, run.track));
+                write_full_box(out, b"tfdt", 1, 0, |out| put_u64(out, run.decode_time));
+                // data-offset, sample-duration, sample-size, sample-flags and
+                // sample-composition-time-offsets, which are signed in version 1.
+                write_full_box(out, b"trun", 1, 0x0f01, |out| {
+                    put_u32(out, run.samples.len() as u32);
+                    data_offsets.push(out.len());
+                    put_u32(out, 0);
+                    for sample in &run.samples {
+                        put_u32(out, sample.duration);
+                        put_u32(out, sample.data.len() as u32);
+                        put_u32(out, sample.flags);
+                        put_u32(out, sample.composition_offset as u32);
+                    }
+                });
+            });
+        }
+    });
+
+    let mut data_offset = out.len() + 8;
+    for (run, position) in runs.iter().zip(data_offsets) {
+        out[position..position + 4].copy_from_slice(&(data_offset as u32).to_be_bytes());
+        data_offset += run.samples.iter().map(|s| s.data.len()).sum::<usize>();
+    }
+
+    write_box(&mut out, b"mdat", |out| {
+        for sample in runs.iter().flat_map(|run| &run.samples) {
+            out.extend_from_slice(&sample.data);
+        }
+    });
+    out
+}
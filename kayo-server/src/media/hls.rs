@@ -0,0 +1,590 @@
+//! Packages media files for HTTP Live Streaming: playlists of fragmented MP4 segments cut at
+//! keyframes, which players seek in without downloading the whole file.
+//!
+//! Playlists refer to the other parts of a package by URIs without credentials, since the
+//! signature of a presigned request only covers its own query string. When requests have to be
+//! signed, the bucket policy has to let anyone get the objects that are streamed.
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+use super::bits::invalid_data;
+use super::fmp4::{self, Run, Sample, NON_SYNC_SAMPLE, SYNC_SAMPLE};
+use super::mp4::{self, SampleInfo, TrackSamples};
+use super::probe::{read_at, Container, Stream, StreamKind};
+use super::remux::Remuxer;
+
+/// The duration that segments are cut at, at the first keyframe after it.
+const TARGET_DURATION: f64 = 6.0;
+
+/// The characters escaped in the names of the objects that playlists refer to.
+pub const NAME_ENCODE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Where the samples of the segments are read from.
+enum Source {
+    /// A Matroska file, remuxed cluster by cluster.
+    Matroska,
+    /// An MP4 file, whose samples are copied.
+    Mp4(Vec<TrackSamples>),
+}
+
+/// Where the samples of a segment are in the file.
+enum Location {
+    /// The clusters from an offset until another one, or until the end of the file.
+    Clusters { start: u64, end: Option<u64> },
+    /// The range of samples of each track.
+    Samples(Vec<Range<usize>>),
+}
+
+/// A part of the media cut at a keyframe.
+pub struct Segment {
+    /// The duration in seconds.
+    pub duration: f64,
+    /// The number of bytes of media data.
+    size: u64,
+    location: Location,
+}
+
+/// A media file cut into segments.
+pub struct Package {
+    /// Identifies the version of the file that the segments were cut from.
+    pub etag: String,
+    /// The number of the audio track, or `None` for the default one.
+    audio: Option<u64>,
+    source: Source,
+    streams: Vec<Stream>,
+    pub init: Vec<u8>,
+    pub segments: Vec<Segment>,
+}
+
+impl Package {
+    /// Cuts the video track and an audio track of an MP4, Matroska or WebM file into segments.
+    /// The audio track is chosen by its number, or is the default one otherwise.
+    pub fn open(
+        path: &Path,
+        container: Container,
+        audio: Option<u64>,
+        etag: String,
+    ) -> io::Result<Self> {
+        let (source, streams, init, segments) = match container {
+            Container::Matroska | Container::Webm => open_matroska(path, audio)?,
+            Container::Mp4 | Container::Mov => open_mp4(path, audio)?,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("{container} files cannot be segmented"),
+                ))
+            }
+        };
+        Ok(Self {
+            etag,
+            audio,
+            source,
+            streams,
+            init,
+            segments,
+        })
+    }
+
+    /// The streams of the selected tracks.
+    pub fn streams(&self) -> &[Stream] {
+        &self.streams
+    }
+
+    /// Returns the peak and average bitrates of the segments, in bits per second.
+    pub fn bandwidth(&self) -> (u64, u64) {
+        let durations = self.segments.iter().map(|s| s.duration).sum::<f64>();
+        let size = self.segments.iter().map(|s| s.size).sum::<u64>() + self.init.len() as u64;
+        let average = bitrate(size, durations);
+        let peak = self
+            .segments
+            .iter()
+            .map(|s| bitrate(s.size, s.duration))
+            .max()
+            .unwrap_or(average);
+        (peak, average)
+    }
+
+    /// Writes the playlist listing the variant of the media, whose URIs are relative to the
+    /// object named `name`.
+    pub fn master_playlist(&self, name: &str) -> String {
+        let (peak, average) = self.bandwidth();
+        let mut attributes = format!("BANDWIDTH={peak},AVERAGE-BANDWIDTH={average}");
+        let codecs = self
+            .streams
+            .iter()
+            .map(|s| s.codecs.as_deref())
+            .collect::<Option<Vec<_>>>();
+        if let Some(codecs) = codecs {
+            write!(attributes, ",CODECS=\"{}\"", codecs.join(",")).unwrap();
+        }
+        if let Some(video) = self.streams.iter().find(|s| s.kind == StreamKind::Video) {
+            if let (Some(width), Some(height)) = (video.width, video.height) {
+                write!(attributes, ",RESOLUTION={width}x{height}").unwrap();
+            }
+            if let Some(rate) = video.frame_rate {
+                write!(attributes, ",FRAME-RATE={rate:.3}").unwrap();
+            }
+        }
+
+        format!(
+            "#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-INDEPENDENT-SEGMENTS\n\
+             #EXT-X-STREAM-INF:{attributes}\n{}\n",
+            self.uri(name, "media"),
+        )
+    }
+
+    /// Writes the playlist listing the segments, whose URIs are relative to the object named
+    /// `name`.
+    pub fn media_playlist(&self, name: &str) -> String {
+        let target = self
+            .segments
+            .iter()
+            .map(|s| s.duration)
+            .fold(TARGET_DURATION, f64::max)
+            .round();
+        let mut playlist = format!(
+            "#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-TARGETDURATION:{target}\n\
+             #EXT-X-MEDIA-SEQUENCE:0\n#EXT-X-PLAYLIST-TYPE:VOD\n#EXT-X-INDEPENDENT-SEGMENTS\n\
+             #EXT-X-MAP:URI=\"{}\"\n",
+            self.uri(name, "init"),
+        );
+        for (index, segment) in self.segments.iter().enumerate() {
+            let uri = self.uri(name, &index.to_string());
+            writeln!(playlist, "#EXTINF:{:.3},\n{uri}", segment.duration).unwrap();
+        }
+        playlist.push_str("#EXT-X-ENDLIST\n");
+        playlist
+    }
+
+    /// Returns the URI of a part of the package, relative to the object named `name`. It is not
+    /// signed, even if the playlist referring to it was requested with a presigned URL.
+    pub fn uri(&self, name: &str, part: &str) -> String {
+        let mut uri = format!("{}?kayo-hls={part}", utf8_percent_encode(name, NAME_ENCODE));
+        if let Some(audio) = self.audio {
+            write!(uri, "&kayo-audio={audio}").unwrap();
+        }
+        uri
+    }
+
+    /// Writes the segment numbered `index` from 0, read from the file at `path`.
+    pub fn write_segment<W: Write>(
+        &self,
+        path: &Path,
+        index: usize,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        let segment = self
+            .segments
+            .get(index)
+            .ok_or_else(|| invalid_data("no such segment"))?;
+        match (&self.source, &segment.location) {
+            (Source::Matroska, Location::Clusters { start, end }) => {
+                let mut remuxer = Remuxer::open(path, self.audio)?;
+                remuxer.write_fragment(*start, *end, index as u32 + 1, writer)?;
+            }
+            (Source::Mp4(tracks), Location::Samples(ranges)) => {
+                let mut reader = BufReader::new(File::open(path)?);
+                let mut runs = Vec::new();
+                for ((track, range), id) in tracks.iter().zip(ranges).zip(1..) {
+                    if range.is_empty() {
+                        continue;
+                    }
+                    let mut samples = Vec::with_capacity(range.len());
+                    for i in range.clone() {
+                        let info = track.samples[i];
+                        let duration = match track.samples.get(i + 1) {
+                            Some(next) => next.decode_time - info.decode_time,
+                            None => samples.last().map_or(0, |s: &Sample| s.duration.into()),
+                        };
+                        let data = read_at(&mut reader, info.offset, info.size.into())?;
+                        if data.len() != info.size as usize {
+                            return Err(io::ErrorKind::UnexpectedEof.into());
+                        }
+                        samples.push(Sample {
+                            duration: duration as u32,
+                            flags: if info.sync {
+                                SYNC_SAMPLE
+                            } else {
+                                NON_SYNC_SAMPLE
+                            },
+                            composition_offset: info.composition_offset,
+                            data,
+                        });
+                    }
+                    runs.push(Run {
+                        track: id,
+                        decode_time: track.samples[range.start].decode_time,
+                        samples,
+                    });
+                }
+                writer.write_all(&fmp4::fragment(index as u32 + 1, &runs))?;
+            }
+            _ => unreachable!("segment location does not match its source"),
+        }
+        writer.flush()
+    }
+}
+
+/// Converts a size in bytes over a duration in seconds to bits per second.
+fn bitrate(size: u64, duration: f64) -> u64 {
+    if 0.0 < duration {
+        (size as f64 * 8.0 / duration).ceil() as u64
+    } else {
+        0
+    }
+}
+
+/// Picks the indices of the keyframes that segments start at, given the time of each frame in
+/// seconds and whether it is a keyframe.
+fn cut(frames: impl IntoIterator<Item = (f64, bool)>) -> Vec<usize> {
+    let mut starts: Vec<(usize, f64)> = Vec::new();
+    for (i, (time, keyframe)) in frames.into_iter().enumerate() {
+        let due = starts
+            .last()
+            .is_none_or(|(_, start)| TARGET_DURATION <= time - start);
+        if keyframe && due || starts.is_empty() {
+            starts.push((i, time));
+        }
+    }
+    starts.into_iter().map(|(i, _)| i).collect()
+}
+
+/// Returns the durations of segments starting at `times`, the last one lasting until `end`.
+fn durations(times: &[f64], end: Option<f64>) -> Vec<f64> {
+    times
+        .iter()
+        .enumerate()
+        .map(|(i, start)| match times.get(i + 1).copied().or(end) {
+            Some(next) => (next - start).max(0.0),
+            None => TARGET_DURATION,
+        })
+        .collect()
+}
+
+type Parts = (Source, Vec<Stream>, Vec<u8>, Vec<Segment>);
+
+fn open_matroska(path: &Path, audio: Option<u64>) -> io::Result<Parts> {
+    let len = fs::metadata(path)?.len();
+    let mut remuxer = Remuxer::open(path, audio)?;
+    let keyframes = remuxer.keyframes()?;
+    let starts = cut(keyframes.iter().map(|(time, _)| (*time, true)));
+    if starts.is_empty() {
+        return Err(invalid_data("missing Cluster element"));
+    }
+
+    let mut times = starts.iter().map(|i| keyframes[*i].0).collect::<Vec<_>>();
+    // The first segment also holds what precedes the first keyframe.
+    times[0] = 0.0;
+    let durations = durations(&times, remuxer.duration());
+    let segments = starts
+        .iter()
+        .enumerate()
+        .map(|(i, start)| {
+            let end = starts.get(i + 1).map(|next| keyframes[*next].1);
+            let start = keyframes[*start].1;
+            Segment {
+                duration: durations[i],
+                size: end.unwrap_or(len).saturating_sub(start),
+                location: Location::Clusters { start, end },
+            }
+        })
+        .collect();
+
+    let streams = remuxer.streams().cloned().collect();
+    Ok((Source::Matroska, streams, remuxer.init_segment(), segments))
+}
+
+fn open_mp4(path: &Path, audio: Option<u64>) -> io::Result<Parts> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let (duration, tracks) = mp4::sample_tables(&mut BufReader::new(file), len)?;
+
+    let mut video = None;
+    let mut audio_track = None;
+    for track in tracks {
+        match track.stream.kind {
+            StreamKind::Video if video.is_none() => video = Some(track),
+            StreamKind::Audio
+                if audio_track.is_none() && audio.is_none_or(|n| n == track.stream.id) =>
+            {
+                audio_track = Some(track)
+            }
+            _ => {}
+        }
+    }
+    if let (Some(number), None) = (audio, &audio_track) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("there is no audio track {number}"),
+        ));
+    }
+    let tracks = video.into_iter().chain(audio_track).collect::<Vec<_>>();
+    let Some(main) = tracks.first() else {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "no video or audio track",
+        ));
+    };
+
+    // Segments are cut at the keyframes of the video, or of the only audio track, and the
+    // other track is cut at the same times.
+    let seconds = |track: &TrackSamples, sample: &SampleInfo| {
+        (sample.decode_time as i64 + i64::from(sample.composition_offset)) as f64
+            / f64::from(track.timescale.max(1))
+    };
+    let starts = cut(main.samples.iter().map(|s| (seconds(main, s), s.sync)));
+    if starts.is_empty() {
+        return Err(invalid_data("no samples"));
+    }
+    let mut times = starts
+        .iter()
+        .map(|i| seconds(main, &main.samples[*i]))
+        .collect::<Vec<_>>();
+    times[0] = 0.0;
+
+    let mut ranges = vec![Vec::new(); starts.len()];
+    for (t, track) in tracks.iter().enumerate() {
+        let bounds = if t == 0 {
+            starts.clone()
+        } else {
+            times
+                .iter()
+                .map(|time| track.samples.partition_point(|s| seconds(track, s) < *time))
+                .collect()
+        };
+        for (s, start) in bounds.iter().enumerate() {
+            let start = if s == 0 { 0 } else { *start };
+            let end = bounds.get(s + 1).copied().unwrap_or(track.samples.len());
+            ranges[s].push(start..end.max(start));
+        }
+    }
+
+    let durations = durations(&times, duration);
+    let segments = ranges
+        .into_iter()
+        .zip(durations)
+        .map(|(ranges, duration)| Segment {
+            duration,
+            size: tracks
+                .iter()
+                .zip(&ranges)
+                .map(|(track, range)| {
+                    track.samples[range.clone()]
+                        .iter()
+                        .map(|s| u64::from(s.size))
+                        .sum::<u64>()
+                })
+                .sum(),
+            location: Location::Samples(ranges),
+        })
+        .collect();
+
+    let headers = tracks
+        .iter()
+        .zip(1..)
+        .map(|(track, id)| fmp4::Track {
+            id,
+            kind: track.stream.kind,
+            timescale: track.timescale,
+            width: track.stream.width.unwrap_or_default() as u16,
+            height: track.stream.height.unwrap_or_default() as u16,
+            language: fmp4::language(track.stream.language.as_deref()),
+            sample_entry: track.sample_entry.clone(),
+        })
+        .collect::<Vec<_>>();
+    let duration = duration.map(|d| (d * f64::from(fmp4::TIMESCALE)) as u64);
+    let init = fmp4::init_segment(&headers, duration);
+    let streams = tracks.iter().map(|t| t.stream.clone()).collect();
+    Ok((Source::Mp4(tracks), streams, init, segments))
+}
+
+/// The number of packages kept by a [`Cache`].
+const MAX_CACHE_ENTRIES: usize = 64;
+
+/// A file and the number of the audio track packaged with its video.
+type CacheKey = (PathBuf, Option<u64>);
+
+/// Keeps the segments of files until they are modified, so that their playlists stay the same
+/// while they are played.
+#[derive(Clone, Default)]
+pub struct Cache(Arc<Mutex<HashMap<CacheKey, Arc<Package>>>>);
+
+impl Cache {
+    /// Returns the package of the file at `path` like [`Package::open`], cutting it only if
+    /// it is not cached.
+    pub fn package(
+        &self,
+        path: &Path,
+        container: Container,
+        audio: Option<u64>,
+    ) -> io::Result<Arc<Package>> {
+        let etag = etag(&fs::metadata(path)?)?;
+        let key = (path.to_path_buf(), audio);
+        if let Some(package) = self.0.lock().unwrap().get(&key) {
+            if package.etag == etag {
+                return Ok(package.clone());
+            }
+        }
+
+        let package = Arc::new(Package::open(path, container, audio, etag)?);
+        let mut entries = self.0.lock().unwrap();
+        if MAX_CACHE_ENTRIES <= entries.len() {
+            entries.clear();
+        }
+        entries.insert(key, package.clone());
+        Ok(package)
+    }
+}
+
+/// Derives an entity tag from the modification time and size of a file.
+pub fn etag(metadata: &fs::Metadata) -> io::Result<String> {
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok(format!(
+        "\"{:x}-{:x}\"",
+        modified.as_nanos(),
+        metadata.len()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::matroska::tests::{remove_sample, sample_file};
+    use crate::media::mp4::{boxes, find, find_all, full_box};
+
+    fn kinds(data: &[u8]) -> Vec<[u8; 4]> {
+        boxes(data).map(|b| b.unwrap().0).collect()
+    }
+
+    #[test]
+    fn cuts() {
+        // Segments start at the first keyframe at least 6 seconds after the previous start.
+        let frames = [
+            (0.0, true),
+            (2.0, false),
+            (4.0, true),
+            (6.0, false),
+            (6.5, true),
+            (7.0, true),
+            (12.5, true),
+        ];
+        assert_eq!(cut(frames), [0, 4, 6]);
+        // The first segment starts at the first frame, even if it is not a keyframe.
+        assert_eq!(cut([(0.5, false), (1.0, true), (7.0, true)]), [0, 2]);
+        assert!(cut([]).is_empty());
+    }
+
+    #[test]
+    fn segment_durations() {
+        assert_eq!(durations(&[0.0, 6.5, 12.5], Some(15.0)), [6.5, 6.0, 2.5]);
+        // Without a duration, the last segment is assumed to last the target duration.
+        assert_eq!(durations(&[0.0, 6.5], None), [6.5, TARGET_DURATION]);
+        // A duration ending before the last keyframe does not make it negative.
+        assert_eq!(durations(&[0.0, 6.5], Some(6.0)), [6.5, 0.0]);
+        assert_eq!(bitrate(1000, 2.0), 4000);
+        assert_eq!(bitrate(1000, 0.0), 0);
+    }
+
+    #[test]
+    fn playlists() {
+        let path = sample_file();
+        let package =
+            Package::open(&path, Container::Matroska, Some(2), "\"1-1\"".to_string()).unwrap();
+        let durations = package
+            .segments
+            .iter()
+            .map(|s| s.duration)
+            .collect::<Vec<_>>();
+        assert_eq!(durations, [6.0, 6.0]);
+
+        let media = package.media_playlist("my video.mkv");
+        assert_eq!(
+            media,
+            "#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-TARGETDURATION:6\n#EXT-X-MEDIA-SEQUENCE:0\n\
+             #EXT-X-PLAYLIST-TYPE:VOD\n#EXT-X-INDEPENDENT-SEGMENTS\n\
+             #EXT-X-MAP:URI=\"my%20video.mkv?kayo-hls=init&kayo-audio=2\"\n\
+             #EXTINF:6.000,\nmy%20video.mkv?kayo-hls=0&kayo-audio=2\n\
+             #EXTINF:6.000,\nmy%20video.mkv?kayo-hls=1&kayo-audio=2\n\
+             #EXT-X-ENDLIST\n"
+        );
+
+        let (peak, average) = package.bandwidth();
+        assert!(0 < peak && 0 < average);
+        let master = package.master_playlist("my video.mkv");
+        assert_eq!(
+            master,
+            format!(
+                "#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-INDEPENDENT-SEGMENTS\n\
+                 #EXT-X-STREAM-INF:BANDWIDTH={peak},AVERAGE-BANDWIDTH={average},\
+                 CODECS=\"avc1.64001f,mp4a.40.2\",RESOLUTION=320x240,FRAME-RATE=25.000\n\
+                 my%20video.mkv?kayo-hls=media&kayo-audio=2\n"
+            )
+        );
+
+        // The default audio track is not named in URIs.
+        let package = Package::open(&path, Container::Matroska, None, String::new()).unwrap();
+        assert_eq!(package.uri("a/b.mkv", "init"), "a%2Fb.mkv?kayo-hls=init");
+
+        let e = Package::open(&path, Container::Ogg, None, String::new())
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::Unsupported);
+        remove_sample(&path);
+    }
+
+    #[test]
+    fn segments() {
+        let path = sample_file();
+        let package = Package::open(&path, Container::Matroska, None, String::new()).unwrap();
+        assert_eq!(kinds(&package.init), [*b"ftyp", *b"moov"]);
+
+        // The second segment holds the clusters from 6 seconds, numbered from 1.
+        let mut out = Vec::new();
+        package.write_segment(&path, 1, &mut out).unwrap();
+        assert_eq!(kinds(&out), [*b"moof", *b"mdat"]);
+        let moof = find(&out, &[b"moof"]).unwrap();
+        let (_, _, mut mfhd) = full_box(find(moof, &[b"mfhd"]).unwrap()).unwrap();
+        assert_eq!(mfhd.u32().unwrap(), 2);
+        let trafs = find_all(moof, b"traf").collect::<Vec<_>>();
+        assert_eq!(trafs.len(), 2);
+        let (_, _, mut tfdt) = full_box(find(trafs[0], &[b"tfdt"]).unwrap()).unwrap();
+        assert_eq!(tfdt.u64().unwrap(), 6000);
+        let (_, _, mut trun) = full_box(find(trafs[0], &[b"trun"]).unwrap()).unwrap();
+        assert_eq!(trun.u32().unwrap(), 6);
+
+        let e = package
+            .write_segment(&path, 2, &mut Vec::new())
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        remove_sample(&path);
+    }
+
+    #[test]
+    fn cache() {
+        let path = sample_file();
+        let cache = Cache::default();
+        let package = cache.package(&path, Container::Matroska, None).unwrap();
+        assert_eq!(package.etag, etag(&fs::metadata(&path).unwrap()).unwrap());
+        let cached = cache.package(&path, Container::Matroska, None).unwrap();
+        assert!(Arc::ptr_eq(&package, &cached));
+        let other = cache.package(&path, Container::Matroska, Some(2)).unwrap();
+        assert!(!Arc::ptr_eq(&package, &other));
+
+        remove_sample(&path);
+    }
+}
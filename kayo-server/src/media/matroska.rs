@@ -0,0 +1,964 @@
+use std::io::{self, Read, Seek};
+
+use super::bits::{invalid_data, ByteReader};
+use super::codec::{self, Pcm};
+use super::probe::{read_at, Container, MediaInfo, Stream, StreamKind};
+
+const EBML: u32 = 0x1a45dfa3;
+const DOC_TYPE: u32 = 0x4282;
+const SEGMENT: u32 = 0x18538067;
+const SEEK_HEAD: u32 = 0x114d9b74;
+const SEEK: u32 = 0x4dbb;
+const SEEK_ID: u32 = 0x53ab;
+const SEEK_POSITION: u32 = 0x53ac;
+const INFO: u32 = 0x1549a966;
+const TIMESTAMP_SCALE: u32 = 0x2ad7b1;
+const DURATION: u32 = 0x4489;
+const TRACKS: u32 = 0x1654ae6b;
+const TRACK_ENTRY: u32 = 0xae;
+const TRACK_NUMBER: u32 = 0xd7;
+const TRACK_TYPE: u32 = 0x83;
+const FLAG_DEFAULT: u32 = 0x88;
+const DEFAULT_DURATION: u32 = 0x23e383;
+const NAME: u32 = 0x536e;
+const LANGUAGE: u32 = 0x22b59c;
+const LANGUAGE_BCP47: u32 = 0x22b59d;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63a2;
+const VIDEO: u32 = 0xe0;
+const PIXEL_WIDTH: u32 = 0xb0;
+const PIXEL_HEIGHT: u32 = 0xba;
+const AUDIO: u32 = 0xe1;
+const SAMPLING_FREQUENCY: u32 = 0xb5;
+const OUTPUT_SAMPLING_FREQUENCY: u32 = 0x78b5;
+const CHANNELS: u32 = 0x9f;
+const BIT_DEPTH: u32 = 0x6264;
+const CONTENT_ENCODINGS: u32 = 0x6d80;
+const CONTENT_ENCODING: u32 = 0x6240;
+const CONTENT_COMPRESSION: u32 = 0x5034;
+const CONTENT_COMP_ALGO: u32 = 0x4254;
+const CONTENT_COMP_SETTINGS: u32 = 0x4255;
+const CUES: u32 = 0x1c53bb6b;
+const CUE_POINT: u32 = 0xbb;
+const CUE_TIME: u32 = 0xb3;
+const CUE_TRACK_POSITIONS: u32 = 0xb7;
+const CUE_TRACK: u32 = 0xf7;
+const CUE_CLUSTER_POSITION: u32 = 0xf1;
+const CLUSTER: u32 = 0x1f43b675;
+const TIMESTAMP: u32 = 0xe7;
+const SIMPLE_BLOCK: u32 = 0xa3;
+const BLOCK_GROUP: u32 = 0xa0;
+const BLOCK: u32 = 0xa1;
+const BLOCK_DURATION: u32 = 0x9b;
+const REFERENCE_BLOCK: u32 = 0xfb;
+const CHAPTERS: u32 = 0x1043a770;
+const TAGS: u32 = 0x1254c367;
+const ATTACHMENTS: u32 = 0x1941a469;
+
+/// The largest top level element that is read in memory.
+const MAX_ELEMENT_LEN: u64 = 16 << 20;
+
+/// The header of an element in a file.
+struct Header {
+    id: u32,
+    header_len: u64,
+    /// The size of the body, or `None` if unknown.
+    size: Option<u64>,
+}
+
+fn element_id(r: &mut ByteReader) -> io::Result<u32> {
+    let first = r.u8()?;
+    let len = first.leading_zeros() + 1;
+    if 4 < len {
+        return Err(invalid_data("invalid element ID"));
+    }
+
+    let mut id = u32::from(first);
+    for _ in 1..len {
+        id = id << 8 | u32::from(r.u8()?);
+    }
+    Ok(id)
+}
+
+fn element_size(r: &mut ByteReader) -> io::Result<Option<u64>> {
+    let first = r.u8()?;
+    let len = first.leading_zeros() + 1;
+    if 8 < len {
+        return Err(invalid_data("invalid element size"));
+    }
+
+    let mask = 0xffu8.checked_shr(len).unwrap_or(0);
+    let mut size = u64::from(first & mask);
+    let mut unknown = first & mask == mask;
+    for _ in 1..len {
+        let byte = r.u8()?;
+        size = size << 8 | u64::from(byte);
+        unknown &= byte == 0xff;
+    }
+    Ok((!unknown).then_some(size))
+}
+
+fn read_header<R: Read + Seek>(reader: &mut R, offset: u64) -> io::Result<Header> {
+    let data = read_at(reader, offset, 12)?;
+    let mut r = ByteReader::new(&data);
+    let id = element_id(&mut r)?;
+    let size = element_size(&mut r)?;
+    Ok(Header {
+        id,
+        header_len: r.position() as u64,
+        size,
+    })
+}
+
+/// Returns the offset following the element at `offset`, whose body is `size` bytes.
+fn element_end(offset: u64, header: &Header, size: u64) -> io::Result<u64> {
+    offset
+        .checked_add(header.header_len)
+        .and_then(|body| body.checked_add(size))
+        .ok_or_else(|| invalid_data("invalid element size"))
+}
+
+fn read_body<R: Read + Seek>(reader: &mut R, offset: u64, header: &Header) -> io::Result<Vec<u8>> {
+    let size = header
+        .size
+        .ok_or_else(|| invalid_data("unknown element size"))?;
+    if MAX_ELEMENT_LEN < size {
+        return Err(invalid_data("element too large"));
+    }
+
+    read_at(reader, offset + header.header_len, size)
+}
+
+/// Iterates over the child elements of a master element, yielding their ID and body.
+fn elements(data: &[u8]) -> impl Iterator<Item = (u32, &[u8])> {
+    let mut r = ByteReader::new(data);
+    std::iter::from_fn(move || {
+        let id = element_id(&mut r).ok()?;
+        let size = element_size(&mut r).ok()?;
+        let size = size.map_or(r.remaining(), |size| size as usize);
+        Some((id, r.bytes(size).ok()?))
+    })
+}
+
+fn uint(data: &[u8]) -> u64 {
+    data.iter()
+        .fold(0, |value, byte| value << 8 | u64::from(*byte))
+}
+
+fn float(data: &[u8]) -> Option<f64> {
+    match data.len() {
+        4 => Some(f64::from(f32::from_be_bytes(data.try_into().unwrap()))),
+        8 => Some(f64::from_be_bytes(data.try_into().unwrap())),
+        _ => None,
+    }
+}
+
+fn string(data: &[u8]) -> String {
+    String::from_utf8_lossy(data)
+        .trim_end_matches('\0')
+        .to_string()
+}
+
+/// The headers of a Matroska segment, and where to find its clusters.
+pub struct Segment {
+    pub container: Container,
+    /// The offset of the segment body, which positions are relative to.
+    offset: u64,
+    end: u64,
+    /// The duration of a timestamp tick in nanoseconds.
+    pub timestamp_scale: u64,
+    /// The duration in seconds.
+    pub duration: Option<f64>,
+    pub tracks: Vec<Track>,
+    first_cluster: Option<u64>,
+    cues: Option<u64>,
+}
+
+/// A track entry and what is needed to read its frames.
+pub struct Track {
+    pub stream: Stream,
+    pub codec_private: Option<Vec<u8>>,
+    /// The duration of a frame in nanoseconds.
+    pub default_duration: Option<u64>,
+    /// The bytes stripped from the start of every frame, with header stripping compression.
+    pub stripped_header: Vec<u8>,
+    /// Whether frames are compressed or encrypted otherwise.
+    pub encoded: bool,
+}
+
+/// A cue point, giving the cluster to start reading from to present a track from a time.
+pub struct CuePoint {
+    /// The time in timestamp ticks.
+    pub time: u64,
+    pub track: u64,
+    /// The offset of the cluster in the file.
+    pub cluster: u64,
+}
+
+/// A cluster of blocks.
+pub struct Cluster {
+    pub blocks: Vec<Block>,
+    /// The offset following the cluster.
+    pub end: u64,
+}
+
+/// The frames of a track starting at the same time, more than one with lacing.
+pub struct Block {
+    pub track: u64,
+    /// The presentation time of the first frame in timestamp ticks.
+    pub timestamp: i64,
+    pub keyframe: bool,
+    /// The duration in timestamp ticks, if stored.
+    pub duration: Option<u64>,
+    pub frames: Vec<Vec<u8>>,
+}
+
+pub fn probe<R: Read + Seek>(reader: &mut R, len: u64) -> io::Result<MediaInfo> {
+    let segment = Segment::open(reader, len)?;
+    let mut media = MediaInfo::new(segment.container);
+    media.duration = segment.duration;
+    media.streams = segment.tracks.into_iter().map(|t| t.stream).collect();
+    Ok(media)
+}
+
+impl Segment {
+    /// Reads the headers of the first segment of a file.
+    pub fn open<R: Read + Seek>(reader: &mut R, len: u64) -> io::Result<Self> {
+        let header = read_header(reader, 0)?;
+        if header.id != EBML {
+            return Err(invalid_data("missing EBML header"));
+        }
+        let ebml = read_body(reader, 0, &header)?;
+        let container = match elements(&ebml).find(|(id, _)| *id == DOC_TYPE) {
+            Some((_, doc_type)) if doc_type == b"webm" => Container::Webm,
+            _ => Container::Matroska,
+        };
+
+        let offset = element_end(0, &header, header.size.unwrap_or_default())?;
+        let header = read_header(reader, offset)?;
+        if header.id != SEGMENT {
+            return Err(invalid_data("missing Segment element"));
+        }
+        let segment = offset + header.header_len;
+        let end = header
+            .size
+            .and_then(|size| segment.checked_add(size))
+            .map_or(len, |end| len.min(end));
+
+        let mut positions = Vec::new();
+        let mut info = None;
+        let mut tracks = None;
+        let mut cues = None;
+        let mut first_cluster = None;
+        let mut offset = segment;
+        while offset < end {
+            let header = read_header(reader, offset)?;
+            match header.id {
+                SEEK_HEAD => {
+                    let seek_head = read_body(reader, offset, &header)?;
+                    for (_, seek) in elements(&seek_head).filter(|(id, _)| *id == SEEK) {
+                        let mut id = None;
+                        let mut position = None;
+                        for (child, data) in elements(seek) {
+                            match child {
+                                SEEK_ID => id = Some(uint(data) as u32),
+                                SEEK_POSITION => position = Some(uint(data)),
+                                _ => {}
+                            }
+                        }
+                        let position = position.and_then(|p| segment.checked_add(p));
+                        if let (Some(id), Some(position)) = (id, position) {
+                            positions.push((id, position));
+                        }
+                    }
+                }
+                INFO => info = Some(read_body(reader, offset, &header)?),
+                TRACKS => tracks = Some(read_body(reader, offset, &header)?),
+                CUES => cues = Some(offset),
+                CLUSTER => {
+                    first_cluster = Some(offset);
+                    break;
+                }
+                _ => {}
+            }
+
+            let Some(size) = header.size else {
+                break;
+            };
+            offset = element_end(offset, &header, size)?;
+        }
+
+        // Elements after the clusters are only found through the seek head.
+        for (id, position) in positions {
+            if position >= end {
+                continue;
+            }
+            match id {
+                INFO | TRACKS => {
+                    let slot = if id == INFO { &mut info } else { &mut tracks };
+                    if slot.is_none() {
+                        let header = read_header(reader, position)?;
+                        if header.id == id {
+                            *slot = Some(read_body(reader, position, &header)?);
+                        }
+                    }
+                }
+                CUES => {
+                    cues.get_or_insert(position);
+                }
+                _ => {}
+            }
+        }
+
+        let mut timestamp_scale = 1_000_000;
+        let mut duration = None;
+        if let Some(info) = info {
+            for (id, data) in elements(&info) {
+                match id {
+                    TIMESTAMP_SCALE => timestamp_scale = uint(data).max(1),
+                    DURATION => duration = float(data),
+                    _ => {}
+                }
+            }
+        }
+
+        let mut entries = Vec::new();
+        if let Some(tracks) = tracks {
+            for (_, entry) in elements(&tracks).filter(|(id, _)| *id == TRACK_ENTRY) {
+                if let Some(track) = parse_track(entry)? {
+                    entries.push(track);
+                }
+            }
+        }
+
+        Ok(Self {
+            container,
+            offset: segment,
+            end,
+            timestamp_scale,
+            duration: duration
+                .map(|duration| duration * timestamp_scale as f64 / 1e9)
+                .filter(|duration| 0.0 < *duration),
+            tracks: entries,
+            first_cluster,
+            cues,
+        })
+    }
+
+    /// The offset of the first cluster, if any.
+    pub fn first_cluster(&self) -> Option<u64> {
+        self.first_cluster
+    }
+
+    /// Reads the index of the clusters, which is empty if the segment has none.
+    pub fn cues<R: Read + Seek>(&self, reader: &mut R) -> io::Result<Vec<CuePoint>> {
+        let Some(offset) = self.cues else {
+            return Ok(Vec::new());
+        };
+        let header = read_header(reader, offset)?;
+        if header.id != CUES {
+            return Ok(Vec::new());
+        }
+
+        let cues = read_body(reader, offset, &header)?;
+        let mut points = Vec::new();
+        for (_, point) in elements(&cues).filter(|(id, _)| *id == CUE_POINT) {
+            let mut time = None;
+            for (id, data) in elements(point) {
+                match id {
+                    CUE_TIME => time = Some(uint(data)),
+                    CUE_TRACK_POSITIONS => {
+                        let mut track = None;
+                        let mut cluster = None;
+                        for (id, data) in elements(data) {
+                            match id {
+                                CUE_TRACK => track = Some(uint(data)),
+                                CUE_CLUSTER_POSITION => cluster = Some(uint(data)),
+                                _ => {}
+                            }
+                        }
+                        let cluster = cluster.and_then(|c| self.offset.checked_add(c));
+                        if let (Some(time), Some(track), Some(cluster)) = (time, track, cluster) {
+                            points.push(CuePoint {
+                                time,
+                                track,
+                                cluster,
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(points)
+    }
+
+    /// Finds the cluster at or after `offset` without reading its blocks, returning its offset,
+    /// its timestamp and the offset following it if its size is known.
+    pub fn cluster_timestamp<R: Read + Seek>(
+        &self,
+        reader: &mut R,
+        offset: u64,
+    ) -> io::Result<Option<(u64, u64, Option<u64>)>> {
+        let Some((offset, header)) = self.find_cluster(reader, offset)? else {
+            return Ok(None);
+        };
+        // The timestamp is the first child of a cluster.
+        let body = offset + header.header_len;
+        let data = read_at(reader, body, 16)?;
+        let timestamp = match elements(&data).next() {
+            Some((TIMESTAMP, data)) => uint(data),
+            _ => return Err(invalid_data("missing cluster timestamp")),
+        };
+        Ok(Some((
+            offset,
+            timestamp,
+            header.size.and_then(|size| body.checked_add(size)),
+        )))
+    }
+
+    fn find_cluster<R: Read + Seek>(
+        &self,
+        reader: &mut R,
+        mut offset: u64,
+    ) -> io::Result<Option<(u64, Header)>> {
+        while offset < self.end {
+            let header = match read_header(reader, offset) {
+                Ok(header) => header,
+                // Files that are still being written end with a partial element.
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            if header.id == CLUSTER {
+                return Ok(Some((offset, header)));
+            }
+            let Some(size) = header.size else {
+                return Ok(None);
+            };
+            offset = element_end(offset, &header, size)?;
+        }
+        Ok(None)
+    }
+
+    /// Reads the cluster at or after `offset`, or returns `None` past the last one.
+    pub fn read_cluster<R: Read + Seek>(
+        &self,
+        reader: &mut R,
+        offset: u64,
+    ) -> io::Result<Option<Cluster>> {
+        let Some((offset, header)) = self.find_cluster(reader, offset)? else {
+            return Ok(None);
+        };
+        let body = offset + header.header_len;
+        let end = header
+            .size
+            .and_then(|size| body.checked_add(size))
+            .map_or(self.end, |end| self.end.min(end));
+
+        let mut timestamp = 0;
+        let mut blocks = Vec::new();
+        let mut offset = body;
+        while offset < end {
+            // The blocks read so far are kept when the rest is truncated or damaged.
+            let child = match read_header(reader, offset) {
+                Ok(child) => child,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::UnexpectedEof | io::ErrorKind::InvalidData
+                    ) =>
+                {
+                    offset = end;
+                    break;
+                }
+                Err(e) => return Err(e),
+            };
+            // A cluster of unknown size ends where another top level element starts.
+            if header.size.is_none() && is_top_level(child.id) {
+                break;
+            }
+            let size = child
+                .size
+                .ok_or_else(|| invalid_data("unknown element size"))?;
+
+            if matches!(child.id, TIMESTAMP | SIMPLE_BLOCK | BLOCK_GROUP) {
+                let data = read_body(reader, offset, &child)?;
+                if (data.len() as u64) < size {
+                    offset = end;
+                    break;
+                }
+                match child.id {
+                    TIMESTAMP => timestamp = uint(&data),
+                    SIMPLE_BLOCK => blocks.push(block(&data, true)?),
+                    _ => {
+                        if let Some(block) = block_group(&data)? {
+                            blocks.push(block);
+                        }
+                    }
+                }
+            }
+            offset = element_end(offset, &child, size)?;
+        }
+
+        let timestamp = i64::try_from(timestamp).unwrap_or(i64::MAX);
+        for block in &mut blocks {
+            block.timestamp = block.timestamp.saturating_add(timestamp);
+        }
+        Ok(Some(Cluster {
+            blocks,
+            end: offset,
+        }))
+    }
+}
+
+fn is_top_level(id: u32) -> bool {
+    matches!(
+        id,
+        EBML | SEGMENT | SEEK_HEAD | INFO | TRACKS | CUES | CLUSTER | CHAPTERS | TAGS | ATTACHMENTS
+    )
+}
+
+/// Parses a `SimpleBlock` or the `Block` of a group, with a timestamp relative to its cluster.
+fn block(data: &[u8], simple: bool) -> io::Result<Block> {
+    let mut r = ByteReader::new(data);
+    let track = element_size(&mut r)?.ok_or_else(|| invalid_data("invalid track number"))?;
+    let timestamp = i64::from(r.u16()? as i16);
+    let flags = r.u8()?;
+
+    let lacing = flags >> 1 & 3;
+    let mut frames = Vec::new();
+    if lacing != 0 {
+        let count = usize::from(r.u8()?) + 1;
+        let mut sizes = Vec::with_capacity(count);
+        match lacing {
+            // Xiph lacing
+            1 => {
+                for _ in 1..count {
+                    let mut size = 0;
+                    loop {
+                        let byte = r.u8()?;
+                        size += usize::from(byte);
+                        if byte != 0xff {
+                            break;
+                        }
+                    }
+                    sizes.push(size);
+                }
+            }
+            // EBML lacing, with the differences between sizes after the first one
+            3 => {
+                let mut size = element_size(&mut r)?.ok_or_else(|| invalid_data("invalid lace"))?;
+                sizes.push(size as usize);
+                for _ in 2..count {
+                    size = size.wrapping_add_signed(signed_size(&mut r)?);
+                    sizes.push(size as usize);
+                }
+            }
+            // Fixed-size lacing
+            _ => sizes.resize(count - 1, r.remaining() / count),
+        }
+        for size in sizes {
+            frames.push(r.bytes(size)?.to_vec());
+        }
+    }
+    frames.push(r.rest().to_vec());
+
+    Ok(Block {
+        track,
+        timestamp,
+        keyframe: simple && flags & 0x80 != 0,
+        duration: None,
+        frames,
+    })
+}
+
+fn signed_size(r: &mut ByteReader) -> io::Result<i64> {
+    let first = r.u8()?;
+    let len = first.leading_zeros() + 1;
+    if 8 < len {
+        return Err(invalid_data("invalid lace size"));
+    }
+
+    let mut value = i64::from(first & 0xff >> len);
+    for _ in 1..len {
+        value = value << 8 | i64::from(r.u8()?);
+    }
+    Ok(value - ((1 << (7 * len - 1)) - 1))
+}
+
+fn block_group(data: &[u8]) -> io::Result<Option<Block>> {
+    let mut block = None;
+    let mut duration = None;
+    let mut referenced = false;
+    for (id, data) in elements(data) {
+        match id {
+            BLOCK => block = Some(self::block(data, false)?),
+            BLOCK_DURATION => duration = Some(uint(data)),
+            REFERENCE_BLOCK => referenced = true,
+            _ => {}
+        }
+    }
+    Ok(block.map(|block| Block {
+        keyframe: !referenced,
+        duration,
+        ..block
+    }))
+}
+
+fn parse_track(entry: &[u8]) -> io::Result<Option<Track>> {
+    let mut number = 0;
+    let mut kind = None;
+    let mut codec_id = String::new();
+    let mut codec_private = None;
+    let mut language = None;
+    let mut language_bcp47 = None;
+    let mut name = None;
+    let mut default = true;
+    let mut default_duration = None;
+    let mut video = None;
+    let mut audio = None;
+    let mut encodings = None;
+    for (id, data) in elements(entry) {
+        match id {
+            TRACK_NUMBER => number = uint(data),
+            TRACK_TYPE => {
+                kind = match uint(data) {
+                    1 => Some(StreamKind::Video),
+                    2 => Some(StreamKind::Audio),
+                    17 => Some(StreamKind::Subtitle),
+                    _ => Some(StreamKind::Data),
+                }
+            }
+            CODEC_ID => codec_id = string(data),
+            CODEC_PRIVATE => codec_private = Some(data),
+            LANGUAGE => language = Some(string(data)),
+            LANGUAGE_BCP47 => language_bcp47 = Some(string(data)),
+            NAME => name = Some(string(data)),
+            FLAG_DEFAULT => default = uint(data) != 0,
+            DEFAULT_DURATION => default_duration = Some(uint(data)),
+            VIDEO => video = Some(data),
+            AUDIO => audio = Some(data),
+            CONTENT_ENCODINGS => encodings = Some(data),
+            _ => {}
+        }
+    }
+    let Some(kind) = kind else {
+        return Ok(None);
+    };
+
+    let codec = match codec_id.as_str() {
+        "V_MPEG4/ISO/AVC" => "h264",
+        "V_MPEGH/ISO/HEVC" => "hevc",
+        "V_VP8" => "vp8",
+        "V_VP9" => "vp9",
+        "V_AV1" => "av1",
+        "V_MPEG1" => "mpeg1video",
+        "V_MPEG2" => "mpeg2video",
+        "V_MPEG4/ISO/SP" | "V_MPEG4/ISO/ASP" | "V_MPEG4/ISO/AP" => "mpeg4",
+        "V_THEORA" => "theora",
+        "V_PRORES" => "prores",
+        "A_AC3" => "ac3",
+        "A_EAC3" => "eac3",
+        "A_TRUEHD" => "truehd",
+        "A_OPUS" => "opus",
+        "A_VORBIS" => "vorbis",
+        "A_FLAC" => "flac",
+        "A_ALAC" => "alac",
+        "A_MPEG/L3" => "mp3",
+        "A_MPEG/L2" => "mp2",
+        "S_TEXT/UTF8" => "subrip",
+        "S_TEXT/ASS" | "S_TEXT/SSA" | "S_ASS" | "S_SSA" => "ass",
+        "S_TEXT/WEBVTT" => "webvtt",
+        "S_VOBSUB" => "dvd_subtitle",
+        "S_HDMV/PGS" => "hdmv_pgs_subtitle",
+        "S_DVBSUB" => "dvb_subtitle",
+        id if id.starts_with("A_AAC") => "aac",
+        id if id.starts_with("A_DTS") => "dts",
+        id => id,
+    };
+
+    let mut stream = Stream::new(number, kind, codec);
+    stream.default = Some(default);
+    stream.title = name.filter(|name| !name.is_empty());
+    stream.language = language_bcp47
+        .or(language)
+        .or_else(|| Some("eng".to_string()))
+        .filter(|language| language != "und");
+
+    if let Some(video) = video {
+        for (id, data) in elements(video) {
+            match id {
+                PIXEL_WIDTH => stream.width = Some(uint(data) as u32),
+                PIXEL_HEIGHT => stream.height = Some(uint(data) as u32),
+                _ => {}
+            }
+        }
+        stream.frame_rate = default_duration
+            .filter(|duration| *duration != 0)
+            .map(|duration| 1e9 / duration as f64);
+    }
+
+    if let Some(audio) = audio {
+        let mut output_sampling_frequency = None;
+        stream.channels = Some(1);
+        for (id, data) in elements(audio) {
+            match id {
+                SAMPLING_FREQUENCY => stream.sample_rate = float(data).map(|f| f as u32),
+                OUTPUT_SAMPLING_FREQUENCY => output_sampling_frequency = float(data),
+                CHANNELS => stream.channels = Some(uint(data) as u16),
+                BIT_DEPTH => stream.bit_depth = Some(uint(data) as u8),
+                _ => {}
+            }
+        }
+        if let Some(frequency) = output_sampling_frequency {
+            stream.sample_rate = Some(frequency as u32);
+        }
+
+        let bits = stream.bit_depth.map_or(16, u16::from);
+        let pcm = match codec_id.as_str() {
+            // 8-bit integer samples are unsigned in Matroska, and wider ones signed.
+            "A_PCM/INT/LIT" | "A_PCM/INT/BIG" if bits == 8 => Some((Pcm::Unsigned, false)),
+            "A_PCM/INT/LIT" => Some((Pcm::Signed, false)),
+            "A_PCM/INT/BIG" => Some((Pcm::Signed, true)),
+            "A_PCM/FLOAT/IEEE" => Some((Pcm::Float, false)),
+            _ => None,
+        };
+        if let Some((format, big_endian)) = pcm {
+            stream.codec = codec::pcm(format, bits, big_endian);
+        }
+    }
+
+    match (stream.codec.as_str(), codec_private) {
+        ("h264", Some(data)) => codec::avc_config(&mut stream, data)?,
+        ("hevc", Some(data)) => codec::hevc_config(&mut stream, "hvc1", data)?,
+        ("av1", Some(data)) => codec::av1_config(&mut stream, data)?,
+        ("aac", Some(data)) => codec::audio_specific_config(&mut stream, data)?,
+        ("aac", None) => stream.codecs = Some("mp4a.40.2".to_string()),
+        ("opus" | "vorbis" | "flac" | "vp8", _) => stream.codecs = Some(stream.codec.clone()),
+        ("mp3", _) => stream.codecs = Some("mp4a.40.34".to_string()),
+        ("ac3", _) => stream.codecs = Some("ac-3".to_string()),
+        ("eac3", _) => stream.codecs = Some("ec-3".to_string()),
+        _ => {}
+    }
+
+    let mut stripped_header = Vec::new();
+    let mut encoded = false;
+    let encodings = elements(encodings.unwrap_or_default());
+    for (_, encoding) in encodings.filter(|(id, _)| *id == CONTENT_ENCODING) {
+        let Some((_, compression)) = elements(encoding).find(|(id, _)| *id == CONTENT_COMPRESSION)
+        else {
+            // Encryption
+            encoded = true;
+            continue;
+        };
+        let mut algorithm = 0;
+        for (id, data) in elements(compression) {
+            match id {
+                CONTENT_COMP_ALGO => algorithm = uint(data),
+                CONTENT_COMP_SETTINGS => stripped_header.extend_from_slice(data),
+                _ => {}
+            }
+        }
+        // Only header stripping is cheap to undo.
+        encoded |= algorithm != 3;
+    }
+
+    Ok(Some(Track {
+        stream,
+        codec_private: codec_private.map(<[u8]>::to_vec),
+        default_duration,
+        stripped_header,
+        encoded,
+    }))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Cursor;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// The H.264 configuration of the sample video track, without parameter sets.
+    pub const AVC_CONFIG: [u8; 6] = [1, 0x64, 0x00, 0x1f, 0xff, 0xe0];
+    /// The AAC-LC configuration of the sample audio track, at 44.1 kHz in stereo.
+    pub const AAC_CONFIG: [u8; 2] = [0x12, 0x10];
+
+    /// Encodes an element with a size of 1 byte if it fits, or 8 bytes otherwise.
+    fn element(id: u32, body: &[u8]) -> Vec<u8> {
+        let id = id.to_be_bytes();
+        let start = id.iter().position(|byte| *byte != 0).unwrap_or(3);
+        let size = match body.len() {
+            len @ 0..0x7f => vec![0x80 | len as u8],
+            len => [&[0x01][..], &(len as u64).to_be_bytes()[1..]].concat(),
+        };
+        [&id[start..], &size, body].concat()
+    }
+
+    fn simple_block(track: u8, timestamp: i16, keyframe: bool, frame: &[u8]) -> Vec<u8> {
+        let flags = if keyframe { 0x80 } else { 0 };
+        let header = [&[0x80 | track][..], &timestamp.to_be_bytes(), &[flags]].concat();
+        element(SIMPLE_BLOCK, &[&header[..], frame].concat())
+    }
+
+    /// Builds a file lasting 12 seconds, with an H.264 track at 25 frames per second and an AAC
+    /// track, numbered 1 and 2. Clusters start every 2 seconds with a keyframe, followed by a
+    /// frame 40 ms later, and audio frames at 0 and 20 ms. There are no cues.
+    pub fn sample() -> Vec<u8> {
+        let ebml = element(EBML, &element(DOC_TYPE, b"matroska"));
+        let info = element(
+            INFO,
+            &[
+                element(TIMESTAMP_SCALE, &1_000_000u64.to_be_bytes()),
+                element(DURATION, &12000f64.to_be_bytes()),
+            ]
+            .concat(),
+        );
+        let video = [
+            element(TRACK_NUMBER, &[1]),
+            element(TRACK_TYPE, &[1]),
+            element(CODEC_ID, b"V_MPEG4/ISO/AVC"),
+            element(CODEC_PRIVATE, &AVC_CONFIG),
+            element(DEFAULT_DURATION, &40_000_000u64.to_be_bytes()),
+            element(
+                VIDEO,
+                &[
+                    element(PIXEL_WIDTH, &320u16.to_be_bytes()),
+                    element(PIXEL_HEIGHT, &240u16.to_be_bytes()),
+                ]
+                .concat(),
+            ),
+        ];
+        let audio = [
+            element(TRACK_NUMBER, &[2]),
+            element(TRACK_TYPE, &[2]),
+            element(CODEC_ID, b"A_AAC"),
+            element(CODEC_PRIVATE, &AAC_CONFIG),
+            element(
+                AUDIO,
+                &[
+                    element(SAMPLING_FREQUENCY, &44100f64.to_be_bytes()),
+                    element(CHANNELS, &[2]),
+                ]
+                .concat(),
+            ),
+        ];
+        let tracks = element(
+            TRACKS,
+            &[
+                element(TRACK_ENTRY, &video.concat()),
+                element(TRACK_ENTRY, &audio.concat()),
+            ]
+            .concat(),
+        );
+
+        let mut body = [info, tracks].concat();
+        for (i, time) in (0..12000u64).step_by(2000).enumerate() {
+            let i = i as u8;
+            let cluster = [
+                element(TIMESTAMP, &time.to_be_bytes()),
+                simple_block(1, 0, true, &[0, 0, 0, 2, 0x65, i]),
+                simple_block(2, 0, true, &[0x21, i]),
+                simple_block(2, 20, true, &[0x21, i]),
+                simple_block(1, 40, false, &[0, 0, 0, 2, 0x41, i]),
+            ];
+            body.extend(element(CLUSTER, &cluster.concat()));
+        }
+        [ebml, element(SEGMENT, &body)].concat()
+    }
+
+    /// Writes the [`sample`] file in a new temporary directory, returning its path.
+    pub fn sample_file() -> PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = std::env::temp_dir().join(format!(
+            "kayo-matroska-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sample.mkv");
+        fs::write(&path, sample()).unwrap();
+        path
+    }
+
+    /// Removes a file written by [`sample_file`] with its directory.
+    pub fn remove_sample(path: &Path) {
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn children() {
+        let data = [0x42, 0x86, 0x81, 0x01, 0xec, 0x82, 0xaa, 0xbb];
+        let children = elements(&data).collect::<Vec<_>>();
+        assert_eq!(children, [(0x4286, &[0x01][..]), (0xec, &[0xaa, 0xbb][..])]);
+
+        // An unknown size extends to the end of the parent.
+        let children = elements(&[0x42, 0x86, 0xff, 0x01, 0x02]).collect::<Vec<_>>();
+        assert_eq!(children, [(0x4286, &[0x01, 0x02][..])]);
+    }
+
+    #[test]
+    fn invalid_children() {
+        // Truncated bodies and headers
+        assert_eq!(elements(&[0x42, 0x86, 0x85, 0x01]).count(), 0);
+        assert_eq!(elements(&[0x42, 0x86, 0x40]).count(), 0);
+        assert_eq!(elements(&[0x42]).count(), 0);
+        // Sizes of 8 bytes past the end of the parent
+        let data = [
+            0x42, 0x86, 0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe, 0x00,
+        ];
+        assert_eq!(elements(&data).count(), 0);
+        // IDs longer than 4 bytes and sizes longer than 8 bytes
+        assert_eq!(elements(&[0x00, 0x81, 0x00]).count(), 0);
+        assert_eq!(elements(&[0x42, 0x86, 0x00, 0x01]).count(), 0);
+        // The valid children before an invalid one are kept.
+        let data = [0xec, 0x80, 0x42, 0x86, 0x85];
+        assert_eq!(elements(&data).collect::<Vec<_>>(), [(0xec, &[][..])]);
+    }
+
+    #[test]
+    fn element_ends() {
+        let header = Header {
+            id: 0x1a45dfa3,
+            header_len: 12,
+            size: None,
+        };
+        assert_eq!(element_end(4, &header, 20).unwrap(), 36);
+        assert!(element_end(u64::MAX - 12, &header, 1).is_err());
+        assert!(element_end(0, &header, u64::MAX).is_err());
+    }
+
+    #[test]
+    fn segment() {
+        let data = sample();
+        let mut reader = Cursor::new(&data);
+        let segment = Segment::open(&mut reader, data.len() as u64).unwrap();
+        assert_eq!(segment.container, Container::Matroska);
+        assert_eq!(segment.duration, Some(12.0));
+
+        let streams = segment.tracks.iter().map(|t| &t.stream).collect::<Vec<_>>();
+        assert_eq!(streams[0].codecs.as_deref(), Some("avc1.64001f"));
+        assert_eq!(
+            (streams[0].width, streams[0].height),
+            (Some(320), Some(240))
+        );
+        assert_eq!(streams[0].frame_rate, Some(25.0));
+        assert_eq!(streams[1].codecs.as_deref(), Some("mp4a.40.2"));
+        assert_eq!(streams[1].sample_rate, Some(44100));
+        assert!(segment.cues(&mut reader).unwrap().is_empty());
+
+        let mut offset = segment.first_cluster().unwrap();
+        let mut timestamps = Vec::new();
+        while let Some(cluster) = segment.read_cluster(&mut reader, offset).unwrap() {
+            let blocks = &cluster.blocks;
+            assert_eq!(blocks.len(), 4);
+            assert_eq!((blocks[3].track, blocks[3].keyframe), (1, false));
+            assert_eq!(blocks[3].timestamp - blocks[0].timestamp, 40);
+            timestamps.push(blocks[0].timestamp);
+            offset = cluster.end;
+        }
+        assert_eq!(timestamps, [0, 2000, 4000, 6000, 8000, 10000]);
+    }
+}
@@ -0,0 +1,210 @@
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// The number of bytes at the start of a file that are enough to recognize its format.
+pub const HEADER_LEN: usize = 512;
+
+const MPEG_TS_PACKET_LEN: usize = 188;
+
+/// Detects the MIME type of the file at `path`, from its header if it is a known format, or from
+/// its extension otherwise.
+pub fn detect_file(path: &Path) -> io::Result<Option<&'static str>> {
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    File::open(path)?
+        .take(HEADER_LEN as u64)
+        .read_to_end(&mut header)?;
+    Ok(detect(path, &header))
+}
+
+/// Detects the MIME type of a file from `header`, the first [`HEADER_LEN`] bytes of the file,
+/// falling back to the extension of `path`.
+pub fn detect(path: &Path, header: &[u8]) -> Option<&'static str> {
+    sniff(header, is_audio(path)).or_else(|| from_extension(path))
+}
+
+/// Guesses the MIME type of a file from the extension of `path` alone, without reading it.
+pub fn from_extension(path: &Path) -> Option<&'static str> {
+    match extension(path).as_deref() {
+        Some("m4a" | "m4b") => Some("audio/mp4"),
+        Some("mka") => Some("audio/x-matroska"),
+        Some("weba") => Some("audio/webm"),
+        Some("opus") => Some("audio/ogg"),
+        Some("srt") => Some("application/x-subrip"),
+        Some("ass" | "ssa") => Some("text/x-ssa"),
+        Some("vtt") => Some("text/vtt"),
+        Some("ts" | "m2ts" | "mts") => Some("video/mp2t"),
+        _ => mime_guess::from_path(path).first_raw(),
+    }
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .map(str::to_ascii_lowercase)
+}
+
+/// Returns whether `path` has the extension of an audio file in a container that is also used
+/// for video, since they are only told apart by their extension.
+fn is_audio(path: &Path) -> bool {
+    matches!(
+        extension(path).as_deref(),
+        Some("m4a" | "m4b" | "mka" | "weba" | "oga" | "opus")
+    )
+}
+
+fn sniff(header: &[u8], audio: bool) -> Option<&'static str> {
+    let starts_with = |offset: usize, magic: &[u8]| {
+        header
+            .get(offset..)
+            .is_some_and(|bytes| bytes.starts_with(magic))
+    };
+
+    if starts_with(4, b"ftyp") {
+        let mime = match header.get(8..12)? {
+            b"M4A " | b"M4B " | b"M4P " => "audio/mp4",
+            b"qt  " => "video/quicktime",
+            brand if brand.starts_with(b"3g") => "video/3gpp",
+            _ if audio => "audio/mp4",
+            _ => "video/mp4",
+        };
+        return Some(mime);
+    }
+
+    if starts_with(0, b"\x1a\x45\xdf\xa3") {
+        let webm = header.windows(4).any(|w| w == b"webm");
+        let mime = match (webm, audio) {
+            (true, true) => "audio/webm",
+            (true, false) => "video/webm",
+            (false, true) => "audio/x-matroska",
+            (false, false) => "video/x-matroska",
+        };
+        return Some(mime);
+    }
+
+    if starts_with(0, b"OggS") {
+        // The first page holds the identification header of the first stream.
+        return Some(if starts_with(28, b"\x80theora") {
+            "video/ogg"
+        } else {
+            "audio/ogg"
+        });
+    }
+
+    if starts_with(0, b"RIFF") {
+        return match header.get(8..12)? {
+            b"WAVE" => Some("audio/wav"),
+            b"AVI " => Some("video/x-msvideo"),
+            b"WEBP" => Some("image/webp"),
+            _ => None,
+        };
+    }
+
+    let mime = match header {
+        [b'f', b'L', b'a', b'C', ..] => "audio/flac",
+        [b'I', b'D', b'3', ..] => "audio/mpeg",
+        [b'F', b'L', b'V', 1, ..] => "video/x-flv",
+        [b'W', b'E', b'B', b'V', b'T', b'T', ..] => "text/vtt",
+        [0x89, b'P', b'N', b'G', ..] => "image/png",
+        [0xff, 0xd8, 0xff, ..] => "image/jpeg",
+        [b'G', b'I', b'F', b'8', ..] => "image/gif",
+        // ADTS frames have a layer of 0, unlike MPEG audio frames.
+        [0xff, b, ..] if b & 0xf6 == 0xf0 => "audio/aac",
+        [0xff, b, ..] if b & 0xe0 == 0xe0 => "audio/mpeg",
+        [0x47, ..] if header.get(MPEG_TS_PACKET_LEN) == Some(&0x47) => "video/mp2t",
+        _ => return None,
+    };
+    Some(mime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::matroska::tests::{remove_sample, sample_file};
+    use std::fs;
+
+    fn mime(name: &str, header: &[u8]) -> Option<&'static str> {
+        detect(Path::new(name), header)
+    }
+
+    #[test]
+    fn headers() {
+        let ftyp = |brand: &[u8; 4]| [&[0, 0, 0, 0x18][..], b"ftyp", brand, &[0; 12]].concat();
+        assert_eq!(mime("a", &ftyp(b"isom")), Some("video/mp4"));
+        assert_eq!(mime("a", &ftyp(b"M4A ")), Some("audio/mp4"));
+        assert_eq!(mime("a", &ftyp(b"qt  ")), Some("video/quicktime"));
+        assert_eq!(mime("a", &ftyp(b"3gp5")), Some("video/3gpp"));
+
+        let ebml =
+            |doc_type: &[u8]| [&[0x1a, 0x45, 0xdf, 0xa3, 0x88, 0x42, 0x82][..], doc_type].concat();
+        assert_eq!(mime("a", &ebml(b"\x88matroska")), Some("video/x-matroska"));
+        assert_eq!(mime("a", &ebml(b"\x84webm")), Some("video/webm"));
+
+        let ogg = |codec: &[u8]| [&b"OggS"[..], &[0; 24], codec].concat();
+        assert_eq!(mime("a", &ogg(b"\x80theora")), Some("video/ogg"));
+        assert_eq!(mime("a", &ogg(b"OpusHead")), Some("audio/ogg"));
+
+        let riff = |form: &[u8; 4]| [&b"RIFF"[..], &[0; 4], form].concat();
+        assert_eq!(mime("a", &riff(b"WAVE")), Some("audio/wav"));
+        assert_eq!(mime("a", &riff(b"AVI ")), Some("video/x-msvideo"));
+        assert_eq!(mime("a", &riff(b"ABCD")), None);
+
+        assert_eq!(mime("a", b"fLaC\0\0\0\x22"), Some("audio/flac"));
+        assert_eq!(mime("a", b"ID3\x04\0"), Some("audio/mpeg"));
+        assert_eq!(mime("a", &[0xff, 0xf1, 0x50, 0x80]), Some("audio/aac"));
+        assert_eq!(mime("a", &[0xff, 0xfb, 0x90, 0x64]), Some("audio/mpeg"));
+        assert_eq!(mime("a", b"WEBVTT\n\n"), Some("text/vtt"));
+        assert_eq!(mime("a", b"\x89PNG\r\n\x1a\n"), Some("image/png"));
+
+        // Transport streams are told by the sync byte of their second packet.
+        let mut ts = vec![0; MPEG_TS_PACKET_LEN + 1];
+        (ts[0], ts[MPEG_TS_PACKET_LEN]) = (0x47, 0x47);
+        assert_eq!(mime("a", &ts), Some("video/mp2t"));
+        assert_eq!(mime("a", &ts[..MPEG_TS_PACKET_LEN]), None);
+    }
+
+    #[test]
+    fn audio_extensions() {
+        // Containers shared with video hold audio according to their extension.
+        let ftyp = [&[0, 0, 0, 0x18][..], b"ftypisom", &[0; 12]].concat();
+        assert_eq!(mime("a.M4A", &ftyp), Some("audio/mp4"));
+        let ebml = [
+            0x1a, 0x45, 0xdf, 0xa3, 0x84, 0x42, 0x82, 0x84, b'w', b'e', b'b', b'm',
+        ];
+        assert_eq!(mime("a.weba", &ebml), Some("audio/webm"));
+        assert_eq!(mime("a.mka", &ebml[..4]), Some("audio/x-matroska"));
+    }
+
+    #[test]
+    fn extensions() {
+        // Headers take precedence over extensions.
+        assert_eq!(mime("a.txt", b"fLaC"), Some("audio/flac"));
+        // Unknown or missing headers fall back to the extension.
+        assert_eq!(mime("a.mkv", b""), Some("video/x-matroska"));
+        assert_eq!(
+            mime("a.srt", b"1\n00:00:00,000 --> "),
+            Some("application/x-subrip")
+        );
+        assert_eq!(mime("a.ass", b"[Script Info]"), Some("text/x-ssa"));
+        assert_eq!(mime("a.m2ts", &[0; 4]), Some("video/mp2t"));
+        assert_eq!(mime("a.opus", b""), Some("audio/ogg"));
+        assert_eq!(mime("a.txt", b"text"), Some("text/plain"));
+        assert_eq!(mime("a", b"text"), None);
+        assert_eq!(mime("a.unknown-extension", &[0xff]), None);
+
+        assert_eq!(from_extension(Path::new("a/b.MP4")), Some("video/mp4"));
+        assert_eq!(from_extension(Path::new("a.m4b")), Some("audio/mp4"));
+    }
+
+    #[test]
+    fn files() {
+        let path = sample_file();
+        assert_eq!(detect_file(&path).unwrap(), Some("video/x-matroska"));
+        let renamed = path.with_file_name("sample.txt");
+        fs::rename(&path, &renamed).unwrap();
+        assert_eq!(detect_file(&renamed).unwrap(), Some("video/x-matroska"));
+        assert!(detect_file(&path).is_err());
+        remove_sample(&renamed);
+    }
+}
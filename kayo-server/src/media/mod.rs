@@ -0,0 +1,20 @@
+mod ass;
+mod bits;
+pub mod cast;
+mod codec;
+pub mod dash;
+pub mod faststart;
+mod flac;
+mod fmp4;
+pub mod hls;
+mod matroska;
+pub mod mime;
+mod mp3;
+mod mp4;
+mod mpegts;
+mod ogg;
+pub mod probe;
+pub mod remux;
+pub mod subtitle;
+mod wav;
+pub mod webvtt;
@@ -0,0 +1,193 @@
+use std::io::{self, Read, Seek};
+
+use super::bits::{invalid_data, ByteReader};
+use super::probe::{read_at, Container, MediaInfo, Stream, StreamKind};
+
+/// The number of bytes searched for the first frame after the ID3 tags.
+const SCAN_LEN: u64 = 64 << 10;
+
+const BITRATES: [[u32; 15]; 5] = [
+    // MPEG-1 layer I, II and III.
+    [
+        0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+    ],
+    [
+        0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+    ],
+    [
+        0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+    ],
+    // MPEG-2 and MPEG-2.5 layer I, then II and III.
+    [
+        0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+    ],
+    [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+];
+
+/// The header of an MPEG audio frame.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameHeader {
+    /// 1 for MPEG-1, 2 for MPEG-2 and 3 for MPEG-2.5.
+    version: u8,
+    layer: u8,
+    /// The bitrate in kbit/s.
+    bitrate: u32,
+    sample_rate: u32,
+    channels: u16,
+    len: usize,
+}
+
+impl FrameHeader {
+    fn samples(&self) -> u32 {
+        match (self.layer, self.version) {
+            (1, _) => 384,
+            (3, 2 | 3) => 576,
+            _ => 1152,
+        }
+    }
+
+    /// Sets the codec details of `stream` from the header.
+    pub fn describe(&self, stream: &mut Stream) {
+        stream.codec = format!("mp{}", self.layer);
+        stream.sample_rate = Some(self.sample_rate);
+        stream.channels = Some(self.channels);
+        stream.bitrate = Some(u64::from(self.bitrate) * 1000);
+    }
+}
+
+/// Parses the header of the MPEG audio frame at the start of `data`.
+pub fn frame_header(data: &[u8]) -> Option<FrameHeader> {
+    let header = u32::from_be_bytes(data.get(..4)?.try_into().unwrap());
+    if header >> 21 != 0x7ff {
+        return None;
+    }
+
+    let version = match header >> 19 & 3 {
+        0 => 3,
+        2 => 2,
+        3 => 1,
+        _ => return None,
+    };
+    let layer = match header >> 17 & 3 {
+        1 => 3,
+        2 => 2,
+        3 => 1,
+        _ => return None,
+    };
+    let table = match (version, layer) {
+        (1, layer) => usize::from(layer) - 1,
+        (_, 1) => 3,
+        _ => 4,
+    };
+    let bitrate = *BITRATES[table].get((header >> 12 & 15) as usize)?;
+    let sample_rate = [44100, 48000, 32000].get((header >> 10 & 3) as usize)? >> (version - 1);
+    let padding = (header >> 9 & 1) as usize;
+    let channels = if header >> 6 & 3 == 3 { 1 } else { 2 };
+    if bitrate == 0 {
+        return None;
+    }
+
+    let len = match (layer, version) {
+        (1, _) => (12 * bitrate as usize * 1000 / sample_rate as usize + padding) * 4,
+        (3, 2 | 3) => 72 * bitrate as usize * 1000 / sample_rate as usize + padding,
+        _ => 144 * bitrate as usize * 1000 / sample_rate as usize + padding,
+    };
+
+    Some(FrameHeader {
+        version,
+        layer,
+        bitrate,
+        sample_rate,
+        channels,
+        len,
+    })
+}
+
+/// Returns the length of the ID3v2 tag at the start of `data`, if any.
+pub fn id3_len(data: &[u8]) -> usize {
+    match data {
+        [b'I', b'D', b'3', _, _, flags, size @ ..] if 4 <= size.len() => {
+            let size = size[..4]
+                .iter()
+                .fold(0, |len, byte| len << 7 | usize::from(byte & 0x7f));
+            let footer = if flags & 0x10 != 0 { 10 } else { 0 };
+            10 + size + footer
+        }
+        _ => 0,
+    }
+}
+
+pub fn probe<R: Read + Seek>(reader: &mut R, len: u64) -> io::Result<MediaInfo> {
+    let mut start = 0;
+    loop {
+        let header = read_at(reader, start, 10)?;
+        match id3_len(&header) {
+            0 => break,
+            tag => start += tag as u64,
+        }
+    }
+
+    let data = read_at(reader, start, SCAN_LEN)?;
+    let (offset, header) = (0..data.len())
+        .find_map(|offset| {
+            let header = frame_header(&data[offset..])?;
+            // The next frame confirms that this is not a false sync.
+            let next = data.get(offset + header.len..).unwrap_or_default();
+            (next.is_empty() || frame_header(next).is_some()).then_some((offset, header))
+        })
+        .ok_or_else(|| invalid_data("missing MPEG audio frame"))?;
+    let start = start + offset as u64;
+    let frame = &data[offset..];
+
+    let mut stream = Stream::new(0, StreamKind::Audio, "");
+    header.describe(&mut stream);
+
+    // A Xing, Info or VBRI header in the first frame gives the number of frames.
+    let side_info = match (header.version, header.channels) {
+        (1, 1) => 17,
+        (1, _) => 32,
+        (_, 1) => 9,
+        _ => 17,
+    };
+    let mut frames = None;
+    let mut bytes = None;
+    let xing = frame.get(4 + side_info..).unwrap_or_default();
+    if xing.starts_with(b"Xing") || xing.starts_with(b"Info") {
+        let mut r = ByteReader::new(&xing[4..]);
+        let flags = r.u32()?;
+        if flags & 1 != 0 {
+            frames = Some(r.u32()?);
+        }
+        if flags & 2 != 0 {
+            bytes = Some(r.u32()?);
+        }
+    } else if frame.get(36..40) == Some(b"VBRI") {
+        let mut r = ByteReader::new(&frame[40..]);
+        r.skip(6)?; // version, delay, quality
+        bytes = Some(r.u32()?);
+        frames = Some(r.u32()?);
+    }
+
+    // An ID3v1 tag takes the last 128 bytes.
+    let end = match len.checked_sub(128) {
+        Some(tag) if read_at(reader, tag, 3)? == b"TAG" => tag,
+        _ => len,
+    };
+    let bytes = bytes.map_or(end.saturating_sub(start), u64::from);
+
+    let mut info = MediaInfo::new(Container::Mp3);
+    info.duration = match frames {
+        Some(frames) => {
+            Some(f64::from(frames) * f64::from(header.samples()) / f64::from(header.sample_rate))
+        }
+        None => Some(bytes as f64 * 8.0 / (f64::from(header.bitrate) * 1000.0)),
+    }
+    .filter(|duration| 0.0 < *duration);
+    if let (Some(_), Some(duration)) = (frames, info.duration) {
+        stream.bitrate = Some((bytes as f64 * 8.0 / duration) as u64);
+    }
+    info.bitrate = stream.bitrate;
+    info.streams.push(stream);
+
+    Ok(info)
+}
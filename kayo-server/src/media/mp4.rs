@@ -0,0 +1,880 @@
+use std::io::{self, Read, Seek};
+use std::ops::Range;
+
+use super::bits::{invalid_data, ByteReader};
+use super::codec::{self, Pcm};
+use super::probe::{read_at, seconds, Container, MediaInfo, Stream, StreamKind};
+
+/// The largest `moov` box that is read in memory.
+const MAX_MOOV_LEN: u64 = 64 << 20;
+
+/// The header of a box in a file.
+#[derive(Debug, Clone, Copy)]
+pub struct BoxHeader {
+    pub kind: [u8; 4],
+    pub offset: u64,
+    pub header_len: u64,
+    /// The size of the box, including its header.
+    pub size: u64,
+}
+
+impl BoxHeader {
+    pub fn end(&self) -> u64 {
+        self.offset + self.size
+    }
+}
+
+/// Reads the header of the box at `offset` in a file of `len` bytes, or returns `None` at the
+/// end of the file.
+pub fn read_header<R: Read + Seek>(
+    reader: &mut R,
+    offset: u64,
+    len: u64,
+) -> io::Result<Option<BoxHeader>> {
+    if offset.checked_add(8).is_none_or(|end| len < end) {
+        return Ok(None);
+    }
+
+    let data = read_at(reader, offset, 16)?;
+    let mut r = ByteReader::new(&data);
+    let size = r.u32()?;
+    let kind = r.bytes(4)?.try_into().unwrap();
+    let (header_len, size) = match size {
+        0 => (8, len - offset),
+        1 => (16, r.u64()?),
+        size => (8, u64::from(size)),
+    };
+    if size < header_len || offset.checked_add(size).is_none_or(|end| len < end) {
+        return Err(invalid_data("invalid box size"));
+    }
+
+    Ok(Some(BoxHeader {
+        kind,
+        offset,
+        header_len,
+        size,
+    }))
+}
+
+/// Reads the body of a box, up to [`MAX_MOOV_LEN`] bytes.
+pub fn read_body<R: Read + Seek>(reader: &mut R, header: &BoxHeader) -> io::Result<Vec<u8>> {
+    let len = header.size - header.header_len;
+    if MAX_MOOV_LEN < len {
+        return Err(invalid_data("box too large"));
+    }
+
+    read_at(reader, header.offset + header.header_len, len)
+}
+
+/// Iterates over the boxes in the body of a box, yielding their type and body.
+pub struct Boxes<'a> {
+    data: &'a [u8],
+}
+
+pub fn boxes(data: &[u8]) -> Boxes<'_> {
+    Boxes { data }
+}
+
+impl<'a> Iterator for Boxes<'a> {
+    type Item = io::Result<([u8; 4], &'a [u8])>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+
+        match split_box(self.data) {
+            Ok((kind, body, rest)) => {
+                self.data = rest;
+                Some(Ok((kind, body)))
+            }
+            Err(error) => {
+                self.data = &[];
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+/// Splits the first box from `data`, returning its type, its body and the data after it.
+fn split_box(data: &[u8]) -> io::Result<([u8; 4], &[u8], &[u8])> {
+    let mut r = ByteReader::new(data);
+    let size = r.u32()?;
+    let kind = r.bytes(4)?.try_into().unwrap();
+    let (header_len, size) = match size {
+        0 => (8, data.len()),
+        1 => (16, usize::try_from(r.u64()?).unwrap_or(usize::MAX)),
+        size => (8, size as usize),
+    };
+    if size < header_len || data.len() < size {
+        return Err(invalid_data("invalid box size"));
+    }
+
+    Ok((kind, &data[header_len..size], &data[size..]))
+}
+
+/// Returns the body of the first box found at `path` in `data`.
+pub fn find<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+    let (first, rest) = path.split_first()?;
+    let body = boxes(data)
+        .map_while(Result::ok)
+        .find(|(kind, _)| kind == *first)?
+        .1;
+    if rest.is_empty() {
+        Some(body)
+    } else {
+        find(body, rest)
+    }
+}
+
+/// Returns the bodies of all the boxes of type `kind` in `data`.
+pub fn find_all<'a>(data: &'a [u8], kind: &'a [u8; 4]) -> impl Iterator<Item = &'a [u8]> {
+    boxes(data)
+        .map_while(Result::ok)
+        .filter(move |(k, _)| k == kind)
+        .map(|(_, body)| body)
+}
+
+/// Reads the version and flags of a full box, returning a reader over the rest of its body.
+pub fn full_box(data: &[u8]) -> io::Result<(u8, u32, ByteReader<'_>)> {
+    let mut r = ByteReader::new(data);
+    let version = r.u8()?;
+    let flags = r.u24()?;
+    Ok((version, flags, r))
+}
+
+/// Reads the timescale and duration of a `mvhd` or `mdhd` box.
+fn timescale_duration(data: &[u8]) -> io::Result<(u32, u64, ByteReader<'_>)> {
+    let (version, _, mut r) = full_box(data)?;
+    if version == 1 {
+        r.skip(16)?; // creation_time, modification_time
+        let timescale = r.u32()?;
+        let duration = r.u64()?;
+        Ok((timescale, duration, r))
+    } else {
+        r.skip(8)?;
+        let timescale = r.u32()?;
+        let duration = r.u32()?;
+        let duration = if duration == u32::MAX {
+            0
+        } else {
+            u64::from(duration)
+        };
+        Ok((timescale, duration, r))
+    }
+}
+
+/// Reads the `moov` box of a file, and its first `sidx` box if any.
+fn read_moov<R: Read + Seek>(reader: &mut R, len: u64) -> io::Result<(Vec<u8>, Option<Vec<u8>>)> {
+    let mut moov = None;
+    let mut sidx = None;
+    let mut offset = 0;
+    while let Some(header) = read_header(reader, offset, len)? {
+        match &header.kind {
+            b"moov" => moov = Some(read_body(reader, &header)?),
+            b"sidx" if sidx.is_none() => sidx = Some(read_body(reader, &header)?),
+            _ => {}
+        }
+        offset = header.end();
+    }
+    let moov = moov.ok_or_else(|| invalid_data("missing moov box"))?;
+    Ok((moov, sidx))
+}
+
+pub fn probe<R: Read + Seek>(
+    reader: &mut R,
+    len: u64,
+    container: Container,
+) -> io::Result<MediaInfo> {
+    let (moov, sidx) = read_moov(reader, len)?;
+
+    let mut info = MediaInfo::new(container);
+    let mvhd = find(&moov, &[b"mvhd"]).ok_or_else(|| invalid_data("missing mvhd box"))?;
+    let (timescale, mut duration, _) = timescale_duration(mvhd)?;
+    if duration == 0 {
+        if let Some(mehd) = find(&moov, &[b"mvex", b"mehd"]) {
+            let (version, _, mut r) = full_box(mehd)?;
+            duration = if version == 1 {
+                r.u64()?
+            } else {
+                u64::from(r.u32()?)
+            };
+        }
+    }
+    info.duration = seconds(duration, u64::from(timescale));
+    if info.duration.is_none() {
+        info.duration = sidx.as_deref().map(sidx_duration).transpose()?.flatten();
+    }
+
+    for trak in find_all(&moov, b"trak") {
+        if let Some(stream) = parse_track(trak)? {
+            info.streams.push(stream);
+        }
+    }
+
+    Ok(info)
+}
+
+/// The byte ranges of a fragmented file that players read before its fragments.
+pub struct SegmentIndex {
+    /// The boxes up to the `moov` box included.
+    pub initialization: Range<u64>,
+    /// The `sidx` box.
+    pub index: Range<u64>,
+}
+
+/// Finds the `sidx` box following the `moov` box of a fragmented file.
+pub fn segment_index<R: Read + Seek>(reader: &mut R, len: u64) -> io::Result<Option<SegmentIndex>> {
+    let mut moov_end = None;
+    let mut offset = 0;
+    while let Some(header) = read_header(reader, offset, len)? {
+        match &header.kind {
+            b"moov" => moov_end = Some(header.end()),
+            b"sidx" => {
+                return Ok(moov_end.map(|end| SegmentIndex {
+                    initialization: 0..end,
+                    index: header.offset..header.end(),
+                }))
+            }
+            b"moof" | b"mdat" => break,
+            _ => {}
+        }
+        offset = header.end();
+    }
+    Ok(None)
+}
+
+/// A sample of a track, in decoding order.
+#[derive(Debug, Clone, Copy)]
+pub struct SampleInfo {
+    pub offset: u64,
+    pub size: u32,
+    pub decode_time: u64,
+    /// The presentation time minus the decoding time, including the shift of the edit list.
+    pub composition_offset: i32,
+    pub sync: bool,
+}
+
+/// A track and where its samples are.
+pub struct TrackSamples {
+    pub stream: Stream,
+    pub timescale: u32,
+    /// The first entry of the `stsd` box, header included.
+    pub sample_entry: Vec<u8>,
+    pub samples: Vec<SampleInfo>,
+}
+
+/// Reads the sample tables of the audio and video tracks of a file that is not fragmented,
+/// returning them with the duration of the movie.
+pub fn sample_tables<R: Read + Seek>(
+    reader: &mut R,
+    len: u64,
+) -> io::Result<(Option<f64>, Vec<TrackSamples>)> {
+    let (moov, _) = read_moov(reader, len)?;
+    if find(&moov, &[b"mvex"]).is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "fragmented MP4 files have no sample tables",
+        ));
+    }
+    let mvhd = find(&moov, &[b"mvhd"]).ok_or_else(|| invalid_data("missing mvhd box"))?;
+    let (movie_timescale, duration, _) = timescale_duration(mvhd)?;
+
+    let mut tracks = Vec::new();
+    for trak in find_all(&moov, b"trak") {
+        let Some(stream) = parse_track(trak)? else {
+            continue;
+        };
+        let mdhd = find(trak, &[b"mdia", b"mdhd"]);
+        let stbl = find(trak, &[b"mdia", b"minf", b"stbl"]);
+        let stsd = stbl.and_then(|stbl| find(stbl, &[b"stsd"]));
+        let (Some(mdhd), Some(stbl), Some(stsd)) = (mdhd, stbl, stsd) else {
+            continue;
+        };
+        if !matches!(stream.kind, StreamKind::Video | StreamKind::Audio) {
+            continue;
+        }
+
+        let (timescale, ..) = timescale_duration(mdhd)?;
+        let (_, _, mut r) = full_box(stsd)?;
+        r.u32()?; // entry_count
+        let entries = r.rest();
+        let entry_len = ByteReader::new(entries).u32()? as usize;
+        let sample_entry = entries
+            .get(..entry_len)
+            .ok_or_else(|| invalid_data("invalid sample entry"))?
+            .to_vec();
+
+        let shift = edit_shift(trak, movie_timescale, timescale)?;
+        tracks.push(TrackSamples {
+            stream,
+            timescale,
+            sample_entry,
+            samples: samples(stbl, shift, len)?,
+        });
+    }
+
+    Ok((seconds(duration, u64::from(movie_timescale)), tracks))
+}
+
+/// Returns how much the edit list of a track delays its presentation, in media ticks.
+fn edit_shift(trak: &[u8], movie_timescale: u32, timescale: u32) -> io::Result<i64> {
+    let Some(elst) = find(trak, &[b"edts", b"elst"]) else {
+        return Ok(0);
+    };
+    let (version, _, mut r) = full_box(elst)?;
+    let mut delay: i64 = 0;
+    for _ in 0..r.u32()? {
+        let (duration, media_time) = if version == 1 {
+            (r.u64()?, r.u64()? as i64)
+        } else {
+            (u64::from(r.u32()?), i64::from(r.u32()? as i32))
+        };
+        r.u32()?; // media_rate
+        if media_time != -1 {
+            return Ok(delay.saturating_sub(media_time));
+        }
+        // An empty edit.
+        let ticks =
+            duration.saturating_mul(u64::from(timescale)) / u64::from(movie_timescale.max(1));
+        delay = delay.saturating_add(i64::try_from(ticks).unwrap_or(i64::MAX));
+    }
+    Ok(delay)
+}
+
+/// Reads the sample tables of a track in a file of `len` bytes.
+fn samples(stbl: &[u8], shift: i64, len: u64) -> io::Result<Vec<SampleInfo>> {
+    let missing = |kind| invalid_data(&format!("missing {kind} box"));
+
+    let stsz = find(stbl, &[b"stsz"]).ok_or_else(|| missing("stsz"))?;
+    let (_, _, mut r) = full_box(stsz)?;
+    let sample_size = r.u32()?;
+    let count = r.u32()? as usize;
+    // The count is checked so that a small file cannot make it allocate without bounds.
+    let valid = if sample_size != 0 {
+        (count as u64).saturating_mul(u64::from(sample_size)) <= len
+    } else {
+        count.saturating_mul(4) <= r.remaining()
+    };
+    if !valid {
+        return Err(invalid_data("invalid sample count"));
+    }
+    let mut samples = Vec::with_capacity(count.min(1 << 20));
+    for _ in 0..count {
+        samples.push(SampleInfo {
+            offset: 0,
+            size: if sample_size != 0 {
+                sample_size
+            } else {
+                r.u32()?
+            },
+            decode_time: 0,
+            composition_offset: shift as i32,
+            sync: true,
+        });
+    }
+
+    let chunk_offsets = if let Some(stco) = find(stbl, &[b"stco"]) {
+        let (_, _, mut r) = full_box(stco)?;
+        (0..r.u32()?)
+            .map(|_| r.u32().map(u64::from))
+            .collect::<io::Result<Vec<_>>>()?
+    } else {
+        let co64 = find(stbl, &[b"co64"]).ok_or_else(|| missing("stco"))?;
+        let (_, _, mut r) = full_box(co64)?;
+        (0..r.u32()?)
+            .map(|_| r.u64())
+            .collect::<io::Result<Vec<_>>>()?
+    };
+
+    let stsc = find(stbl, &[b"stsc"]).ok_or_else(|| missing("stsc"))?;
+    let (_, _, mut r) = full_box(stsc)?;
+    let mut runs = Vec::new();
+    for _ in 0..r.u32()? {
+        let first_chunk = r.u32()? as usize;
+        let samples_per_chunk = r.u32()? as usize;
+        r.u32()?; // sample_description_index
+        runs.push((first_chunk.saturating_sub(1), samples_per_chunk));
+    }
+    let mut sample = 0;
+    for (i, (first_chunk, samples_per_chunk)) in runs.iter().enumerate() {
+        let last_chunk = runs
+            .get(i + 1)
+            .map_or(chunk_offsets.len(), |(next, _)| *next)
+            .min(chunk_offsets.len());
+        let chunks = chunk_offsets
+            .get(*first_chunk..last_chunk)
+            .unwrap_or_default();
+        for chunk_offset in chunks {
+            let mut offset = *chunk_offset;
+            for _ in 0..*samples_per_chunk {
+                let Some(info) = samples.get_mut(sample) else {
+                    break;
+                };
+                info.offset = offset;
+                offset = offset.saturating_add(u64::from(info.size));
+                sample += 1;
+            }
+        }
+    }
+
+    let stts = find(stbl, &[b"stts"]).ok_or_else(|| missing("stts"))?;
+    let (_, _, mut r) = full_box(stts)?;
+    let mut infos = samples.iter_mut();
+    let mut time = 0;
+    for _ in 0..r.u32()? {
+        let count = r.u32()?;
+        let delta = u64::from(r.u32()?);
+        for info in infos.by_ref().take(count as usize) {
+            info.decode_time = time;
+            time = time.saturating_add(delta);
+        }
+    }
+
+    if let Some(ctts) = find(stbl, &[b"ctts"]) {
+        let (_, _, mut r) = full_box(ctts)?;
+        let mut infos = samples.iter_mut();
+        for _ in 0..r.u32()? {
+            let count = r.u32()?;
+            // Negative offsets are also found in version 0 boxes.
+            let offset = r.u32()? as i32;
+            for info in infos.by_ref().take(count as usize) {
+                info.composition_offset = info.composition_offset.saturating_add(offset);
+            }
+        }
+    }
+
+    if let Some(stss) = find(stbl, &[b"stss"]) {
+        samples.iter_mut().for_each(|info| info.sync = false);
+        let (_, _, mut r) = full_box(stss)?;
+        for _ in 0..r.u32()? {
+            let number = r.u32()? as usize;
+            if let Some(info) = number.checked_sub(1).and_then(|i| samples.get_mut(i)) {
+                info.sync = true;
+            }
+        }
+    }
+
+    Ok(samples)
+}
+
+/// Returns the total duration of the subsegments referenced by a `sidx` box.
+fn sidx_duration(data: &[u8]) -> io::Result<Option<f64>> {
+    let (version, _, mut r) = full_box(data)?;
+    r.u32()?; // reference_ID
+    let timescale = r.u32()?;
+    r.skip(if version == 0 { 8 } else { 16 })?; // earliest_presentation_time, first_offset
+    r.u16()?; // reserved
+    let count = r.u16()?;
+    let mut duration = 0;
+    for _ in 0..count {
+        r.u32()?; // reference_type, referenced_size
+        duration += u64::from(r.u32()?);
+        r.u32()?; // SAP
+    }
+
+    Ok(seconds(duration, u64::from(timescale)))
+}
+
+fn parse_track(trak: &[u8]) -> io::Result<Option<Stream>> {
+    let (Some(tkhd), Some(mdia)) = (find(trak, &[b"tkhd"]), find(trak, &[b"mdia"])) else {
+        return Ok(None);
+    };
+
+    let (version, flags, mut r) = full_box(tkhd)?;
+    r.skip(if version == 1 { 16 } else { 8 })?; // creation_time, modification_time
+    let id = r.u32()?;
+    r.skip(if version == 1 { 12 } else { 8 })?; // reserved, duration
+    r.skip(52)?; // reserved, layer, alternate_group, volume, reserved, matrix
+    let width = r.u32()? >> 16;
+    let height = r.u32()? >> 16;
+
+    let handler = find(mdia, &[b"hdlr"])
+        .and_then(|hdlr| hdlr.get(8..12))
+        .unwrap_or_default();
+    let kind = match handler {
+        b"vide" => StreamKind::Video,
+        b"soun" => StreamKind::Audio,
+        b"sbtl" | b"subt" | b"text" | b"clcp" => StreamKind::Subtitle,
+        _ => StreamKind::Data,
+    };
+
+    let mut stream = Stream::new(u64::from(id), kind, "");
+    // The track_enabled flag.
+    stream.default = Some(flags & 1 != 0);
+    if kind == StreamKind::Video && width != 0 && height != 0 {
+        stream.width = Some(width);
+        stream.height = Some(height);
+    }
+
+    let mut duration = None;
+    let mut timescale = 0;
+    if let Some(mdhd) = find(mdia, &[b"mdhd"]) {
+        let (scale, ticks, mut r) = timescale_duration(mdhd)?;
+        timescale = scale;
+        duration = seconds(ticks, u64::from(scale));
+        let language = r.u16()?;
+        if 0x400 <= language {
+            let code: String = [10, 5, 0]
+                .iter()
+                .map(|shift| char::from((language >> shift & 0x1f) as u8 + 0x60))
+                .collect();
+            stream.language = Some(code).filter(|code| code != "und");
+        }
+    }
+    if let Some(elng) = find(mdia, &[b"elng"]) {
+        let (_, _, mut r) = full_box(elng)?;
+        let tag = String::from_utf8_lossy(r.rest());
+        let tag = tag.trim_end_matches('\0');
+        if !tag.is_empty() && tag != "und" {
+            stream.language = Some(tag.to_string());
+        }
+    }
+
+    if let Some(name) = find(trak, &[b"udta", b"name"]) {
+        let name = String::from_utf8_lossy(name);
+        let name = name.trim_end_matches('\0');
+        if !name.is_empty() {
+            stream.title = Some(name.to_string());
+        }
+    }
+
+    let Some(stbl) = find(mdia, &[b"minf", b"stbl"]) else {
+        return Ok(Some(stream));
+    };
+
+    if let Some(stsd) = find(stbl, &[b"stsd"]) {
+        let (_, _, mut r) = full_box(stsd)?;
+        r.u32()?; // entry_count
+        if let Some(entry) = boxes(r.rest()).next() {
+            let (format, body) = entry?;
+            sample_entry(&mut stream, &format, body)?;
+        }
+    }
+
+    if kind == StreamKind::Video && stream.frame_rate.is_none() {
+        if let Some(stts) = find(stbl, &[b"stts"]) {
+            let (_, _, mut r) = full_box(stts)?;
+            let (mut samples, mut ticks) = (0u64, 0u64);
+            for _ in 0..r.u32()? {
+                let count = u64::from(r.u32()?);
+                samples += count;
+                ticks += count * u64::from(r.u32()?);
+            }
+            if ticks != 0 {
+                stream.frame_rate = Some(samples as f64 * f64::from(timescale) / ticks as f64);
+            }
+        }
+    }
+
+    if stream.bitrate.is_none() {
+        if let (Some(stsz), Some(duration)) = (find(stbl, &[b"stsz"]), duration) {
+            let (_, _, mut r) = full_box(stsz)?;
+            let sample_size = u64::from(r.u32()?);
+            let count = r.u32()?;
+            let total = if sample_size != 0 {
+                sample_size * u64::from(count)
+            } else {
+                let mut total = 0;
+                for _ in 0..count {
+                    total += u64::from(r.u32()?);
+                }
+                total
+            };
+            if total != 0 {
+                stream.bitrate = Some((total as f64 * 8.0 / duration) as u64);
+            }
+        }
+    }
+
+    Ok(Some(stream))
+}
+
+/// Sets the codec details of a stream from its sample entry.
+fn sample_entry(stream: &mut Stream, format: &[u8; 4], body: &[u8]) -> io::Result<()> {
+    let tag = String::from_utf8_lossy(format).trim().to_string();
+    stream.codec = match format {
+        b"avc1" | b"avc3" => "h264",
+        b"hvc1" | b"hev1" => "hevc",
+        b"vp08" => "vp8",
+        b"vp09" => "vp9",
+        b"av01" => "av1",
+        b"mp4a" => "aac",
+        b"ac-3" => "ac3",
+        b"ec-3" => "eac3",
+        b"Opus" => "opus",
+        b"fLaC" => "flac",
+        b"alac" => "alac",
+        b".mp3" => "mp3",
+        b"dtsc" | b"dtsh" | b"dtsl" | b"dtse" => "dts",
+        b"tx3g" => "mov_text",
+        b"wvtt" => "webvtt",
+        b"stpp" => "ttml",
+        b"c608" => "eia_608",
+        b"mp4v" => "mpeg4",
+        b"jpeg" => "mjpeg",
+        b"apcn" | b"apch" | b"apcs" | b"apco" | b"ap4h" => "prores",
+        b"ulaw" => "pcm_mulaw",
+        b"alaw" => "pcm_alaw",
+        _ => &tag,
+    }
+    .to_string();
+    if matches!(format, b"ac-3" | b"ec-3" | b"mp4a" | b"mp4v") {
+        stream.codecs = Some(tag.clone());
+    }
+
+    let children = match stream.kind {
+        StreamKind::Video => {
+            let mut r = ByteReader::new(body);
+            r.skip(24)?; // reserved, data_reference_index, pre_defined, reserved
+            let width = u32::from(r.u16()?);
+            let height = u32::from(r.u16()?);
+            stream.width.get_or_insert(width);
+            stream.height.get_or_insert(height);
+            body.get(78..).unwrap_or_default()
+        }
+        StreamKind::Audio => {
+            let mut r = ByteReader::new(body);
+            r.skip(8)?; // reserved, data_reference_index
+            let version = r.u16()?;
+            r.skip(6)?; // revision_level, vendor
+            stream.channels = Some(r.u16()?);
+            let mut bits = r.u16()?; // samplesize
+            r.u32()?; // pre_defined, reserved
+            stream.sample_rate = Some(r.u32()? >> 16);
+            let mut flags = None;
+            match version {
+                1 => {
+                    r.skip(16)?;
+                }
+                2 => {
+                    r.u32()?; // sizeOfStructOnly
+                    stream.sample_rate = Some(r.f64()? as u32);
+                    stream.channels = Some(r.u32()? as u16);
+                    r.u32()?; // always7F000000
+                    bits = r.u32()? as u16; // constBitsPerChannel
+                    flags = Some(r.u32()?); // formatSpecificFlags
+                    r.skip(8)?;
+                }
+                _ => {}
+            }
+            if let Some(codec) = pcm(format, bits, flags) {
+                stream.codec = codec;
+            }
+            r.rest()
+        }
+        _ => return Ok(()),
+    };
+
+    for child in boxes(children) {
+        let (kind, data) = child?;
+        match &kind {
+            b"avcC" => codec::avc_config(stream, data)?,
+            b"hvcC" => codec::hevc_config(stream, &tag, data)?,
+            b"vpcC" => codec::vp9_config(stream, data)?,
+            b"av1C" => codec::av1_config(stream, data)?,
+            b"esds" => esds(stream, data)?,
+            b"wave" => {
+                if let Some(esds_box) = find(data, &[b"esds"]) {
+                    esds(stream, esds_box)?;
+                }
+                // Samples are big-endian unless an `enda` box tells otherwise.
+                let little_endian = find(data, &[b"enda"]).is_some_and(|enda| enda != [0, 0]);
+                if let Some(codec) = stream.codec.strip_suffix("be").filter(|_| little_endian) {
+                    stream.codec = format!("{codec}le");
+                }
+            }
+            b"dac3" => codec::dac3(stream, data)?,
+            b"dec3" => codec::dec3(stream, data)?,
+            b"dOps" => {
+                let mut r = ByteReader::new(data);
+                r.u8()?; // Version
+                stream.channels = Some(u16::from(r.u8()?));
+                stream.codecs = Some("opus".to_string());
+            }
+            b"dfLa" => stream.codecs = Some("flac".to_string()),
+            b"btrt" => {
+                let mut r = ByteReader::new(data);
+                r.skip(8)?; // bufferSizeDB, maxBitrate
+                let bitrate = u64::from(r.u32()?);
+                if bitrate != 0 {
+                    stream.bitrate = Some(bitrate);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Names the linear PCM audio of a sample entry in `format`, with samples of `bits` bits and the
+/// `formatSpecificFlags` of version 2 sound descriptions.
+fn pcm(format: &[u8; 4], bits: u16, flags: Option<u32>) -> Option<String> {
+    let (format, bits, big_endian) = match format {
+        b"raw " => (Pcm::Unsigned, 8, false),
+        b"twos" if bits == 8 => (Pcm::Signed, 8, true),
+        b"twos" => (Pcm::Signed, 16, true),
+        b"sowt" => (Pcm::Signed, 16, false),
+        b"in24" => (Pcm::Signed, 24, true),
+        b"in32" => (Pcm::Signed, 32, true),
+        b"fl32" => (Pcm::Float, 32, true),
+        b"fl64" => (Pcm::Float, 64, true),
+        b"lpcm" => {
+            let flags = flags?;
+            let format = match (flags & 1 != 0, flags & 4 != 0) {
+                (true, _) => Pcm::Float,
+                (false, true) => Pcm::Signed,
+                (false, false) => Pcm::Unsigned,
+            };
+            (format, bits, flags & 2 != 0)
+        }
+        _ => return None,
+    };
+    Some(codec::pcm(format, bits, big_endian))
+}
+
+/// Sets the codec details of a stream from an `ES_Descriptor` box.
+fn esds(stream: &mut Stream, data: &[u8]) -> io::Result<()> {
+    let (_, _, mut r) = full_box(data)?;
+    let mut object_type = 0;
+    while !r.is_empty() {
+        let tag = r.u8()?;
+        let mut size = 0usize;
+        loop {
+            let byte = r.u8()?;
+            size = size << 7 | usize::from(byte & 0x7f);
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+
+        match tag {
+            // ES_Descriptor, followed by its nested descriptors.
+            3 => {
+                r.u16()?; // ES_ID
+                let flags = r.u8()?;
+                if flags & 0x80 != 0 {
+                    r.u16()?; // dependsOn_ES_ID
+                }
+                if flags & 0x40 != 0 {
+                    let len = r.u8()?;
+                    r.skip(usize::from(len))?; // URLstring
+                }
+                if flags & 0x20 != 0 {
+                    r.u16()?; // OCR_ES_Id
+                }
+            }
+            // DecoderConfigDescriptor, followed by its nested descriptors.
+            4 => {
+                object_type = r.u8()?;
+                r.skip(4)?; // streamType, upStream, reserved, bufferSizeDB
+                r.u32()?; // maxBitrate
+                let bitrate = u64::from(r.u32()?);
+                if bitrate != 0 {
+                    stream.bitrate = Some(bitrate);
+                }
+                stream.codec = match object_type {
+                    0x40 | 0x66..=0x68 => "aac",
+                    0x69 | 0x6b => "mp3",
+                    0xa5 => "ac3",
+                    0xa6 => "eac3",
+                    0xa9 | 0xac => "dts",
+                    0xad => "opus",
+                    0xdd => "vorbis",
+                    0x20 => "mpeg4",
+                    0x60..=0x65 => "mpeg2video",
+                    0x6a => "mpeg1video",
+                    0x6c => "mjpeg",
+                    _ => stream.codec.as_str(),
+                }
+                .to_string();
+                stream.codecs = Some(format!("mp4a.{object_type:02x}"));
+            }
+            // DecoderSpecificInfo.
+            5 => {
+                let info = r.bytes(size)?;
+                if matches!(object_type, 0x40 | 0x66..=0x68) {
+                    codec::audio_specific_config(stream, info)?;
+                }
+            }
+            _ => r.skip(size)?,
+        }
+    }
+
+    if stream.kind == StreamKind::Video {
+        stream.codecs = Some(format!("mp4v.{object_type:02x}"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn header(data: &[u8], offset: u64) -> io::Result<Option<BoxHeader>> {
+        read_header(&mut Cursor::new(data), offset, data.len() as u64)
+    }
+
+    #[test]
+    fn headers() {
+        let data = [&[0, 0, 0, 16][..], b"free", &[0; 8]].concat();
+        let free = header(&data, 0).unwrap().unwrap();
+        assert_eq!((&free.kind, free.header_len, free.size), (b"free", 8, 16));
+
+        let data = [&[0, 0, 0, 0][..], b"mdat", &[0; 12]].concat();
+        let mdat = header(&data, 0).unwrap().unwrap();
+        assert_eq!((mdat.header_len, mdat.size), (8, 20));
+
+        let data = [&[0, 0, 0, 1][..], b"mdat", &24u64.to_be_bytes(), &[0; 8]].concat();
+        let mdat = header(&data, 0).unwrap().unwrap();
+        assert_eq!((mdat.header_len, mdat.size), (16, 24));
+
+        // Nothing is left after the last box.
+        assert!(header(&data, 20).unwrap().is_none());
+        assert!(header(&data, u64::MAX).unwrap().is_none());
+    }
+
+    #[test]
+    fn invalid_headers() {
+        // Truncated large size
+        assert!(header(&[&[0, 0, 0, 1][..], b"mdat", &[0; 4]].concat(), 0).is_err());
+        // Sizes smaller than the header
+        assert!(header(&[&[0, 0, 0, 4][..], b"free"].concat(), 0).is_err());
+        let data = [&[0, 0, 0, 1][..], b"mdat", &8u64.to_be_bytes()].concat();
+        assert!(header(&data, 0).is_err());
+        // Sizes past the end of the file, or overflowing
+        assert!(header(&[&[0, 0, 0, 32][..], b"free", &[0; 8]].concat(), 0).is_err());
+        let data = [&[0, 0, 0, 1][..], b"mdat", &u64::MAX.to_be_bytes()].concat();
+        assert!(header(&data, 0).is_err());
+        let data = [
+            &[0; 8][..],
+            &[0, 0, 0, 1],
+            b"mdat",
+            &(u64::MAX - 7).to_be_bytes(),
+        ]
+        .concat();
+        assert!(header(&data, 8).is_err());
+    }
+
+    #[test]
+    fn split_boxes() {
+        let data = [&[0, 0, 0, 9][..], b"free", &[1], &[0, 0, 0, 8], b"skip"].concat();
+        let (kind, body, rest) = split_box(&data).unwrap();
+        assert_eq!((&kind, body, rest.len()), (b"free", &[1][..], 8));
+
+        assert!(split_box(&[0, 0, 0]).is_err());
+        assert!(split_box(&[&[0, 0, 0, 4][..], b"free"].concat()).is_err());
+        assert!(split_box(&[&[0, 0, 0, 16][..], b"free"].concat()).is_err());
+        let data = [&[0, 0, 0, 1][..], b"mdat", &u64::MAX.to_be_bytes()].concat();
+        assert!(split_box(&data).is_err());
+
+        // Iteration stops at the first invalid box.
+        let data = [&[0, 0, 0, 8][..], b"skip", &[0, 0, 0, 32], b"free", &[0; 8]].concat();
+        let kinds = boxes(&data)
+            .map(|b| b.map(|(kind, _)| kind).ok())
+            .collect::<Vec<_>>();
+        assert_eq!(kinds, [Some(*b"skip"), None]);
+    }
+}
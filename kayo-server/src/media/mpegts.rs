@@ -0,0 +1,298 @@
+use std::io::{self, Read, Seek};
+
+use super::bits::{invalid_data, ByteReader};
+use super::codec;
+use super::mp3;
+use super::probe::{read_at, Container, MediaInfo, Stream, StreamKind};
+
+pub const PACKET_LEN: usize = 188;
+
+/// The number of bytes read at the start and at the end of the file.
+const SCAN_LEN: u64 = 4 << 20;
+
+/// The number of bytes of the first PES packet of a stream kept to inspect its codec.
+const MAX_PES_LEN: usize = 64 << 10;
+
+const PTS_CLOCK: f64 = 90000.0;
+
+/// A transport stream packet carrying a payload.
+pub struct Packet<'a> {
+    pub pid: u16,
+    /// Whether the payload starts a PES packet or a section.
+    pub start: bool,
+    pub payload: &'a [u8],
+}
+
+/// Iterates over the packets of `data` that carry a payload, starting at the first packet.
+pub fn packets(data: &[u8]) -> impl Iterator<Item = Packet<'_>> {
+    let start = (0..PACKET_LEN)
+        .find(|i| {
+            data.get(*i) == Some(&0x47) && data.get(i + PACKET_LEN).is_none_or(|b| *b == 0x47)
+        })
+        .unwrap_or(data.len());
+    data[start..]
+        .chunks_exact(PACKET_LEN)
+        .filter(|packet| packet[0] == 0x47)
+        .filter_map(|packet| {
+            let pid = u16::from_be_bytes([packet[1], packet[2]]) & 0x1fff;
+            let start = packet[1] & 0x40 != 0;
+            let payload = match packet[3] >> 4 & 3 {
+                1 => &packet[4..],
+                3 => packet.get(5 + usize::from(packet[4])..)?,
+                _ => return None,
+            };
+            Some(Packet {
+                pid,
+                start,
+                payload,
+            })
+        })
+}
+
+/// Returns the body of the section starting in `payload`, without its CRC.
+fn section(payload: &[u8]) -> io::Result<ByteReader<'_>> {
+    let mut r = ByteReader::new(payload);
+    let pointer = r.u8()?;
+    r.skip(usize::from(pointer))?;
+    r.u8()?; // table_id
+    let len = usize::from(r.u16()? & 0xfff);
+    let body = r.bytes(len.saturating_sub(4))?;
+    let mut r = ByteReader::new(body);
+    r.skip(5)?; // table_id_extension, version_number, section_number, last_section_number
+    Ok(r)
+}
+
+/// Returns the presentation timestamp of the PES packet starting in `payload`, and the offset of
+/// its data.
+pub fn pes_header(payload: &[u8]) -> io::Result<(Option<u64>, usize)> {
+    let mut r = ByteReader::new(payload);
+    if r.u24()? != 1 {
+        return Err(invalid_data("missing PES start code"));
+    }
+    r.u8()?; // stream_id
+    r.u16()?; // PES_packet_length
+    let flags = r.u16()?;
+    let len = usize::from(r.u8()?);
+    let header = r.bytes(len)?;
+    let pts = (flags & 0x80 != 0 && 5 <= header.len()).then(|| {
+        u64::from(header[0] >> 1 & 7) << 30
+            | u64::from(header[1]) << 22
+            | u64::from(header[2] >> 1) << 15
+            | u64::from(header[3]) << 7
+            | u64::from(header[4] >> 1)
+    });
+    Ok((pts, r.position()))
+}
+
+struct Elementary {
+    stream_type: u8,
+    stream: Stream,
+    pes: Vec<u8>,
+    complete: bool,
+    first_pts: Option<u64>,
+    last_pts: Option<u64>,
+}
+
+pub fn probe<R: Read + Seek>(reader: &mut R, len: u64) -> io::Result<MediaInfo> {
+    let head = read_at(reader, 0, SCAN_LEN)?;
+    let mut pmt_pids = Vec::new();
+    let mut parsed_pmts = Vec::new();
+    let mut streams: Vec<(u16, Elementary)> = Vec::new();
+    for packet in packets(&head) {
+        if packet.pid == 0 {
+            if packet.start && pmt_pids.is_empty() {
+                let mut r = section(packet.payload)?;
+                while 4 <= r.remaining() {
+                    let program = r.u16()?;
+                    let pid = r.u16()? & 0x1fff;
+                    if program != 0 {
+                        pmt_pids.push(pid);
+                    }
+                }
+            }
+        } else if pmt_pids.contains(&packet.pid) {
+            if packet.start && !parsed_pmts.contains(&packet.pid) {
+                parsed_pmts.push(packet.pid);
+                for (pid, stream) in parse_pmt(packet.payload)? {
+                    if streams.iter().all(|(p, _)| *p != pid) {
+                        streams.push((pid, stream));
+                    }
+                }
+            }
+        } else if let Some((_, es)) = streams.iter_mut().find(|(pid, _)| *pid == packet.pid) {
+            if es.complete {
+                continue;
+            }
+
+            if packet.start {
+                if !es.pes.is_empty() {
+                    es.complete = true;
+                    continue;
+                }
+                let Ok((pts, offset)) = pes_header(packet.payload) else {
+                    continue;
+                };
+                es.first_pts = pts;
+                es.pes.extend_from_slice(&packet.payload[offset..]);
+            } else if !es.pes.is_empty() {
+                es.pes.extend_from_slice(packet.payload);
+            }
+            es.complete |= MAX_PES_LEN <= es.pes.len();
+        }
+    }
+    if parsed_pmts.is_empty() {
+        return Err(invalid_data("missing program map table"));
+    }
+
+    let tail = if len <= SCAN_LEN {
+        head
+    } else {
+        read_at(reader, len - SCAN_LEN, SCAN_LEN)?
+    };
+    for packet in packets(&tail).filter(|packet| packet.start) {
+        if let Some((_, es)) = streams.iter_mut().find(|(pid, _)| *pid == packet.pid) {
+            if let Ok((Some(pts), _)) = pes_header(packet.payload) {
+                es.last_pts = Some(pts);
+            }
+        }
+    }
+
+    let mut info = MediaInfo::new(Container::MpegTs);
+    for (_, mut es) in streams {
+        inspect(&mut es);
+        if let (Some(first), Some(last)) = (es.first_pts, es.last_pts) {
+            let duration = last.wrapping_sub(first) & ((1 << 33) - 1);
+            let duration = duration as f64 / PTS_CLOCK;
+            if info.duration.is_none_or(|d| d < duration) {
+                info.duration = Some(duration).filter(|d| 0.0 < *d);
+            }
+        }
+        info.streams.push(es.stream);
+    }
+
+    Ok(info)
+}
+
+fn parse_pmt(payload: &[u8]) -> io::Result<Vec<(u16, Elementary)>> {
+    let mut r = section(payload)?;
+    r.u16()?; // PCR_PID
+    let len = usize::from(r.u16()? & 0xfff);
+    r.skip(len)?; // program_info
+
+    let mut streams = Vec::new();
+    while 5 <= r.remaining() {
+        let stream_type = r.u8()?;
+        let pid = r.u16()? & 0x1fff;
+        let len = usize::from(r.u16()? & 0xfff);
+        let descriptors = r.bytes(len)?;
+
+        let (kind, mut codec) = match stream_type {
+            0x01 => (StreamKind::Video, "mpeg1video"),
+            0x02 => (StreamKind::Video, "mpeg2video"),
+            0x10 => (StreamKind::Video, "mpeg4"),
+            0x1b => (StreamKind::Video, "h264"),
+            0x24 => (StreamKind::Video, "hevc"),
+            0x03 | 0x04 => (StreamKind::Audio, "mp2"),
+            0x0f => (StreamKind::Audio, "aac"),
+            0x11 => (StreamKind::Audio, "aac_latm"),
+            0x81 => (StreamKind::Audio, "ac3"),
+            0x87 => (StreamKind::Audio, "eac3"),
+            0x82 | 0x85 | 0x8a => (StreamKind::Audio, "dts"),
+            0x83 => (StreamKind::Audio, "truehd"),
+            0x90 => (StreamKind::Subtitle, "hdmv_pgs_subtitle"),
+            0x15 => (StreamKind::Data, "timed_id3"),
+            0x86 => (StreamKind::Data, "scte_35"),
+            _ => (StreamKind::Data, "bin_data"),
+        };
+
+        let mut language = None;
+        let mut d = ByteReader::new(descriptors);
+        while 2 <= d.remaining() {
+            let tag = d.u8()?;
+            let len = usize::from(d.u8()?);
+            let data = d.bytes(len)?;
+            match tag {
+                0x0a | 0x59 | 0x56 if 3 <= data.len() => {
+                    language = Some(String::from_utf8_lossy(&data[..3]).to_string());
+                }
+                _ => {}
+            }
+            if stream_type == 0x06 {
+                match (tag, data) {
+                    (0x6a, _) | (0x05, b"AC-3") => codec = "ac3",
+                    (0x7a, _) | (0x05, b"EAC3") => codec = "eac3",
+                    (0x7b, _) => codec = "dts",
+                    (0x05, b"Opus") => codec = "opus",
+                    (0x59, _) => codec = "dvb_subtitle",
+                    (0x56, _) => codec = "dvb_teletext",
+                    _ => {}
+                }
+            }
+        }
+        let kind = match codec {
+            "ac3" | "eac3" | "dts" | "opus" => StreamKind::Audio,
+            "dvb_subtitle" | "dvb_teletext" => StreamKind::Subtitle,
+            _ => kind,
+        };
+
+        let mut stream = Stream::new(u64::from(pid), kind, codec);
+        stream.language = language.filter(|language| language != "und");
+        streams.push((
+            pid,
+            Elementary {
+                stream_type,
+                stream,
+                pes: Vec::new(),
+                complete: false,
+                first_pts: None,
+                last_pts: None,
+            },
+        ));
+    }
+
+    Ok(streams)
+}
+
+/// Completes the codec details of a stream from the data of its first PES packet.
+fn inspect(es: &mut Elementary) {
+    let data = &es.pes;
+    let stream = &mut es.stream;
+    match stream.codec.as_str() {
+        "h264" => {
+            let sps = nal_units(data).find(|nal| nal.first().is_some_and(|b| b & 0x1f == 7));
+            if let Some(sps) = sps {
+                let _ = codec::h264_sps(stream, sps);
+            }
+        }
+        "aac" => {
+            let _ = codec::adts_header(stream, data);
+        }
+        "ac3" | "eac3" => {
+            if let Some(offset) = data.windows(2).position(|w| w == [0x0b, 0x77]) {
+                if let Ok(name) = codec::ac3_header(stream, &data[offset..]) {
+                    stream.codec = name.to_string();
+                }
+            }
+        }
+        "mp2" if matches!(es.stream_type, 0x03 | 0x04) => {
+            if let Some(header) = mp3::frame_header(data) {
+                header.describe(stream);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Iterates over the NAL units of an Annex B byte stream.
+fn nal_units(data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let starts: Vec<usize> = data
+        .windows(3)
+        .enumerate()
+        .filter(|(_, w)| *w == [0, 0, 1])
+        .map(|(i, _)| i + 3)
+        .collect();
+    (0..starts.len()).map(move |i| {
+        let end = starts.get(i + 1).map_or(data.len(), |next| next - 3);
+        &data[starts[i]..end]
+    })
+}
@@ -0,0 +1,202 @@
+use std::io::{self, Read, Seek};
+
+use super::bits::{invalid_data, ByteReader};
+use super::flac;
+use super::probe::{read_at, Container, MediaInfo, Stream, StreamKind};
+
+/// The number of bytes read at the start and at the end of the file.
+const SCAN_LEN: u64 = 256 << 10;
+
+/// A page of an Ogg bitstream.
+struct Page<'a> {
+    beginning: bool,
+    granule: i64,
+    serial: u32,
+    /// The first packet starting on the page.
+    packet: &'a [u8],
+}
+
+/// Iterates over the pages found in `data`.
+fn pages(data: &[u8]) -> impl Iterator<Item = Page<'_>> {
+    let mut offset = 0;
+    std::iter::from_fn(move || loop {
+        let start = offset + data.get(offset..)?.windows(4).position(|w| w == b"OggS")?;
+        offset = start + 4;
+        if let Ok((page, len)) = page(&data[start..]) {
+            offset = start + len;
+            return Some(page);
+        }
+    })
+}
+
+fn page(data: &[u8]) -> io::Result<(Page<'_>, usize)> {
+    let mut r = ByteReader::new(data);
+    r.skip(4)?; // capture_pattern
+    if r.u8()? != 0 {
+        return Err(invalid_data("unsupported Ogg version"));
+    }
+    let header_type = r.u8()?;
+    let granule = r.i64_le()?;
+    let serial = r.u32_le()?;
+    r.skip(8)?; // page_sequence_number, CRC_checksum
+    let count = usize::from(r.u8()?);
+    let lacing = r.bytes(count)?;
+    let len: usize = lacing.iter().map(|len| usize::from(*len)).sum();
+    let body = r.bytes(len)?;
+    let first = lacing.iter().position(|len| *len < 255).map_or(len, |i| {
+        lacing[..=i].iter().map(|len| usize::from(*len)).sum()
+    });
+    Ok((
+        Page {
+            beginning: header_type & 2 != 0,
+            granule,
+            serial,
+            packet: &body[..first],
+        },
+        r.position(),
+    ))
+}
+
+/// A logical bitstream and how to convert its granule positions to seconds.
+struct Logical {
+    serial: u32,
+    stream: Stream,
+    rate: f64,
+    pre_skip: i64,
+    /// The shift of the keyframe number in Theora granule positions.
+    granule_shift: Option<u32>,
+    last_granule: Option<i64>,
+}
+
+impl Logical {
+    fn seconds(&self, granule: i64) -> f64 {
+        let frames = match self.granule_shift {
+            Some(shift) => (granule >> shift) + (granule & ((1 << shift) - 1)),
+            None => granule - self.pre_skip,
+        };
+        frames as f64 / self.rate
+    }
+}
+
+pub fn probe<R: Read + Seek>(reader: &mut R, len: u64) -> io::Result<MediaInfo> {
+    let head = read_at(reader, 0, SCAN_LEN)?;
+    let mut streams = Vec::new();
+    for page in pages(&head) {
+        if !page.beginning {
+            break;
+        }
+        if let Some(stream) = identify(page.serial, page.packet)? {
+            streams.push(stream);
+        }
+    }
+    if streams.is_empty() {
+        return Err(invalid_data("missing Ogg identification header"));
+    }
+
+    let tail = read_at(reader, len.saturating_sub(SCAN_LEN), SCAN_LEN)?;
+    for page in pages(&tail) {
+        if let Some(stream) = streams.iter_mut().find(|s| s.serial == page.serial) {
+            if 0 <= page.granule {
+                stream.last_granule = Some(page.granule);
+            }
+        }
+    }
+
+    let mut info = MediaInfo::new(Container::Ogg);
+    for stream in streams {
+        if let Some(granule) = stream.last_granule {
+            let duration = stream.seconds(granule);
+            if info.duration.is_none_or(|d| d < duration) {
+                info.duration = Some(duration).filter(|d| 0.0 < *d);
+            }
+        }
+        info.streams.push(stream.stream);
+    }
+
+    Ok(info)
+}
+
+/// Identifies the codec of a logical bitstream from its first packet.
+fn identify(serial: u32, packet: &[u8]) -> io::Result<Option<Logical>> {
+    let mut r = ByteReader::new(packet);
+    let logical = |stream, rate| Logical {
+        serial,
+        stream,
+        rate,
+        pre_skip: 0,
+        granule_shift: None,
+        last_granule: None,
+    };
+
+    if packet.starts_with(b"\x01vorbis") {
+        r.skip(7)?;
+        r.u32_le()?; // vorbis_version
+        let mut stream = Stream::new(u64::from(serial), StreamKind::Audio, "vorbis");
+        stream.codecs = Some("vorbis".to_string());
+        stream.channels = Some(u16::from(r.u8()?));
+        let rate = r.u32_le()?;
+        stream.sample_rate = Some(rate);
+        r.u32_le()?; // bitrate_maximum
+        let nominal = r.u32_le()? as i32;
+        if 0 < nominal {
+            stream.bitrate = Some(nominal as u64);
+        }
+        return Ok(Some(logical(stream, f64::from(rate))));
+    }
+
+    if packet.starts_with(b"OpusHead") {
+        r.skip(8)?;
+        r.u8()?; // version
+        let mut stream = Stream::new(u64::from(serial), StreamKind::Audio, "opus");
+        stream.codecs = Some("opus".to_string());
+        stream.channels = Some(u16::from(r.u8()?));
+        let pre_skip = r.u16_le()?;
+        stream.sample_rate = Some(48000);
+        let mut logical = logical(stream, 48000.0);
+        logical.pre_skip = i64::from(pre_skip);
+        return Ok(Some(logical));
+    }
+
+    if packet.starts_with(b"\x80theora") {
+        r.skip(7)?;
+        r.skip(3)?; // VMAJ, VMIN, VREV
+        r.skip(4)?; // FMBW, FMBH
+        let mut stream = Stream::new(u64::from(serial), StreamKind::Video, "theora");
+        stream.codecs = Some("theora".to_string());
+        stream.width = Some(r.u24()?);
+        stream.height = Some(r.u24()?);
+        r.skip(2)?; // PICX, PICY
+        let numerator = r.u32()?;
+        let denominator = r.u32()?;
+        r.skip(7)?; // PARN, PARD, CS
+        let bitrate = r.u24()?;
+        if bitrate != 0 {
+            stream.bitrate = Some(u64::from(bitrate));
+        }
+        let shift = u32::from(r.u16()? >> 5 & 0x1f);
+        let rate = if denominator != 0 {
+            f64::from(numerator) / f64::from(denominator)
+        } else {
+            0.0
+        };
+        stream.frame_rate = Some(rate).filter(|rate| 0.0 < *rate);
+        let mut logical = logical(stream, rate);
+        logical.granule_shift = Some(shift);
+        return Ok(Some(logical));
+    }
+
+    if packet.starts_with(b"\x7fFLAC") {
+        r.skip(9)?; // signature, version, number of header packets
+        if r.bytes(4)? != b"fLaC" {
+            return Err(invalid_data("missing fLaC marker"));
+        }
+        r.u32()?; // metadata block header
+        let mut stream = Stream::new(u64::from(serial), StreamKind::Audio, "flac");
+        stream.codecs = Some("flac".to_string());
+        flac::stream_info(&mut stream, r.rest())?;
+        let rate = f64::from(stream.sample_rate.unwrap_or_default());
+        return Ok(Some(logical(stream, rate)));
+    }
+
+    Ok(None)
+}
@@ -0,0 +1,305 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use super::{flac, matroska, mime, mp3, mp4, mpegts, ogg, wav};
+
+/// The container format of a media file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Container {
+    Mp4,
+    Mov,
+    Matroska,
+    Webm,
+    MpegTs,
+    Mp3,
+    Flac,
+    Ogg,
+    Wav,
+}
+
+impl Container {
+    fn from_mime(mime: &str) -> Option<Self> {
+        match mime {
+            "video/mp4" | "audio/mp4" | "video/3gpp" => Some(Self::Mp4),
+            "video/quicktime" => Some(Self::Mov),
+            "video/x-matroska" | "audio/x-matroska" => Some(Self::Matroska),
+            "video/webm" | "audio/webm" => Some(Self::Webm),
+            "video/mp2t" => Some(Self::MpegTs),
+            "audio/mpeg" => Some(Self::Mp3),
+            "audio/flac" => Some(Self::Flac),
+            "audio/ogg" | "video/ogg" => Some(Self::Ogg),
+            "audio/wav" => Some(Self::Wav),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Container {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Mp4 => "mp4",
+            Self::Mov => "mov",
+            Self::Matroska => "matroska",
+            Self::Webm => "webm",
+            Self::MpegTs => "mpegts",
+            Self::Mp3 => "mp3",
+            Self::Flac => "flac",
+            Self::Ogg => "ogg",
+            Self::Wav => "wav",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamKind {
+    Video,
+    Audio,
+    Subtitle,
+    Data,
+}
+
+/// A stream of a media file, as described by the headers of its container.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Stream {
+    /// The track ID, track number, PID or serial number of the stream, depending on the
+    /// container.
+    pub id: u64,
+    pub kind: StreamKind,
+    pub codec: String,
+    /// The codec as an RFC 6381 `codecs` parameter, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub codecs: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bit_depth: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frame_rate: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bitrate: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channels: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample_rate: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default: Option<bool>,
+}
+
+impl Stream {
+    pub fn new(id: u64, kind: StreamKind, codec: impl Into<String>) -> Self {
+        Self {
+            id,
+            kind,
+            codec: codec.into(),
+            codecs: None,
+            profile: None,
+            level: None,
+            bit_depth: None,
+            width: None,
+            height: None,
+            frame_rate: None,
+            bitrate: None,
+            channels: None,
+            sample_rate: None,
+            language: None,
+            title: None,
+            default: None,
+        }
+    }
+}
+
+/// What the headers of a media file tell about it.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct MediaInfo {
+    pub container: Container,
+    /// The duration in seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    /// The overall bitrate in bits per second.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bitrate: Option<u64>,
+    pub streams: Vec<Stream>,
+}
+
+impl MediaInfo {
+    pub fn new(container: Container) -> Self {
+        Self {
+            container,
+            duration: None,
+            bitrate: None,
+            streams: Vec::new(),
+        }
+    }
+}
+
+/// Parses the headers of the media file at `path`, or returns `None` if it is not in a supported
+/// format.
+pub fn probe(path: &Path) -> io::Result<Option<MediaInfo>> {
+    let file = File::open(path)?;
+    let metadata = file.metadata()?;
+    if !metadata.is_file() {
+        return Err(io::ErrorKind::NotFound.into());
+    }
+    let len = metadata.len();
+    let mut reader = BufReader::new(file);
+
+    let mut header = Vec::with_capacity(mime::HEADER_LEN);
+    (&mut reader)
+        .take(mime::HEADER_LEN as u64)
+        .read_to_end(&mut header)?;
+    reader.rewind()?;
+
+    let Some(container) = mime::detect(path, &header).and_then(Container::from_mime) else {
+        return Ok(None);
+    };
+
+    let mut info = match container {
+        Container::Mp4 | Container::Mov => mp4::probe(&mut reader, len, container)?,
+        Container::Matroska | Container::Webm => matroska::probe(&mut reader, len)?,
+        Container::MpegTs => mpegts::probe(&mut reader, len)?,
+        Container::Mp3 => mp3::probe(&mut reader, len)?,
+        Container::Flac => flac::probe(&mut reader, len)?,
+        Container::Ogg => ogg::probe(&mut reader, len)?,
+        Container::Wav => wav::probe(&mut reader, len)?,
+    };
+
+    if info.bitrate.is_none() {
+        info.bitrate = info
+            .duration
+            .filter(|d| 0.0 < *d)
+            .map(|d| (len as f64 * 8.0 / d) as u64);
+    }
+
+    Ok(Some(info))
+}
+
+/// The number of files whose media information is kept by a [`Cache`].
+const MAX_CACHE_ENTRIES: usize = 4096;
+
+/// The media information of a file, along with when it was last used.
+struct CacheEntry {
+    modified: SystemTime,
+    len: u64,
+    info: Option<Arc<MediaInfo>>,
+    used: u64,
+}
+
+/// The cached files, along with the order they were last used in to evict the least recently
+/// used one when the cache is full.
+#[derive(Default)]
+struct Entries {
+    files: HashMap<PathBuf, CacheEntry>,
+    uses: BTreeMap<u64, PathBuf>,
+    clock: u64,
+}
+
+impl Entries {
+    fn get(
+        &mut self,
+        path: &Path,
+        modified: SystemTime,
+        len: u64,
+    ) -> Option<Option<Arc<MediaInfo>>> {
+        let entry = self.files.get_mut(path)?;
+        if (entry.modified, entry.len) != (modified, len) {
+            return None;
+        }
+        self.clock += 1;
+        let path = self.uses.remove(&entry.used)?;
+        entry.used = self.clock;
+        self.uses.insert(self.clock, path);
+        Some(entry.info.clone())
+    }
+
+    fn insert(
+        &mut self,
+        path: &Path,
+        modified: SystemTime,
+        len: u64,
+        info: Option<Arc<MediaInfo>>,
+    ) {
+        self.clock += 1;
+        let entry = CacheEntry {
+            modified,
+            len,
+            info,
+            used: self.clock,
+        };
+        if let Some(old) = self.files.insert(path.to_path_buf(), entry) {
+            self.uses.remove(&old.used);
+        }
+        self.uses.insert(self.clock, path.to_path_buf());
+
+        while MAX_CACHE_ENTRIES < self.files.len() {
+            let Some((_, oldest)) = self.uses.pop_first() else {
+                break;
+            };
+            self.files.remove(&oldest);
+        }
+    }
+}
+
+impl fmt::Debug for Entries {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Entries")
+            .field("len", &self.files.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Keeps the media information of files until they are modified, so that listings do not parse
+/// the same headers again.
+#[derive(Debug, Clone, Default)]
+pub struct Cache(Arc<Mutex<Entries>>);
+
+impl Cache {
+    /// Returns the media information of the file at `path` like [`probe`], parsing its headers
+    /// only if they are not cached.
+    pub fn probe(&self, path: &Path) -> io::Result<Option<Arc<MediaInfo>>> {
+        let metadata = fs::metadata(path)?;
+        let (modified, len) = (metadata.modified()?, metadata.len());
+        if let Some(info) = self.0.lock().unwrap().get(path, modified, len) {
+            return Ok(info);
+        }
+
+        let info = probe(path)?.map(Arc::new);
+        self.0
+            .lock()
+            .unwrap()
+            .insert(path, modified, len, info.clone());
+        Ok(info)
+    }
+}
+
+/// Converts a duration in units of `1 / timescale` seconds to seconds.
+pub fn seconds(duration: u64, timescale: u64) -> Option<f64> {
+    (timescale != 0 && duration != 0).then(|| duration as f64 / timescale as f64)
+}
+
+/// Reads `len` bytes at `offset`, or fewer at the end of the file.
+pub fn read_at<R: Read + Seek>(reader: &mut R, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+    reader.seek(io::SeekFrom::Start(offset))?;
+    let mut data = Vec::new();
+    reader.take(len).read_to_end(&mut data)?;
+    Ok(data)
+}
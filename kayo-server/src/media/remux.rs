@@ -0,0 +1,721 @@
+//! Remuxes Matroska and WebM files to fragmented MP4 without transcoding, with a fragment per
+//! cluster.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, Write};
+use std::path::Path;
+
+use super::bits::invalid_data;
+use super::codec;
+use super::fmp4::{self, Run, Sample, NON_SYNC_SAMPLE, SYNC_SAMPLE, TIMESCALE};
+use super::matroska::{Block, Segment, Track};
+use super::probe::{Container, Stream, StreamKind};
+
+/// The containers that can be remuxed.
+pub const CONTAINERS: [Container; 2] = [Container::Matroska, Container::Webm];
+
+/// The codecs that can be stored in MP4.
+pub const VIDEO_CODECS: [&str; 4] = ["h264", "hevc", "vp9", "av1"];
+pub const AUDIO_CODECS: [&str; 6] = ["aac", "mp3", "ac3", "eac3", "opus", "flac"];
+
+/// The number of clusters searched for the first frame of a track whose configuration is only
+/// found in its frames.
+const FRAME_SEARCH_CLUSTERS: usize = 16;
+
+fn unsupported(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, message)
+}
+
+/// A track copied to the output.
+struct Output {
+    /// The index of the Matroska track in the segment.
+    index: usize,
+    /// The number of the Matroska track.
+    number: u64,
+    id: u32,
+    kind: StreamKind,
+    stripped_header: Vec<u8>,
+    /// The duration of a frame in nanoseconds, if constant.
+    default_duration: Option<u64>,
+    /// The decoding time of the last sample written.
+    last_decode_time: Option<i64>,
+    /// Whether a keyframe was written, before which frames cannot be decoded.
+    started: bool,
+}
+
+/// Writes a Matroska file as fragmented MP4.
+pub struct Remuxer {
+    reader: BufReader<File>,
+    segment: Segment,
+    tracks: Vec<Output>,
+    headers: Vec<fmp4::Track>,
+    first_cluster: u64,
+    /// The offset of the next cluster to read.
+    offset: u64,
+    /// The Matroska timestamp that becomes the start of the output.
+    base: i64,
+    sequence: u32,
+}
+
+impl Remuxer {
+    /// Prepares to remux the video track and an audio track of a Matroska file. The audio
+    /// track is chosen by its number, or is the default one otherwise.
+    pub fn open(path: &Path, audio: Option<u64>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        let segment = Segment::open(&mut reader, len)?;
+
+        let mut selected = Vec::new();
+        let video = segment
+            .tracks
+            .iter()
+            .find(|t| t.stream.kind == StreamKind::Video);
+        if let Some(video) = video {
+            if !VIDEO_CODECS.contains(&video.stream.codec.as_str()) {
+                let codec = &video.stream.codec;
+                return Err(unsupported(format!(
+                    "{codec} video cannot be stored in MP4"
+                )));
+            }
+            selected.push(video);
+        }
+
+        let mut audio_tracks = segment
+            .tracks
+            .iter()
+            .filter(|t| t.stream.kind == StreamKind::Audio);
+        let audio = match audio {
+            Some(number) => Some(audio_tracks.find(|t| t.stream.id == number).ok_or_else(
+                || {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("there is no audio track {number}"),
+                    )
+                },
+            )?),
+            None => {
+                let supported = audio_tracks
+                    .filter(|t| AUDIO_CODECS.contains(&t.stream.codec.as_str()))
+                    .collect::<Vec<_>>();
+                supported
+                    .iter()
+                    .find(|t| t.stream.default == Some(true))
+                    .or(supported.first())
+                    .copied()
+            }
+        };
+        selected.extend(audio);
+        if selected.is_empty() {
+            return Err(unsupported(
+                "no video or audio track can be stored in MP4".to_string(),
+            ));
+        }
+
+        let mut tracks = Vec::new();
+        let mut headers = Vec::new();
+        for (track, id) in selected.into_iter().zip(1..) {
+            let index = segment
+                .tracks
+                .iter()
+                .position(|t| std::ptr::eq(t, track))
+                .unwrap();
+            if track.encoded {
+                let number = track.stream.id;
+                return Err(unsupported(format!("track {number} is compressed")));
+            }
+            let stream = &track.stream;
+            headers.push(fmp4::Track {
+                id,
+                kind: stream.kind,
+                timescale: TIMESCALE,
+                width: stream.width.unwrap_or_default() as u16,
+                height: stream.height.unwrap_or_default() as u16,
+                language: fmp4::language(stream.language.as_deref()),
+                sample_entry: sample_entry(&segment, &mut reader, track)?,
+            });
+            tracks.push(Output {
+                index,
+                number: stream.id,
+                id,
+                kind: stream.kind,
+                stripped_header: track.stripped_header.clone(),
+                default_duration: track.default_duration,
+                last_decode_time: None,
+                started: false,
+            });
+        }
+
+        let first_cluster = segment
+            .first_cluster()
+            .ok_or_else(|| invalid_data("missing Cluster element"))?;
+        Ok(Self {
+            reader,
+            segment,
+            tracks,
+            headers,
+            first_cluster,
+            offset: first_cluster,
+            base: 0,
+            sequence: 0,
+        })
+    }
+
+    /// The streams of the selected tracks.
+    pub fn streams(&self) -> impl Iterator<Item = &Stream> {
+        self.tracks
+            .iter()
+            .map(|track| &self.segment.tracks[track.index].stream)
+    }
+
+    /// The duration of the file in seconds.
+    pub fn duration(&self) -> Option<f64> {
+        self.segment.duration
+    }
+
+    /// Lists the times in seconds from which the video can be presented, with the offset of
+    /// the cluster to start reading from.
+    pub fn keyframes(&mut self) -> io::Result<Vec<(f64, u64)>> {
+        let video = self.tracks.iter().find(|t| t.kind == StreamKind::Video);
+        let scale = self.segment.timestamp_scale as f64;
+        let keyframes = keyframes(&self.segment, &mut self.reader, self.first_cluster, video)?;
+        Ok(keyframes
+            .into_iter()
+            .map(|(time, offset)| (time as f64 * scale / 1e9, offset))
+            .collect())
+    }
+
+    /// Starts from the keyframe preceding `start` seconds.
+    pub fn seek(&mut self, start: f64) -> io::Result<()> {
+        let target = (start * 1e9 / self.segment.timestamp_scale as f64) as u64;
+        if target == 0 {
+            return Ok(());
+        }
+
+        let video = self.tracks.iter().find(|t| t.kind == StreamKind::Video);
+        let keyframes = keyframes(&self.segment, &mut self.reader, self.first_cluster, video)?;
+        let (time, offset) = keyframes
+            .into_iter()
+            .take_while(|(time, _)| *time <= target)
+            .last()
+            .unwrap_or((0, self.first_cluster));
+        self.offset = offset;
+        self.base = time as i64;
+        Ok(())
+    }
+
+    /// The time in the file that the output starts from, in seconds.
+    pub fn start(&self) -> f64 {
+        self.base as f64 * self.segment.timestamp_scale as f64 / 1e9
+    }
+
+    /// Builds the initialization segment, giving the duration from the start.
+    pub fn init_segment(&self) -> Vec<u8> {
+        let duration = self
+            .segment
+            .duration
+            .map(|duration| (duration - self.start()).max(0.0))
+            .map(|duration| (duration * f64::from(TIMESCALE)) as u64);
+        fmp4::init_segment(&self.headers, duration)
+    }
+
+    pub fn write_to<W: Write>(mut self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.init_segment())?;
+        while let Some(cluster) = self.segment.read_cluster(&mut self.reader, self.offset)? {
+            self.offset = cluster.end;
+            let runs = self.runs(&cluster.blocks);
+            if !runs.is_empty() {
+                self.sequence += 1;
+                writer.write_all(&fmp4::fragment(self.sequence, &runs))?;
+            }
+        }
+        writer.flush()
+    }
+
+    /// Writes the clusters from `offset` until `end`, if given, as a single fragment numbered
+    /// `sequence`.
+    pub fn write_fragment<W: Write>(
+        &mut self,
+        offset: u64,
+        end: Option<u64>,
+        sequence: u32,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        self.offset = offset;
+        let mut runs: Vec<Run> = Vec::new();
+        while end.is_none_or(|end| self.offset < end) {
+            let Some(cluster) = self.segment.read_cluster(&mut self.reader, self.offset)? else {
+                break;
+            };
+            self.offset = cluster.end;
+            for run in self.runs(&cluster.blocks) {
+                match runs.iter_mut().find(|r| r.track == run.track) {
+                    Some(previous) => previous.append(run),
+                    None => runs.push(run),
+                }
+            }
+        }
+        if !runs.is_empty() {
+            writer.write_all(&fmp4::fragment(sequence, &runs))?;
+        }
+        Ok(())
+    }
+
+    /// Converts the frames of a cluster to samples.
+    fn runs(&mut self, blocks: &[Block]) -> Vec<Run> {
+        let scale = self.segment.timestamp_scale;
+        let ticks = |timestamp: i64| timestamp * scale as i64 / 1_000_000;
+        let mut runs = Vec::new();
+        for track in &mut self.tracks {
+            let mut frames = Vec::new();
+            for block in blocks.iter().filter(|b| b.track == track.number) {
+                let count = block.frames.len() as u64;
+                for (i, frame) in (0..).zip(&block.frames) {
+                    // Laced frames follow each other.
+                    let offset = match (track.default_duration, block.duration) {
+                        (Some(duration), _) => duration * i / scale,
+                        (None, Some(duration)) => duration * i / count,
+                        (None, None) => 0,
+                    };
+                    let timestamp = block.timestamp + offset as i64;
+                    let keyframe = block.keyframe || track.kind == StreamKind::Audio;
+                    if timestamp < self.base || !(track.started || keyframe) {
+                        continue;
+                    }
+                    track.started = true;
+
+                    let mut data = Vec::with_capacity(track.stripped_header.len() + frame.len());
+                    data.extend_from_slice(&track.stripped_header);
+                    data.extend_from_slice(frame);
+                    frames.push((ticks(timestamp - self.base), keyframe, data));
+                }
+            }
+            if frames.is_empty() {
+                continue;
+            }
+
+            // Frames are stored in decoding order with their presentation times, which are the
+            // decoding times once sorted.
+            let mut decode_times = frames.iter().map(|(time, ..)| *time).collect::<Vec<_>>();
+            decode_times.sort_unstable();
+            let mut previous = track.last_decode_time;
+            for time in &mut decode_times {
+                if let Some(previous) = previous.filter(|previous| *time <= *previous) {
+                    *time = previous + 1;
+                }
+                previous = Some(*time);
+            }
+
+            let default_duration = track.default_duration.map(|d| (d / 1_000_000) as u32);
+            let mut samples: Vec<Sample> = Vec::with_capacity(frames.len());
+            for (i, (time, keyframe, data)) in frames.into_iter().enumerate() {
+                let decode_time = decode_times[i];
+                let duration = match decode_times.get(i + 1) {
+                    Some(next) => (next - decode_time) as u32,
+                    None => default_duration
+                        .or(samples.last().map(|s| s.duration))
+                        .unwrap_or_default(),
+                };
+                samples.push(Sample {
+                    duration,
+                    flags: if keyframe {
+                        SYNC_SAMPLE
+                    } else {
+                        NON_SYNC_SAMPLE
+                    },
+                    composition_offset: (time - decode_time) as i32,
+                    data,
+                });
+            }
+            track.last_decode_time = decode_times.last().copied();
+            runs.push(Run {
+                track: track.id,
+                decode_time: decode_times[0] as u64,
+                samples,
+            });
+        }
+        runs
+    }
+}
+
+/// Lists the timestamps of the keyframes that the video can be presented from, with the
+/// offset of their cluster, in order.
+fn keyframes<R: Read + Seek>(
+    segment: &Segment,
+    reader: &mut R,
+    first_cluster: u64,
+    video: Option<&Output>,
+) -> io::Result<Vec<(u64, u64)>> {
+    let cues = segment.cues(reader)?;
+    let video_cues = cues
+        .iter()
+        .filter(|cue| video.is_some_and(|video| video.number == cue.track))
+        .map(|cue| (cue.time, cue.cluster))
+        .collect::<Vec<_>>();
+    let mut keyframes = if video_cues.is_empty() {
+        cues.iter().map(|cue| (cue.time, cue.cluster)).collect()
+    } else {
+        video_cues
+    };
+    if !keyframes.is_empty() {
+        keyframes.sort_unstable();
+        keyframes.dedup_by_key(|(_, cluster)| *cluster);
+        return Ok(keyframes);
+    }
+
+    // Without cues, clusters are assumed to start with a keyframe.
+    let mut offset = first_cluster;
+    while let Some((cluster, timestamp, end)) = segment.cluster_timestamp(reader, offset)? {
+        keyframes.push((timestamp, cluster));
+        let Some(end) = end else {
+            break;
+        };
+        offset = end;
+    }
+    Ok(keyframes)
+}
+
+fn config_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    fmp4::write_box(&mut out, kind, |out| out.extend_from_slice(body));
+    out
+}
+
+/// Builds the MP4 sample entry of a track.
+fn sample_entry<R: Read + Seek>(
+    segment: &Segment,
+    reader: &mut R,
+    track: &Track,
+) -> io::Result<Vec<u8>> {
+    let stream = &track.stream;
+    let codec = stream.codec.as_str();
+    let private = || {
+        track
+            .codec_private
+            .as_deref()
+            .ok_or_else(|| invalid_data(&format!("missing {codec} codec private data")))
+    };
+    let width = stream.width.unwrap_or_default() as u16;
+    let height = stream.height.unwrap_or_default() as u16;
+    let channels = stream.channels.unwrap_or(2);
+    let sample_rate = stream.sample_rate.unwrap_or(48000);
+    let visual =
+        |format, config: Vec<u8>| Ok(fmp4::visual_sample_entry(format, width, height, &config));
+    let audio = |format, config: Vec<u8>| {
+        Ok(fmp4::audio_sample_entry(
+            format,
+            channels,
+            sample_rate,
+            &config,
+        ))
+    };
+
+    match codec {
+        "h264" => visual(b"avc1", config_box(b"avcC", private()?)),
+        "hevc" => visual(b"hvc1", config_box(b"hvcC", private()?)),
+        "av1" => visual(b"av01", config_box(b"av1C", private()?)),
+        "vp9" => {
+            let mut config = Vec::new();
+            fmp4::write_full_box(&mut config, b"vpcC", 1, 0, |out| {
+                let profile = stream
+                    .profile
+                    .as_deref()
+                    .and_then(|p| p.strip_prefix("Profile "))
+                    .and_then(|p| p.parse::<u8>().ok())
+                    .unwrap_or_default();
+                let bit_depth = stream
+                    .bit_depth
+                    .unwrap_or(if 2 <= profile { 10 } else { 8 });
+                out.push(profile);
+                out.push(vp9_level(u64::from(width) * u64::from(height)));
+                // 4:2:0 subsampling with colocated chroma, limited range
+                out.push(bit_depth << 4 | 1 << 1);
+                // Unspecified colour primaries, transfer characteristics and matrix
+                out.extend_from_slice(&[2, 2, 2]);
+                out.extend_from_slice(&[0, 0]); // codecInitializationDataSize
+            });
+            visual(b"vp09", config)
+        }
+        "aac" => {
+            let config = match &track.codec_private {
+                Some(config) => config.clone(),
+                None => codec::aac_lc_config(sample_rate, channels),
+            };
+            audio(b"mp4a", fmp4::esds(0x40, &config))
+        }
+        "mp3" => audio(b"mp4a", fmp4::esds(0x6b, &[])),
+        "ac3" => {
+            let frame = first_frame(segment, reader, track)?;
+            audio(
+                b"ac-3",
+                config_box(b"dac3", &codec::ac3_specific_box(&frame)?),
+            )
+        }
+        "eac3" => {
+            let frame = first_frame(segment, reader, track)?;
+            audio(
+                b"ec-3",
+                config_box(b"dec3", &codec::eac3_specific_box(&frame)?),
+            )
+        }
+        "opus" => {
+            let config = config_box(b"dOps", &codec::opus_specific_box(private()?)?);
+            Ok(fmp4::audio_sample_entry(b"Opus", channels, 48000, &config))
+        }
+        "flac" => {
+            let blocks = private()?
+                .strip_prefix(b"fLaC")
+                .ok_or_else(|| invalid_data("missing fLaC marker"))?;
+            let mut config = Vec::new();
+            fmp4::write_full_box(&mut config, b"dfLa", 0, 0, |out| {
+                out.extend_from_slice(blocks)
+            });
+            audio(b"fLaC", config)
+        }
+        codec => Err(unsupported(format!("{codec} cannot be stored in MP4"))),
+    }
+}
+
+/// The lowest VP9 level allowing pictures of `samples` luma samples.
+fn vp9_level(samples: u64) -> u8 {
+    const LEVELS: [(u64, u8); 8] = [
+        (36864, 10),
+        (73728, 11),
+        (122880, 20),
+        (245760, 21),
+        (552960, 30),
+        (983040, 31),
+        (2228224, 40),
+        (8912896, 50),
+    ];
+    LEVELS
+        .iter()
+        .find(|(max, _)| samples <= *max)
+        .map_or(60, |(_, level)| *level)
+}
+
+/// Reads the first frame of a track, for codecs configured from their frames.
+fn first_frame<R: Read + Seek>(
+    segment: &Segment,
+    reader: &mut R,
+    track: &Track,
+) -> io::Result<Vec<u8>> {
+    let mut offset = segment.first_cluster().unwrap_or(u64::MAX);
+    for _ in 0..FRAME_SEARCH_CLUSTERS {
+        let Some(cluster) = segment.read_cluster(reader, offset)? else {
+            break;
+        };
+        offset = cluster.end;
+        let frame = cluster
+            .blocks
+            .into_iter()
+            .filter(|block| block.track == track.stream.id)
+            .find_map(|block| block.frames.into_iter().next());
+        if let Some(frame) = frame {
+            return Ok([track.stripped_header.as_slice(), &frame].concat());
+        }
+    }
+    Err(invalid_data(&format!(
+        "no {} frame in the first clusters",
+        track.stream.codec
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::matroska::tests::{remove_sample, sample_file, AAC_CONFIG, AVC_CONFIG};
+    use crate::media::mp4::{boxes, find, find_all, full_box};
+
+    fn kinds(data: &[u8]) -> Vec<[u8; 4]> {
+        boxes(data).map(|b| b.unwrap().0).collect()
+    }
+
+    /// The track, decoding time and samples of a track fragment.
+    type TrackFragment = (u32, u64, Vec<(u32, u32)>);
+
+    /// Returns the track of a track fragment, its decoding time and the duration and flags of
+    /// its samples.
+    fn track_fragment(traf: &[u8]) -> TrackFragment {
+        let (_, _, mut tfhd) = full_box(find(traf, &[b"tfhd"]).unwrap()).unwrap();
+        let (_, _, mut tfdt) = full_box(find(traf, &[b"tfdt"]).unwrap()).unwrap();
+        let (_, _, mut trun) = full_box(find(traf, &[b"trun"]).unwrap()).unwrap();
+        let count = trun.u32().unwrap();
+        trun.u32().unwrap(); // data_offset
+        let samples = (0..count)
+            .map(|_| {
+                let duration = trun.u32().unwrap();
+                trun.u32().unwrap(); // sample_size
+                let flags = trun.u32().unwrap();
+                trun.u32().unwrap(); // sample_composition_time_offset
+                (duration, flags)
+            })
+            .collect();
+        (tfhd.u32().unwrap(), tfdt.u64().unwrap(), samples)
+    }
+
+    /// Returns the sequence number of a movie fragment and its track fragments.
+    fn movie_fragment(moof: &[u8]) -> (u32, Vec<TrackFragment>) {
+        let (_, _, mut mfhd) = full_box(find(moof, &[b"mfhd"]).unwrap()).unwrap();
+        let trafs = find_all(moof, b"traf").map(track_fragment).collect();
+        (mfhd.u32().unwrap(), trafs)
+    }
+
+    /// Returns the duration given by the `mehd` box of an initialization segment.
+    fn fragment_duration(init: &[u8]) -> u64 {
+        let (version, _, mut mehd) =
+            full_box(find(init, &[b"moov", b"mvex", b"mehd"]).unwrap()).unwrap();
+        assert_eq!(version, 1);
+        mehd.u64().unwrap()
+    }
+
+    #[test]
+    fn init_segment() {
+        let path = sample_file();
+        let remuxer = Remuxer::open(&path, None).unwrap();
+        let codecs = remuxer
+            .streams()
+            .map(|s| s.codecs.as_deref().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(codecs, ["avc1.64001f", "mp4a.40.2"]);
+        assert_eq!(remuxer.duration(), Some(12.0));
+
+        let init = remuxer.init_segment();
+        assert_eq!(kinds(&init), [*b"ftyp", *b"moov"]);
+        let moov = find(&init, &[b"moov"]).unwrap();
+        assert_eq!(kinds(moov), [*b"mvhd", *b"trak", *b"trak", *b"mvex"]);
+        assert_eq!(fragment_duration(&init), 12000);
+        let trex_tracks = find_all(find(moov, &[b"mvex"]).unwrap(), b"trex")
+            .map(|trex| full_box(trex).unwrap().2.u32().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(trex_tracks, [1, 2]);
+
+        let traks = find_all(moov, b"trak").collect::<Vec<_>>();
+        let handler = |trak| &find(trak, &[b"mdia", b"hdlr"]).unwrap()[8..12];
+        assert_eq!(handler(traks[0]), b"vide");
+        assert_eq!(handler(traks[1]), b"soun");
+
+        let sample_entries = |trak| {
+            let stsd = find(trak, &[b"mdia", b"minf", b"stbl", b"stsd"]).unwrap();
+            // Version and flags, entry_count
+            &stsd[8..]
+        };
+        let avc1 = find(sample_entries(traks[0]), &[b"avc1"]).unwrap();
+        assert_eq!(&avc1[24..28], [1, 0x40, 0, 0xf0]); // width, height
+        assert_eq!(find(&avc1[78..], &[b"avcC"]).unwrap(), AVC_CONFIG);
+        let mp4a = find(sample_entries(traks[1]), &[b"mp4a"]).unwrap();
+        assert_eq!(&mp4a[16..18], [0, 2]); // channelcount
+        let esds = find(&mp4a[28..], &[b"esds"]).unwrap();
+        // The decoder specific info holds the configuration, and is followed by SLConfig.
+        assert!(esds.ends_with(
+            &[
+                &[0x05, 0x80, 0x80, 0x80, 0x02][..],
+                &AAC_CONFIG,
+                &[0x06, 0x80, 0x80, 0x80, 0x01, 0x02]
+            ]
+            .concat()
+        ));
+
+        let e = Remuxer::open(&path, Some(3)).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        remove_sample(&path);
+    }
+
+    #[test]
+    fn fragments() {
+        let path = sample_file();
+        let mut out = Vec::new();
+        Remuxer::open(&path, None)
+            .unwrap()
+            .write_to(&mut out)
+            .unwrap();
+
+        let mut kinds = kinds(&out).into_iter();
+        assert_eq!(kinds.next(), Some(*b"ftyp"));
+        assert_eq!(kinds.next(), Some(*b"moov"));
+        assert!(kinds
+            .collect::<Vec<_>>()
+            .chunks(2)
+            .all(|c| c == [*b"moof", *b"mdat"]));
+
+        // A fragment per cluster, with the times of the frames in milliseconds.
+        let fragments = find_all(&out, b"moof")
+            .map(movie_fragment)
+            .collect::<Vec<_>>();
+        assert_eq!(fragments.len(), 6);
+        for (sequence, (number, trafs)) in (1..).zip(&fragments) {
+            let time = u64::from(sequence - 1) * 2000;
+            assert_eq!(*number, sequence);
+            assert_eq!(
+                trafs[0],
+                (1, time, vec![(40, SYNC_SAMPLE), (40, NON_SYNC_SAMPLE)])
+            );
+            // Audio frames last until the next one, and the last like the one before.
+            assert_eq!(
+                trafs[1],
+                (2, time, vec![(20, SYNC_SAMPLE), (20, SYNC_SAMPLE)])
+            );
+        }
+
+        // Each sample is in the media data following its fragment.
+        let mdat = find(&out, &[b"mdat"]).unwrap();
+        assert_eq!(
+            mdat,
+            [
+                &[0, 0, 0, 2, 0x65, 0, 0, 0, 0, 2, 0x41, 0][..],
+                &[0x21, 0, 0x21, 0]
+            ]
+            .concat()
+        );
+        remove_sample(&path);
+    }
+
+    #[test]
+    fn seek() {
+        let path = sample_file();
+        let mut remuxer = Remuxer::open(&path, Some(2)).unwrap();
+        let keyframes = remuxer.keyframes().unwrap();
+        let times = keyframes.iter().map(|(time, _)| *time).collect::<Vec<_>>();
+        assert_eq!(times, [0.0, 2.0, 4.0, 6.0, 8.0, 10.0]);
+
+        // Output starts at the keyframe preceding the time, and its times from 0.
+        remuxer.seek(5.0).unwrap();
+        assert_eq!(remuxer.start(), 4.0);
+        assert_eq!(fragment_duration(&remuxer.init_segment()), 8000);
+        let mut out = Vec::new();
+        remuxer.write_to(&mut out).unwrap();
+        let fragments = find_all(&out, b"moof")
+            .map(movie_fragment)
+            .collect::<Vec<_>>();
+        assert_eq!(fragments.len(), 4);
+        assert_eq!((fragments[0].1[0].1, fragments[3].1[1].1), (0, 6000));
+
+        // Clusters written as a single fragment end each sample where the next one starts.
+        let mut remuxer = Remuxer::open(&path, None).unwrap();
+        let mut out = Vec::new();
+        remuxer
+            .write_fragment(keyframes[3].1, Some(keyframes[5].1), 7, &mut out)
+            .unwrap();
+        assert_eq!(kinds(&out), [*b"moof", *b"mdat"]);
+        let (sequence, trafs) = movie_fragment(find(&out, &[b"moof"]).unwrap());
+        assert_eq!(sequence, 7);
+        let video = &trafs[0];
+        assert_eq!((video.0, video.1), (1, 6000));
+        let durations = video
+            .2
+            .iter()
+            .map(|(duration, _)| *duration)
+            .collect::<Vec<_>>();
+        assert_eq!(durations, [40, 1960, 40, 40]);
+        remove_sample(&path);
+    }
+
+    #[test]
+    fn vp9_levels() {
+        assert_eq!(vp9_level(256 * 144), 10);
+        assert_eq!(vp9_level(320 * 240), 20);
+        assert_eq!(vp9_level(1920 * 1080), 40);
+        assert_eq!(vp9_level(3840 * 2160), 50);
+        assert_eq!(vp9_level(7680 * 4320), 60);
+    }
+}
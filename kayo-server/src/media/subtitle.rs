@@ -0,0 +1,162 @@
+//! Finds the subtitle files accompanying media files, which are named after them like
+//! `Movie.srt`, `Movie.en.vtt` or `Movie.ja.forced.ass`.
+
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Tags that look like language codes but tell what the subtitles are for.
+const NOT_LANGUAGES: [&str; 2] = ["cc", "sdh"];
+
+/// The format of a subtitle file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Srt,
+    Vtt,
+    Ass,
+    Ssa,
+}
+
+impl Format {
+    /// Returns the format of subtitle files with `extension`.
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "srt" => Some(Self::Srt),
+            "vtt" => Some(Self::Vtt),
+            "ass" => Some(Self::Ass),
+            "ssa" => Some(Self::Ssa),
+            _ => None,
+        }
+    }
+}
+
+/// A subtitle file accompanying a media file.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename = "Subtitle", rename_all = "PascalCase")]
+pub struct Subtitle {
+    /// The key of the subtitle object.
+    pub key: String,
+    /// The language tag from the file name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    pub format: Format,
+    /// The other tags from the file name, such as `forced` or `sdh`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+/// Finds the subtitles of the media object `key` among `keys`, the sorted keys of the objects in
+/// the same directory.
+pub fn sidecars(key: &str, keys: &[String]) -> Vec<Subtitle> {
+    let name = key.rsplit('/').next().unwrap_or_default();
+    let prefix = &key[..key.len() - name.len()];
+    let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+    let start = format!("{prefix}{stem}.");
+    let first = keys.partition_point(|other| *other < start);
+
+    keys[first..]
+        .iter()
+        .take_while(|other| other.starts_with(&start))
+        .filter_map(|other| {
+            let rest = &other[start.len()..];
+            if rest.contains('/') {
+                return None;
+            }
+            let (tags, extension) = rest.rsplit_once('.').unwrap_or(("", rest));
+            let format = Format::from_extension(extension)?;
+
+            let mut tags = tags.split('.').filter(|tag| !tag.is_empty()).peekable();
+            let language = tags.next_if(|tag| is_language(tag)).map(str::to_string);
+            let label = tags.collect::<Vec<_>>().join(".");
+            Some(Subtitle {
+                key: other.to_string(),
+                language,
+                format,
+                label: (!label.is_empty()).then_some(label),
+            })
+        })
+        .collect()
+}
+
+/// Finds the subtitles of the media object `key` stored at `path`, reading its directory.
+pub fn find(path: &Path, key: &str) -> io::Result<Vec<Subtitle>> {
+    let Some(directory) = path.parent() else {
+        return Ok(Vec::new());
+    };
+    let name = key.rsplit('/').next().unwrap_or_default();
+    let prefix = &key[..key.len() - name.len()];
+
+    let mut keys = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        if let (true, Some(name)) = (entry.file_type()?.is_file(), entry.file_name().to_str()) {
+            keys.push(format!("{prefix}{name}"));
+        }
+    }
+    keys.sort();
+    Ok(sidecars(key, &keys))
+}
+
+/// Returns whether `tag` looks like a BCP 47 language tag, such as `en`, `jpn` or `pt-BR`.
+fn is_language(tag: &str) -> bool {
+    let mut subtags = tag.split('-');
+    let primary = subtags.next().unwrap_or_default();
+    (2..=3).contains(&primary.len())
+        && primary.bytes().all(|b| b.is_ascii_alphabetic())
+        && !NOT_LANGUAGES.contains(&primary.to_ascii_lowercase().as_str())
+        && subtags
+            .all(|s| (1..=8).contains(&s.len()) && s.bytes().all(|b| b.is_ascii_alphanumeric()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sidecars_of_media() {
+        let mut keys = [
+            "movies/Movie 2.srt",
+            "movies/Movie.d/extra.srt",
+            "movies/Movie.en.srt",
+            "movies/Movie.ja.forced.ass",
+            "movies/Movie.mkv",
+            "movies/Movie.pt-BR.vtt",
+            "movies/Movie.sdh.srt",
+            "movies/Movie.srt",
+            "movies/Movie.txt",
+            "movies/Movies.srt",
+            "movies/other/Movie.srt",
+        ]
+        .map(String::from);
+        keys.sort();
+
+        let subtitles = sidecars("movies/Movie.mkv", &keys);
+        let subtitles = subtitles
+            .iter()
+            .map(|s| {
+                let (language, label) = (s.language.as_deref(), s.label.as_deref());
+                (s.key.as_str(), language, s.format, label)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            subtitles,
+            [
+                ("movies/Movie.en.srt", Some("en"), Format::Srt, None),
+                (
+                    "movies/Movie.ja.forced.ass",
+                    Some("ja"),
+                    Format::Ass,
+                    Some("forced")
+                ),
+                ("movies/Movie.pt-BR.vtt", Some("pt-BR"), Format::Vtt, None),
+                ("movies/Movie.sdh.srt", None, Format::Srt, Some("sdh")),
+                ("movies/Movie.srt", None, Format::Srt, None),
+            ]
+        );
+
+        assert!(sidecars("Movie.mkv", &keys).is_empty());
+        assert_eq!(sidecars("Movie", &["Movie.srt".to_string()]).len(), 1);
+    }
+}
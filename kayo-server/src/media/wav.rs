@@ -0,0 +1,90 @@
+use std::io::{self, Read, Seek};
+
+use super::bits::{invalid_data, ByteReader};
+use super::codec::{self, Pcm};
+use super::probe::{read_at, Container, MediaInfo, Stream, StreamKind};
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+pub fn probe<R: Read + Seek>(reader: &mut R, len: u64) -> io::Result<MediaInfo> {
+    let header = read_at(reader, 0, 12)?;
+    if header.get(..4) != Some(b"RIFF") || header.get(8..12) != Some(b"WAVE") {
+        return Err(invalid_data("missing RIFF WAVE header"));
+    }
+
+    let mut stream = None;
+    let mut byte_rate = 0;
+    let mut data_len = None;
+    let mut offset = 12;
+    while offset + 8 <= len && (stream.is_none() || data_len.is_none()) {
+        let chunk = read_at(reader, offset, 8)?;
+        let mut r = ByteReader::new(&chunk);
+        let id = r.bytes(4)?;
+        let size = u64::from(r.u32_le()?);
+        match id {
+            b"fmt " => {
+                let fmt = read_at(reader, offset + 8, size.min(40))?;
+                let (parsed, rate) = format(&fmt)?;
+                stream = Some(parsed);
+                byte_rate = rate;
+            }
+            // The size of the data chunk is wrong in files written while streaming.
+            b"data" => data_len = Some(size.min(len - offset - 8)),
+            _ => {}
+        }
+        offset += 8 + size + size % 2;
+    }
+    let mut stream = stream.ok_or_else(|| invalid_data("missing fmt chunk"))?;
+
+    let mut info = MediaInfo::new(Container::Wav);
+    if byte_rate != 0 {
+        stream.bitrate = Some(u64::from(byte_rate) * 8);
+        info.duration = data_len
+            .map(|len| len as f64 / f64::from(byte_rate))
+            .filter(|duration| 0.0 < *duration);
+    }
+    info.streams.push(stream);
+    Ok(info)
+}
+
+/// Parses a `fmt ` chunk, returning the stream it describes and its byte rate.
+fn format(data: &[u8]) -> io::Result<(Stream, u32)> {
+    let mut r = ByteReader::new(data);
+    let mut tag = r.u16_le()?;
+    let channels = r.u16_le()?;
+    let sample_rate = r.u32_le()?;
+    let byte_rate = r.u32_le()?;
+    r.u16_le()?; // block_align
+    let bits = r.u16_le().unwrap_or_default();
+    if tag == WAVE_FORMAT_EXTENSIBLE {
+        r.u16_le()?; // cbSize
+        r.u16_le()?; // wValidBitsPerSample
+        r.u32_le()?; // dwChannelMask
+
+        // The sub format GUID starts with the format tag.
+        tag = r.u16_le()?;
+    }
+
+    let codec = match tag {
+        // 8-bit samples are unsigned in WAV files, and wider ones signed.
+        WAVE_FORMAT_PCM if bits == 8 => codec::pcm(Pcm::Unsigned, bits, false),
+        WAVE_FORMAT_PCM => codec::pcm(Pcm::Signed, bits, false),
+        WAVE_FORMAT_IEEE_FLOAT => codec::pcm(Pcm::Float, bits, false),
+        0x0006 => "pcm_alaw".to_string(),
+        0x0007 => "pcm_mulaw".to_string(),
+        0x0011 => "adpcm_ima_wav".to_string(),
+        0x0055 => "mp3".to_string(),
+        0x2000 => "ac3".to_string(),
+        tag => format!("0x{tag:04x}"),
+    };
+
+    let mut stream = Stream::new(0, StreamKind::Audio, codec);
+    stream.channels = Some(channels);
+    stream.sample_rate = Some(sample_rate);
+    if bits != 0 && matches!(tag, WAVE_FORMAT_PCM | WAVE_FORMAT_IEEE_FLOAT) {
+        stream.bit_depth = Some(bits as u8);
+    }
+    Ok((stream, byte_rate))
+}
@@ -0,0 +1,256 @@
+//! Converts subtitle files to WebVTT, the only format of text tracks that Cast receivers take.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::ass;
+use super::subtitle::Format;
+
+/// A cue of subtitles, with times in milliseconds.
+pub struct Cue {
+    pub start: i64,
+    pub end: i64,
+    /// The cue settings placing the cue, separated by spaces.
+    pub settings: String,
+    /// The text of the cue, in WebVTT markup.
+    pub text: String,
+}
+
+/// Converts the subtitle file at `path` in `format` to WebVTT, with its cues delayed by `offset`
+/// seconds, or returns `None` if the format cannot be converted.
+pub fn convert(path: &Path, format: Format, offset: f64) -> io::Result<Option<String>> {
+    let text = decode(&fs::read(path)?);
+    let offset = (offset * 1000.0).round() as i64;
+    Ok(match format {
+        Format::Srt => Some(write(parse_srt(&text), offset)),
+        Format::Vtt => Some(shift(&text, offset)),
+        Format::Ass | Format::Ssa => Some(write(ass::parse(&text), offset)),
+    })
+}
+
+/// Decodes a subtitle file, which is often in Latin-1 rather than UTF-8 when it is SRT.
+fn decode(data: &[u8]) -> String {
+    let data = data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(data);
+    match std::str::from_utf8(data) {
+        Ok(text) => text.to_string(),
+        Err(_) => data.iter().map(|&b| char::from(b)).collect(),
+    }
+}
+
+fn parse_srt(text: &str) -> Vec<Cue> {
+    let mut cues = Vec::new();
+    let mut lines = text.lines().peekable();
+    while let Some(line) = lines.next() {
+        // Cue numbers are skipped along with anything else that does not give the timings.
+        let Some((start, end, _)) = timings(line) else {
+            continue;
+        };
+        let mut text = Vec::new();
+        while let Some(line) = lines.next_if(|line| !line.trim().is_empty()) {
+            text.push(srt_markup(line));
+        }
+        cues.push(Cue {
+            start,
+            end,
+            settings: String::new(),
+            text: text.join("\n"),
+        });
+    }
+    cues
+}
+
+/// Converts a line of SRT text to WebVTT markup. Italic, bold and underline tags are kept, while
+/// other tags and ASS override blocks are dropped.
+fn srt_markup(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        let skip = match c {
+            '<' => rest.find('>').inspect(|&end| {
+                let tag = rest[1..end].trim().to_ascii_lowercase();
+                let (closing, tag) = match tag.strip_prefix('/') {
+                    Some(tag) => ("/", tag),
+                    None => ("", tag.as_str()),
+                };
+                let name = tag.split_whitespace().next().unwrap_or_default();
+                if matches!(name, "i" | "b" | "u") {
+                    let _ = write!(out, "<{closing}{name}>");
+                }
+            }),
+            '{' if rest.starts_with("{\\") => rest.find('}'),
+            _ => None,
+        };
+        if let Some(end) = skip {
+            rest = &rest[end + 1..];
+            continue;
+        }
+        escape(c, &mut out);
+        rest = &rest[c.len_utf8()..];
+    }
+    out
+}
+
+/// Appends `c` to WebVTT text, escaping the characters that start markup.
+pub fn escape(c: char, out: &mut String) {
+    match c {
+        '&' => out.push_str("&amp;"),
+        '<' => out.push_str("&lt;"),
+        '>' => out.push_str("&gt;"),
+        c => out.push(c),
+    }
+}
+
+/// Writes cues delayed by `offset` milliseconds in a WebVTT file, dropping the ones that would
+/// end before the start.
+fn write(cues: Vec<Cue>, offset: i64) -> String {
+    let mut out = String::from("WEBVTT\n");
+    for cue in cues {
+        let start = cue.start.saturating_add(offset).max(0);
+        let end = cue.end.saturating_add(offset);
+        if end <= start || cue.text.is_empty() {
+            continue;
+        }
+        let _ = write!(out, "\n{} --> {}", time(start), time(end));
+        if !cue.settings.is_empty() {
+            let _ = write!(out, " {}", cue.settings);
+        }
+        let _ = write!(out, "\n{}\n", cue.text);
+    }
+    out
+}
+
+/// Delays the cues of a WebVTT file by `offset` milliseconds, dropping the ones that would end
+/// before the start.
+fn shift(text: &str, offset: i64) -> String {
+    let mut out = String::new();
+    let mut lines = text.lines().peekable();
+    while lines.peek().is_some() {
+        let mut block = Vec::new();
+        while let Some(line) = lines.next_if(|line| !line.trim().is_empty()) {
+            block.push(line.to_string());
+        }
+        while lines.next_if(|line| line.trim().is_empty()).is_some() {}
+
+        let cue = block
+            .iter()
+            .position(|line| timings(line).is_some())
+            .filter(|_| offset != 0);
+        if let Some(index) = cue {
+            let (start, end, settings) = timings(&block[index]).unwrap();
+            let (start, end) = (
+                start.saturating_add(offset).max(0),
+                end.saturating_add(offset),
+            );
+            if end <= start {
+                continue;
+            }
+            block[index] = format!("{} --> {}{settings}", time(start), time(end));
+        }
+        if !block.is_empty() {
+            out.push_str(&block.join("\n"));
+            out.push_str("\n\n");
+        }
+    }
+    out
+}
+
+/// Parses the line giving the start and end times of a cue, along with what follows them.
+fn timings(line: &str) -> Option<(i64, i64, &str)> {
+    let (start, rest) = line.split_once("-->")?;
+    let rest = rest.trim_start();
+    let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    Some((
+        timestamp(start.trim())?,
+        timestamp(&rest[..end])?,
+        &rest[end..],
+    ))
+}
+
+/// Parses a timestamp like `01:02:03,456` in SRT, `01:02:03.456` in WebVTT or `1:02:03.45` in
+/// ASS, in milliseconds.
+pub fn timestamp(s: &str) -> Option<i64> {
+    let (time, fraction) = s.split_once([',', '.']).unwrap_or((s, "0"));
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let parts = time.split(':').collect::<Vec<_>>();
+    if !(2..=3).contains(&parts.len()) || !parts.iter().all(|part| digits(part)) {
+        return None;
+    }
+    if fraction.len() > 3 || !digits(fraction) {
+        return None;
+    }
+    let seconds = parts.iter().try_fold(0, |seconds: i64, part| {
+        seconds
+            .checked_mul(60)?
+            .checked_add(part.parse::<i64>().ok()?)
+    })?;
+    let ms = format!("{fraction:0<3}").parse::<i64>().ok()?;
+    seconds.checked_mul(1000)?.checked_add(ms)
+}
+
+/// Formats a time in milliseconds as a WebVTT timestamp.
+fn time(ms: i64) -> String {
+    let (seconds, ms) = (ms / 1000, ms % 1000);
+    format!(
+        "{:02}:{:02}:{:02}.{ms:03}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(timestamp("01:02:03,456"), Some(3_723_456));
+        assert_eq!(timestamp("01:02:03.456"), Some(3_723_456));
+        assert_eq!(timestamp("1:02:03.45"), Some(3_723_450));
+        assert_eq!(timestamp("02:03.4"), Some(123_400));
+        assert_eq!(timestamp("01:02:03"), Some(3_723_000));
+        assert_eq!(timestamp("03.000"), None);
+        assert_eq!(timestamp("1:2:3:4"), None);
+        assert_eq!(timestamp("01:02:03.4567"), None);
+        assert_eq!(timestamp("-1:02:03"), None);
+        assert_eq!(timestamp("01::03"), None);
+        assert_eq!(timestamp("9999999999999999:00:00"), None);
+    }
+
+    #[test]
+    fn srt() {
+        let cues = parse_srt(
+            "1\n00:00:01,000 --> 00:00:02,500\n<i>Hello</i> & <font color=\"red\">bye</font>\n\
+             {\\an8}Top\n\n2\n00:00:03,000 --> 00:00:04,000\nSecond\n",
+        );
+        assert_eq!(cues.len(), 2);
+        assert_eq!((cues[0].start, cues[0].end), (1000, 2500));
+        assert_eq!(cues[0].text, "<i>Hello</i> &amp; bye\nTop");
+        assert_eq!((cues[1].start, cues[1].end), (3000, 4000));
+        assert_eq!(cues[1].text, "Second");
+    }
+
+    #[test]
+    fn shifts() {
+        let text = "WEBVTT\n\nNOTE kept\n\n1\n00:00:01.000 --> 00:00:02.000 align:left\nOne\n\n\
+                    00:00:05.000 --> 00:00:06.000\nTwo\n";
+        assert_eq!(
+            shift(text, 3000),
+            "WEBVTT\n\nNOTE kept\n\n1\n00:00:04.000 --> 00:00:05.000 align:left\nOne\n\n\
+             00:00:08.000 --> 00:00:09.000\nTwo\n\n"
+        );
+        assert_eq!(
+            shift(text, -1500),
+            "WEBVTT\n\nNOTE kept\n\n1\n00:00:00.000 --> 00:00:00.500 align:left\nOne\n\n\
+             00:00:03.500 --> 00:00:04.500\nTwo\n\n"
+        );
+        assert_eq!(
+            shift(text, -2000),
+            "WEBVTT\n\nNOTE kept\n\n00:00:03.000 --> 00:00:04.000\nTwo\n\n"
+        );
+        assert_eq!(shift(text, i64::MIN), "WEBVTT\n\nNOTE kept\n\n");
+        assert_eq!(shift(text, i64::MAX), "WEBVTT\n\nNOTE kept\n\n");
+    }
+}
@@ -1,9 +1,12 @@
 use bytes::{BufMut, Bytes, BytesMut};
 use serde::ser::{self, Error as SerError};
+use std::borrow::Cow;
 use std::error::Error as StdError;
 use std::fmt;
 use std::io;
-use xml::writer::{Error as XmlError, EventWriter, XmlEvent};
+use std::mem;
+use xml::common::XmlVersion;
+use xml::writer::{EmitterConfig, Error as XmlError, EventWriter, XmlEvent};
 
 #[derive(Debug)]
 pub enum Error {
@@ -43,29 +46,203 @@ impl From<XmlError> for Error {
 
 type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// Options controlling the serialized document.
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    /// Whether to start the document with an XML declaration.
+    pub declaration: bool,
+    /// The `standalone` attribute of the XML declaration, omitted if `None`.
+    pub standalone: Option<bool>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            declaration: true,
+            standalone: None,
+        }
+    }
+}
+
 pub fn to_bytes<T: ser::Serialize>(value: &T) -> Result<Bytes> {
-    let mut s = Serializer::new(BytesMut::new().writer());
+    to_bytes_with_config(value, &Config::default())
+}
+
+pub fn to_bytes_with_config<T: ser::Serialize>(value: &T, config: &Config) -> Result<Bytes> {
+    let mut s = Serializer::new(BytesMut::new().writer(), config)?;
     value.serialize(&mut s)?;
-    Ok(s.into_inner().into_inner().freeze())
+    Ok(s.into_inner()?.into_inner().freeze())
 }
 
-struct Serializer<W>(EventWriter<W>);
+/// Serializes `value` into `writer` as it goes, without buffering the whole document.
+pub fn to_writer<W: io::Write, T: ser::Serialize>(writer: W, value: &T) -> Result<()> {
+    to_writer_with_config(writer, value, &Config::default())
+}
+
+pub fn to_writer_with_config<W: io::Write, T: ser::Serialize>(
+    writer: W,
+    value: &T,
+    config: &Config,
+) -> Result<()> {
+    let mut s = Serializer::new(writer, config)?;
+    value.serialize(&mut s)?;
+    s.into_inner()?.flush().map_err(|e| Error::Xml(e.into()))
+}
+
+/// A start tag that is not written yet, since namespaces and attributes may still be added to it.
+struct StartElement {
+    name: &'static str,
+    namespaces: Vec<(Option<&'static str>, String)>,
+    attributes: Vec<(&'static str, String)>,
+}
+
+/// The state of the element wrapping the value of a struct field or map entry.
+#[derive(Default)]
+enum FieldElement {
+    /// The value is not wrapped, or each of its items is wrapped separately.
+    #[default]
+    None,
+    /// The element is written as soon as the value writes any content, so that values without
+    /// any content, like `None`, leave the field out.
+    Pending(Cow<'static, str>),
+    Open,
+}
+
+struct Serializer<W> {
+    writer: EventWriter<W>,
+    pending: Option<StartElement>,
+    field: FieldElement,
+    key: Option<String>,
+}
 
 impl<W: io::Write> Serializer<W> {
-    fn new(sink: W) -> Self {
-        Self(EventWriter::new(sink))
+    fn new(sink: W, config: &Config) -> Result<Self> {
+        let mut writer = EventWriter::new_with_config(
+            sink,
+            EmitterConfig::new().write_document_declaration(config.declaration),
+        );
+        if config.declaration {
+            writer.write(XmlEvent::StartDocument {
+                version: XmlVersion::Version10,
+                encoding: Some("UTF-8"),
+                standalone: config.standalone,
+            })?;
+        }
+
+        Ok(Self {
+            writer,
+            pending: None,
+            field: FieldElement::None,
+            key: None,
+        })
+    }
+
+    fn into_inner(mut self) -> Result<W> {
+        self.flush()?;
+        Ok(self.writer.into_inner())
+    }
+
+    fn start_element(&mut self, name: &'static str) -> Result<()> {
+        self.open_field()?;
+        self.flush()?;
+        self.pending = Some(StartElement {
+            name,
+            namespaces: Vec::new(),
+            attributes: Vec::new(),
+        });
+        Ok(())
     }
 
-    fn into_inner(self) -> W {
-        self.0.into_inner()
+    /// Returns the element that has just been started, if none of its content has been written.
+    fn pending(&mut self) -> Result<&mut StartElement> {
+        self.pending.as_mut().ok_or_else(|| {
+            Error::custom("namespaces and attributes must precede the content of an element")
+        })
+    }
+
+    /// Declares a namespace on the element that has just been started. The namespace is the
+    /// default one if `prefix` is `None`.
+    fn declare_namespace(&mut self, prefix: Option<&'static str>, uri: String) -> Result<()> {
+        self.pending()?.namespaces.push((prefix, uri));
+        Ok(())
+    }
+
+    /// Adds an attribute to the element that has just been started.
+    fn add_attribute(&mut self, name: &'static str, value: String) -> Result<()> {
+        self.pending()?.attributes.push((name, value));
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if let Some(element) = self.pending.take() {
+            let mut event = XmlEvent::start_element(element.name);
+            for (prefix, uri) in &element.namespaces {
+                event = match prefix {
+                    Some(prefix) => event.ns(*prefix, uri.as_str()),
+                    None => event.default_ns(uri.as_str()),
+                };
+            }
+            for (name, value) in &element.attributes {
+                event = event.attr(*name, value);
+            }
+            self.writer.write(event)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the start tag of the field whose value is being serialized, if not written yet.
+    fn open_field(&mut self) -> Result<()> {
+        if let FieldElement::Pending(name) = &self.field {
+            let name = name.clone();
+            self.field = FieldElement::Open;
+            self.flush()?;
+            self.writer.write(XmlEvent::start_element(name.as_ref()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Serializes `value` wrapped in an element named `name`, or as is if `name` is `None`.
+    fn serialize_field<T: ?Sized + ser::Serialize>(
+        &mut self,
+        name: Option<Cow<'static, str>>,
+        value: &T,
+    ) -> Result<()> {
+        let field = name.map_or(FieldElement::None, FieldElement::Pending);
+        let outer = mem::replace(&mut self.field, field);
+        value.serialize(&mut *self)?;
+        if let FieldElement::Open = mem::replace(&mut self.field, outer) {
+            self.write(XmlEvent::end_element())?;
+        }
+
+        Ok(())
+    }
+
+    /// Takes the name of the field being serialized, to wrap each item of a sequence in it.
+    fn take_field(&mut self) -> Option<Cow<'static, str>> {
+        match mem::take(&mut self.field) {
+            FieldElement::Pending(name) => Some(name),
+            field => {
+                self.field = field;
+                None
+            }
+        }
+    }
+
+    fn write<'a, E: Into<XmlEvent<'a>>>(&mut self, event: E) -> Result<()> {
+        self.open_field()?;
+        self.flush()?;
+        self.writer.write(event)?;
+        Ok(())
     }
 }
 
-impl<W: io::Write> serde::Serializer for &mut Serializer<W> {
+impl<'a, W: io::Write> serde::Serializer for &'a mut Serializer<W> {
     type Ok = ();
     type Error = Error;
-    type SerializeSeq = Self;
-    type SerializeTuple = Self;
+    type SerializeSeq = Sequence<'a, W>;
+    type SerializeTuple = Sequence<'a, W>;
     type SerializeTupleStruct = Self;
     type SerializeTupleVariant = Self;
     type SerializeMap = Self;
@@ -73,77 +250,77 @@ impl<W: io::Write> serde::Serializer for &mut Serializer<W> {
     type SerializeStructVariant = Self;
 
     fn serialize_bool(self, v: bool) -> Result<Self::Ok, Self::Error> {
-        self.0.write(XmlEvent::characters(&v.to_string()))?;
+        self.write(XmlEvent::characters(&v.to_string()))?;
         Ok(())
     }
 
     fn serialize_i8(self, v: i8) -> Result<Self::Ok, Self::Error> {
-        self.0.write(XmlEvent::characters(&v.to_string()))?;
+        self.write(XmlEvent::characters(&v.to_string()))?;
         Ok(())
     }
 
     fn serialize_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
-        self.0.write(XmlEvent::characters(&v.to_string()))?;
+        self.write(XmlEvent::characters(&v.to_string()))?;
         Ok(())
     }
 
     fn serialize_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
-        self.0.write(XmlEvent::characters(&v.to_string()))?;
+        self.write(XmlEvent::characters(&v.to_string()))?;
         Ok(())
     }
 
     fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
-        self.0.write(XmlEvent::characters(&v.to_string()))?;
+        self.write(XmlEvent::characters(&v.to_string()))?;
         Ok(())
     }
 
     fn serialize_i128(self, v: i128) -> Result<Self::Ok, Self::Error> {
-        self.0.write(XmlEvent::characters(&v.to_string()))?;
+        self.write(XmlEvent::characters(&v.to_string()))?;
         Ok(())
     }
 
     fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
-        self.0.write(XmlEvent::characters(&v.to_string()))?;
+        self.write(XmlEvent::characters(&v.to_string()))?;
         Ok(())
     }
 
     fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
-        self.0.write(XmlEvent::characters(&v.to_string()))?;
+        self.write(XmlEvent::characters(&v.to_string()))?;
         Ok(())
     }
 
     fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
-        self.0.write(XmlEvent::characters(&v.to_string()))?;
+        self.write(XmlEvent::characters(&v.to_string()))?;
         Ok(())
     }
 
     fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
-        self.0.write(XmlEvent::characters(&v.to_string()))?;
+        self.write(XmlEvent::characters(&v.to_string()))?;
         Ok(())
     }
 
     fn serialize_u128(self, v: u128) -> Result<Self::Ok, Self::Error> {
-        self.0.write(XmlEvent::characters(&v.to_string()))?;
+        self.write(XmlEvent::characters(&v.to_string()))?;
         Ok(())
     }
 
     fn serialize_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
-        self.0.write(XmlEvent::characters(&v.to_string()))?;
+        self.write(XmlEvent::characters(&v.to_string()))?;
         Ok(())
     }
 
     fn serialize_f64(self, v: f64) -> Result<Self::Ok, Self::Error> {
-        self.0.write(XmlEvent::characters(&v.to_string()))?;
+        self.write(XmlEvent::characters(&v.to_string()))?;
         Ok(())
     }
 
     fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
-        self.0.write(XmlEvent::characters(&v.to_string()))?;
+        self.write(XmlEvent::characters(&v.to_string()))?;
         Ok(())
     }
 
     fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
-        self.0.write(XmlEvent::characters(v))?;
+        self.write(XmlEvent::characters(v))?;
         Ok(())
     }
 
@@ -167,8 +344,8 @@ impl<W: io::Write> serde::Serializer for &mut Serializer<W> {
     }
 
     fn serialize_unit_struct(self, name: &'static str) -> Result<Self::Ok, Self::Error> {
-        self.0.write(XmlEvent::start_element(name))?;
-        self.0.write(XmlEvent::end_element())?;
+        self.write(XmlEvent::start_element(name))?;
+        self.write(XmlEvent::end_element())?;
         Ok(())
     }
 
@@ -178,7 +355,7 @@ impl<W: io::Write> serde::Serializer for &mut Serializer<W> {
         _: u32,
         variant: &'static str,
     ) -> Result<Self::Ok, Self::Error> {
-        self.0.write(XmlEvent::characters(variant))?;
+        self.write(XmlEvent::characters(variant))?;
         Ok(())
     }
 
@@ -187,9 +364,9 @@ impl<W: io::Write> serde::Serializer for &mut Serializer<W> {
         name: &'static str,
         value: &T,
     ) -> Result<Self::Ok, Self::Error> {
-        self.0.write(XmlEvent::start_element(name))?;
+        self.write(XmlEvent::start_element(name))?;
         value.serialize(&mut *self)?;
-        self.0.write(XmlEvent::end_element())?;
+        self.write(XmlEvent::end_element())?;
         Ok(())
     }
 
@@ -200,18 +377,24 @@ impl<W: io::Write> serde::Serializer for &mut Serializer<W> {
         variant: &'static str,
         value: &T,
     ) -> Result<Self::Ok, Self::Error> {
-        self.0.write(XmlEvent::start_element(variant))?;
+        self.write(XmlEvent::start_element(variant))?;
         value.serialize(&mut *self)?;
-        self.0.write(XmlEvent::end_element())?;
+        self.write(XmlEvent::end_element())?;
         Ok(())
     }
 
     fn serialize_seq(self, _: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
-        Ok(self)
+        Ok(Sequence {
+            field: self.take_field(),
+            serializer: self,
+        })
     }
 
     fn serialize_tuple(self, _: usize) -> Result<Self::SerializeTuple, Self::Error> {
-        Ok(self)
+        Ok(Sequence {
+            field: self.take_field(),
+            serializer: self,
+        })
     }
 
     fn serialize_tuple_struct(
@@ -219,7 +402,7 @@ impl<W: io::Write> serde::Serializer for &mut Serializer<W> {
         name: &'static str,
         _: usize,
     ) -> Result<Self::SerializeTupleStruct, Self::Error> {
-        self.0.write(XmlEvent::start_element(name))?;
+        self.write(XmlEvent::start_element(name))?;
         Ok(self)
     }
 
@@ -230,11 +413,12 @@ impl<W: io::Write> serde::Serializer for &mut Serializer<W> {
         variant: &'static str,
         _: usize,
     ) -> Result<Self::SerializeTupleVariant, Self::Error> {
-        self.0.write(XmlEvent::start_element(variant))?;
+        self.write(XmlEvent::start_element(variant))?;
         Ok(self)
     }
 
     fn serialize_map(self, _: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        self.open_field()?;
         Ok(self)
     }
 
@@ -243,7 +427,7 @@ impl<W: io::Write> serde::Serializer for &mut Serializer<W> {
         name: &'static str,
         _: usize,
     ) -> Result<Self::SerializeStruct, Self::Error> {
-        self.0.write(XmlEvent::start_element(name))?;
+        self.start_element(name)?;
         Ok(self)
     }
 
@@ -254,12 +438,19 @@ impl<W: io::Write> serde::Serializer for &mut Serializer<W> {
         variant: &'static str,
         _: usize,
     ) -> Result<Self::SerializeStructVariant, Self::Error> {
-        self.0.write(XmlEvent::start_element(variant))?;
+        self.write(XmlEvent::start_element(variant))?;
         Ok(self)
     }
 }
 
-impl<W: io::Write> ser::SerializeSeq for &mut Serializer<W> {
+/// Serializes the items of a sequence, each wrapped in an element named after the field holding
+/// the sequence, if any.
+struct Sequence<'a, W> {
+    serializer: &'a mut Serializer<W>,
+    field: Option<Cow<'static, str>>,
+}
+
+impl<W: io::Write> ser::SerializeSeq for Sequence<'_, W> {
     type Ok = ();
     type Error = Error;
 
@@ -267,7 +458,7 @@ impl<W: io::Write> ser::SerializeSeq for &mut Serializer<W> {
         &mut self,
         value: &T,
     ) -> Result<(), Self::Error> {
-        value.serialize(&mut **self)
+        self.serializer.serialize_field(self.field.clone(), value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
@@ -275,7 +466,7 @@ impl<W: io::Write> ser::SerializeSeq for &mut Serializer<W> {
     }
 }
 
-impl<W: io::Write> ser::SerializeTuple for &mut Serializer<W> {
+impl<W: io::Write> ser::SerializeTuple for Sequence<'_, W> {
     type Ok = ();
     type Error = Error;
 
@@ -283,7 +474,7 @@ impl<W: io::Write> ser::SerializeTuple for &mut Serializer<W> {
         &mut self,
         value: &T,
     ) -> Result<(), Self::Error> {
-        value.serialize(&mut **self)
+        self.serializer.serialize_field(self.field.clone(), value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
@@ -329,7 +520,7 @@ impl<W: io::Write> ser::SerializeMap for &mut Serializer<W> {
 
     fn serialize_key<T: ?Sized + ser::Serialize>(&mut self, key: &T) -> Result<(), Self::Error> {
         let key = key.serialize(KeySerializer)?;
-        self.0.write(XmlEvent::start_element(key.as_str()))?;
+        self.key = Some(key.ok_or_else(|| Error::custom("unsupported key type"))?);
         Ok(())
     }
 
@@ -337,12 +528,14 @@ impl<W: io::Write> ser::SerializeMap for &mut Serializer<W> {
         &mut self,
         value: &T,
     ) -> Result<(), Self::Error> {
-        value.serialize(&mut **self)?;
-        Ok(())
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| Error::custom("map value serialized before its key"))?;
+        Serializer::serialize_field(self, Some(Cow::Owned(key)), value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        self.0.write(XmlEvent::end_element())?;
         Ok(())
     }
 }
@@ -356,21 +549,33 @@ impl<W: io::Write> ser::SerializeStruct for &mut Serializer<W> {
         key: &'static str,
         value: &T,
     ) -> Result<(), Self::Error> {
-        if !key.is_empty() {
-            self.0.write(XmlEvent::start_element(key))?;
+        if key == "xmlns" {
+            return match value.serialize(KeySerializer)? {
+                Some(uri) => self.declare_namespace(None, uri),
+                None => Ok(()),
+            };
         }
 
-        value.serialize(&mut **self)?;
+        if let Some(prefix) = key.strip_prefix("xmlns:") {
+            return match value.serialize(KeySerializer)? {
+                Some(uri) => self.declare_namespace(Some(prefix), uri),
+                None => Ok(()),
+            };
+        }
 
-        if !key.is_empty() {
-            self.0.write(XmlEvent::end_element())?;
+        if let Some(name) = key.strip_prefix('@') {
+            return match value.serialize(KeySerializer)? {
+                Some(value) => self.add_attribute(name, value),
+                None => Ok(()),
+            };
         }
 
-        Ok(())
+        let name = (!key.is_empty()).then_some(Cow::Borrowed(key));
+        Serializer::serialize_field(self, name, value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        self.0.write(XmlEvent::end_element())?;
+        self.write(XmlEvent::end_element())?;
         Ok(())
     }
 }
@@ -384,22 +589,21 @@ impl<W: io::Write> ser::SerializeStructVariant for &mut Serializer<W> {
         key: &'static str,
         value: &T,
     ) -> Result<(), Self::Error> {
-        self.0.write(XmlEvent::start_element(key))?;
-        value.serialize(&mut **self)?;
-        self.0.write(XmlEvent::end_element())?;
-        Ok(())
+        Serializer::serialize_field(self, Some(Cow::Borrowed(key)), value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        self.0.write(XmlEvent::end_element())?;
+        self.write(XmlEvent::end_element())?;
         Ok(())
     }
 }
 
+/// Serializes map keys, namespace URIs and attribute values, which are plain text. Values that
+/// are `None` serialize to `None`, so that optional namespaces and attributes are left out.
 struct KeySerializer;
 
 impl ser::Serializer for KeySerializer {
-    type Ok = String;
+    type Ok = Option<String>;
     type Error = Error;
     type SerializeSeq = Self;
     type SerializeTuple = Self;
@@ -410,71 +614,73 @@ impl ser::Serializer for KeySerializer {
     type SerializeStructVariant = Self;
 
     fn serialize_bool(self, v: bool) -> Result<Self::Ok, Self::Error> {
-        Ok(v.to_string())
+        Ok(Some(v.to_string()))
     }
 
     fn serialize_i8(self, v: i8) -> Result<Self::Ok, Self::Error> {
-        Ok(v.to_string())
+        Ok(Some(v.to_string()))
     }
 
     fn serialize_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
-        Ok(v.to_string())
+        Ok(Some(v.to_string()))
     }
 
     fn serialize_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
-        Ok(v.to_string())
+        Ok(Some(v.to_string()))
     }
 
     fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
-        Ok(v.to_string())
+        Ok(Some(v.to_string()))
     }
 
     fn serialize_i128(self, v: i128) -> Result<Self::Ok, Self::Error> {
-        Ok(v.to_string())
+        Ok(Some(v.to_string()))
     }
 
     fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
-        Ok(v.to_string())
+        Ok(Some(v.to_string()))
     }
 
     fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
-        Ok(v.to_string())
+        Ok(Some(v.to_string()))
     }
 
     fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
-        Ok(v.to_string())
+        Ok(Some(v.to_string()))
     }
 
     fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
-        Ok(v.to_string())
+        Ok(Some(v.to_string()))
     }
 
     fn serialize_u128(self, v: u128) -> Result<Self::Ok, Self::Error> {
-        Ok(v.to_string())
+        Ok(Some(v.to_string()))
     }
 
     fn serialize_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
-        Ok(v.to_string())
+        Ok(Some(v.to_string()))
     }
 
     fn serialize_f64(self, v: f64) -> Result<Self::Ok, Self::Error> {
-        Ok(v.to_string())
+        Ok(Some(v.to_string()))
     }
 
     fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
-        Ok(v.to_string())
+        Ok(Some(v.to_string()))
     }
 
     fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
-        Ok(v.to_string())
+        Ok(Some(v.to_string()))
     }
 
     fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, Self::Error> {
-        String::from_utf8(v.to_vec()).map_err(Error::custom)
+        String::from_utf8(v.to_vec())
+            .map(Some)
+            .map_err(Error::custom)
     }
 
     fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
-        todo!()
+        Ok(None)
     }
 
     fn serialize_some<T: ?Sized + ser::Serialize>(
@@ -489,7 +695,7 @@ impl ser::Serializer for KeySerializer {
     }
 
     fn serialize_unit_struct(self, name: &'static str) -> Result<Self::Ok, Self::Error> {
-        Ok(name.into())
+        Ok(Some(name.into()))
     }
 
     fn serialize_unit_variant(
@@ -498,7 +704,7 @@ impl ser::Serializer for KeySerializer {
         _: u32,
         variant: &'static str,
     ) -> Result<Self::Ok, Self::Error> {
-        Ok(variant.into())
+        Ok(Some(variant.into()))
     }
 
     fn serialize_newtype_struct<T: ?Sized + ser::Serialize>(
@@ -569,7 +775,7 @@ impl ser::Serializer for KeySerializer {
 }
 
 impl ser::SerializeSeq for KeySerializer {
-    type Ok = String;
+    type Ok = Option<String>;
     type Error = Error;
 
     fn serialize_element<T: ?Sized + ser::Serialize>(&mut self, _: &T) -> Result<(), Self::Error> {
@@ -582,7 +788,7 @@ impl ser::SerializeSeq for KeySerializer {
 }
 
 impl ser::SerializeTuple for KeySerializer {
-    type Ok = String;
+    type Ok = Option<String>;
     type Error = Error;
 
     fn serialize_element<T: ?Sized + ser::Serialize>(&mut self, _: &T) -> Result<(), Self::Error> {
@@ -595,7 +801,7 @@ impl ser::SerializeTuple for KeySerializer {
 }
 
 impl ser::SerializeTupleStruct for KeySerializer {
-    type Ok = String;
+    type Ok = Option<String>;
     type Error = Error;
 
     fn serialize_field<T: ?Sized + ser::Serialize>(&mut self, _: &T) -> Result<(), Self::Error> {
@@ -608,7 +814,7 @@ impl ser::SerializeTupleStruct for KeySerializer {
 }
 
 impl ser::SerializeTupleVariant for KeySerializer {
-    type Ok = String;
+    type Ok = Option<String>;
     type Error = Error;
 
     fn serialize_field<T: ?Sized + ser::Serialize>(&mut self, _: &T) -> Result<(), Self::Error> {
@@ -621,7 +827,7 @@ impl ser::SerializeTupleVariant for KeySerializer {
 }
 
 impl ser::SerializeMap for KeySerializer {
-    type Ok = String;
+    type Ok = Option<String>;
     type Error = Error;
 
     fn serialize_key<T: ?Sized + ser::Serialize>(&mut self, _: &T) -> Result<(), Self::Error> {
@@ -638,7 +844,7 @@ impl ser::SerializeMap for KeySerializer {
 }
 
 impl ser::SerializeStruct for KeySerializer {
-    type Ok = String;
+    type Ok = Option<String>;
     type Error = Error;
 
     fn serialize_field<T: ?Sized + ser::Serialize>(
@@ -655,7 +861,7 @@ impl ser::SerializeStruct for KeySerializer {
 }
 
 impl ser::SerializeStructVariant for KeySerializer {
-    type Ok = String;
+    type Ok = Option<String>;
     type Error = Error;
 
     fn serialize_field<T: ?Sized + ser::Serialize>(
@@ -670,3 +876,164 @@ impl ser::SerializeStructVariant for KeySerializer {
         unimplemented!()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serialize;
+    use std::collections::{BTreeMap, HashMap};
+
+    fn to_string<T: ser::Serialize>(value: &T) -> String {
+        let config = Config {
+            declaration: false,
+            ..Config::default()
+        };
+        String::from_utf8(to_bytes_with_config(value, &config).unwrap().to_vec()).unwrap()
+    }
+
+    #[derive(Serialize)]
+    struct Tagging {
+        #[serde(rename = "Key")]
+        keys: Vec<String>,
+        #[serde(rename = "TagSet")]
+        tags: BTreeMap<String, String>,
+        #[serde(rename = "VersionId")]
+        version_id: Option<String>,
+    }
+
+    #[test]
+    fn sequences() {
+        let tagging = Tagging {
+            keys: vec!["a".to_string(), "b".to_string()],
+            tags: BTreeMap::new(),
+            version_id: None,
+        };
+        assert_eq!(
+            to_string(&tagging),
+            "<Tagging><Key>a</Key><Key>b</Key><TagSet /></Tagging>"
+        );
+    }
+
+    #[test]
+    fn maps() {
+        let tagging = Tagging {
+            keys: Vec::new(),
+            tags: BTreeMap::from([
+                ("Color".to_string(), "red".to_string()),
+                ("Size".to_string(), "XL".to_string()),
+            ]),
+            version_id: Some("1".to_string()),
+        };
+        assert_eq!(
+            to_string(&tagging),
+            "<Tagging><TagSet><Color>red</Color><Size>XL</Size></TagSet>\
+             <VersionId>1</VersionId></Tagging>"
+        );
+
+        #[derive(Serialize)]
+        struct Metadata {
+            #[serde(rename = "Entries")]
+            entries: HashMap<&'static str, u32>,
+        }
+
+        let metadata = Metadata {
+            entries: HashMap::from([("Width", 1920)]),
+        };
+        assert_eq!(
+            to_string(&metadata),
+            "<Metadata><Entries><Width>1920</Width></Entries></Metadata>"
+        );
+    }
+
+    #[derive(Serialize)]
+    struct Grantee {
+        #[serde(rename = "xmlns")]
+        namespace: Option<&'static str>,
+        #[serde(rename = "xmlns:xsi")]
+        xsi: Option<&'static str>,
+        #[serde(rename = "@xsi:type")]
+        r#type: Option<&'static str>,
+        #[serde(rename = "@Id")]
+        id: Option<u32>,
+        #[serde(rename = "DisplayName")]
+        display_name: &'static str,
+    }
+
+    #[test]
+    fn namespaces_and_attributes() {
+        let grantee = Grantee {
+            namespace: Some("http://s3.amazonaws.com/doc/2006-03-01/"),
+            xsi: Some("http://www.w3.org/2001/XMLSchema-instance"),
+            r#type: Some("CanonicalUser"),
+            id: Some(7),
+            display_name: "kayo & co",
+        };
+        assert_eq!(
+            to_string(&grantee),
+            "<Grantee xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\" \
+             xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" \
+             xsi:type=\"CanonicalUser\" Id=\"7\">\
+             <DisplayName>kayo &amp; co</DisplayName></Grantee>"
+        );
+
+        let grantee = Grantee {
+            namespace: None,
+            xsi: None,
+            r#type: None,
+            id: None,
+            display_name: "kayo",
+        };
+        assert_eq!(
+            to_string(&grantee),
+            "<Grantee><DisplayName>kayo</DisplayName></Grantee>"
+        );
+    }
+
+    #[test]
+    fn misplaced_attributes() {
+        #[derive(Serialize)]
+        struct Late {
+            #[serde(rename = "Name")]
+            name: &'static str,
+            #[serde(rename = "@Id")]
+            id: u32,
+        }
+
+        let config = Config {
+            declaration: false,
+            ..Config::default()
+        };
+        assert!(to_bytes_with_config(&Late { name: "a", id: 1 }, &config).is_err());
+
+        let keys = BTreeMap::from([(None::<&str>, 1)]);
+        assert!(to_bytes_with_config(&keys, &config).is_err());
+    }
+
+    #[test]
+    fn declarations() {
+        #[derive(Serialize)]
+        struct Empty {}
+
+        let to_string = |config| {
+            String::from_utf8(to_bytes_with_config(&Empty {}, &config).unwrap().to_vec()).unwrap()
+        };
+        assert_eq!(
+            to_string(Config::default()),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?><Empty />"
+        );
+        assert_eq!(
+            to_string(Config {
+                declaration: true,
+                standalone: Some(true),
+            }),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?><Empty />"
+        );
+        assert_eq!(
+            to_string(Config {
+                declaration: false,
+                standalone: Some(true),
+            }),
+            "<Empty />"
+        );
+    }
+}